@@ -1,10 +1,57 @@
 #![no_std]
 
+/// Version of the [`H7Api`] table provided by the host.
+pub const API_VERSION: u32 = 3;
+
 pub type AppEntryPoint = extern "C" fn(*const H7Api) -> i32;
 
+/// Returned by the app entry point if the host API is incompatible with the app.
+pub const EXIT_INCOMPATIBLE_API: i32 = -0x4837;
+
+/// Optional host functionality. Check the `capabilities` bitmap before calling the
+/// related functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Capability {
+    Alloc = 1 << 0,
+    Io = 1 << 1,
+    /// Screen info and drawing, version 2
+    Gpu = 1 << 2,
+    /// Files on the host filesystems, version 3
+    Fs = 1 << 3,
+}
+
+impl Capability {
+    #[inline(always)]
+    pub const fn bit(self) -> u32 {
+        self as u32
+    }
+
+    /// Size of a table that has all entries of this capability
+    pub const fn table_size(self) -> usize {
+        match self {
+            Capability::Alloc | Capability::Io => H7Api::MIN_SIZE,
+            Capability::Gpu => core::mem::offset_of!(H7Api, open),
+            Capability::Fs => core::mem::size_of::<H7Api>(),
+        }
+    }
+}
+
+/// Function table passed to the app entry point.
+///
+/// New entries must only ever be appended to the end of the table, bumping [`API_VERSION`].
+/// Apps built against a newer table can then check `size`, `version` and `capabilities`
+/// before calling functions the host might not provide.
 #[derive(Debug, Clone)]
 #[repr(C)]
 pub struct H7Api {
+    // Header
+    /// Size of the table in bytes, as provided by the host
+    pub size: usize,
+    /// [`API_VERSION`] of the host
+    pub version: u32,
+    /// Bitmap of [`Capability`]
+    pub capabilities: u32,
     // Sys, Mem
     pub alloc: extern "C" fn(size: usize, align: usize) -> *mut u8,
     pub free: extern "C" fn(ptr: *mut u8),
@@ -13,13 +60,209 @@ pub struct H7Api {
     pub getc: extern "C" fn() -> u8,
     pub putc: extern "C" fn(c: u8) -> i32,
     pub puts: extern "C" fn(start: *const u8, len: usize) -> i32,
-    // GPU
-    // pub screen_width_px: extern "C" fn() -> u32,
-    // pub screen_height_px: extern "C" fn() -> u32,
-    // pub screen_width_char: extern "C" fn() -> u32,
-    // pub screen_height_char: extern "C" fn() -> u32,
-    // pub dot: extern "C" fn(x1: u32, y1: u32, color: u16) -> i32,
-    // pub line: extern "C" fn(x1: u32, y1: u32, x2: u32, y2: u32, stroke: u32, color: u16) -> i32,
-    // pub square: extern "C" fn(x1: u32, y1: u32, x2: u32, y2: u32, stroke: u32, color: u16) -> i32,
-    // pub square_fill: extern "C" fn(x1: u32, y1: u32, x2: u32, y2: u32, color: u16) -> i32,
+    // ---- Version 1 ends here ----
+    // GPU, colors are RGB565
+    pub screen_width_px: extern "C" fn() -> u32,
+    pub screen_height_px: extern "C" fn() -> u32,
+    pub screen_width_char: extern "C" fn() -> u32,
+    pub screen_height_char: extern "C" fn() -> u32,
+    pub dot: extern "C" fn(x1: u32, y1: u32, color: u16) -> i32,
+    pub line: extern "C" fn(x1: u32, y1: u32, x2: u32, y2: u32, stroke: u32, color: u16) -> i32,
+    pub square: extern "C" fn(x1: u32, y1: u32, x2: u32, y2: u32, stroke: u32, color: u16) -> i32,
+    pub square_fill: extern "C" fn(x1: u32, y1: u32, x2: u32, y2: u32, color: u16) -> i32,
+    // ---- Version 2 ends here ----
+    // FS, paths are utf-8 `device:/path`. Negative return values are an `FsError`.
+    /// Open a file in an [`OpenMode`], returns a handle
+    pub open: extern "C" fn(path: *const u8, path_len: usize, mode: u32) -> i32,
+    /// Returns the number of bytes read, 0 at the end of the file
+    pub read: extern "C" fn(handle: i32, buf: *mut u8, len: usize) -> i32,
+    /// Returns the number of bytes written
+    pub write: extern "C" fn(handle: i32, buf: *const u8, len: usize) -> i32,
+    /// Returns the new offset, `whence` is a [`Whence`]
+    pub seek: extern "C" fn(handle: i32, offset: i32, whence: u32) -> i32,
+    pub close: extern "C" fn(handle: i32) -> i32,
+    /// Entry `index` of a directory, returns 1 if there is one, 0 past the last entry
+    pub read_dir:
+        extern "C" fn(path: *const u8, path_len: usize, index: u32, entry: *mut DirEntry) -> i32,
+    pub stat: extern "C" fn(path: *const u8, path_len: usize, stat: *mut FileStat) -> i32,
+    // ---- Version 3 ends here ----
+}
+
+impl H7Api {
+    /// Size of the smallest valid table, version 1.
+    pub const MIN_SIZE: usize = core::mem::offset_of!(H7Api, screen_width_px);
+
+    /// The host provides `capability` and the table is large enough for its entries
+    #[inline(always)]
+    pub const fn has(&self, capability: Capability) -> bool {
+        self.capabilities & capability.bit() != 0 && self.size >= capability.table_size()
+    }
+
+    /// Check that a table provided by a host can be used by an app built against this crate.
+    /// Entries past version 1 are only there if [`H7Api::has`] their capability.
+    pub fn is_compatible(&self) -> bool {
+        self.version >= 1 && self.size >= Self::MIN_SIZE
+    }
+}
+
+/// Errors of the file functions, returned as negative values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum FsError {
+    /// Any other host or storage error
+    Io = -1,
+    NotFound = -2,
+    AlreadyExists = -3,
+    IsDirectory = -4,
+    /// Not utf-8, no or unknown device, or too long
+    InvalidPath = -5,
+    /// Not an open file
+    BadHandle = -6,
+    /// The host's handle table is full
+    TooManyOpenFiles = -7,
+    InvalidOffset = -8,
+    /// Reading a file opened for writing or the other way around
+    WrongMode = -9,
+    NoSpace = -10,
+    /// Not provided by the host or the filesystem
+    Unsupported = -11,
+}
+
+impl FsError {
+    #[inline(always)]
+    pub const fn code(self) -> i32 {
+        self as i32
+    }
+
+    /// The error for a return value, `None` if it is not negative
+    pub const fn from_code(code: i32) -> Option<Self> {
+        Some(match code {
+            0.. => return None,
+            -2 => Self::NotFound,
+            -3 => Self::AlreadyExists,
+            -4 => Self::IsDirectory,
+            -5 => Self::InvalidPath,
+            -6 => Self::BadHandle,
+            -7 => Self::TooManyOpenFiles,
+            -8 => Self::InvalidOffset,
+            -9 => Self::WrongMode,
+            -10 => Self::NoSpace,
+            -11 => Self::Unsupported,
+            _ => Self::Io,
+        })
+    }
+}
+
+impl core::fmt::Display for FsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io => write!(f, "I/O error"),
+            Self::NotFound => write!(f, "Not found"),
+            Self::AlreadyExists => write!(f, "Already exists"),
+            Self::IsDirectory => write!(f, "Is a directory"),
+            Self::InvalidPath => write!(f, "Invalid path"),
+            Self::BadHandle => write!(f, "Bad file handle"),
+            Self::TooManyOpenFiles => write!(f, "Too many open files"),
+            Self::InvalidOffset => write!(f, "Invalid offset"),
+            Self::WrongMode => write!(f, "File not opened for that"),
+            Self::NoSpace => write!(f, "No space left"),
+            Self::Unsupported => write!(f, "Not supported"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum OpenMode {
+    Read = 0,
+    /// Create or truncate
+    Write = 1,
+    /// Create if it doesn't exist, writes go to the end
+    Append = 2,
+}
+
+impl OpenMode {
+    pub const fn from_u32(mode: u32) -> Option<Self> {
+        match mode {
+            0 => Some(Self::Read),
+            1 => Some(Self::Write),
+            2 => Some(Self::Append),
+            _ => None,
+        }
+    }
+}
+
+/// Where `seek` offsets are relative to, the C `SEEK_SET`, `SEEK_CUR` and `SEEK_END`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Whence {
+    Start = 0,
+    Current = 1,
+    End = 2,
+}
+
+impl Whence {
+    pub const fn from_u32(whence: u32) -> Option<Self> {
+        match whence {
+            0 => Some(Self::Start),
+            1 => Some(Self::Current),
+            2 => Some(Self::End),
+            _ => None,
+        }
+    }
+}
+
+/// Longest file name in a [`DirEntry`], longer names are cut off
+pub const NAME_MAX: usize = 255;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct FileStat {
+    pub size: u32,
+    pub is_dir: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct DirEntry {
+    pub stat: FileStat,
+    /// Length of the name without the terminating nul
+    pub name_len: u32,
+    /// Utf-8 name, nul terminated, [`NAME_MAX`] + 1 bytes
+    pub name: [u8; 256],
+}
+
+impl DirEntry {
+    pub const fn new() -> Self {
+        Self {
+            stat: FileStat {
+                size: 0,
+                is_dir: false,
+            },
+            name_len: 0,
+            name: [0; 256],
+        }
+    }
+
+    /// Store `name`, cut off at [`NAME_MAX`] bytes
+    pub fn set_name(&mut self, name: &str) {
+        let mut len = name.len().min(NAME_MAX);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        self.name[len] = 0;
+        self.name_len = len as u32;
+    }
+
+    pub fn name(&self) -> &str {
+        let len = (self.name_len as usize).min(NAME_MAX);
+        core::str::from_utf8(&self.name[..len]).unwrap_or_default()
+    }
+}
+
+impl Default for DirEntry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
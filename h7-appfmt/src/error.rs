@@ -0,0 +1,117 @@
+use crate::{Fingerprint, PublicKey};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppFmtError {
+    /// Not enough data for a header (actual)
+    TooShort(usize),
+    /// Not an h7 app
+    BadMagic([u8; 4]),
+    /// Image was created for a newer format
+    UnsupportedFormat(u16),
+    /// Header size field is invalid
+    BadHeaderSize(u16),
+    /// Header is corrupt
+    HeaderCrc { provided: u32, calculated: u32 },
+    /// Payload is shorter than the header claims
+    PayloadTruncated { expected: u32, actual: u32 },
+    /// Payload is corrupt
+    PayloadCrc { provided: u32, calculated: u32 },
+    /// App requires a newer API than the host provides
+    ApiTooNew { required: u32, provided: u32 },
+    /// App is linked for another address
+    LoadAddress { expected: u32, actual: u32 },
+    /// App does not fit in app memory
+    TooLarge { size: usize, max: usize },
+    /// Entry point is outside of the payload
+    EntryOutOfRange(u32),
+    /// Entry point is neither a valid arm nor thumb address
+    EntryAlignment(u32),
+    /// Unknown relocation table entry
+    BadRelocation(u32),
+    /// Relocation offset is outside of the payload
+    RelocationOutOfRange(u32),
+    /// Relocated address does not point into the app (offset, value)
+    RelocationTarget { offset: u32, value: u32 },
+    /// Relocated instruction is not the expected one (offset)
+    RelocationInstruction(u32),
+    /// App can not be moved to this address
+    RelocationAlignment(u32),
+    /// App has no signature
+    Unsigned,
+    /// Signature block is missing after the body
+    SignatureTruncated,
+    /// App is signed by a key that is not trusted
+    UntrustedKey(PublicKey),
+    /// Signature does not match the app, it was modified after signing
+    BadSignature,
+    /// Compressed payload is corrupt (decompressed offset)
+    Decompress(u32),
+}
+
+impl core::fmt::Display for AppFmtError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::TooShort(len) => write!(f, "Not enough data for an app header ({len} bytes)"),
+            Self::BadMagic(magic) => write!(f, "Not an h7 app (magic {magic:02x?})"),
+            Self::UnsupportedFormat(v) => write!(f, "Unsupported app format version {v}"),
+            Self::BadHeaderSize(s) => write!(f, "Invalid header size {s}"),
+            Self::HeaderCrc {
+                provided,
+                calculated,
+            } => write!(
+                f,
+                "Header CRC mismatch (provided 0x{provided:08x}, calculated 0x{calculated:08x})"
+            ),
+            Self::PayloadTruncated { expected, actual } => {
+                write!(
+                    f,
+                    "Payload truncated (expected {expected} bytes, got {actual})"
+                )
+            }
+            Self::PayloadCrc {
+                provided,
+                calculated,
+            } => write!(
+                f,
+                "Payload CRC mismatch (provided 0x{provided:08x}, calculated 0x{calculated:08x})"
+            ),
+            Self::ApiTooNew { required, provided } => {
+                write!(f, "App requires API v{required}, host provides v{provided}")
+            }
+            Self::LoadAddress { expected, actual } => {
+                write!(
+                    f,
+                    "App linked for 0x{actual:08x}, expected 0x{expected:08x}"
+                )
+            }
+            Self::TooLarge { size, max } => write!(f, "App too large ({size} bytes, max {max})"),
+            Self::EntryOutOfRange(offset) => {
+                write!(f, "Entry offset 0x{offset:x} outside of payload")
+            }
+            Self::EntryAlignment(addr) => write!(f, "Invalid entry address 0x{addr:08x}"),
+            Self::BadRelocation(raw) => write!(f, "Invalid relocation 0x{raw:08x}"),
+            Self::RelocationOutOfRange(offset) => {
+                write!(f, "Relocation at 0x{offset:x} outside of payload")
+            }
+            Self::RelocationTarget { offset, value } => write!(
+                f,
+                "Relocation at 0x{offset:x} points outside of the app (0x{value:08x})"
+            ),
+            Self::RelocationInstruction(offset) => {
+                write!(f, "Unexpected instruction at relocation 0x{offset:x}")
+            }
+            Self::RelocationAlignment(addr) => {
+                write!(f, "App can not be relocated to 0x{addr:08x}")
+            }
+            Self::Unsigned => write!(f, "App is not signed"),
+            Self::SignatureTruncated => write!(f, "Signature block truncated"),
+            Self::UntrustedKey(key) => {
+                write!(f, "App signed by untrusted key {}", Fingerprint(key))
+            }
+            Self::BadSignature => write!(f, "Signature mismatch, the app was modified"),
+            Self::Decompress(offset) => {
+                write!(f, "Compressed payload is corrupt at 0x{offset:x}")
+            }
+        }
+    }
+}
@@ -0,0 +1,683 @@
+#![no_std]
+
+mod error;
+mod load;
+mod lz4;
+mod reloc;
+mod sign;
+
+pub use {
+    error::AppFmtError,
+    load::{load, LoadedImage, Loader, MAX_HEADER_SIZE},
+    lz4::{decompress, Lz4Decoder},
+    reloc::{relocations, Relocation, RelocationKind, MOVW_ALIGN, RELOCATION_SIZE},
+    sign::{
+        public_key, Fingerprint, PublicKey, SignatureBlock, PUBLIC_KEY_SIZE, SEED_SIZE,
+        SIGNATURE_BLOCK_SIZE, SIGNATURE_SIZE,
+    },
+};
+
+pub const MAGIC: [u8; 4] = *b"H7AP";
+pub const FORMAT_VERSION: u16 = 3;
+pub const HEADER_SIZE: usize = 104;
+/// Header size of format version 1, without the relocation count
+pub const HEADER_SIZE_V1: usize = 96;
+/// Header size of format version 2, without the stored payload size
+pub const HEADER_SIZE_V2: usize = 100;
+pub const NAME_LEN: usize = 32;
+pub const VERSION_LEN: usize = 16;
+pub const DEFAULT_LOAD_ADDRESS: u32 = 0x2400_0000;
+/// App memory at the default load address, AXI SRAM
+pub const DEFAULT_APP_SIZE: usize = 512 * 1024;
+
+/// The payload is followed by a relocation table, the app can be loaded at any address
+pub const FLAG_RELOCATABLE: u32 = 1 << 0;
+/// The body is followed by a signature block, signing the header and the body
+pub const FLAG_SIGNED: u32 = 1 << 1;
+/// The payload is stored as an LZ4 block, `payload_size` is the decompressed size
+pub const FLAG_COMPRESSED: u32 = 1 << 2;
+
+const ARM_ADDR_ALIGN: u32 = 4;
+const THUMB_ADDR_ALIGN: u32 = 2;
+const THUMB_MASK: u32 = 0x0000_0001;
+
+const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_MPEG_2);
+
+/// CRC used for both the header and the payload. Matches the default
+/// configuration of the STM32H7 CRC peripheral.
+pub fn crc32(data: &[u8]) -> u32 {
+    CRC.checksum(data)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppHeader {
+    pub format_version: u16,
+    pub header_size: u16,
+    pub api_version: u32,
+    pub load_address: u32,
+    pub entry_offset: u32,
+    pub payload_size: u32,
+    pub text_size: u32,
+    pub data_size: u32,
+    pub bss_size: u32,
+    pub flags: u32,
+    pub name: [u8; NAME_LEN],
+    pub version: [u8; VERSION_LEN],
+    /// Entries in the relocation table following the payload
+    pub reloc_count: u32,
+    /// CRC of the payload as loaded and the relocation table
+    pub payload_crc: u32,
+    /// Size of the payload in the image, compressed or the payload size
+    pub stored_size: u32,
+}
+
+impl AppHeader {
+    /// Create a header for `payload`. `entry_address` is the absolute address of the
+    /// entry point, including the thumb bit. The section sizes are 0 until set from the app's
+    /// sections.
+    pub fn new(api_version: u32, load_address: u32, entry_address: u32, payload: &[u8]) -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            header_size: HEADER_SIZE as u16,
+            api_version,
+            load_address,
+            entry_offset: entry_address.wrapping_sub(load_address),
+            payload_size: payload.len() as u32,
+            text_size: 0,
+            data_size: 0,
+            bss_size: 0,
+            flags: 0,
+            name: [0; NAME_LEN],
+            version: [0; VERSION_LEN],
+            reloc_count: 0,
+            payload_crc: crc32(payload),
+            stored_size: payload.len() as u32,
+        }
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        copy_str(&mut self.name, name)
+    }
+
+    pub fn set_version(&mut self, version: &str) {
+        copy_str(&mut self.version, version)
+    }
+
+    pub fn name(&self) -> &str {
+        read_str(&self.name)
+    }
+
+    pub fn version(&self) -> &str {
+        read_str(&self.version)
+    }
+
+    pub fn entry_address(&self) -> u32 {
+        self.load_address.wrapping_add(self.entry_offset)
+    }
+
+    /// Size of the app once loaded, payload and zeroed .bss
+    pub fn memory_size(&self) -> usize {
+        self.payload_size as usize + self.bss_size as usize
+    }
+
+    pub fn is_relocatable(&self) -> bool {
+        self.flags & FLAG_RELOCATABLE != 0
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.flags & FLAG_SIGNED != 0
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.flags & FLAG_COMPRESSED != 0
+    }
+
+    /// The payload is stored as `stored`, the LZ4 block of the payload
+    pub fn set_compressed(&mut self, stored: &[u8]) {
+        self.flags |= FLAG_COMPRESSED;
+        self.stored_size = stored.len() as u32;
+    }
+
+    pub fn table_size(&self) -> usize {
+        self.reloc_count as usize * RELOCATION_SIZE
+    }
+
+    /// Size of the image after the header, signature included
+    pub fn stored_body_size(&self) -> usize {
+        let signature = match self.is_signed() {
+            true => SIGNATURE_BLOCK_SIZE,
+            false => 0,
+        };
+        self.stored_size as usize + self.table_size() + signature
+    }
+
+    /// Memory needed to load the app, the relocation table is read into .bss first
+    pub fn load_size(&self) -> usize {
+        self.memory_size()
+            .max(self.payload_size as usize + self.table_size())
+    }
+
+    /// Add a relocation table, the CRC is calculated over `payload` and `table`
+    pub fn set_relocations(&mut self, payload: &[u8], table: &[u8]) {
+        self.flags |= FLAG_RELOCATABLE;
+        self.reloc_count = (table.len() / RELOCATION_SIZE) as u32;
+        let mut digest = CRC.digest();
+        digest.update(payload);
+        digest.update(table);
+        self.payload_crc = digest.finalize();
+    }
+
+    /// Parse and validate a header. Only the header is checked, see [`AppHeader::payload`].
+    pub fn from_bytes(data: &[u8]) -> Result<Self, AppFmtError> {
+        if data.len() < HEADER_SIZE_V1 {
+            return Err(AppFmtError::TooShort(data.len()));
+        }
+        if data[0..4] != MAGIC {
+            return Err(AppFmtError::BadMagic([data[0], data[1], data[2], data[3]]));
+        }
+
+        let format_version = be_u16(data, 4);
+        if format_version == 0 || format_version > FORMAT_VERSION {
+            return Err(AppFmtError::UnsupportedFormat(format_version));
+        }
+
+        let header_size = be_u16(data, 6);
+        let min_size = match format_version {
+            1 => HEADER_SIZE_V1,
+            2 => HEADER_SIZE_V2,
+            _ => HEADER_SIZE,
+        };
+        if (header_size as usize) < min_size || header_size as usize > data.len() {
+            return Err(AppFmtError::BadHeaderSize(header_size));
+        }
+
+        let crc_offset = header_size as usize - 4;
+        let provided_crc = be_u32(data, crc_offset);
+        let calculated_crc = crc32(&data[..crc_offset]);
+        if provided_crc != calculated_crc {
+            return Err(AppFmtError::HeaderCrc {
+                provided: provided_crc,
+                calculated: calculated_crc,
+            });
+        }
+
+        let mut name = [0u8; NAME_LEN];
+        name.copy_from_slice(&data[40..72]);
+        let mut version = [0u8; VERSION_LEN];
+        version.copy_from_slice(&data[72..88]);
+        let payload_size = be_u32(data, 20);
+        let (reloc_count, payload_crc, stored_size) = match format_version {
+            1 => (0, be_u32(data, 88), payload_size),
+            2 => (be_u32(data, 88), be_u32(data, 92), payload_size),
+            _ => (be_u32(data, 88), be_u32(data, 92), be_u32(data, 96)),
+        };
+
+        Ok(Self {
+            format_version,
+            header_size,
+            api_version: be_u32(data, 8),
+            load_address: be_u32(data, 12),
+            entry_offset: be_u32(data, 16),
+            payload_size,
+            text_size: be_u32(data, 24),
+            data_size: be_u32(data, 28),
+            bss_size: be_u32(data, 32),
+            flags: be_u32(data, 36),
+            name,
+            version,
+            reloc_count,
+            payload_crc,
+            stored_size,
+        })
+    }
+
+    /// Serialize the header, computing the header CRC.
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut out = [0u8; HEADER_SIZE];
+        out[0..4].copy_from_slice(&MAGIC);
+        out[4..6].copy_from_slice(&FORMAT_VERSION.to_be_bytes());
+        out[6..8].copy_from_slice(&(HEADER_SIZE as u16).to_be_bytes());
+        out[8..12].copy_from_slice(&self.api_version.to_be_bytes());
+        out[12..16].copy_from_slice(&self.load_address.to_be_bytes());
+        out[16..20].copy_from_slice(&self.entry_offset.to_be_bytes());
+        out[20..24].copy_from_slice(&self.payload_size.to_be_bytes());
+        out[24..28].copy_from_slice(&self.text_size.to_be_bytes());
+        out[28..32].copy_from_slice(&self.data_size.to_be_bytes());
+        out[32..36].copy_from_slice(&self.bss_size.to_be_bytes());
+        out[36..40].copy_from_slice(&self.flags.to_be_bytes());
+        out[40..72].copy_from_slice(&self.name);
+        out[72..88].copy_from_slice(&self.version);
+        out[88..92].copy_from_slice(&self.reloc_count.to_be_bytes());
+        out[92..96].copy_from_slice(&self.payload_crc.to_be_bytes());
+        out[96..100].copy_from_slice(&self.stored_size.to_be_bytes());
+        let header_crc = crc32(&out[..HEADER_SIZE - 4]);
+        out[100..104].copy_from_slice(&header_crc.to_be_bytes());
+        out
+    }
+
+    /// Get the payload following the header in `image`, as stored.
+    pub fn payload<'i>(&self, image: &'i [u8]) -> Result<&'i [u8], AppFmtError> {
+        self.body(image)
+            .map(|body| &body[..self.stored_size as usize])
+    }
+
+    /// Get the relocation table following the payload in `image`.
+    pub fn relocation_table<'i>(&self, image: &'i [u8]) -> Result<&'i [u8], AppFmtError> {
+        self.body(image)
+            .map(|body| &body[self.stored_size as usize..])
+    }
+
+    /// Get the payload as stored and the relocation table. Without compression this is the
+    /// data covered by the payload CRC, see [`LoadedImage::content`].
+    pub fn body<'i>(&self, image: &'i [u8]) -> Result<&'i [u8], AppFmtError> {
+        let start = self.header_size as usize;
+        let size = self.stored_size as usize + self.table_size();
+        image
+            .get(start..start + size)
+            .ok_or(AppFmtError::PayloadTruncated {
+                expected: size as u32,
+                actual: image.len().saturating_sub(start) as u32,
+            })
+    }
+
+    /// Get the signature block following the body in `image`.
+    pub fn signature_block(&self, image: &[u8]) -> Result<SignatureBlock, AppFmtError> {
+        if !self.is_signed() {
+            return Err(AppFmtError::Unsigned);
+        }
+        let start = self.header_size as usize + self.body(image)?.len();
+        image
+            .get(start..start + SIGNATURE_BLOCK_SIZE)
+            .and_then(|block| block.try_into().ok())
+            .map(SignatureBlock::from_bytes)
+            .ok_or(AppFmtError::SignatureTruncated)
+    }
+
+    /// Compare a CRC calculated over the body with the one in the header.
+    pub fn check_payload_crc(&self, calculated: u32) -> Result<u32, AppFmtError> {
+        if calculated == self.payload_crc {
+            Ok(calculated)
+        } else {
+            Err(AppFmtError::PayloadCrc {
+                provided: self.payload_crc,
+                calculated,
+            })
+        }
+    }
+
+    /// Check that the app can run on a host providing `api_version`, loading apps at
+    /// `load_address` with `max_size` bytes available. Relocatable apps run at any address.
+    pub fn check_compatible(
+        &self,
+        api_version: u32,
+        load_address: u32,
+        max_size: usize,
+    ) -> Result<(), AppFmtError> {
+        if self.api_version > api_version {
+            return Err(AppFmtError::ApiTooNew {
+                required: self.api_version,
+                provided: api_version,
+            });
+        }
+        if self.load_address != load_address && !self.is_relocatable() {
+            return Err(AppFmtError::LoadAddress {
+                expected: load_address,
+                actual: self.load_address,
+            });
+        }
+        if self.load_size() > max_size {
+            return Err(AppFmtError::TooLarge {
+                size: self.load_size(),
+                max: max_size,
+            });
+        }
+        if self.entry_offset & !THUMB_MASK >= self.payload_size {
+            return Err(AppFmtError::EntryOutOfRange(self.entry_offset));
+        }
+        check_entry_alignment(self.entry_address()).map(|_| ())
+    }
+
+    /// Apply the relocation `table` to the payload at the start of `memory`, to run it at
+    /// `address` instead of the load address. Every relocated address has to point into the
+    /// app, .bss included.
+    pub fn relocate(
+        &self,
+        memory: &mut [u8],
+        table: &[u8],
+        address: u32,
+    ) -> Result<(), AppFmtError> {
+        let max = memory.len();
+        let payload =
+            memory
+                .get_mut(..self.payload_size as usize)
+                .ok_or(AppFmtError::TooLarge {
+                    size: self.payload_size as usize,
+                    max,
+                })?;
+        let range = self.load_address..=self.load_address.wrapping_add(self.memory_size() as u32);
+        let delta = address.wrapping_sub(self.load_address);
+        for reloc in relocations(table) {
+            reloc::apply(payload, reloc?, range.clone(), delta)?;
+        }
+        Ok(())
+    }
+}
+
+/// LSB is not part of the actual address, but rather indicate if the cpu should
+/// switch to arm or thumb mode. 0 = ARM, 1 = THUMB
+pub fn check_entry_alignment(addr: u32) -> Result<&'static str, AppFmtError> {
+    let a = addr & !THUMB_MASK;
+    match (
+        addr & THUMB_MASK,    // Thumb?
+        a % THUMB_ADDR_ALIGN, // Valid Thumb alignment?
+        a % ARM_ADDR_ALIGN,   // Valid ARM alignment?
+    ) {
+        (1, 0, _) => Ok("valid thumb"),
+        (0, _, 0) => Ok("valid arm"),
+        _ => Err(AppFmtError::EntryAlignment(addr)),
+    }
+}
+
+pub(crate) fn be_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+fn copy_str(dst: &mut [u8], src: &str) {
+    dst.fill(0);
+    // Truncate on a char boundary so that the field is always valid utf-8
+    let mut len = src.len().min(dst.len());
+    while !src.is_char_boundary(len) {
+        len -= 1;
+    }
+    dst[..len].copy_from_slice(&src.as_bytes()[..len]);
+}
+
+fn read_str(src: &[u8]) -> &str {
+    let len = src.iter().position(|b| *b == 0).unwrap_or(src.len());
+    core::str::from_utf8(&src[..len]).unwrap_or("<invalid>")
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use {super::*, std::vec::Vec};
+
+    const LOAD: u32 = DEFAULT_LOAD_ADDRESS;
+
+    /// Entry point word, `movw r0, #0x0108` and `movt r0, #0x2400`
+    const PAYLOAD: [u8; 12] = [
+        0x09, 0x00, 0x00, 0x24, 0x40, 0xf2, 0x08, 0x10, 0xc2, 0xf2, 0x00, 0x40,
+    ];
+
+    fn relocations() -> [u8; 12] {
+        let mut table = [0u8; 12];
+        for (entry, reloc) in table.chunks_exact_mut(RELOCATION_SIZE).zip([
+            Relocation::new(RelocationKind::Abs32, 0),
+            Relocation::new(RelocationKind::ThumbMovw, 4),
+            Relocation::new(RelocationKind::ThumbMovt, 8),
+        ]) {
+            entry.copy_from_slice(&reloc.to_bytes());
+        }
+        table
+    }
+
+    fn header() -> AppHeader {
+        let mut header = AppHeader::new(1, LOAD, LOAD + 9, &PAYLOAD);
+        header.bss_size = 0x200;
+        header.set_relocations(&PAYLOAD, &relocations());
+        header
+    }
+
+    #[test]
+    fn roundtrip() {
+        let header = header();
+        let mut image = header.to_bytes().to_vec();
+        image.extend_from_slice(&PAYLOAD);
+        image.extend_from_slice(&relocations());
+
+        let parsed = AppHeader::from_bytes(&image).unwrap();
+        assert_eq!(parsed, header);
+        assert!(parsed.is_relocatable());
+        assert_eq!(parsed.payload(&image).unwrap(), PAYLOAD);
+        assert_eq!(parsed.relocation_table(&image).unwrap(), relocations());
+        let crc = crc32(parsed.body(&image).unwrap());
+        assert_eq!(parsed.check_payload_crc(crc), Ok(crc));
+        assert_eq!(
+            parsed.body(&image[..image.len() - 1]),
+            Err(AppFmtError::PayloadTruncated {
+                expected: 24,
+                actual: 23
+            })
+        );
+    }
+
+    #[test]
+    fn layout_roundtrip() {
+        let mut header = AppHeader::new(1, LOAD, LOAD + 9, &PAYLOAD);
+        header.text_size = 8;
+        header.data_size = 4;
+        header.bss_size = 0x100;
+        header.set_name("hello");
+        header.set_version("1.2.3");
+        let bytes = header.to_bytes();
+        assert_eq!(bytes[24..36], [0, 0, 0, 8, 0, 0, 0, 4, 0, 0, 1, 0]);
+
+        let parsed = AppHeader::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!((parsed.name(), parsed.version()), ("hello", "1.2.3"));
+        assert_eq!(parsed.entry_address(), LOAD + 9);
+        assert_eq!(parsed.memory_size(), PAYLOAD.len() + 0x100);
+    }
+
+    #[test]
+    fn header_errors() {
+        let header = AppHeader::new(1, LOAD, LOAD + 9, &PAYLOAD).to_bytes();
+        let with = |offset: usize, value: &[u8]| {
+            let mut bytes = header;
+            bytes[offset..offset + value.len()].copy_from_slice(value);
+            AppHeader::from_bytes(&bytes)
+        };
+        assert_eq!(
+            AppHeader::from_bytes(&header[..50]),
+            Err(AppFmtError::TooShort(50))
+        );
+        assert_eq!(with(0, b"H7AX"), Err(AppFmtError::BadMagic(*b"H7AX")));
+        assert_eq!(with(4, &[0, 9]), Err(AppFmtError::UnsupportedFormat(9)));
+        assert_eq!(with(6, &[0, 20]), Err(AppFmtError::BadHeaderSize(20)));
+        assert_eq!(with(6, &[1, 0]), Err(AppFmtError::BadHeaderSize(256)));
+        assert!(matches!(with(40, b"x"), Err(AppFmtError::HeaderCrc { .. })));
+    }
+
+    #[test]
+    fn older_formats() {
+        let header = AppHeader::new(1, LOAD, LOAD + 9, &PAYLOAD);
+        let v3 = header.to_bytes();
+        let mut image = v3[..HEADER_SIZE_V1].to_vec();
+        image[4..6].copy_from_slice(&1u16.to_be_bytes());
+        image[6..8].copy_from_slice(&(HEADER_SIZE_V1 as u16).to_be_bytes());
+        image[88..92].copy_from_slice(&header.payload_crc.to_be_bytes());
+        let header_crc = crc32(&image[..92]);
+        image[92..96].copy_from_slice(&header_crc.to_be_bytes());
+        image.extend_from_slice(&PAYLOAD);
+
+        let parsed = AppHeader::from_bytes(&image).unwrap();
+        assert_eq!(parsed.format_version, 1);
+        assert_eq!(parsed.reloc_count, 0);
+        assert_eq!(parsed.payload_crc, header.payload_crc);
+        assert_eq!(parsed.body(&image).unwrap(), PAYLOAD);
+
+        let mut image = v3[..HEADER_SIZE_V2].to_vec();
+        image[4..6].copy_from_slice(&2u16.to_be_bytes());
+        image[6..8].copy_from_slice(&(HEADER_SIZE_V2 as u16).to_be_bytes());
+        let header_crc = crc32(&image[..96]);
+        image[96..100].copy_from_slice(&header_crc.to_be_bytes());
+        image.extend_from_slice(&PAYLOAD);
+
+        let parsed = AppHeader::from_bytes(&image).unwrap();
+        assert_eq!(parsed.format_version, 2);
+        assert_eq!(parsed.stored_size, PAYLOAD.len() as u32);
+        assert_eq!(parsed.body(&image).unwrap(), PAYLOAD);
+        let mut memory = [0u8; 16];
+        assert_eq!(load(&image, &mut memory).unwrap().content(), PAYLOAD);
+    }
+
+    #[test]
+    fn relocate() {
+        let header = header();
+        let mut memory = PAYLOAD;
+        header
+            .relocate(&mut memory, &relocations(), 0x3000_0000)
+            .unwrap();
+        assert_eq!(memory[0..4], 0x3000_0009u32.to_le_bytes());
+        // The low half stays, the high half moves
+        assert_eq!(memory[4..8], PAYLOAD[4..8]);
+        assert_eq!(memory[8..12], [0xc3, 0xf2, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn relocate_errors() {
+        let header = header();
+        let table = relocations();
+
+        let mut memory = PAYLOAD;
+        assert_eq!(
+            header.relocate(&mut memory, &table, 0x3000_0100),
+            Err(AppFmtError::RelocationAlignment(0x3000_0100))
+        );
+
+        let mut memory = PAYLOAD;
+        memory[0..4].copy_from_slice(&0x0800_0000u32.to_le_bytes());
+        assert_eq!(
+            header.relocate(&mut memory, &table, 0x3000_0000),
+            Err(AppFmtError::RelocationTarget {
+                offset: 0,
+                value: 0x0800_0000
+            })
+        );
+
+        let mut memory = PAYLOAD;
+        let table = Relocation::new(RelocationKind::ThumbMovt, 0).to_bytes();
+        assert_eq!(
+            header.relocate(&mut memory, &table, 0x3000_0000),
+            Err(AppFmtError::RelocationInstruction(0))
+        );
+
+        let table = Relocation::new(RelocationKind::Abs32, 10).to_bytes();
+        assert_eq!(
+            header.relocate(&mut memory, &table, 0x3000_0000),
+            Err(AppFmtError::RelocationOutOfRange(10))
+        );
+        assert_eq!(
+            Relocation::from_bytes(0xf000_0000u32.to_be_bytes()),
+            Err(AppFmtError::BadRelocation(0xf000_0000))
+        );
+    }
+
+    /// A signed relocatable app and the key it was signed with
+    fn signed_image(seed: &[u8; SEED_SIZE]) -> (Vec<u8>, PublicKey) {
+        let mut header = header();
+        header.flags |= FLAG_SIGNED;
+        let mut image = header.to_bytes().to_vec();
+        image.extend_from_slice(&PAYLOAD);
+        image.extend_from_slice(&relocations());
+        let block = SignatureBlock::sign(seed, &image);
+        image.extend_from_slice(&block.to_bytes());
+        (image, public_key(seed))
+    }
+
+    /// Load `image` and check its signature
+    fn verify(image: &[u8], trusted: &[PublicKey]) -> Result<PublicKey, AppFmtError> {
+        let mut memory = [0u8; 0x300];
+        load(image, &mut memory)?.verify_signature(trusted)
+    }
+
+    #[test]
+    fn signature() {
+        let (image, key) = signed_image(&[1; SEED_SIZE]);
+        let other = public_key(&[2; SEED_SIZE]);
+        let parsed = AppHeader::from_bytes(&image).unwrap();
+        assert!(parsed.is_signed());
+        assert_eq!(verify(&image, &[other, key]), Ok(key));
+        assert_eq!(
+            verify(&image, &[other]),
+            Err(AppFmtError::UntrustedKey(key))
+        );
+        assert_eq!(
+            parsed.signature_block(&image).map(|block| block.public_key),
+            Ok(key)
+        );
+        assert_eq!(
+            parsed.signature_block(&image[..image.len() - 1]),
+            Err(AppFmtError::SignatureTruncated)
+        );
+        assert_eq!(
+            verify(&image[..image.len() - 1], &[key]),
+            Err(AppFmtError::PayloadTruncated {
+                expected: 120,
+                actual: 119
+            })
+        );
+
+        let mut unsigned = header().to_bytes().to_vec();
+        unsigned.extend_from_slice(&PAYLOAD);
+        unsigned.extend_from_slice(&relocations());
+        assert_eq!(verify(&unsigned, &[key]), Err(AppFmtError::Unsigned));
+        assert_eq!(header().signature_block(&image), Err(AppFmtError::Unsigned));
+    }
+
+    #[test]
+    fn signature_tampered() {
+        let (image, key) = signed_image(&[1; SEED_SIZE]);
+
+        // Payload, relocation table and signature
+        for offset in [HEADER_SIZE + 4, HEADER_SIZE + 13, image.len() - 1] {
+            let mut tampered = image.clone();
+            tampered[offset] ^= 1;
+            assert_eq!(verify(&tampered, &[key]), Err(AppFmtError::BadSignature));
+        }
+
+        // A valid header for another address
+        let mut header = AppHeader::from_bytes(&image).unwrap();
+        header.load_address = 0x3000_0000;
+        let mut tampered = image.clone();
+        tampered[..HEADER_SIZE].copy_from_slice(&header.to_bytes());
+        assert_eq!(verify(&tampered, &[key]), Err(AppFmtError::BadSignature));
+
+        // Signed with another key, claiming to be a trusted one
+        let (mut forged, _) = signed_image(&[2; SEED_SIZE]);
+        let len = forged.len();
+        forged[len - SIGNATURE_BLOCK_SIZE..len - SIGNATURE_SIZE].copy_from_slice(&key);
+        assert_eq!(verify(&forged, &[key]), Err(AppFmtError::BadSignature));
+    }
+
+    #[test]
+    fn compatible() {
+        let mut header = header();
+        assert_eq!(header.check_compatible(1, 0x3000_0000, 1024), Ok(()));
+        assert_eq!(
+            header.check_compatible(1, 0x3000_0000, 16),
+            Err(AppFmtError::TooLarge {
+                size: 0x20c,
+                max: 16
+            })
+        );
+        header.flags = 0;
+        assert_eq!(
+            header.check_compatible(1, 0x3000_0000, 1024),
+            Err(AppFmtError::LoadAddress {
+                expected: 0x3000_0000,
+                actual: LOAD
+            })
+        );
+    }
+}
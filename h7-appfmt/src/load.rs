@@ -0,0 +1,401 @@
+//! Loading an image as it arrives, in chunks from a file or the serial port, without a copy
+//! of the whole image in memory
+
+use crate::{
+    be_u16, lz4::Lz4Decoder, AppFmtError, AppHeader, PublicKey, SignatureBlock, HEADER_SIZE_V1,
+    MAGIC, SIGNATURE_BLOCK_SIZE,
+};
+
+/// Largest header a loader accepts, newer formats may grow the header up to this
+pub const MAX_HEADER_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Header,
+    Payload,
+    Table,
+    Signature,
+    Done,
+}
+
+/// Loads an image into memory, the payload decompressed at the start followed by the
+/// relocation table
+pub struct Loader<'m> {
+    stage: Stage,
+    header_data: [u8; MAX_HEADER_SIZE],
+    header: Option<AppHeader>,
+    memory: &'m mut [u8],
+    /// Bytes of the image so far
+    received: usize,
+    /// Bytes written to memory
+    written: usize,
+    lz4: Lz4Decoder,
+    signature: [u8; SIGNATURE_BLOCK_SIZE],
+}
+
+impl Default for Loader<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'m> Loader<'m> {
+    pub fn new() -> Self {
+        Self {
+            stage: Stage::Header,
+            header_data: [0; MAX_HEADER_SIZE],
+            header: None,
+            memory: Default::default(),
+            received: 0,
+            written: 0,
+            lz4: Lz4Decoder::new(),
+            signature: [0; SIGNATURE_BLOCK_SIZE],
+        }
+    }
+
+    /// Feed the next bytes of the image. Once the header is complete `memory` is called with
+    /// it, to get the memory the app is loaded into.
+    pub fn feed<E: From<AppFmtError>>(
+        &mut self,
+        mut data: &[u8],
+        memory: impl FnOnce(&AppHeader) -> Result<&'m mut [u8], E>,
+    ) -> Result<(), E> {
+        let mut memory = Some(memory);
+        while !data.is_empty() && self.stage != Stage::Done {
+            let n = match self.stage {
+                Stage::Header => self.read_header(data)?,
+                stage => self.read_body(stage, data)?,
+            };
+            self.received += n;
+            data = &data[n..];
+
+            let complete = self.header.filter(|_| self.stage == Stage::Header);
+            if let Some((header, memory)) = complete.and_then(|h| Some((h, memory.take()?))) {
+                let memory = memory(&header)?;
+                if memory.len() < header.load_size() {
+                    return Err(AppFmtError::TooLarge {
+                        size: header.load_size(),
+                        max: memory.len(),
+                    }
+                    .into());
+                }
+                self.memory = memory;
+                self.stage = Stage::Payload;
+            }
+            self.next_stage()?;
+        }
+        Ok(())
+    }
+
+    /// Collect the header, returns how much of `data` was used
+    fn read_header(&mut self, data: &[u8]) -> Result<usize, AppFmtError> {
+        // Magic, format version and header size first
+        let size = match self.received {
+            0..=7 => 8,
+            _ => be_u16(&self.header_data, 6) as usize,
+        };
+        let n = data.len().min(size - self.received);
+        self.header_data[self.received..self.received + n].copy_from_slice(&data[..n]);
+        let received = self.received + n;
+        if received < size {
+            return Ok(n);
+        }
+        if received == 8 {
+            let size = be_u16(&self.header_data, 6) as usize;
+            let magic = &self.header_data[0..4];
+            if magic != MAGIC {
+                return Err(AppFmtError::BadMagic([
+                    magic[0], magic[1], magic[2], magic[3],
+                ]));
+            }
+            if !(HEADER_SIZE_V1..=MAX_HEADER_SIZE).contains(&size) {
+                return Err(AppFmtError::BadHeaderSize(size as u16));
+            }
+            return Ok(n);
+        }
+        self.header = Some(AppHeader::from_bytes(&self.header_data[..size])?);
+        Ok(n)
+    }
+
+    /// Copy or decompress the body, returns how much of `data` was used
+    fn read_body(&mut self, stage: Stage, data: &[u8]) -> Result<usize, AppFmtError> {
+        let Some(header) = self.header else {
+            return Ok(0);
+        };
+        let payload_size = header.payload_size as usize;
+        let offset = self.received - header.header_size as usize;
+        let table_offset = offset.saturating_sub(header.stored_size as usize);
+        match stage {
+            Stage::Payload => {
+                let n = data.len().min(header.stored_size as usize - offset);
+                if header.is_compressed() {
+                    let out = &mut self.memory[..payload_size];
+                    self.lz4.decode(&data[..n], out, &mut self.written)?;
+                } else {
+                    self.memory[self.written..self.written + n].copy_from_slice(&data[..n]);
+                    self.written += n;
+                }
+                Ok(n)
+            }
+            Stage::Table => {
+                let n = data.len().min(header.table_size() - table_offset);
+                self.memory[self.written..self.written + n].copy_from_slice(&data[..n]);
+                self.written += n;
+                Ok(n)
+            }
+            _ => {
+                let offset = table_offset - header.table_size();
+                let n = data.len().min(SIGNATURE_BLOCK_SIZE - offset);
+                self.signature[offset..offset + n].copy_from_slice(&data[..n]);
+                Ok(n)
+            }
+        }
+    }
+
+    /// Move on from stages that are complete
+    fn next_stage(&mut self) -> Result<(), AppFmtError> {
+        let Some(header) = self.header else {
+            return Ok(());
+        };
+        let body = self.received - header.header_size as usize;
+        loop {
+            let (done, next) = match self.stage {
+                // Once there is memory for the app
+                Stage::Header => (false, Stage::Payload),
+                Stage::Payload => (body == header.stored_size as usize, Stage::Table),
+                Stage::Table => (
+                    body == header.stored_size as usize + header.table_size(),
+                    Stage::Signature,
+                ),
+                Stage::Signature => (
+                    !header.is_signed() || body == header.stored_body_size(),
+                    Stage::Done,
+                ),
+                Stage::Done => return Ok(()),
+            };
+            if !done {
+                return Ok(());
+            }
+            if self.stage == Stage::Payload {
+                if header.is_compressed() {
+                    self.lz4.finish(self.written)?;
+                }
+                if self.written != header.payload_size as usize {
+                    return Err(AppFmtError::Decompress(self.written as u32));
+                }
+            }
+            self.stage = next;
+        }
+    }
+
+    /// The whole image was fed
+    pub fn finish(self) -> Result<LoadedImage<'m>, AppFmtError> {
+        let header = match (self.stage, self.header) {
+            (Stage::Done, Some(header)) => header,
+            (_, Some(header)) => {
+                return Err(AppFmtError::PayloadTruncated {
+                    expected: header.stored_body_size() as u32,
+                    actual: (self.received - header.header_size as usize) as u32,
+                })
+            }
+            (_, None) => return Err(AppFmtError::TooShort(self.received)),
+        };
+        Ok(LoadedImage {
+            header,
+            header_data: self.header_data,
+            signature: header
+                .is_signed()
+                .then(|| SignatureBlock::from_bytes(self.signature)),
+            memory: self.memory,
+        })
+    }
+}
+
+/// An image in memory, not relocated yet
+pub struct LoadedImage<'m> {
+    pub header: AppHeader,
+    header_data: [u8; MAX_HEADER_SIZE],
+    pub signature: Option<SignatureBlock>,
+    memory: &'m mut [u8],
+}
+
+impl<'m> LoadedImage<'m> {
+    /// The payload as loaded followed by the relocation table, covered by the payload CRC
+    pub fn content(&self) -> &[u8] {
+        &self.memory[..self.header.payload_size as usize + self.header.table_size()]
+    }
+
+    /// Check that the app is signed by one of the `trusted` keys, returns the key
+    pub fn verify_signature(&self, trusted: &[PublicKey]) -> Result<PublicKey, AppFmtError> {
+        let block = self.signature.ok_or(AppFmtError::Unsigned)?;
+        if !trusted.contains(&block.public_key) {
+            return Err(AppFmtError::UntrustedKey(block.public_key));
+        }
+        let header = &self.header_data[..self.header.header_size as usize];
+        block.verify(&[header, self.content()])?;
+        Ok(block.public_key)
+    }
+
+    /// Relocate the payload to run at `address` and zero the rest of the memory, the .bss
+    pub fn relocate(self, address: u32) -> Result<AppHeader, AppFmtError> {
+        let table_size = self.header.table_size();
+        let (payload, rest) = self.memory.split_at_mut(self.header.payload_size as usize);
+        self.header
+            .relocate(payload, &rest[..table_size], address)?;
+        rest.fill(0);
+        Ok(self.header)
+    }
+}
+
+/// Load a whole `image` into `memory`
+pub fn load<'m>(image: &[u8], memory: &'m mut [u8]) -> Result<LoadedImage<'m>, AppFmtError> {
+    let mut loader = Loader::new();
+    loader.feed(image, move |_| Ok::<_, AppFmtError>(memory))?;
+    loader.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use {
+        super::*,
+        crate::{crc32, public_key, Relocation, RelocationKind, FLAG_SIGNED, HEADER_SIZE},
+        std::vec::Vec,
+    };
+
+    const LOAD: u32 = 0x2400_0000;
+    const SEED: [u8; 32] = [3; 32];
+
+    /// Entry point and a pointer to the end, then something that compresses
+    fn payload() -> Vec<u8> {
+        let mut payload = [LOAD + 9, LOAD + 0x400]
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect::<Vec<_>>();
+        payload.extend((0..0x3f8).map(|i| (i / 16) as u8));
+        payload
+    }
+
+    /// A relocatable image, compressed and signed if asked for
+    fn image(compress: bool, sign: bool) -> Vec<u8> {
+        let payload = payload();
+        let table = Relocation::new(RelocationKind::Abs32, 4).to_bytes();
+        let mut header = AppHeader::new(1, LOAD, LOAD + 9, &payload);
+        header.bss_size = 0x100;
+        header.set_relocations(&payload, &table);
+        let stored = match compress {
+            true => lz4_flex::block::compress(&payload),
+            false => payload.clone(),
+        };
+        if compress {
+            header.set_compressed(&stored);
+        }
+        if sign {
+            header.flags |= FLAG_SIGNED;
+        }
+        let mut image = header.to_bytes().to_vec();
+        let signed = [&image[..], &payload, &table].concat();
+        image.extend_from_slice(&stored);
+        image.extend_from_slice(&table);
+        if sign {
+            image.extend_from_slice(&SignatureBlock::sign(&SEED, &signed).to_bytes());
+        }
+        image
+    }
+
+    /// Feed `image` in chunks of `chunk` bytes
+    fn feed<'m>(
+        image: &[u8],
+        chunk: usize,
+        memory: &'m mut [u8],
+    ) -> Result<LoadedImage<'m>, AppFmtError> {
+        let mut loader = Loader::new();
+        let mut memory = Some(memory);
+        for data in image.chunks(chunk) {
+            loader.feed(data, |_| memory.take().ok_or(AppFmtError::Unsigned))?;
+        }
+        loader.finish()
+    }
+
+    #[test]
+    fn chunks() {
+        let payload = payload();
+        for (compress, sign) in [(false, false), (true, false), (true, true)] {
+            let image = image(compress, sign);
+            if compress {
+                assert!(image.len() < payload.len());
+            }
+            for chunk in [1, 3, 7, 64, 100, 4096] {
+                let mut memory = [0xffu8; 0x600];
+                let loaded = feed(&image, chunk, &mut memory).unwrap();
+                assert_eq!(loaded.content()[..payload.len()], payload);
+                let crc = crc32(loaded.content());
+                assert_eq!(loaded.header.check_payload_crc(crc), Ok(crc));
+                let signature = loaded.verify_signature(&[public_key(&SEED)]);
+                match sign {
+                    true => assert_eq!(signature, Ok(public_key(&SEED))),
+                    false => assert_eq!(signature, Err(AppFmtError::Unsigned)),
+                }
+
+                loaded.relocate(0x3000_0000).unwrap();
+                assert_eq!(memory[4..8], 0x3000_0400u32.to_le_bytes());
+                assert!(memory[payload.len()..].iter().all(|b| *b == 0));
+            }
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let image = image(true, true);
+        let mut memory = [0u8; 0x600];
+
+        assert_eq!(
+            feed(&image, 64, &mut memory[..0x4ff]).err(),
+            Some(AppFmtError::TooLarge {
+                size: 0x500,
+                max: 0x4ff
+            })
+        );
+        assert_eq!(
+            feed(&image[..image.len() - 1], 64, &mut memory).err(),
+            Some(AppFmtError::PayloadTruncated {
+                expected: (image.len() - HEADER_SIZE) as u32,
+                actual: (image.len() - HEADER_SIZE - 1) as u32
+            })
+        );
+        assert_eq!(
+            feed(&image[..50], 64, &mut memory).err(),
+            Some(AppFmtError::TooShort(50))
+        );
+        assert_eq!(
+            feed(b"MZ\0\0\0\0\0\0", 64, &mut memory).err(),
+            Some(AppFmtError::BadMagic(*b"MZ\0\0"))
+        );
+
+        let mut bad = image.clone();
+        bad[6..8].copy_from_slice(&0x1000u16.to_be_bytes());
+        assert_eq!(
+            feed(&bad, 64, &mut memory).err(),
+            Some(AppFmtError::BadHeaderSize(0x1000))
+        );
+
+        // A match before the start of the payload
+        let mut bad = image.clone();
+        bad[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&[0x10, 0xaa, 0x10, 0x00]);
+        assert_eq!(
+            feed(&bad, 64, &mut memory).err(),
+            Some(AppFmtError::Decompress(1))
+        );
+
+        // Decompresses to less than the payload size
+        let mut header = AppHeader::from_bytes(&image).unwrap();
+        header.payload_size += 1;
+        let mut bad = image.clone();
+        bad[..HEADER_SIZE].copy_from_slice(&header.to_bytes());
+        assert_eq!(
+            feed(&bad, 64, &mut memory).err(),
+            Some(AppFmtError::Decompress(0x400))
+        );
+    }
+}
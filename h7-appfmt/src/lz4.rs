@@ -0,0 +1,231 @@
+//! Decompression of LZ4 blocks as they arrive, the input can be split anywhere
+
+use crate::AppFmtError;
+
+/// Matches are at least this long, the token holds the length minus this
+const MIN_MATCH: usize = 4;
+/// Token nibble meaning that the length continues in the following bytes
+const MORE: usize = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Token,
+    /// Literal length bytes (length so far, match nibble)
+    LiteralLength(usize, u8),
+    /// Literals left to copy (count, match nibble)
+    Literals(usize, u8),
+    /// Little endian match offset, the low byte once read (low byte, match nibble)
+    Offset(Option<u8>, u8),
+    /// Match length bytes (offset, length so far)
+    MatchLength(usize, usize),
+}
+
+/// Decoder of an LZ4 block. The output is the history matches are copied from, so it has to
+/// be the whole decompressed data.
+#[derive(Debug, Clone)]
+pub struct Lz4Decoder {
+    state: State,
+}
+
+impl Default for Lz4Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lz4Decoder {
+    pub fn new() -> Self {
+        Self {
+            state: State::Token,
+        }
+    }
+
+    /// Decompress the next bytes of the block into `out` from `pos`, `pos` is moved past what
+    /// was written
+    pub fn decode(
+        &mut self,
+        input: &[u8],
+        out: &mut [u8],
+        pos: &mut usize,
+    ) -> Result<(), AppFmtError> {
+        let mut i = 0;
+        while i < input.len() {
+            let byte = input[i];
+            self.state = match self.state {
+                State::Token => {
+                    i += 1;
+                    let (literals, matches) = ((byte >> 4) as usize, byte & 0xf);
+                    match literals {
+                        0 => State::Offset(None, matches),
+                        MORE => State::LiteralLength(MORE, matches),
+                        n => State::Literals(n, matches),
+                    }
+                }
+                State::LiteralLength(len, matches) => {
+                    i += 1;
+                    let len = len.saturating_add(byte as usize);
+                    match byte {
+                        255 => State::LiteralLength(len, matches),
+                        _ => State::Literals(len, matches),
+                    }
+                }
+                State::Literals(remaining, matches) => {
+                    let n = remaining.min(input.len() - i);
+                    out.get_mut(*pos..*pos + n)
+                        .ok_or(AppFmtError::Decompress(*pos as u32))?
+                        .copy_from_slice(&input[i..i + n]);
+                    i += n;
+                    *pos += n;
+                    match remaining - n {
+                        0 => State::Offset(None, matches),
+                        left => State::Literals(left, matches),
+                    }
+                }
+                State::Offset(None, matches) => {
+                    i += 1;
+                    State::Offset(Some(byte), matches)
+                }
+                State::Offset(Some(low), matches) => {
+                    i += 1;
+                    let offset = u16::from_le_bytes([low, byte]) as usize;
+                    match matches as usize {
+                        MORE => State::MatchLength(offset, MORE + MIN_MATCH),
+                        len => {
+                            copy_match(out, pos, offset, len + MIN_MATCH)?;
+                            State::Token
+                        }
+                    }
+                }
+                State::MatchLength(offset, len) => {
+                    i += 1;
+                    let len = len.saturating_add(byte as usize);
+                    match byte {
+                        255 => State::MatchLength(offset, len),
+                        _ => {
+                            copy_match(out, pos, offset, len)?;
+                            State::Token
+                        }
+                    }
+                }
+            };
+        }
+        Ok(())
+    }
+
+    /// Check that the block ended after a complete sequence, `pos` is the decompressed size
+    pub fn finish(&self, pos: usize) -> Result<(), AppFmtError> {
+        match self.state {
+            // The last sequence has literals only
+            State::Token | State::Offset(None, _) => Ok(()),
+            _ => Err(AppFmtError::Decompress(pos as u32)),
+        }
+    }
+}
+
+/// Copy `len` bytes from `offset` back in `out`, they may overlap what is being written
+fn copy_match(
+    out: &mut [u8],
+    pos: &mut usize,
+    offset: usize,
+    len: usize,
+) -> Result<(), AppFmtError> {
+    let start = *pos;
+    if offset == 0 || offset > start || len > out.len() - start {
+        return Err(AppFmtError::Decompress(start as u32));
+    }
+    if offset >= len {
+        out.copy_within(start - offset..start - offset + len, start);
+    } else {
+        for i in start..start + len {
+            out[i] = out[i - offset];
+        }
+    }
+    *pos += len;
+    Ok(())
+}
+
+/// Decompress a whole block into `out`, returns the decompressed size
+pub fn decompress(input: &[u8], out: &mut [u8]) -> Result<usize, AppFmtError> {
+    let mut decoder = Lz4Decoder::new();
+    let mut pos = 0;
+    decoder.decode(input, out, &mut pos)?;
+    decoder.finish(pos)?;
+    Ok(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use {super::*, std::vec::Vec};
+
+    fn data() -> Vec<u8> {
+        let mut data = Vec::new();
+        for i in 0..2000u32 {
+            data.extend_from_slice(&(i / 7).to_le_bytes());
+        }
+        data.extend_from_slice(&[0xaa; 300]);
+        data.extend_from_slice(b"h7 app, h7 app, h7 app");
+        data
+    }
+
+    #[test]
+    fn streaming() {
+        let data = data();
+        let compressed = lz4_flex::block::compress(&data);
+        assert!(compressed.len() < data.len() / 2);
+
+        let mut out = [0u8; 9000];
+        assert_eq!(decompress(&compressed, &mut out), Ok(data.len()));
+        assert_eq!(out[..data.len()], data);
+
+        // Split at every possible size of chunk up to some
+        for chunk in 1..40 {
+            let mut out = [0u8; 9000];
+            let mut decoder = Lz4Decoder::new();
+            let mut pos = 0;
+            for input in compressed.chunks(chunk) {
+                decoder.decode(input, &mut out, &mut pos).unwrap();
+            }
+            assert_eq!(decoder.finish(pos), Ok(()));
+            assert_eq!(out[..pos], data);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let data = data();
+        let compressed = lz4_flex::block::compress(&data);
+
+        // Output too small
+        let mut out = [0u8; 1000];
+        assert!(matches!(
+            decompress(&compressed, &mut out),
+            Err(AppFmtError::Decompress(_))
+        ));
+
+        // Ends in the middle of a sequence
+        let mut out = [0u8; 9000];
+        assert!(matches!(
+            decompress(&compressed[..compressed.len() / 2], &mut out),
+            Err(AppFmtError::Decompress(_))
+        ));
+
+        // Match before the start, 1 literal and a match at offset 2
+        assert_eq!(
+            decompress(&[0x10, b'a', 0x02, 0x00], &mut out),
+            Err(AppFmtError::Decompress(1))
+        );
+        // Offset 0
+        assert_eq!(
+            decompress(&[0x10, b'a', 0x00, 0x00], &mut out),
+            Err(AppFmtError::Decompress(1))
+        );
+        // Overlapping match, 'a' repeated, then the last literals
+        assert_eq!(
+            decompress(&[0x13, b'a', 0x01, 0x00, 0x10, b'b'], &mut out),
+            Ok(9)
+        );
+        assert_eq!(out[..9], *b"aaaaaaaab");
+    }
+}
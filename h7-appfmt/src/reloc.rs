@@ -0,0 +1,139 @@
+use {crate::AppFmtError, core::ops::RangeInclusive};
+
+/// Size of an entry in the relocation table
+pub const RELOCATION_SIZE: usize = 4;
+
+/// Relocations that touch half of an address leave the low half alone, apps using them can
+/// only be moved in steps of this
+pub const MOVW_ALIGN: u32 = 0x1_0000;
+
+const KIND_SHIFT: u32 = 28;
+const OFFSET_MASK: u32 = (1 << KIND_SHIFT) - 1;
+
+/// Opcode bits of the first halfword of Thumb MOVW (T3) and MOVT (T1), the rest is Rd and imm16
+const THUMB_MOV_MASK: u16 = 0xfbf0;
+const THUMB_MOVW: u16 = 0xf240;
+const THUMB_MOVT: u16 = 0xf2c0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RelocationKind {
+    /// Little endian word holding an address (R_ARM_ABS32, GOT entries)
+    Abs32 = 0,
+    /// Thumb MOVW loading the low half of an address (R_ARM_THM_MOVW_ABS_NC)
+    ThumbMovw = 1,
+    /// Thumb MOVT loading the high half of an address (R_ARM_THM_MOVT_ABS)
+    ThumbMovt = 2,
+}
+
+/// A place in the payload that holds an address in the app. Stored big endian as the kind
+/// in the top 4 bits and the payload offset in the rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Relocation {
+    pub offset: u32,
+    pub kind: RelocationKind,
+}
+
+impl Relocation {
+    /// Largest payload offset a relocation can point to
+    pub const MAX_OFFSET: u32 = OFFSET_MASK;
+
+    pub fn new(kind: RelocationKind, offset: u32) -> Self {
+        Self { offset, kind }
+    }
+
+    pub fn from_bytes(data: [u8; RELOCATION_SIZE]) -> Result<Self, AppFmtError> {
+        let raw = u32::from_be_bytes(data);
+        let kind = match raw >> KIND_SHIFT {
+            0 => RelocationKind::Abs32,
+            1 => RelocationKind::ThumbMovw,
+            2 => RelocationKind::ThumbMovt,
+            _ => return Err(AppFmtError::BadRelocation(raw)),
+        };
+        Ok(Self::new(kind, raw & OFFSET_MASK))
+    }
+
+    pub fn to_bytes(self) -> [u8; RELOCATION_SIZE] {
+        ((self.kind as u32) << KIND_SHIFT | self.offset & OFFSET_MASK).to_be_bytes()
+    }
+}
+
+/// Decode a relocation table
+pub fn relocations(table: &[u8]) -> impl Iterator<Item = Result<Relocation, AppFmtError>> + '_ {
+    table
+        .chunks_exact(RELOCATION_SIZE)
+        .map(|entry| Relocation::from_bytes([entry[0], entry[1], entry[2], entry[3]]))
+}
+
+/// Move the address at `reloc` in `memory` by `delta`, it has to point into `range`, the app as
+/// linked
+pub(crate) fn apply(
+    memory: &mut [u8],
+    reloc: Relocation,
+    range: RangeInclusive<u32>,
+    delta: u32,
+) -> Result<(), AppFmtError> {
+    let offset = reloc.offset as usize;
+    let Some(word) = memory.get_mut(offset..offset + 4) else {
+        return Err(AppFmtError::RelocationOutOfRange(reloc.offset));
+    };
+    match reloc.kind {
+        RelocationKind::Abs32 => {
+            let value = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            if !range.contains(&value) {
+                return Err(AppFmtError::RelocationTarget {
+                    offset: reloc.offset,
+                    value,
+                });
+            }
+            word.copy_from_slice(&value.wrapping_add(delta).to_le_bytes());
+        }
+        RelocationKind::ThumbMovw | RelocationKind::ThumbMovt => {
+            let (hw1, hw2) = (
+                u16::from_le_bytes([word[0], word[1]]),
+                u16::from_le_bytes([word[2], word[3]]),
+            );
+            let opcode = match reloc.kind {
+                RelocationKind::ThumbMovw => THUMB_MOVW,
+                _ => THUMB_MOVT,
+            };
+            if hw1 & THUMB_MOV_MASK != opcode || hw2 & 0x8000 != 0 {
+                return Err(AppFmtError::RelocationInstruction(reloc.offset));
+            }
+            if delta & (MOVW_ALIGN - 1) != 0 {
+                return Err(AppFmtError::RelocationAlignment(
+                    range.start().wrapping_add(delta),
+                ));
+            }
+            if reloc.kind == RelocationKind::ThumbMovt {
+                let value = (thumb_imm16(hw1, hw2) as u32) << 16;
+                // Only the high half is known, any address in the app may start with it
+                if value > *range.end() || value | 0xffff < *range.start() {
+                    return Err(AppFmtError::RelocationTarget {
+                        offset: reloc.offset,
+                        value,
+                    });
+                }
+                let imm16 = (value.wrapping_add(delta) >> 16) as u16;
+                let (hw1, hw2) = set_thumb_imm16(hw1, hw2, imm16);
+                word[0..2].copy_from_slice(&hw1.to_le_bytes());
+                word[2..4].copy_from_slice(&hw2.to_le_bytes());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// imm16 of a Thumb MOVW/MOVT, imm4:i:imm3:imm8
+fn thumb_imm16(hw1: u16, hw2: u16) -> u16 {
+    let imm4 = hw1 & 0xf;
+    let i = (hw1 >> 10) & 1;
+    let imm3 = (hw2 >> 12) & 0x7;
+    let imm8 = hw2 & 0xff;
+    imm4 << 12 | i << 11 | imm3 << 8 | imm8
+}
+
+fn set_thumb_imm16(hw1: u16, hw2: u16, imm16: u16) -> (u16, u16) {
+    let hw1 = (hw1 & !0x040f) | (imm16 >> 12) | ((imm16 >> 11) & 1) << 10;
+    let hw2 = (hw2 & !0x70ff) | ((imm16 >> 8) & 0x7) << 12 | (imm16 & 0xff);
+    (hw1, hw2)
+}
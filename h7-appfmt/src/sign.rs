@@ -0,0 +1,73 @@
+use {
+    crate::AppFmtError,
+    ed25519_compact::{KeyPair, PublicKey as EdPublicKey, Seed, Signature},
+};
+
+pub const PUBLIC_KEY_SIZE: usize = 32;
+pub const SIGNATURE_SIZE: usize = 64;
+/// Size of the secret seed a key pair is derived from
+pub const SEED_SIZE: usize = 32;
+/// The public key followed by the signature
+pub const SIGNATURE_BLOCK_SIZE: usize = PUBLIC_KEY_SIZE + SIGNATURE_SIZE;
+
+/// An Ed25519 public key
+pub type PublicKey = [u8; PUBLIC_KEY_SIZE];
+
+/// Ed25519 signature of the header and body of an app, and the key it was made with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignatureBlock {
+    pub public_key: PublicKey,
+    pub signature: [u8; SIGNATURE_SIZE],
+}
+
+impl SignatureBlock {
+    /// Sign `message` with the key pair derived from `seed`
+    pub fn sign(seed: &[u8; SEED_SIZE], message: &[u8]) -> Self {
+        let key_pair = KeyPair::from_seed(Seed::new(*seed));
+        Self {
+            public_key: *key_pair.pk,
+            signature: *key_pair.sk.sign(message, None),
+        }
+    }
+
+    /// Check that the signature of the message, `parts` one after the other, was made with
+    /// `public_key`
+    pub fn verify(&self, parts: &[&[u8]]) -> Result<(), AppFmtError> {
+        let mut state = EdPublicKey::new(self.public_key)
+            .verify_incremental(&Signature::new(self.signature))
+            .map_err(|_| AppFmtError::BadSignature)?;
+        parts.iter().for_each(|part| state.absorb(part));
+        state.verify().map_err(|_| AppFmtError::BadSignature)
+    }
+
+    pub fn from_bytes(data: [u8; SIGNATURE_BLOCK_SIZE]) -> Self {
+        let mut block = Self {
+            public_key: [0; PUBLIC_KEY_SIZE],
+            signature: [0; SIGNATURE_SIZE],
+        };
+        block.public_key.copy_from_slice(&data[..PUBLIC_KEY_SIZE]);
+        block.signature.copy_from_slice(&data[PUBLIC_KEY_SIZE..]);
+        block
+    }
+
+    pub fn to_bytes(&self) -> [u8; SIGNATURE_BLOCK_SIZE] {
+        let mut out = [0u8; SIGNATURE_BLOCK_SIZE];
+        out[..PUBLIC_KEY_SIZE].copy_from_slice(&self.public_key);
+        out[PUBLIC_KEY_SIZE..].copy_from_slice(&self.signature);
+        out
+    }
+}
+
+/// Public key of the key pair derived from `seed`
+pub fn public_key(seed: &[u8; SEED_SIZE]) -> PublicKey {
+    *KeyPair::from_seed(Seed::new(*seed)).pk
+}
+
+/// Short form of a key for humans, the first 8 bytes in hex
+pub struct Fingerprint<'k>(pub &'k PublicKey);
+
+impl core::fmt::Display for Fingerprint<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.0[..8].iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
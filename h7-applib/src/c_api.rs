@@ -1,8 +1,40 @@
-use crate::Host;
+use {
+    crate::Host,
+    h7_api::{DirEntry, FileStat},
+};
 
 pub const MALLOC_DEFAULT_ALIGN: usize = 8;
 
+// Capabilities, see h7_has_capability. Must match h7_api::Capability
+pub const H7_CAP_ALLOC: u32 = 1 << 0;
+pub const H7_CAP_IO: u32 = 1 << 1;
+pub const H7_CAP_GPU: u32 = 1 << 2;
+pub const H7_CAP_FS: u32 = 1 << 3;
+
+// Modes for h7_fopen. Must match h7_api::OpenMode
+pub const H7_FREAD: u32 = 0;
+/// Create or truncate
+pub const H7_FWRITE: u32 = 1;
+/// Create if it doesn't exist, writes go to the end
+pub const H7_FAPPEND: u32 = 2;
+
+// Whence for h7_fseek. Must match h7_api::Whence
+pub const H7_SEEK_SET: u32 = 0;
+pub const H7_SEEK_CUR: u32 = 1;
+pub const H7_SEEK_END: u32 = 2;
+
 // Sys, Mem
+#[no_mangle]
+pub extern "C" fn h7_api_version() -> u32 {
+    Host::api_version()
+}
+
+/// Check for a capability, `capability` is a bit from the host capability bitmap.
+#[no_mangle]
+pub extern "C" fn h7_has_capability(capability: u32) -> bool {
+    Host::capabilities() & capability != 0
+}
+
 #[cfg(feature = "alloc")]
 #[no_mangle]
 pub unsafe extern "C" fn h7_malloc(size: usize) -> *mut u8 {
@@ -48,6 +80,96 @@ pub unsafe extern "C" fn h7_puts(msg: *const u8) -> i32 {
     Host::puts(str_slice)
 }
 
+// GPU, colors are RGB565. Drawing returns -1 if the host has no GPU.
+#[no_mangle]
+pub extern "C" fn h7_screen_width() -> u32 {
+    Host::screen_width_px()
+}
+
+#[no_mangle]
+pub extern "C" fn h7_screen_height() -> u32 {
+    Host::screen_height_px()
+}
+
+#[no_mangle]
+pub extern "C" fn h7_screen_columns() -> u32 {
+    Host::screen_width_char()
+}
+
+#[no_mangle]
+pub extern "C" fn h7_screen_rows() -> u32 {
+    Host::screen_height_char()
+}
+
+#[no_mangle]
+pub extern "C" fn h7_draw_dot(x: u32, y: u32, color: u16) -> i32 {
+    Host::dot(x, y, color)
+}
+
+#[no_mangle]
+pub extern "C" fn h7_draw_line(x1: u32, y1: u32, x2: u32, y2: u32, stroke: u32, color: u16) -> i32 {
+    Host::line(x1, y1, x2, y2, stroke, color)
+}
+
+#[no_mangle]
+pub extern "C" fn h7_draw_rect(x1: u32, y1: u32, x2: u32, y2: u32, stroke: u32, color: u16) -> i32 {
+    Host::square(x1, y1, x2, y2, stroke, color)
+}
+
+#[no_mangle]
+pub extern "C" fn h7_fill_rect(x1: u32, y1: u32, x2: u32, y2: u32, color: u16) -> i32 {
+    Host::square_fill(x1, y1, x2, y2, color)
+}
+
+// FS, paths are `device:/path`. Negative return values are an error, see h7_api::FsError.
+
+/// Open a file, returns a handle
+#[no_mangle]
+pub unsafe extern "C" fn h7_fopen(path: *const u8, mode: u32) -> i32 {
+    Host::open(cstd::str(path), mode)
+}
+
+/// Returns the number of bytes read, 0 at the end of the file
+#[no_mangle]
+pub unsafe extern "C" fn h7_fread(handle: i32, buf: *mut u8, len: usize) -> i32 {
+    Host::read(handle, core::slice::from_raw_parts_mut(buf, len))
+}
+
+/// Returns the number of bytes written. Writes always go to the end of the file.
+#[no_mangle]
+pub unsafe extern "C" fn h7_fwrite(handle: i32, buf: *const u8, len: usize) -> i32 {
+    Host::write(handle, core::slice::from_raw_parts(buf, len))
+}
+
+/// Returns the new offset. Only files opened with H7_FREAD can seek.
+#[no_mangle]
+pub extern "C" fn h7_fseek(handle: i32, offset: i32, whence: u32) -> i32 {
+    Host::seek(handle, offset, whence)
+}
+
+#[no_mangle]
+pub extern "C" fn h7_fclose(handle: i32) -> i32 {
+    Host::close(handle)
+}
+
+/// Entry `index` of a directory, returns 1 if there is one, 0 past the last entry
+#[no_mangle]
+pub unsafe extern "C" fn h7_readdir(path: *const u8, index: u32, entry: *mut DirEntry) -> i32 {
+    match entry.as_mut() {
+        Some(entry) => Host::read_dir(cstd::str(path), index, entry),
+        None => Host::read_dir(cstd::str(path), index, &mut DirEntry::new()),
+    }
+}
+
+/// `stat` may be null to only check that `path` exists
+#[no_mangle]
+pub unsafe extern "C" fn h7_stat(path: *const u8, stat: *mut FileStat) -> i32 {
+    match stat.as_mut() {
+        Some(stat) => Host::stat(cstd::str(path), stat),
+        None => Host::stat(cstd::str(path), &mut FileStat::default()),
+    }
+}
+
 mod cstd {
     pub(crate) unsafe fn strlen(s: *const u8) -> usize {
         let mut result = 0;
@@ -56,4 +178,9 @@ mod cstd {
         }
         result
     }
+
+    /// The host checks that paths are utf-8
+    pub(crate) unsafe fn str<'a>(s: *const u8) -> &'a str {
+        core::str::from_utf8_unchecked(core::slice::from_raw_parts(s, strlen(s)))
+    }
 }
@@ -0,0 +1,230 @@
+//! Files on the host filesystems, modeled after `std::fs`.
+//!
+//! Paths include the device like in the shell, `sdcard:/data/save.bin`. Files opened for
+//! writing can only be appended to, seeking is for files opened for reading. The host closes
+//! files the app leaves open when it exits.
+
+use crate::Host;
+
+pub use h7_api::{FsError, OpenMode};
+
+pub type Result<T> = core::result::Result<T, FsError>;
+
+/// Turn a host return value into a result
+fn check(ret: i32) -> Result<u32> {
+    match FsError::from_code(ret) {
+        Some(e) => Err(e),
+        None => Ok(ret as u32),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u32),
+    Current(i32),
+    End(i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata(h7_api::FileStat);
+
+impl Metadata {
+    pub fn is_dir(&self) -> bool {
+        self.0.is_dir
+    }
+
+    pub fn is_file(&self) -> bool {
+        !self.0.is_dir
+    }
+
+    /// Size in bytes
+    pub fn len(&self) -> u32 {
+        self.0.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.size == 0
+    }
+}
+
+/// An open file, closed when dropped
+#[derive(Debug)]
+pub struct File {
+    handle: i32,
+}
+
+impl File {
+    /// Open a file for reading
+    pub fn open(path: &str) -> Result<Self> {
+        Self::open_with(path, OpenMode::Read)
+    }
+
+    /// Create a file for writing, truncates it if it exists
+    pub fn create(path: &str) -> Result<Self> {
+        Self::open_with(path, OpenMode::Write)
+    }
+
+    /// Open a file for writing at the end, creates it if it doesn't exist
+    pub fn append(path: &str) -> Result<Self> {
+        Self::open_with(path, OpenMode::Append)
+    }
+
+    pub fn open_with(path: &str, mode: OpenMode) -> Result<Self> {
+        let handle = check(Host::open(path, mode as u32))?;
+        Ok(Self {
+            handle: handle as i32,
+        })
+    }
+
+    /// Read from the current offset, returns the number of bytes read, 0 at the end of the file
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        check(Host::read(self.handle, buf)).map(|n| n as usize)
+    }
+
+    /// Fill `buf`, [`FsError::InvalidOffset`] if the file ends before that
+    pub fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.read(buf)? {
+                0 => return Err(FsError::InvalidOffset),
+                n => buf = &mut buf[n..],
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the rest of the file into `buf`, returns the number of bytes read
+    #[cfg(feature = "alloc")]
+    pub fn read_to_end(&mut self, buf: &mut alloc::vec::Vec<u8>) -> Result<usize> {
+        let start = buf.len();
+        let mut chunk = [0u8; 512];
+        loop {
+            match self.read(&mut chunk)? {
+                0 => return Ok(buf.len() - start),
+                n => buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+    }
+
+    /// Write to the end of the file, returns the number of bytes written
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        check(Host::write(self.handle, buf)).map(|n| n as usize)
+    }
+
+    pub fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf)? {
+                0 => return Err(FsError::NoSpace),
+                n => buf = &buf[n..],
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the read offset, returns the new offset
+    pub fn seek(&mut self, pos: SeekFrom) -> Result<u32> {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(n) => (
+                i32::try_from(n).map_err(|_| FsError::InvalidOffset)?,
+                h7_api::Whence::Start,
+            ),
+            SeekFrom::Current(n) => (n, h7_api::Whence::Current),
+            SeekFrom::End(n) => (n, h7_api::Whence::End),
+        };
+        check(Host::seek(self.handle, offset, whence as u32))
+    }
+
+    pub fn rewind(&mut self) -> Result<()> {
+        self.seek(SeekFrom::Start(0)).map(|_| ())
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        Host::close(self.handle);
+    }
+}
+
+impl core::fmt::Write for File {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
+
+pub fn metadata(path: &str) -> Result<Metadata> {
+    let mut stat = h7_api::FileStat::default();
+    check(Host::stat(path, &mut stat))?;
+    Ok(Metadata(stat))
+}
+
+pub fn exists(path: &str) -> bool {
+    metadata(path).is_ok()
+}
+
+/// Read a whole file
+#[cfg(feature = "alloc")]
+pub fn read(path: &str) -> Result<alloc::vec::Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut buf = alloc::vec::Vec::new();
+    file.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Create or truncate a file and write `data` to it
+pub fn write(path: &str, data: &[u8]) -> Result<()> {
+    File::create(path)?.write_all(data)
+}
+
+/// Iterate over the entries of a directory, without `.` and `..`
+pub fn read_dir(path: &str) -> ReadDir<'_> {
+    ReadDir {
+        path,
+        index: 0,
+        done: false,
+    }
+}
+
+/// Iterator returned by [`read_dir`]. Entries are looked up by index, creating or removing
+/// files in the directory while iterating can skip or repeat entries.
+pub struct ReadDir<'a> {
+    path: &'a str,
+    index: u32,
+    done: bool,
+}
+
+impl Iterator for ReadDir<'_> {
+    type Item = Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut entry = DirEntry(h7_api::DirEntry::new());
+        match check(Host::read_dir(self.path, self.index, &mut entry.0)) {
+            Ok(0) => {
+                self.done = true;
+                None
+            }
+            Ok(_) => {
+                self.index += 1;
+                Some(Ok(entry))
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+pub struct DirEntry(h7_api::DirEntry);
+
+impl DirEntry {
+    /// File name, without the directory
+    pub fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        Metadata(self.0.stat)
+    }
+}
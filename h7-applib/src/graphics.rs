@@ -0,0 +1,69 @@
+use {
+    crate::Host,
+    embedded_graphics_core::{
+        pixelcolor::{IntoStorage, Rgb565},
+        prelude::*,
+        primitives::Rectangle,
+    },
+};
+
+/// The host reported an error while drawing, or has no GPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GpuError;
+
+/// Draw on the host screen using embedded-graphics
+pub struct HostDisplay;
+
+impl HostDisplay {
+    /// `None` if the host has no GPU
+    pub fn new() -> Option<Self> {
+        Host::has(crate::Capability::Gpu).then_some(Self)
+    }
+}
+
+fn check(ret: i32) -> Result<(), GpuError> {
+    match ret {
+        0 => Ok(()),
+        _ => Err(GpuError),
+    }
+}
+
+impl DrawTarget for HostDisplay {
+    type Color = Rgb565;
+    type Error = GpuError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let bounds = self.bounding_box();
+        for Pixel(p, color) in pixels {
+            if bounds.contains(p) {
+                check(Host::dot(p.x as u32, p.y as u32, color.into_storage()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        match area.intersection(&self.bounding_box()).bottom_right() {
+            Some(bottom_right) => {
+                let top_left = area.top_left.component_max(Point::zero());
+                check(Host::square_fill(
+                    top_left.x as u32,
+                    top_left.y as u32,
+                    bottom_right.x as u32,
+                    bottom_right.y as u32,
+                    color.into_storage(),
+                ))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl OriginDimensions for HostDisplay {
+    fn size(&self) -> Size {
+        Size::new(Host::screen_width_px(), Host::screen_height_px())
+    }
+}
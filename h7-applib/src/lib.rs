@@ -7,12 +7,21 @@
 #[cfg(feature = "c-api")]
 pub mod c_api;
 
+pub mod fs;
+
+#[cfg(feature = "graphics")]
+pub mod graphics;
+
+#[cfg(all(feature = "sim", not(target_os = "none")))]
+pub mod sim;
+
 #[cfg(feature = "alloc")]
 extern crate alloc;
 
 pub struct Host;
 
-#[cfg(all(feature = "alloc"))]
+// In the simulator the app shares the process with the host, which needs the system allocator
+#[cfg(all(feature = "alloc", not(all(feature = "sim", not(target_os = "none")))))]
 mod h7_alloc {
     struct H7Allocator;
 
@@ -34,24 +43,39 @@ mod h7_alloc {
 
 use {
     core::mem::MaybeUninit,
-    h7_api::{AppEntryPoint, H7Api},
+    h7_api::{AppEntryPoint, DirEntry, FileStat, FsError, H7Api},
 };
 
+pub use h7_api::Capability;
+
 #[link_section = ".entry_point"]
 #[no_mangle]
 #[used]
 pub static ENTRY_POINT: AppEntryPoint = entry_point;
 static mut API_POINTER: MaybeUninit<&'static H7Api> = MaybeUninit::uninit();
 
+/// The API version the app is built against, `h7-mkapp` puts it in the app header. Not loaded,
+/// see `h7-app.ld`.
+#[cfg(target_os = "none")]
+#[link_section = ".h7_api_version"]
+#[no_mangle]
+#[used]
+pub static H7_API_VERSION: u32 = h7_api::API_VERSION;
+
 /// The function called by the host to start us up. Does some setup, then
 /// jumps to a function called `h7_main` defined by the actual application using
 /// this crate.
 #[no_mangle]
 extern "C" fn entry_point(table: *const H7Api) -> i32 {
-    // Turn the pointer into a reference and store in a static.
-    unsafe {
-        API_POINTER.write(&*table);
-    };
+    // Refuse to run without the version 1 entries. Later entries are only called when the
+    // table is large enough for them, see `H7Api::has`.
+    match unsafe { table.as_ref() } {
+        Some(api) if api.is_compatible() => {
+            // Store the reference in a static.
+            unsafe { API_POINTER.write(api) };
+        }
+        _ => return h7_api::EXIT_INCOMPATIBLE_API,
+    }
 
     extern "C" {
         fn h7_main() -> i32;
@@ -65,7 +89,39 @@ fn get_api() -> &'static H7Api {
     unsafe { API_POINTER.assume_init() }
 }
 
+/// The table, if the host is new enough to provide the GPU entries
+#[inline(always)]
+fn get_gpu_api() -> Option<&'static H7Api> {
+    let api = get_api();
+    api.has(Capability::Gpu).then_some(api)
+}
+
+/// The table, if the host is new enough to provide the file entries
+#[inline(always)]
+fn get_fs_api() -> Option<&'static H7Api> {
+    let api = get_api();
+    api.has(Capability::Fs).then_some(api)
+}
+
 impl Host {
+    /// Version of the API table provided by the host
+    #[inline(always)]
+    pub fn api_version() -> u32 {
+        get_api().version
+    }
+
+    /// Bitmap of [`Capability`] provided by the host
+    #[inline(always)]
+    pub fn capabilities() -> u32 {
+        get_api().capabilities
+    }
+
+    /// Check if the host provides a capability
+    #[inline(always)]
+    pub fn has(capability: Capability) -> bool {
+        get_api().has(capability)
+    }
+
     #[cfg(feature = "alloc")]
     #[inline(always)]
     pub(crate) unsafe fn alloc(layout: core::alloc::Layout) -> *mut u8 {
@@ -97,6 +153,101 @@ impl Host {
     pub fn puts(s: &str) -> i32 {
         (get_api().puts)(s.as_ptr(), s.len())
     }
+
+    // GPU, returns 0 or -1 if the host has no GPU
+
+    #[inline(always)]
+    pub fn screen_width_px() -> u32 {
+        get_gpu_api().map_or(0, |api| (api.screen_width_px)())
+    }
+
+    #[inline(always)]
+    pub fn screen_height_px() -> u32 {
+        get_gpu_api().map_or(0, |api| (api.screen_height_px)())
+    }
+
+    #[inline(always)]
+    pub fn screen_width_char() -> u32 {
+        get_gpu_api().map_or(0, |api| (api.screen_width_char)())
+    }
+
+    #[inline(always)]
+    pub fn screen_height_char() -> u32 {
+        get_gpu_api().map_or(0, |api| (api.screen_height_char)())
+    }
+
+    #[inline(always)]
+    pub fn dot(x: u32, y: u32, color: u16) -> i32 {
+        get_gpu_api().map_or(-1, |api| (api.dot)(x, y, color))
+    }
+
+    #[inline(always)]
+    pub fn line(x1: u32, y1: u32, x2: u32, y2: u32, stroke: u32, color: u16) -> i32 {
+        get_gpu_api().map_or(-1, |api| (api.line)(x1, y1, x2, y2, stroke, color))
+    }
+
+    #[inline(always)]
+    pub fn square(x1: u32, y1: u32, x2: u32, y2: u32, stroke: u32, color: u16) -> i32 {
+        get_gpu_api().map_or(-1, |api| (api.square)(x1, y1, x2, y2, stroke, color))
+    }
+
+    #[inline(always)]
+    pub fn square_fill(x1: u32, y1: u32, x2: u32, y2: u32, color: u16) -> i32 {
+        get_gpu_api().map_or(-1, |api| (api.square_fill)(x1, y1, x2, y2, color))
+    }
+
+    // FS, negative return values are an `FsError`, `Unsupported` if the host has no
+    // filesystems. See `fs` for a safe interface.
+
+    /// Open `path` in an `OpenMode`, returns a handle
+    #[inline(always)]
+    pub fn open(path: &str, mode: u32) -> i32 {
+        get_fs_api().map_or(FsError::Unsupported.code(), |api| {
+            (api.open)(path.as_ptr(), path.len(), mode)
+        })
+    }
+
+    #[inline(always)]
+    pub fn read(handle: i32, buf: &mut [u8]) -> i32 {
+        get_fs_api().map_or(FsError::Unsupported.code(), |api| {
+            (api.read)(handle, buf.as_mut_ptr(), buf.len())
+        })
+    }
+
+    #[inline(always)]
+    pub fn write(handle: i32, buf: &[u8]) -> i32 {
+        get_fs_api().map_or(FsError::Unsupported.code(), |api| {
+            (api.write)(handle, buf.as_ptr(), buf.len())
+        })
+    }
+
+    /// `whence` is a `Whence`, returns the new offset
+    #[inline(always)]
+    pub fn seek(handle: i32, offset: i32, whence: u32) -> i32 {
+        get_fs_api().map_or(FsError::Unsupported.code(), |api| {
+            (api.seek)(handle, offset, whence)
+        })
+    }
+
+    #[inline(always)]
+    pub fn close(handle: i32) -> i32 {
+        get_fs_api().map_or(FsError::Unsupported.code(), |api| (api.close)(handle))
+    }
+
+    /// Entry `index` of the directory `path`, returns 1 if there is one, 0 past the last entry
+    #[inline(always)]
+    pub fn read_dir(path: &str, index: u32, entry: &mut DirEntry) -> i32 {
+        get_fs_api().map_or(FsError::Unsupported.code(), |api| {
+            (api.read_dir)(path.as_ptr(), path.len(), index, entry)
+        })
+    }
+
+    #[inline(always)]
+    pub fn stat(path: &str, stat: &mut FileStat) -> i32 {
+        get_fs_api().map_or(FsError::Unsupported.code(), |api| {
+            (api.stat)(path.as_ptr(), path.len(), stat)
+        })
+    }
 }
 
 impl core::fmt::Write for Host {
@@ -0,0 +1,12 @@
+/// Run the app in the simulator. Call this from `main` when building for a desktop target,
+/// the app's `h7_main` is called in-process on its own thread.
+pub fn main() {
+    match h7_sim::run(Some(crate::entry_point)) {
+        Ok(Some(code)) => std::process::exit(code),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("h7-sim: {e}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -5,6 +5,8 @@ use {
 
 const GEN_DIR: &str = "gen";
 const CONSTS_FILE: &str = "consts.rs";
+/// Public keys of `h7-mkapp keygen`, apps signed with them are trusted
+const KEYS_DIR: &str = "keys";
 
 fn main() {
     rerun_if_changed();
@@ -57,6 +59,8 @@ fn main() {
         }
     }
 
+    contents.push(trusted_keys().unwrap());
+
     fs::write(
         PathBuf::from(GEN_DIR).join(CONSTS_FILE),
         contents.join("\n") + "\n",
@@ -64,6 +68,41 @@ fn main() {
     .unwrap();
 }
 
+/// `TRUSTED_KEYS` from the `.pub` files in the keys directory, hex encoded
+fn trusted_keys() -> Result<String, io::Error> {
+    let mut paths = match fs::read_dir(KEYS_DIR) {
+        Ok(dir) => dir
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    paths.retain(|p| p.extension().is_some_and(|ext| ext == "pub"));
+    paths.sort();
+
+    let mut keys = Vec::with_capacity(paths.len());
+    for path in paths {
+        let hex = fs::read_to_string(&path)?;
+        let hex = hex.trim();
+        if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            panic!("{}: not a 32 byte hex public key", path.display());
+        }
+        let bytes = (0..64)
+            .step_by(2)
+            .map(|i| format!("0x{}", &hex[i..i + 2]))
+            .collect::<Vec<_>>();
+        keys.push(format!(
+            "    // {}\n    [{}],",
+            path.display(),
+            bytes.join(", ")
+        ));
+    }
+    Ok(format!(
+        "pub const TRUSTED_KEYS: &[[u8; 32]] = &[\n{}\n];",
+        keys.join("\n")
+    ))
+}
+
 fn out_dir() -> Result<(), io::Error> {
     let path = PathBuf::from(GEN_DIR);
     if path.exists() {
@@ -75,5 +114,6 @@ fn out_dir() -> Result<(), io::Error> {
 fn rerun_if_changed() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed={KEYS_DIR}");
     println!("cargo:rerun-if-changed=**/*");
 }
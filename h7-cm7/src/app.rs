@@ -1,25 +1,60 @@
 use {
     crate::{
+        consts,
+        display::{self, GPU},
+        fs::{
+            path::Path,
+            sdmmc_fs::SdmmcFsError,
+            vfs::{self, VfsError},
+        },
         mem,
         terminal::{TerminalWriter, TERMINAL_INPUT_FIFO},
         utils,
     },
-    core::{alloc::GlobalAlloc, cell::RefCell, fmt::Write},
+    core::{
+        alloc::{GlobalAlloc, Layout},
+        cell::RefCell,
+        convert::Infallible,
+        fmt::Write,
+        str::FromStr,
+        sync::atomic::{AtomicU8, Ordering},
+    },
     critical_section::Mutex,
-    h7_api::{AppEntryPoint, H7Api},
+    embedded_graphics::{
+        pixelcolor::{raw::RawU16, Rgb565},
+        prelude::*,
+        primitives::{Line, PrimitiveStyle, Rectangle},
+    },
+    embedded_sdmmc::Error as SdError,
+    h7_api::{
+        AppEntryPoint, Capability, DirEntry, FileStat, FsError, H7Api, OpenMode, Whence,
+        API_VERSION,
+    },
+    h7_appfmt::{AppFmtError, AppHeader, Fingerprint, Loader, PublicKey, MOVW_ALIGN},
+    h7_norfs::{LfsError, NorFsError},
 };
 
 const ARM_ADDR_ALIGN: usize = 4;
 const THUMB_ADDR_ALIGN: usize = 2;
 const THUMB_MASK: usize = 0x0000_0001;
+/// Size of the reads of an image from a file
+const LOAD_CHUNK: usize = 4096;
 
 pub const APP_START: *mut u8 = 0x2400_0000usize as *mut u8;
-pub const APP_SIZE: usize = 512 * 1024;
+pub const APP_SIZE: usize = h7_appfmt::DEFAULT_APP_SIZE;
 
-// pub const APP_START: *mut u8 = 0x3000_0000usize as *mut u8;
-// pub const APP_SIZE: usize = 128 * 1024;
+/// SRAM1-3, contiguous and unused by the firmware
+pub const SRAM_START: *mut u8 = 0x3000_0000usize as *mut u8;
+pub const SRAM_SIZE: usize = 288 * 1024;
 
 pub static API: H7Api = H7Api {
+    size: core::mem::size_of::<H7Api>(),
+    version: API_VERSION,
+    capabilities: Capability::Alloc.bit()
+        | Capability::Io.bit()
+        | Capability::Gpu.bit()
+        | Capability::Fs.bit(),
+    // Sys, Mem
     alloc,
     free,
     panic,
@@ -27,26 +62,207 @@ pub static API: H7Api = H7Api {
     getc,
     putc,
     puts,
+    // GPU
+    screen_width_px,
+    screen_height_px,
+    screen_width_char,
+    screen_height_char,
+    dot,
+    line,
+    square,
+    square_fill,
+    // FS
+    open,
+    read,
+    write,
+    seek,
+    close,
+    read_dir,
+    stat,
 };
 
-pub fn get_address(data: &[u8]) -> AppEntryPoint {
-    unsafe {
-        let addr = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
-        let ptr = addr as *const ();
-        core::mem::transmute(ptr)
+/// Files an app can have open at the same time
+pub const MAX_OPEN_FILES: usize = 8;
+
+/// Where an app runs. Apps without relocations only run at the address they are linked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    /// AXI SRAM
+    AxiSram,
+    /// SRAM1-3
+    Sram,
+    /// Allocated from the heap
+    Sdram,
+}
+
+impl Region {
+    pub const ALL: [Region; 3] = [Region::AxiSram, Region::Sram, Region::Sdram];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Region::AxiSram => "axisram",
+            Region::Sram => "sram",
+            Region::Sdram => "sdram",
+        }
+    }
+
+    /// Memory for an app of `size` bytes
+    fn memory(self, size: usize) -> Result<(*mut u8, usize), LoadError> {
+        match self {
+            Region::AxiSram => Ok((APP_START, APP_SIZE)),
+            Region::Sram => Ok((SRAM_START, SRAM_SIZE)),
+            Region::Sdram => {
+                // Apps with MOVW/MOVT relocations only move in steps of 64K
+                let layout = Layout::from_size_align(size.max(1), MOVW_ALIGN as usize)
+                    .map_err(|_| LoadError::OutOfMemory(size))?;
+                let ptr = unsafe { mem::ALLOCATOR.alloc(layout) };
+                if ptr.is_null() {
+                    return Err(LoadError::OutOfMemory(size));
+                }
+                utils::interrupt_free(|cs| {
+                    SDRAM_APP.borrow(cs).replace(Some((ptr as usize, layout)))
+                });
+                Ok((ptr, size))
+            }
+        }
     }
 }
 
-pub fn check_address(addr: AppEntryPoint) -> Result<&'static str, &'static str> {
-    let ptr = addr as *const u8;
-    let masked_addr = (ptr as usize) & !THUMB_MASK;
+impl FromStr for Region {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Region::ALL
+            .into_iter()
+            .find(|region| region.name() == s)
+            .ok_or(())
+    }
+}
+
+/// What to do with apps that are not signed by a trusted key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigPolicy {
+    // From loose to strict
+    /// Don't check signatures
+    Off,
+    /// Load and run, with a warning
+    Warn,
+    /// Refuse to load or run
+    Enforce,
+}
+
+impl SigPolicy {
+    pub const ALL: [SigPolicy; 3] = [SigPolicy::Off, SigPolicy::Warn, SigPolicy::Enforce];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            SigPolicy::Off => "off",
+            SigPolicy::Warn => "warn",
+            SigPolicy::Enforce => "enforce",
+        }
+    }
+}
+
+impl FromStr for SigPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SigPolicy::ALL
+            .into_iter()
+            .find(|policy| policy.name() == s)
+            .ok_or(())
+    }
+}
+
+/// The policy the firmware boots with, `warn` unless built with the `sig-off` or `sig-enforce`
+/// feature
+pub const DEFAULT_SIG_POLICY: SigPolicy =
+    match (cfg!(feature = "sig-enforce"), cfg!(feature = "sig-off")) {
+        (true, _) => SigPolicy::Enforce,
+        (false, true) => SigPolicy::Off,
+        (false, false) => SigPolicy::Warn,
+    };
+
+static SIG_POLICY: AtomicU8 = AtomicU8::new(DEFAULT_SIG_POLICY as u8);
+
+pub fn sig_policy() -> SigPolicy {
+    SigPolicy::ALL[SIG_POLICY.load(Ordering::Relaxed) as usize]
+}
+
+/// Make the policy stricter, it can't be loosened without rebuilding the firmware. `Err` with
+/// the current policy if `policy` is looser.
+pub fn set_sig_policy(policy: SigPolicy) -> Result<(), SigPolicy> {
+    let current = SIG_POLICY.fetch_max(policy as u8, Ordering::Relaxed);
+    match policy as u8 >= current {
+        true => Ok(()),
+        false => Err(SigPolicy::ALL[current as usize]),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LoadedApp {
+    pub header: AppHeader,
+    pub region: Region,
+    /// Where the payload starts, the load address unless relocated
+    pub address: u32,
+    /// The trusted key the app is signed with, or why it isn't
+    pub signature: Result<PublicKey, AppFmtError>,
+}
+
+impl LoadedApp {
+    /// Check the signature against the policy, `Ok` with the reason to warn about if the app
+    /// may run anyway
+    pub fn check_signature(&self) -> Result<Option<AppFmtError>, LoadError> {
+        match (sig_policy(), self.signature) {
+            (SigPolicy::Off, _) | (_, Ok(_)) => Ok(None),
+            (SigPolicy::Warn, Err(e)) => Ok(Some(e)),
+            (SigPolicy::Enforce, Err(e)) => Err(LoadError::Signature(e)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// Invalid or incompatible image
+    Format(AppFmtError),
+    /// No heap left for the app (size)
+    OutOfMemory(usize),
+    /// Not signed by a trusted key, with the policy enforced
+    Signature(AppFmtError),
+    /// Reading the image failed
+    Vfs(VfsError),
+}
+
+impl From<AppFmtError> for LoadError {
+    fn from(err: AppFmtError) -> Self {
+        Self::Format(err)
+    }
+}
+
+impl From<VfsError> for LoadError {
+    fn from(err: VfsError) -> Self {
+        Self::Vfs(err)
+    }
+}
+
+impl core::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Format(e) => write!(f, "{e}"),
+            Self::OutOfMemory(size) => write!(f, "Not enough memory for the app ({size} bytes)"),
+            Self::Signature(e) => write!(f, "{e}, refusing to run unsigned apps"),
+            Self::Vfs(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+pub fn check_address(app: &LoadedApp, addr: AppEntryPoint) -> Result<&'static str, &'static str> {
+    let ptr = addr as usize;
+    let masked_addr = ptr & !THUMB_MASK;
+    let start = app.address as usize;
     match (
-        // SAFTEY: Pointers are never dereferenced and we're far from overflows.
-        unsafe {
-            ptr.ge(&(APP_START.add(4) as *const _))
-                && ptr.lt(&(APP_START.add(APP_SIZE) as *const _))
-        },
-        (ptr as usize) & THUMB_MASK == 1,    // Thumb?
+        ptr >= start + 4 && ptr < start + app.header.memory_size(),
+        ptr & THUMB_MASK == 1,               // Thumb?
         masked_addr % THUMB_ADDR_ALIGN == 0, // Valid Thumb alignment?
         masked_addr % ARM_ADDR_ALIGN == 0,   // Valid ARM alignment?
     ) {
@@ -58,39 +274,139 @@ pub fn check_address(addr: AppEntryPoint) -> Result<&'static str, &'static str>
     }
 }
 
-pub fn app_slice() -> &'static mut [u8] {
-    unsafe { core::slice::from_raw_parts_mut(APP_START, APP_SIZE) }
+/// An app being loaded as its image arrives, straight into the memory it runs from
+pub struct AppLoad {
+    loader: Loader<'static>,
+    region: Region,
+    /// Where the payload starts, once the header is in
+    address: u32,
+}
+
+impl AppLoad {
+    /// Start loading an app into `region`, the app loaded before is gone
+    pub fn new(region: Region) -> Self {
+        utils::interrupt_free(|cs| LOADED_APP.borrow(cs).replace(None));
+        free_sdram();
+        Self {
+            loader: Loader::new(),
+            region,
+            address: 0,
+        }
+    }
+
+    /// Feed the next bytes of the image
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        let (region, address) = (self.region, &mut self.address);
+        self.loader
+            .feed(data, |header| {
+                let (start, size) = region.memory(header.load_size())?;
+                // Refuse apps built against a newer API than the one we provide, or for
+                // another address
+                header.check_compatible(API.version, start as u32, size)?;
+                *address = start as u32;
+                // SAFETY: Not used by the firmware, or allocated for the app
+                Ok(unsafe { core::slice::from_raw_parts_mut(start, size) })
+            })
+            .inspect_err(|_| free_sdram())
+    }
+
+    /// Validate the app once the whole image was fed and relocate it if it's not where it was
+    /// linked for. The rest of the memory is zeroed (.bss).
+    pub fn finish(self) -> Result<LoadedApp, LoadError> {
+        let app = self.place().inspect_err(|_| free_sdram())?;
+        utils::interrupt_free(|cs| LOADED_APP.borrow(cs).replace(Some(app)));
+        Ok(app)
+    }
+
+    fn place(self) -> Result<LoadedApp, LoadError> {
+        let image = self.loader.finish()?;
+        let crc = utils::interrupt_free(|cs| utils::crc(cs, image.content()));
+        image.header.check_payload_crc(crc)?;
+
+        // Checked even with the policy off, it may be enforced before the app runs
+        let signature = image.verify_signature(consts::TRUSTED_KEYS);
+        if let (SigPolicy::Enforce, Err(e)) = (sig_policy(), signature) {
+            return Err(LoadError::Signature(e));
+        }
+
+        let header = image.relocate(self.address)?;
+        Ok(LoadedApp {
+            header,
+            region: self.region,
+            address: self.address,
+            signature,
+        })
+    }
+}
+
+/// Load an app from a file into `region`, returns it with the size of the file
+pub fn load_file(path: Path, region: Region) -> Result<(LoadedApp, usize), LoadError> {
+    let mut file = vfs::File::open(path, vfs::OpenMode::Read)?;
+    let mut app = AppLoad::new(region);
+    let mut buf = [0u8; LOAD_CHUNK];
+    let mut len = 0;
+    loop {
+        match file.read(&mut buf).inspect_err(|_| free_sdram())? {
+            0 => return Ok((app.finish()?, len)),
+            n => {
+                app.feed(&buf[..n])?;
+                len += n;
+            }
+        }
+    }
 }
 
-pub fn verify_app(slice: &[u8]) -> Result<u32, u32> {
-    let len = slice.len();
-    let calculated_crc = utils::interrupt_free(|cs| utils::crc(cs, &slice[..(len - 4)]));
-    let provided_crc = u32::from_be_bytes([
-        slice[len - 4],
-        slice[len - 3],
-        slice[len - 2],
-        slice[len - 1],
-    ]);
-    if calculated_crc == provided_crc {
-        Ok(calculated_crc)
-    } else {
-        Err(calculated_crc)
+/// Free the memory of an app loaded into SDRAM
+fn free_sdram() {
+    if let Some((ptr, layout)) = utils::interrupt_free(|cs| SDRAM_APP.borrow(cs).take()) {
+        unsafe { mem::ALLOCATOR.dealloc(ptr as *mut u8, layout) };
     }
 }
 
-pub fn print_info<W: core::fmt::Write>(w: &mut W, data: &[u8]) -> core::fmt::Result {
-    let crc = verify_app(data);
-    let addr = get_address(data);
+/// The app currently in app memory
+pub fn loaded() -> Option<LoadedApp> {
+    utils::interrupt_free(|cs| *LOADED_APP.borrow(cs).borrow())
+}
+
+pub fn entry_point(app: &LoadedApp) -> AppEntryPoint {
+    unsafe {
+        let ptr = app.address.wrapping_add(app.header.entry_offset) as *const ();
+        core::mem::transmute(ptr)
+    }
+}
+
+pub fn print_info<W: core::fmt::Write>(w: &mut W, app: &LoadedApp) -> core::fmt::Result {
+    let header = &app.header;
+    let addr = entry_point(app);
+    writeln!(w, "Name: {} {}", header.name(), header.version())?;
+    writeln!(
+        w,
+        "Address: {addr:p} ({addr_check}) in {region}, API: v{api}, CRC: 0x{crc:08x}",
+        addr_check = crate::utils::into_ok_or_err(check_address(app, addr)),
+        region = app.region.name(),
+        api = header.api_version,
+        crc = header.payload_crc,
+    )?;
     writeln!(
         w,
-        "Address: {addr:p} ({addr_check}), CRC: 0x{crc:08x} ({crc_check}), Size: 0x{size:x}",
-        addr_check = crate::utils::into_ok_or_err(check_address(addr)),
-        crc = crate::utils::into_ok_or_err(crc),
-        crc_check = if crc.is_ok() { "passed" } else { "failed" },
-        size = data.len()
-    )
+        "Size: 0x{size:x} (text 0x{text:x}, data 0x{data:x}, bss 0x{bss:x}), {relocs} relocations",
+        size = header.memory_size(),
+        text = header.text_size,
+        data = header.data_size,
+        bss = header.bss_size,
+        relocs = header.reloc_count,
+    )?;
+    match app.signature {
+        Ok(key) => writeln!(w, "Signature: trusted key {}", Fingerprint(&key)),
+        Err(e) => writeln!(w, "Signature: {e}"),
+    }
 }
 
+static LOADED_APP: Mutex<RefCell<Option<LoadedApp>>> = Mutex::new(RefCell::new(None));
+
+// Heap memory of an app loaded into SDRAM, freed by the next load
+static SDRAM_APP: Mutex<RefCell<Option<(usize, Layout)>>> = Mutex::new(RefCell::new(None));
+
 // Keep track of app allocations so that we can free leaked application memory
 static APP_ALLOCATIONS: Mutex<RefCell<heapless::FnvIndexMap<usize, core::alloc::Layout, 128>>> =
     Mutex::new(RefCell::new(heapless::FnvIndexMap::new()));
@@ -108,6 +424,20 @@ pub fn free_leaked() -> usize {
     })
 }
 
+const NO_FILE: Option<vfs::File> = None;
+
+// Files opened by the app, the handle is the index
+static APP_FILES: Mutex<RefCell<[Option<vfs::File>; MAX_OPEN_FILES]>> =
+    Mutex::new(RefCell::new([NO_FILE; MAX_OPEN_FILES]));
+
+/// Close the files the app left open, returns how many there were
+pub fn close_leaked() -> usize {
+    utils::interrupt_free(|cs| {
+        let mut files = APP_FILES.borrow(cs).borrow_mut();
+        files.iter_mut().filter_map(Option::take).count()
+    })
+}
+
 extern "C" fn alloc(size: usize, align: usize) -> *mut u8 {
     match core::alloc::Layout::from_size_align(size, align) {
         Ok(layout) => utils::interrupt_free(|cs| {
@@ -176,3 +506,226 @@ extern "C" fn puts(start: *const u8, len: usize) -> i32 {
         _ => -1,
     }
 }
+
+// GPU
+
+/// Run `f` on the display, 0 on success, -1 if the display is not initialized. The console
+/// stays hidden from the first drawing until the app exits.
+fn draw(f: impl FnOnce(&mut display::Display) -> Result<(), Infallible>) -> i32 {
+    utils::interrupt_free(|cs| match GPU.borrow(cs).borrow_mut().as_mut() {
+        Some(gpu) => {
+            gpu.set_app_owned(true);
+            f(&mut **gpu).map(|_| 0).unwrap_or(-1)
+        }
+        None => -1,
+    })
+}
+
+fn point(x: u32, y: u32) -> Point {
+    Point::new(x as i32, y as i32)
+}
+
+fn color(raw: u16) -> Rgb565 {
+    Rgb565::from(RawU16::new(raw))
+}
+
+extern "C" fn screen_width_px() -> u32 {
+    display::SCREEN_WIDTH as u32
+}
+
+extern "C" fn screen_height_px() -> u32 {
+    display::SCREEN_HEIGHT as u32
+}
+
+extern "C" fn screen_width_char() -> u32 {
+    display::SCREEN_WIDTH_CHAR as u32
+}
+
+extern "C" fn screen_height_char() -> u32 {
+    display::SCREEN_HEIGHT_CHAR as u32
+}
+
+extern "C" fn dot(x1: u32, y1: u32, c: u16) -> i32 {
+    draw(|d| embedded_graphics::Pixel(point(x1, y1), color(c)).draw(d))
+}
+
+extern "C" fn line(x1: u32, y1: u32, x2: u32, y2: u32, stroke: u32, c: u16) -> i32 {
+    draw(|d| {
+        Line::new(point(x1, y1), point(x2, y2))
+            .into_styled(PrimitiveStyle::with_stroke(color(c), stroke))
+            .draw(d)
+    })
+}
+
+extern "C" fn square(x1: u32, y1: u32, x2: u32, y2: u32, stroke: u32, c: u16) -> i32 {
+    draw(|d| {
+        Rectangle::with_corners(point(x1, y1), point(x2, y2))
+            .into_styled(PrimitiveStyle::with_stroke(color(c), stroke))
+            .draw(d)
+    })
+}
+
+extern "C" fn square_fill(x1: u32, y1: u32, x2: u32, y2: u32, c: u16) -> i32 {
+    draw(|d| d.fill_solid(&Rectangle::with_corners(point(x1, y1), point(x2, y2)), color(c)))
+}
+
+// FS
+
+impl From<VfsError> for FsError {
+    fn from(err: VfsError) -> Self {
+        match err {
+            VfsError::NoDevice
+            | VfsError::UnknownDevice
+            | VfsError::InvalidPath
+            | VfsError::PathTooLong(_)
+            | VfsError::SdCard(SdmmcFsError::InvalidPath)
+            | VfsError::LittleFs(NorFsError::InvalidPath) => Self::InvalidPath,
+            VfsError::SdCard(SdmmcFsError::NotFound)
+            | VfsError::SdCard(SdmmcFsError::Sdmmc(SdError::FileNotFound))
+            | VfsError::LittleFs(NorFsError::Fs(LfsError::NoSuchEntry)) => Self::NotFound,
+            VfsError::AlreadyExists
+            | VfsError::SdCard(SdmmcFsError::AlreadyExists)
+            | VfsError::SdCard(SdmmcFsError::Sdmmc(SdError::FileAlreadyExists))
+            | VfsError::LittleFs(NorFsError::Fs(LfsError::EntryAlreadyExisted)) => {
+                Self::AlreadyExists
+            }
+            VfsError::IsDirectory
+            | VfsError::SdCard(SdmmcFsError::Sdmmc(SdError::OpenedDirAsFile))
+            | VfsError::LittleFs(NorFsError::Fs(LfsError::PathIsDir)) => Self::IsDirectory,
+            VfsError::SdCard(SdmmcFsError::Sdmmc(SdError::NotEnoughSpace))
+            | VfsError::LittleFs(NorFsError::Fs(LfsError::NoSpace)) => Self::NoSpace,
+            VfsError::InvalidOffset | VfsError::SdCard(SdmmcFsError::InvalidOffset(_)) => {
+                Self::InvalidOffset
+            }
+            VfsError::WrongMode => Self::WrongMode,
+            VfsError::SdCard(SdmmcFsError::Sdmmc(SdError::TooManyOpenFiles))
+            | VfsError::LittleFs(NorFsError::TooManyOpenFiles) => Self::TooManyOpenFiles,
+            VfsError::Unsupported(_) | VfsError::SdCard(SdmmcFsError::Unsupported(_)) => {
+                Self::Unsupported
+            }
+            _ => Self::Io,
+        }
+    }
+}
+
+/// Return value of a file function, the result or a negative [`FsError`]
+fn fs_result(result: Result<i32, FsError>) -> i32 {
+    result.unwrap_or_else(FsError::code)
+}
+
+fn app_path<'a>(start: *const u8, len: usize) -> Result<Path<'a>, FsError> {
+    let s = unsafe { core::slice::from_raw_parts(start, len) };
+    core::str::from_utf8(s)
+        .map(Path::new)
+        .map_err(|_| FsError::InvalidPath)
+}
+
+/// Run `func` on the open file `handle`
+fn with_file(
+    handle: i32,
+    func: impl FnOnce(&mut vfs::File) -> Result<i32, VfsError>,
+) -> Result<i32, FsError> {
+    utils::interrupt_free(|cs| {
+        let mut files = APP_FILES.borrow(cs).borrow_mut();
+        let file = usize::try_from(handle)
+            .ok()
+            .and_then(|i| files.get_mut(i))
+            .and_then(Option::as_mut)
+            .ok_or(FsError::BadHandle)?;
+        Ok(func(file)?)
+    })
+}
+
+extern "C" fn open(path: *const u8, path_len: usize, mode: u32) -> i32 {
+    let mode = match OpenMode::from_u32(mode) {
+        Some(OpenMode::Read) => vfs::OpenMode::Read,
+        Some(OpenMode::Write) => vfs::OpenMode::Write,
+        Some(OpenMode::Append) => vfs::OpenMode::Append,
+        None => return FsError::Unsupported.code(),
+    };
+    fs_result(app_path(path, path_len).and_then(|path| {
+        utils::interrupt_free(|cs| {
+            let mut files = APP_FILES.borrow(cs).borrow_mut();
+            // Find a free handle first, opening for writing truncates the file
+            let (handle, slot) = files
+                .iter_mut()
+                .enumerate()
+                .find(|(_, file)| file.is_none())
+                .ok_or(FsError::TooManyOpenFiles)?;
+            slot.replace(vfs::File::open(path, mode)?);
+            Ok(handle as i32)
+        })
+    }))
+}
+
+extern "C" fn read(handle: i32, buf: *mut u8, len: usize) -> i32 {
+    let buf = unsafe { core::slice::from_raw_parts_mut(buf, len.min(i32::MAX as usize)) };
+    fs_result(with_file(handle, |file| Ok(file.read(buf)? as i32)))
+}
+
+extern "C" fn write(handle: i32, buf: *const u8, len: usize) -> i32 {
+    let buf = unsafe { core::slice::from_raw_parts(buf, len.min(i32::MAX as usize)) };
+    fs_result(with_file(handle, |file| Ok(file.write(buf)? as i32)))
+}
+
+extern "C" fn seek(handle: i32, offset: i32, whence: u32) -> i32 {
+    let pos = match Whence::from_u32(whence) {
+        Some(Whence::Start) => match u32::try_from(offset) {
+            Ok(offset) => vfs::SeekFrom::Start(offset),
+            Err(_) => return FsError::InvalidOffset.code(),
+        },
+        Some(Whence::Current) => vfs::SeekFrom::Current(offset),
+        Some(Whence::End) => vfs::SeekFrom::End(offset),
+        None => return FsError::Unsupported.code(),
+    };
+    fs_result(with_file(handle, |file| {
+        i32::try_from(file.seek(pos)?).map_err(|_| VfsError::InvalidOffset)
+    }))
+}
+
+extern "C" fn close(handle: i32) -> i32 {
+    utils::interrupt_free(|cs| {
+        let mut files = APP_FILES.borrow(cs).borrow_mut();
+        match usize::try_from(handle)
+            .ok()
+            .and_then(|i| files.get_mut(i))
+            .and_then(Option::take)
+        {
+            Some(file) => fs_result(file.close().map(|_| 0).map_err(FsError::from)),
+            None => FsError::BadHandle.code(),
+        }
+    })
+}
+
+extern "C" fn read_dir(path: *const u8, path_len: usize, index: u32, entry: *mut DirEntry) -> i32 {
+    let mut entry = unsafe { entry.as_mut() };
+    fs_result(app_path(path, path_len).and_then(|path| {
+        let mut n = 0;
+        vfs::read_dir(path, |e| {
+            if let (true, Some(entry)) = (n == index, entry.as_mut()) {
+                entry.stat = file_stat(e.metadata);
+                entry.set_name(e.name);
+            }
+            n += 1;
+        })?;
+        Ok((n > index) as i32)
+    }))
+}
+
+extern "C" fn stat(path: *const u8, path_len: usize, stat: *mut FileStat) -> i32 {
+    let stat = unsafe { stat.as_mut() };
+    fs_result(app_path(path, path_len).and_then(|path| {
+        let metadata = vfs::stat(path)?;
+        if let Some(stat) = stat {
+            *stat = file_stat(metadata);
+        }
+        Ok(0)
+    }))
+}
+
+fn file_stat(metadata: vfs::Metadata) -> FileStat {
+    FileStat {
+        size: metadata.size,
+        is_dir: metadata.is_dir,
+    }
+}
@@ -2,32 +2,64 @@ use crate::Led;
 use core::{cell::RefCell, mem};
 use critical_section::Mutex;
 use embedded_display_controller::{DisplayControllerLayer, PixelFormat};
-use h7_display::{FrameBuffer, H7Display};
+use embedded_graphics::{
+    mono_font::{ascii::FONT_8X13, MonoFont},
+    prelude::Size,
+};
+use h7_display::{Cell, FrameBuffer, H7Display, TextConsole};
 use stm32h7xx_hal::{interrupt, ltdc::LtdcLayer1};
 
-type Pixel = embedded_graphics::pixelcolor::Rgb565;
+pub type Pixel = embedded_graphics::pixelcolor::Rgb565;
+pub type Display = H7Display<'static, Pixel, SCREEN_WIDTH, SCREEN_HEIGHT>;
+pub type Console = TextConsole<'static, 'static, Pixel>;
 
 pub const SCREEN_WIDTH: usize = 1024;
 pub const SCREEN_HEIGHT: usize = 768;
 pub const FRAME_BUFFER_SIZE: usize = mem::size_of::<FrameBuffer<Pixel, SCREEN_WIDTH, SCREEN_HEIGHT>>();
 pub const FRAME_BUFFER_ALLOC_SIZE: usize = FRAME_BUFFER_SIZE * 2;
 pub const FRAME_RATE: u32 = 60;
+pub const FONT: MonoFont<'static> = FONT_8X13;
+pub const SCREEN_WIDTH_CHAR: usize = SCREEN_WIDTH / FONT.character_size.width as usize;
+pub const SCREEN_HEIGHT_CHAR: usize = SCREEN_HEIGHT / FONT.character_size.height as usize;
+/// Console lines, the 59 on screen and about 140 of scrollback
+pub const CONSOLE_LINES: usize = 200;
+/// Console screen and scrollback, allocated on the heap. 8 bytes a cell, 200 KiB.
+pub const CONSOLE_CELLS: usize = SCREEN_WIDTH_CHAR * CONSOLE_LINES;
 
 pub static GPU: Mutex<RefCell<Option<Gpu>>> = Mutex::new(RefCell::new(None));
 
 pub struct Gpu {
-    display: H7Display<'static, Pixel, SCREEN_HEIGHT, SCREEN_HEIGHT>,
+    display: Display,
     layer: LtdcLayer1,
+    console: Console,
+    /// An app draws on the display, the console is not drawn until it's given back
+    app_owned: bool,
 }
 
 impl Gpu {
-    pub fn new(display: H7Display<'static, Pixel, SCREEN_HEIGHT, SCREEN_HEIGHT>, mut layer: LtdcLayer1) -> Self {
+    pub fn new(display: Display, mut layer: LtdcLayer1, console_cells: &'static mut [Cell]) -> Self {
         unsafe { layer.enable(display.front_buffer().as_ptr() as *const u16, PixelFormat::RGB565) };
-        Self { display, layer }
+        let console = TextConsole::new(&FONT, console_cells, Size::new(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32));
+        Self { display, layer, console, app_owned: false }
+    }
+
+    pub fn console(&mut self) -> &mut Console {
+        &mut self.console
+    }
+
+    /// Keep the console from drawing over what an app draws, it's drawn in full when given back
+    pub fn set_app_owned(&mut self, owned: bool) {
+        if self.app_owned && !owned {
+            self.console.redraw();
+        }
+        self.app_owned = owned;
     }
 
     pub fn swap(&mut self) {
         if !self.layer.is_swap_pending() {
+            if !self.app_owned {
+                let _ = self.console.draw(&mut self.display);
+            }
             let (front, _) = self.display.swap_buffers();
             unsafe { self.layer.swap_framebuffer(front.as_ptr() as *const u16) };
             unsafe { Led::Blue.toggle() };
@@ -41,7 +73,7 @@ impl Gpu {
 }
 
 impl core::ops::Deref for Gpu {
-    type Target = H7Display<'static, Pixel, SCREEN_HEIGHT, SCREEN_HEIGHT>;
+    type Target = Display;
 
     fn deref(&self) -> &Self::Target {
         &self.display
@@ -54,15 +86,26 @@ impl core::ops::DerefMut for Gpu {
     }
 }
 
-// Interrupt to swap framebuffers
+/// Show the console again after an app drew on the display
+pub fn release_from_app() {
+    crate::utils::interrupt_free(|cs| {
+        if let Some(gpu) = GPU.borrow(cs).borrow_mut().as_mut() {
+            gpu.set_app_owned(false);
+        }
+    });
+}
+
+// Interrupt to swap framebuffers. The GPU is taken out for the swap so that drawing the console
+// doesn't keep the other interrupts waiting. Nothing in thread mode runs in the meantime, the
+// interrupts that write to the console while it's out skip the display.
 #[interrupt]
 fn TIM2() {
-    unsafe {
-        crate::utils::interrupt_free(|cs| {
-            GPU.borrow(cs).borrow_mut().as_mut().unwrap().swap();
-        });
-        stm32h7xx_hal::pac::TIM2::ptr().as_ref().unwrap().sr.write(|w| w.uif().clear_bit());
-    };
+    let gpu = crate::utils::interrupt_free(|cs| GPU.borrow(cs).try_borrow_mut().ok().and_then(|mut gpu| gpu.take()));
+    if let Some(mut gpu) = gpu {
+        gpu.swap();
+        crate::utils::interrupt_free(|cs| GPU.borrow(cs).replace(Some(gpu)));
+    }
+    unsafe { stm32h7xx_hal::pac::TIM2::ptr().as_ref().unwrap().sr.write(|w| w.uif().clear_bit()) };
 }
 
 #[interrupt]
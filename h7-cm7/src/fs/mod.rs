@@ -1,3 +1,21 @@
 pub mod path;
 pub mod qspi_store;
+pub mod ram_disk;
 pub mod sdmmc_fs;
+pub mod vfs;
+
+use {
+    qspi_store::{NorFlash, QSPI_STORE},
+    ram_disk::{RAM_DISK, RAM_DISK_SIZE},
+    vfs::{LittleFs, SdCard, VfsError},
+};
+
+static NOR: LittleFs<NorFlash> = LittleFs(&QSPI_STORE);
+static RAM: LittleFs<h7_norfs::RamFlash<'static, RAM_DISK_SIZE>> = LittleFs(&RAM_DISK);
+
+/// Add the filesystems to the VFS mount table
+pub fn mount_all() -> Result<(), VfsError> {
+    vfs::mount("sdcard", &SdCard)?;
+    vfs::mount("nor", &NOR)?;
+    vfs::mount("ram", &RAM)
+}
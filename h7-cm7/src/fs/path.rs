@@ -1,6 +1,11 @@
+use {core::fmt::Write, heapless::String};
+
+/// Longest path, with the device
+pub const PATH_LEN: usize = 256;
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub struct Path<'p> {
-    // raw: &'p str,
+    raw: &'p str,
     path: &'p str,
     device: Option<&'p str>,
     absolute: bool,
@@ -25,28 +30,29 @@ impl<'p> Path<'p> {
             }
         };
         Self {
-            // raw: raw_trimmed,
+            raw: raw_trimmed,
             path,
             device,
             absolute: path.starts_with('/') || device.is_some(),
         }
     }
 
-    // pub fn raw(&self) -> &'p str {
-    //     self.raw
-    // }
+    pub fn raw(&self) -> &'p str {
+        self.raw
+    }
 
-    // pub fn path(&self) -> &'p str {
-    //     self.path
-    // }
+    pub fn path(&self) -> &'p str {
+        self.path
+    }
 
     pub fn device(&self) -> Option<&'p str> {
         self.device
     }
 
-    // pub fn is_absolute(&self) -> bool {
-    //     self.absolute
-    // }
+    /// Paths with a device are always absolute, `sdcard:apps` is `sdcard:/apps`
+    pub fn is_absolute(&self) -> bool {
+        self.absolute
+    }
 
     pub fn parts(&self) -> core::iter::Peekable<impl Iterator<Item = &'p str>> {
         self.path
@@ -55,9 +61,58 @@ impl<'p> Path<'p> {
             .peekable()
     }
 
-    // pub fn len(&self) -> usize {
-    //     self.parts().count()
-    // }
+    pub fn len(&self) -> usize {
+        self.parts().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parts().next().is_none()
+    }
+
+    /// The path without `.` and `..` parts. `..` stops at the root of absolute paths and is
+    /// kept at the start of relative ones.
+    pub fn normalize(&self) -> Result<PathBuf, PathError> {
+        let mut buf = PathBuf::default();
+        if let Some(device) = self.device {
+            write!(buf.0, "{device}:").map_err(|_| PathError::TooLong)?;
+        }
+        if self.absolute {
+            buf.push_str("/")?;
+        }
+        let root = buf.0.len();
+        for part in self.parts() {
+            match part {
+                "." => {}
+                ".." => {
+                    let tail = &buf.0[root..];
+                    if tail.is_empty() || tail == ".." || tail.ends_with("/..") {
+                        if !self.absolute {
+                            buf.push_part(root, part)?;
+                        }
+                    } else {
+                        let len = tail.rfind('/').map_or(root, |n| root + n);
+                        buf.0.truncate(len);
+                    }
+                }
+                part => buf.push_part(root, part)?,
+            }
+        }
+        Ok(buf)
+    }
+
+    /// `other` relative to this path, or `other` if it's absolute. The result is normalized.
+    pub fn join(&self, other: Path) -> Result<PathBuf, PathError> {
+        if other.is_absolute() {
+            return other.normalize();
+        }
+        let mut buf = String::<PATH_LEN>::new();
+        write!(buf, "{self}").map_err(|_| PathError::TooLong)?;
+        if !buf.is_empty() && !buf.ends_with('/') {
+            buf.push('/').map_err(|_| PathError::TooLong)?;
+        }
+        buf.push_str(other.path).map_err(|_| PathError::TooLong)?;
+        Path::new(buf.as_str()).normalize()
+    }
 }
 
 impl<'p> core::fmt::Display for Path<'p> {
@@ -89,232 +144,351 @@ impl<'s, T: AsRef<str> + ?Sized + 's> From<&'s T> for Path<'s> {
     }
 }
 
-// #[cfg(test)]
-// mod tests {
-
-//     use super::*;
-
-//     #[test]
-//     fn absolute_path() {
-//         let p = Path::new("/abs/path/hello");
-//         assert_eq!(p.raw(), "/abs/path/hello");
-//         assert_eq!(p.path(), "/abs/path/hello");
-//         assert_eq!(p.device(), None);
-//         assert!(p.is_absolute());
-//         assert_eq!(p.to_string(), "/abs/path/hello");
-//         assert_eq!(p.len(), 3);
-//         let mut parts = p.parts();
-//         assert_eq!(parts.next(), Some("abs"));
-//         assert_eq!(parts.next(), Some("path"));
-//         assert_eq!(parts.next(), Some("hello"));
-//         assert_eq!(parts.next(), None);
-//     }
-
-//     #[test]
-//     fn relative_path() {
-//         let p = Path::new("  r/path/hello  ");
-//         assert_eq!(p.raw(), "r/path/hello");
-//         assert_eq!(p.path(), "r/path/hello");
-//         assert_eq!(p.device(), None);
-//         assert!(!p.is_absolute());
-//         assert_eq!(p.to_string(), "r/path/hello");
-//         assert_eq!(p.len(), 3);
-//         let mut parts = p.parts();
-//         assert_eq!(parts.next(), Some("r"));
-//         assert_eq!(parts.next(), Some("path"));
-//         assert_eq!(parts.next(), Some("hello"));
-//         assert_eq!(parts.next(), None);
-//     }
-
-//     #[test]
-//     fn absolute_path_with_device() {
-//         let p = Path::new("A:  /abs/path/hello ");
-//         assert_eq!(p.raw(), "A:  /abs/path/hello");
-//         assert_eq!(p.path(), "/abs/path/hello");
-//         assert_eq!(p.device(), Some("A"));
-//         assert!(p.is_absolute());
-//         assert_eq!(p.to_string(), "A:/abs/path/hello");
-//         assert_eq!(p.len(), 3);
-//         let mut parts = p.parts();
-//         assert_eq!(parts.next(), Some("abs"));
-//         assert_eq!(parts.next(), Some("path"));
-//         assert_eq!(parts.next(), Some("hello"));
-//         assert_eq!(parts.next(), None);
-//     }
-
-//     #[test]
-//     fn absolute_path_with_device_many_space() {
-//         let p = Path::new("  A:      / / / /abs/path/hello/ / /  ");
-//         assert_eq!(p.raw(), "A:      / / / /abs/path/hello/ / /");
-//         assert_eq!(p.path(), "/abs/path/hello");
-//         assert_eq!(p.device(), Some("A"));
-//         assert!(p.is_absolute());
-//         assert_eq!(p.to_string(), "A:/abs/path/hello");
-//         assert_eq!(p.len(), 3);
-//         let mut parts = p.parts();
-//         assert_eq!(parts.next(), Some("abs"));
-//         assert_eq!(parts.next(), Some("path"));
-//         assert_eq!(parts.next(), Some("hello"));
-//         assert_eq!(parts.next(), None);
-//     }
-
-//     #[test]
-//     fn relative_path_with_device() {
-//         let p = Path::new("A:rel/path/hello");
-//         assert_eq!(p.raw(), "A:rel/path/hello");
-//         assert_eq!(p.path(), "rel/path/hello");
-//         assert_eq!(p.device(), Some("A"));
-//         assert!(p.is_absolute());
-//         assert_eq!(p.to_string(), "A:/rel/path/hello");
-//         assert_eq!(p.len(), 3);
-//         let mut parts = p.parts();
-//         assert_eq!(parts.next(), Some("rel"));
-//         assert_eq!(parts.next(), Some("path"));
-//         assert_eq!(parts.next(), Some("hello"));
-//         assert_eq!(parts.next(), None);
-//     }
-
-//     #[test]
-//     fn absolute_root() {
-//         let p = Path::new("/");
-//         assert_eq!(p.raw(), "/");
-//         assert_eq!(p.path(), "/");
-//         assert_eq!(p.device(), None);
-//         assert!(p.is_absolute());
-//         assert_eq!(p.to_string(), "/");
-//         assert_eq!(p.len(), 0);
-//         let mut parts = p.parts();
-//         assert_eq!(parts.next(), None);
-//     }
-
-//     #[test]
-//     fn absolute_root_with_device() {
-//         let p = Path::new("A:/");
-//         assert_eq!(p.raw(), "A:/");
-//         assert_eq!(p.path(), "/");
-//         assert_eq!(p.device(), Some("A"));
-//         assert!(p.is_absolute());
-//         assert_eq!(p.to_string(), "A:/");
-//         assert_eq!(p.len(), 0);
-//         let mut parts = p.parts();
-//         assert_eq!(parts.next(), None);
-//     }
-
-//     #[test]
-//     fn empty() {
-//         let p = Path::new("");
-//         assert_eq!(p.raw(), "");
-//         assert_eq!(p.path(), "");
-//         assert_eq!(p.device(), None);
-//         assert!(!p.is_absolute());
-//         assert_eq!(p.to_string(), "");
-//         assert_eq!(p.len(), 0);
-//         let mut parts = p.parts();
-//         assert_eq!(parts.next(), None);
-//     }
-
-//     #[test]
-//     fn empty_with_device() {
-//         let p = Path::new("A:");
-//         assert_eq!(p.raw(), "A:");
-//         assert_eq!(p.path(), "");
-//         assert_eq!(p.device(), Some("A"));
-//         assert!(p.is_absolute());
-//         assert_eq!(p.to_string(), "A:/");
-//         assert_eq!(p.len(), 0);
-//         let mut parts = p.parts();
-//         assert_eq!(parts.next(), None);
-//     }
-
-//     #[test]
-//     fn absolute_path_many_with_device() {
-//         let p = Path::new("A:////abs/path/hello///");
-//         assert_eq!(p.raw(), "A:////abs/path/hello///");
-//         assert_eq!(p.path(), "/abs/path/hello");
-//         assert_eq!(p.device(), Some("A"));
-//         assert!(p.is_absolute());
-//         assert_eq!(p.to_string(), "A:/abs/path/hello");
-//         assert_eq!(p.len(), 3);
-//         let mut parts = p.parts();
-//         assert_eq!(parts.next(), Some("abs"));
-//         assert_eq!(parts.next(), Some("path"));
-//         assert_eq!(parts.next(), Some("hello"));
-//         assert_eq!(parts.next(), None);
-//     }
-
-//     #[test]
-//     fn absolute_path_many() {
-//         let p = Path::new("////abs/path/hello///");
-//         assert_eq!(p.raw(), "////abs/path/hello///");
-//         assert_eq!(p.path(), "/abs/path/hello");
-//         assert_eq!(p.device(), None);
-//         assert!(p.is_absolute());
-//         assert_eq!(p.to_string(), "/abs/path/hello");
-//         assert_eq!(p.len(), 3);
-//         let mut parts = p.parts();
-//         assert_eq!(parts.next(), Some("abs"));
-//         assert_eq!(parts.next(), Some("path"));
-//         assert_eq!(parts.next(), Some("hello"));
-//         assert_eq!(parts.next(), None);
-//     }
-
-//     #[test]
-//     fn absolute_root_many() {
-//         let p = Path::new("///////////////////");
-//         assert_eq!(p.raw(), "///////////////////");
-//         assert_eq!(p.path(), "/");
-//         assert_eq!(p.device(), None);
-//         assert!(p.is_absolute());
-//         assert_eq!(p.to_string(), "/");
-//         assert_eq!(p.len(), 0);
-//         let mut parts = p.parts();
-//         assert_eq!(parts.next(), None);
-//     }
-
-//     #[test]
-//     fn absolute_root_many_with_device() {
-//         let p = Path::new("A://////////////////");
-//         assert_eq!(p.raw(), "A://////////////////");
-//         assert_eq!(p.path(), "/");
-//         assert_eq!(p.device(), Some("A"));
-//         assert!(p.is_absolute());
-//         assert_eq!(p.to_string(), "A:/");
-//         assert_eq!(p.len(), 0);
-//         let mut parts = p.parts();
-//         assert_eq!(parts.next(), None);
-//     }
-
-//     #[test]
-//     fn new_with_string() {
-//         let s = String::from("A:");
-//         let p = Path::new(&s);
-//         assert!(p.is_absolute());
-//     }
-
-//     #[test]
-//     fn new_with_str() {
-//         let s = "A:";
-//         let p = Path::new(s);
-//         assert!(p.is_absolute());
-//     }
-
-//     #[test]
-//     fn from_string() {
-//         let s = String::from("A:");
-//         let p = Path::from(&s);
-//         assert!(p.is_absolute());
-//     }
-
-//     #[test]
-//     fn from_str() {
-//         let s = "A:";
-//         let p = Path::from(s);
-//         assert!(p.is_absolute());
-//     }
-
-//     #[test]
-//     fn from_str_literal() {
-//         let p = Path::from("A:");
-//         assert!(p.is_absolute());
-//     }
-// }
+/// An owned, normalized path
+#[derive(Debug, Default, PartialEq, Eq, Clone, Hash)]
+pub struct PathBuf(String<PATH_LEN>);
+
+impl PathBuf {
+    pub fn as_path(&self) -> Path<'_> {
+        Path::new(self.0.as_str())
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    fn push_str(&mut self, s: &str) -> Result<(), PathError> {
+        self.0.push_str(s).map_err(|_| PathError::TooLong)
+    }
+
+    /// Add `part` after the parts that follow `root`
+    fn push_part(&mut self, root: usize, part: &str) -> Result<(), PathError> {
+        if self.0.len() > root {
+            self.push_str("/")?;
+        }
+        self.push_str(part)
+    }
+}
+
+impl core::fmt::Display for PathBuf {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathError {
+    /// Longer than `PATH_LEN`
+    TooLong,
+}
+
+impl core::fmt::Display for PathError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::TooLong => write!(f, "Path longer than {PATH_LEN} bytes"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        alloc::string::{String, ToString},
+    };
+
+    #[test]
+    fn absolute_path() {
+        let p = Path::new("/abs/path/hello");
+        assert_eq!(p.raw(), "/abs/path/hello");
+        assert_eq!(p.path(), "/abs/path/hello");
+        assert_eq!(p.device(), None);
+        assert!(p.is_absolute());
+        assert_eq!(p.to_string(), "/abs/path/hello");
+        assert_eq!(p.len(), 3);
+        let mut parts = p.parts();
+        assert_eq!(parts.next(), Some("abs"));
+        assert_eq!(parts.next(), Some("path"));
+        assert_eq!(parts.next(), Some("hello"));
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn relative_path() {
+        let p = Path::new("  r/path/hello  ");
+        assert_eq!(p.raw(), "r/path/hello");
+        assert_eq!(p.path(), "r/path/hello");
+        assert_eq!(p.device(), None);
+        assert!(!p.is_absolute());
+        assert_eq!(p.to_string(), "r/path/hello");
+        assert_eq!(p.len(), 3);
+        let mut parts = p.parts();
+        assert_eq!(parts.next(), Some("r"));
+        assert_eq!(parts.next(), Some("path"));
+        assert_eq!(parts.next(), Some("hello"));
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn absolute_path_with_device() {
+        let p = Path::new("A:  /abs/path/hello ");
+        assert_eq!(p.raw(), "A:  /abs/path/hello");
+        assert_eq!(p.path(), "/abs/path/hello");
+        assert_eq!(p.device(), Some("A"));
+        assert!(p.is_absolute());
+        assert_eq!(p.to_string(), "A:/abs/path/hello");
+        assert_eq!(p.len(), 3);
+        let mut parts = p.parts();
+        assert_eq!(parts.next(), Some("abs"));
+        assert_eq!(parts.next(), Some("path"));
+        assert_eq!(parts.next(), Some("hello"));
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn absolute_path_with_device_many_space() {
+        let p = Path::new("  A:      / / / /abs/path/hello/ / /  ");
+        assert_eq!(p.raw(), "A:      / / / /abs/path/hello/ / /");
+        assert_eq!(p.path(), "/abs/path/hello");
+        assert_eq!(p.device(), Some("A"));
+        assert!(p.is_absolute());
+        assert_eq!(p.to_string(), "A:/abs/path/hello");
+        assert_eq!(p.len(), 3);
+        let mut parts = p.parts();
+        assert_eq!(parts.next(), Some("abs"));
+        assert_eq!(parts.next(), Some("path"));
+        assert_eq!(parts.next(), Some("hello"));
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn relative_path_with_device() {
+        let p = Path::new("A:rel/path/hello");
+        assert_eq!(p.raw(), "A:rel/path/hello");
+        assert_eq!(p.path(), "rel/path/hello");
+        assert_eq!(p.device(), Some("A"));
+        assert!(p.is_absolute());
+        assert_eq!(p.to_string(), "A:/rel/path/hello");
+        assert_eq!(p.len(), 3);
+        let mut parts = p.parts();
+        assert_eq!(parts.next(), Some("rel"));
+        assert_eq!(parts.next(), Some("path"));
+        assert_eq!(parts.next(), Some("hello"));
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn absolute_root() {
+        let p = Path::new("/");
+        assert_eq!(p.raw(), "/");
+        assert_eq!(p.path(), "/");
+        assert_eq!(p.device(), None);
+        assert!(p.is_absolute());
+        assert_eq!(p.to_string(), "/");
+        assert_eq!(p.len(), 0);
+        let mut parts = p.parts();
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn absolute_root_with_device() {
+        let p = Path::new("A:/");
+        assert_eq!(p.raw(), "A:/");
+        assert_eq!(p.path(), "/");
+        assert_eq!(p.device(), Some("A"));
+        assert!(p.is_absolute());
+        assert_eq!(p.to_string(), "A:/");
+        assert_eq!(p.len(), 0);
+        let mut parts = p.parts();
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn empty() {
+        let p = Path::new("");
+        assert_eq!(p.raw(), "");
+        assert_eq!(p.path(), "");
+        assert_eq!(p.device(), None);
+        assert!(!p.is_absolute());
+        assert_eq!(p.to_string(), "");
+        assert_eq!(p.len(), 0);
+        let mut parts = p.parts();
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn empty_with_device() {
+        let p = Path::new("A:");
+        assert_eq!(p.raw(), "A:");
+        assert_eq!(p.path(), "");
+        assert_eq!(p.device(), Some("A"));
+        assert!(p.is_absolute());
+        assert_eq!(p.to_string(), "A:/");
+        assert_eq!(p.len(), 0);
+        let mut parts = p.parts();
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn absolute_path_many_with_device() {
+        let p = Path::new("A:////abs/path/hello///");
+        assert_eq!(p.raw(), "A:////abs/path/hello///");
+        assert_eq!(p.path(), "/abs/path/hello");
+        assert_eq!(p.device(), Some("A"));
+        assert!(p.is_absolute());
+        assert_eq!(p.to_string(), "A:/abs/path/hello");
+        assert_eq!(p.len(), 3);
+        let mut parts = p.parts();
+        assert_eq!(parts.next(), Some("abs"));
+        assert_eq!(parts.next(), Some("path"));
+        assert_eq!(parts.next(), Some("hello"));
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn absolute_path_many() {
+        let p = Path::new("////abs/path/hello///");
+        assert_eq!(p.raw(), "////abs/path/hello///");
+        assert_eq!(p.path(), "/abs/path/hello");
+        assert_eq!(p.device(), None);
+        assert!(p.is_absolute());
+        assert_eq!(p.to_string(), "/abs/path/hello");
+        assert_eq!(p.len(), 3);
+        let mut parts = p.parts();
+        assert_eq!(parts.next(), Some("abs"));
+        assert_eq!(parts.next(), Some("path"));
+        assert_eq!(parts.next(), Some("hello"));
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn absolute_root_many() {
+        let p = Path::new("///////////////////");
+        assert_eq!(p.raw(), "///////////////////");
+        assert_eq!(p.path(), "/");
+        assert_eq!(p.device(), None);
+        assert!(p.is_absolute());
+        assert_eq!(p.to_string(), "/");
+        assert_eq!(p.len(), 0);
+        let mut parts = p.parts();
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn absolute_root_many_with_device() {
+        let p = Path::new("A://////////////////");
+        assert_eq!(p.raw(), "A://////////////////");
+        assert_eq!(p.path(), "/");
+        assert_eq!(p.device(), Some("A"));
+        assert!(p.is_absolute());
+        assert_eq!(p.to_string(), "A:/");
+        assert_eq!(p.len(), 0);
+        let mut parts = p.parts();
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn new_with_string() {
+        let s = String::from("A:");
+        let p = Path::new(&s);
+        assert!(p.is_absolute());
+    }
+
+    #[test]
+    fn new_with_str() {
+        let s = "A:";
+        let p = Path::new(s);
+        assert!(p.is_absolute());
+    }
+
+    #[test]
+    fn from_string() {
+        let s = String::from("A:");
+        let p = Path::from(&s);
+        assert!(p.is_absolute());
+    }
+
+    #[test]
+    fn from_str() {
+        let s = "A:";
+        let p = Path::from(s);
+        assert!(p.is_absolute());
+    }
+
+    #[test]
+    fn from_str_literal() {
+        let p = Path::from("A:");
+        assert!(p.is_absolute());
+    }
+
+    #[test]
+    fn normalize_dots() {
+        let p = Path::new("sdcard:/apps/./games/../hello.h7");
+        assert_eq!(p.normalize().unwrap().as_str(), "sdcard:/apps/hello.h7");
+        let p = Path::new("/a/b/../../..");
+        assert_eq!(p.normalize().unwrap().as_str(), "/");
+        let p = Path::new("A:..");
+        assert_eq!(p.normalize().unwrap().as_str(), "A:/");
+        let p = Path::new("./a/../../b/..");
+        assert_eq!(p.normalize().unwrap().as_str(), "..");
+        let p = Path::new("../../a");
+        assert_eq!(p.normalize().unwrap().as_str(), "../../a");
+        let p = Path::new(".");
+        assert_eq!(p.normalize().unwrap().as_str(), "");
+    }
+
+    #[test]
+    fn normalize_is_a_path() {
+        let buf = Path::new("A:////abs/./path//hello///").normalize().unwrap();
+        let p = buf.as_path();
+        assert_eq!(p, Path::new("A:/abs/path/hello"));
+        assert_eq!(p.device(), Some("A"));
+        assert_eq!(p.len(), 3);
+        assert_eq!(buf.to_string(), "A:/abs/path/hello");
+    }
+
+    #[test]
+    fn join_relative() {
+        let cwd = Path::new("sdcard:/apps");
+        assert_eq!(
+            cwd.join(Path::new("hello.h7")).unwrap().as_str(),
+            "sdcard:/apps/hello.h7"
+        );
+        assert_eq!(
+            cwd.join(Path::new("../docs/./a.txt")).unwrap().as_str(),
+            "sdcard:/docs/a.txt"
+        );
+        assert_eq!(
+            cwd.join(Path::new("../../..")).unwrap().as_str(),
+            "sdcard:/"
+        );
+        assert_eq!(cwd.join(Path::new("")).unwrap().as_str(), "sdcard:/apps");
+        assert_eq!(cwd.join(Path::new(".")).unwrap().as_str(), "sdcard:/apps");
+        let root = Path::new("nor:/");
+        assert_eq!(root.join(Path::new("a/b")).unwrap().as_str(), "nor:/a/b");
+        let rel = Path::new("a");
+        assert_eq!(rel.join(Path::new("../..")).unwrap().as_str(), "..");
+        let empty = Path::new("");
+        assert_eq!(empty.join(Path::new("a/b")).unwrap().as_str(), "a/b");
+    }
+
+    #[test]
+    fn join_absolute() {
+        let cwd = Path::new("sdcard:/apps");
+        assert_eq!(cwd.join(Path::new("/etc/../x")).unwrap().as_str(), "/x");
+        assert_eq!(cwd.join(Path::new("nor:")).unwrap().as_str(), "nor:/");
+        assert_eq!(
+            cwd.join(Path::new("nor:a/../b")).unwrap().as_str(),
+            "nor:/b"
+        );
+    }
+
+    #[test]
+    fn too_long() {
+        let long = "a".repeat(PATH_LEN);
+        let cwd = Path::new("sdcard:/");
+        assert_eq!(cwd.join(Path::new(&long)), Err(PathError::TooLong));
+        let p = Path::new(&long[..PATH_LEN - 8]);
+        assert_eq!(cwd.join(p).unwrap().as_str().len(), PATH_LEN);
+    }
+}
@@ -1,6 +1,8 @@
 use {
+    crate::utils::interrupt_free,
     core::cell::RefCell,
     critical_section::Mutex,
+    h7_norfs::{Flash, NorFs, NorFsError},
     mx25l::Mx25L,
     stm32h7xx_hal::{
         gpio::{gpiog::PG6, Output, PushPull},
@@ -13,7 +15,22 @@ use {
 pub mod mx25l;
 
 pub const QSPI_FLASH_SIZE: usize = 16 * 1024 * 1024;
-pub static QSPI_STORE: Mutex<RefCell<Option<NorFlash>>> = Mutex::new(RefCell::new(None));
+pub static QSPI_STORE: Mutex<RefCell<Option<NorFs<NorFlash>>>> = Mutex::new(RefCell::new(None));
+
+/// Run `func` on the NOR flash filesystem
+pub fn with_nor_fs<R>(
+    func: impl FnOnce(&mut NorFs<NorFlash>) -> Result<R, NorFsError>,
+) -> Result<R, NorFsError> {
+    interrupt_free(|cs| {
+        func(
+            QSPI_STORE
+                .borrow(cs)
+                .borrow_mut()
+                .as_mut()
+                .expect("QSPI flash not initialized"),
+        )
+    })
+}
 
 pub struct NorFlash {
     mx25l: Mx25L<PG6<Output<PushPull>>>,
@@ -52,3 +69,23 @@ impl core::ops::DerefMut for NorFlash {
         &mut self.mx25l
     }
 }
+
+impl Flash for NorFlash {
+    type Error = QspiError;
+
+    const CAPACITY: usize = QSPI_FLASH_SIZE;
+    const SECTOR_SIZE: usize = mx25l::SECTOR_SIZE;
+    const PAGE_SIZE: usize = mx25l::PAGE_SIZE;
+
+    fn read(&mut self, address: u32, data: &mut [u8]) -> Result<(), Self::Error> {
+        self.mx25l.read(address, data)
+    }
+
+    fn program_page(&mut self, address: u32, data: &[u8]) -> Result<(), Self::Error> {
+        self.mx25l.write(address, data)
+    }
+
+    fn erase_sector(&mut self, address: u32) -> Result<(), Self::Error> {
+        self.mx25l.sector_erase(address)
+    }
+}
@@ -10,6 +10,11 @@ use {
 pub mod cmd;
 pub mod status;
 
+pub const PAGE_SIZE: usize = 256;
+pub const SECTOR_SIZE: usize = 4096;
+/// Transfers through the QSPI FIFO are at most 32 bytes
+const FIFO_SIZE: usize = 32;
+
 pub struct Mx25L<CS: OutputPin> {
     qspi: Qspi<QUADSPI>,
     cs: CS,
@@ -83,13 +88,15 @@ impl<CS: OutputPin> Mx25L<CS> {
     }
 
     pub fn read(&mut self, address: u32, data: &mut [u8]) -> Result<(), QspiError> {
-        self.read_extended(
-            QspiWord::U8(cmd::READ),
-            QspiWord::U24(address),
-            QspiWord::None,
-            0,
-            data,
-        )?;
+        for (i, chunk) in data.chunks_mut(FIFO_SIZE).enumerate() {
+            self.read_extended(
+                QspiWord::U8(cmd::READ),
+                QspiWord::U24(address + (i * FIFO_SIZE) as u32),
+                QspiWord::None,
+                0,
+                chunk,
+            )?;
+        }
         Ok(())
     }
 
@@ -102,12 +109,12 @@ impl<CS: OutputPin> Mx25L<CS> {
         //     &[],
         // )?;
 
-        // the internal write buffer is 32 bytes
-        for chunk in data.chunks(32) {
+        // A program wraps around within its page, so data must not cross a page boundary
+        for (i, chunk) in data.chunks(FIFO_SIZE).enumerate() {
             self.enable_write()?;
             self.write_extended(
                 QspiWord::U8(cmd::PP),
-                QspiWord::U24(address),
+                QspiWord::U24(address + (i * FIFO_SIZE) as u32),
                 QspiWord::None,
                 chunk,
             )?;
@@ -116,6 +123,17 @@ impl<CS: OutputPin> Mx25L<CS> {
         Ok(())
     }
 
+    /// Erase the 4KiB sector containing `address`
+    pub fn sector_erase(&mut self, address: u32) -> Result<(), QspiError> {
+        self.enable_write()?;
+        self.write_extended(
+            QspiWord::U8(cmd::SE),
+            QspiWord::U24(address),
+            QspiWord::None,
+            &[],
+        )
+    }
+
     pub fn chip_erase(&mut self) -> Result<(), QspiError> {
         self.enable_write()?;
         self.write_extended(
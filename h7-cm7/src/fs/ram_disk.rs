@@ -0,0 +1,20 @@
+use {
+    core::cell::RefCell,
+    critical_section::Mutex,
+    h7_norfs::{NorFs, NorFsError, RamFlash},
+};
+
+pub const RAM_DISK_SIZE: usize = 512 * 1024;
+
+pub type RamDisk = NorFs<RamFlash<'static, RAM_DISK_SIZE>>;
+
+/// littlefs volume in SDRAM, `ram:`. Empty after every reset.
+pub static RAM_DISK: Mutex<RefCell<Option<RamDisk>>> = Mutex::new(RefCell::new(None));
+
+/// Format and mount a volume in `memory`
+pub fn new(memory: &'static mut [u8; RAM_DISK_SIZE]) -> Result<RamDisk, NorFsError> {
+    let mut disk = NorFs::new(RamFlash::new(memory));
+    disk.format()?;
+    disk.mount()?;
+    Ok(disk)
+}
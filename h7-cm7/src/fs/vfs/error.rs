@@ -0,0 +1,58 @@
+use {crate::fs::sdmmc_fs::SdmmcFsError, h7_norfs::NorFsError};
+
+#[derive(Debug)]
+pub enum VfsError {
+    /// Path without `device:`
+    NoDevice,
+    /// Nothing mounted with that device name
+    UnknownDevice,
+    AlreadyMounted,
+    /// Mount table is full (max)
+    TooManyMounts(usize),
+    AlreadyExists,
+    IsDirectory,
+    /// Path is the same for source and destination, or the root where a file is expected
+    InvalidPath,
+    PathTooLong(usize),
+    /// File does not fit in the buffer (file size)
+    BufferTooSmall(u32),
+    InvalidOffset,
+    /// Reading a file opened for writing or the other way around
+    WrongMode,
+    Unsupported(&'static str),
+    SdCard(SdmmcFsError),
+    LittleFs(NorFsError),
+}
+
+impl From<SdmmcFsError> for VfsError {
+    fn from(err: SdmmcFsError) -> Self {
+        Self::SdCard(err)
+    }
+}
+
+impl From<NorFsError> for VfsError {
+    fn from(err: NorFsError) -> Self {
+        Self::LittleFs(err)
+    }
+}
+
+impl core::fmt::Display for VfsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NoDevice => write!(f, "No device selected"),
+            Self::UnknownDevice => write!(f, "Unknown device"),
+            Self::AlreadyMounted => write!(f, "Already mounted"),
+            Self::TooManyMounts(max) => write!(f, "Too many mounts (max {max})"),
+            Self::AlreadyExists => write!(f, "Already exists"),
+            Self::IsDirectory => write!(f, "Is a directory"),
+            Self::InvalidPath => write!(f, "Invalid path"),
+            Self::PathTooLong(max) => write!(f, "Path too long (max {max} bytes)"),
+            Self::BufferTooSmall(size) => write!(f, "File too large ({size} bytes)"),
+            Self::InvalidOffset => write!(f, "Invalid offset"),
+            Self::WrongMode => write!(f, "File not opened for that"),
+            Self::Unsupported(what) => write!(f, "{what} is not supported"),
+            Self::SdCard(e) => write!(f, "{e}"),
+            Self::LittleFs(e) => write!(f, "{e}"),
+        }
+    }
+}
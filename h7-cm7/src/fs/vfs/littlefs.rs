@@ -0,0 +1,99 @@
+use {
+    super::{volume_path, DirEntry, FileSystem, Handle, Metadata, OpenMode, VfsError},
+    crate::{fs::path::Path, utils::interrupt_free},
+    core::cell::RefCell,
+    critical_section::Mutex,
+    h7_norfs::{FileHandle, Flash, NorFs, NorFsError},
+};
+
+/// A littlefs volume, the NOR flash or the RAM disk
+pub struct LittleFs<F: Flash + 'static>(pub &'static Mutex<RefCell<Option<NorFs<F>>>>);
+
+impl<F: Flash + Send> LittleFs<F> {
+    fn with<R>(
+        &self,
+        func: impl FnOnce(&mut NorFs<F>) -> Result<R, NorFsError>,
+    ) -> Result<R, VfsError> {
+        interrupt_free(|cs| match self.0.borrow(cs).borrow_mut().as_mut() {
+            Some(fs) => Ok(func(fs)?),
+            None => Err(NorFsError::NotMounted.into()),
+        })
+    }
+}
+
+impl<F: Flash + Send> FileSystem for LittleFs<F> {
+    fn open(&self, path: Path, mode: OpenMode) -> Result<(Handle, u32), VfsError> {
+        let path = volume_path(path)?;
+        let mode = match mode {
+            OpenMode::Read => h7_norfs::OpenMode::Read,
+            OpenMode::Write => h7_norfs::OpenMode::Write,
+            OpenMode::Append => h7_norfs::OpenMode::Append,
+        };
+        self.with(|fs| {
+            let file = fs.open(&path, mode)?;
+            match fs.size(file) {
+                Ok(size) => Ok((Handle(file.0), size as u32)),
+                Err(err) => {
+                    let _ = fs.close(file);
+                    Err(err)
+                }
+            }
+        })
+    }
+
+    fn read(&self, file: Handle, data: &mut [u8]) -> Result<usize, VfsError> {
+        self.with(|fs| fs.read(FileHandle(file.0), data))
+    }
+
+    fn write(&self, file: Handle, data: &[u8]) -> Result<usize, VfsError> {
+        self.with(|fs| fs.write(FileHandle(file.0), data))?;
+        Ok(data.len())
+    }
+
+    fn seek(&self, file: Handle, offset: u32) -> Result<(), VfsError> {
+        self.with(|fs| fs.seek(FileHandle(file.0), offset as usize))
+    }
+
+    fn close(&self, file: Handle) -> Result<(), VfsError> {
+        self.with(|fs| fs.close(FileHandle(file.0)))
+    }
+
+    fn read_dir(&self, path: Path, func: &mut dyn FnMut(&DirEntry)) -> Result<(), VfsError> {
+        let path = volume_path(path)?;
+        self.with(|fs| {
+            fs.ls(&path, |e| {
+                func(&DirEntry {
+                    name: e.name,
+                    metadata: Metadata {
+                        is_dir: e.metadata.is_dir,
+                        size: e.metadata.size as u32,
+                    },
+                    modified: None,
+                })
+            })
+        })
+    }
+
+    fn stat(&self, path: Path) -> Result<Metadata, VfsError> {
+        let path = volume_path(path)?;
+        let metadata = self.with(|fs| fs.stat(&path))?;
+        Ok(Metadata {
+            is_dir: metadata.is_dir,
+            size: metadata.size as u32,
+        })
+    }
+
+    fn remove(&self, path: Path) -> Result<(), VfsError> {
+        let path = volume_path(path)?;
+        self.with(|fs| fs.remove(&path))
+    }
+
+    fn rename(&self, from: Path, to: Path) -> Result<(), VfsError> {
+        // littlefs replaces an existing file
+        if self.stat(to).is_ok() {
+            return Err(VfsError::AlreadyExists);
+        }
+        let (from, to) = (volume_path(from)?, volume_path(to)?);
+        self.with(|fs| fs.rename(&from, &to))
+    }
+}
@@ -0,0 +1,300 @@
+use {
+    super::path::{Path, PATH_LEN},
+    crate::utils::interrupt_free,
+    chrono::NaiveDateTime,
+    core::{
+        cell::RefCell,
+        fmt::{self, Write},
+        mem::ManuallyDrop,
+    },
+    critical_section::Mutex,
+    heapless::{String, Vec},
+};
+
+mod error;
+mod littlefs;
+mod sdcard;
+
+pub use {error::VfsError, littlefs::LittleFs, sdcard::SdCard};
+
+pub const MAX_MOUNTS: usize = 4;
+
+/// Mounted filesystems by device name, `sdcard` in `sdcard:/path`
+static MOUNTS: Mutex<RefCell<Vec<(&'static str, &'static dyn FileSystem), MAX_MOUNTS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub is_dir: bool,
+    pub size: u32,
+}
+
+pub struct DirEntry<'a> {
+    pub name: &'a str,
+    pub metadata: Metadata,
+    pub modified: Option<NaiveDateTime>,
+}
+
+/// A file opened by a backend, until it's closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handle(pub usize);
+
+/// A storage backend in the mount table.
+///
+/// Paths still include the device. Files stay open in the backend between calls, `File` keeps
+/// the handle and reads, writes and seeks go on from where the last one stopped.
+pub trait FileSystem: Sync {
+    /// Open a file, returns its handle and size. `Write` and `Append` create it, `Append` starts
+    /// at the end.
+    fn open(&self, path: Path, mode: OpenMode) -> Result<(Handle, u32), VfsError>;
+
+    /// Read from the position of the file, returns the number of bytes read, 0 at the end
+    fn read(&self, file: Handle, data: &mut [u8]) -> Result<usize, VfsError>;
+
+    /// Write at the position of the file, returns the number of bytes written
+    fn write(&self, file: Handle, data: &[u8]) -> Result<usize, VfsError>;
+
+    /// Move the position of the file to `offset` from the start, at most to the end
+    fn seek(&self, file: Handle, offset: u32) -> Result<(), VfsError>;
+
+    fn close(&self, file: Handle) -> Result<(), VfsError>;
+
+    /// Call `func` for each entry in the directory, without `.`, `..` and hidden entries
+    fn read_dir(&self, path: Path, func: &mut dyn FnMut(&DirEntry)) -> Result<(), VfsError>;
+
+    /// The root of the device is a directory
+    fn stat(&self, path: Path) -> Result<Metadata, VfsError>;
+
+    /// Remove a file or an empty directory
+    fn remove(&self, path: Path) -> Result<(), VfsError>;
+
+    /// Rename on the same device, `to` must not exist
+    fn rename(&self, from: Path, to: Path) -> Result<(), VfsError>;
+}
+
+/// Add `fs` to the mount table as `device`
+pub fn mount(device: &'static str, fs: &'static dyn FileSystem) -> Result<(), VfsError> {
+    interrupt_free(|cs| {
+        let mut mounts = MOUNTS.borrow(cs).borrow_mut();
+        if mounts.iter().any(|(d, _)| *d == device) {
+            return Err(VfsError::AlreadyMounted);
+        }
+        mounts
+            .push((device, fs))
+            .map_err(|_| VfsError::TooManyMounts(MAX_MOUNTS))
+    })
+}
+
+/// Names of the mounted devices
+pub fn devices() -> Vec<&'static str, MAX_MOUNTS> {
+    interrupt_free(|cs| MOUNTS.borrow(cs).borrow().iter().map(|(d, _)| *d).collect())
+}
+
+/// Path on the volume, without the device
+fn volume_path(path: Path) -> Result<String<PATH_LEN>, VfsError> {
+    let mut buf = String::new();
+    for part in path.parts() {
+        write!(buf, "/{part}").map_err(|_| VfsError::PathTooLong(PATH_LEN))?;
+    }
+    Ok(buf)
+}
+
+fn lookup(path: Path) -> Result<&'static dyn FileSystem, VfsError> {
+    let device = path.device().ok_or(VfsError::NoDevice)?;
+    interrupt_free(|cs| {
+        MOUNTS
+            .borrow(cs)
+            .borrow()
+            .iter()
+            .find(|(d, _)| *d == device)
+            .map(|(_, fs)| *fs)
+            .ok_or(VfsError::UnknownDevice)
+    })
+}
+
+pub fn read_dir(path: Path, mut func: impl FnMut(&DirEntry)) -> Result<(), VfsError> {
+    lookup(path)?.read_dir(path, &mut func)
+}
+
+pub fn stat(path: Path) -> Result<Metadata, VfsError> {
+    lookup(path)?.stat(path)
+}
+
+pub fn remove(path: Path) -> Result<(), VfsError> {
+    lookup(path)?.remove(path)
+}
+
+/// Call `func` with the file in chunks of `buf.len()` until it returns false
+pub fn read_chunks(
+    path: Path,
+    buf: &mut [u8],
+    mut func: impl FnMut(&[u8]) -> bool,
+) -> Result<(), VfsError> {
+    if stat(path)?.is_dir {
+        return Err(VfsError::IsDirectory);
+    }
+    let mut file = File::open(path, OpenMode::Read)?;
+    loop {
+        match file.read(buf)? {
+            0 => break,
+            n if func(&buf[..n]) => {}
+            _ => break,
+        }
+    }
+    file.close()
+}
+
+/// Read a whole file into `data`, returns the file size
+pub fn read_file(path: Path, data: &mut [u8]) -> Result<usize, VfsError> {
+    let mut file = File::open(path, OpenMode::Read)?;
+    if file.size() as usize > data.len() {
+        return Err(VfsError::BufferTooSmall(file.size()));
+    }
+    let mut len = 0;
+    loop {
+        match file.read(&mut data[len..])? {
+            0 => return Ok(len),
+            n => len += n,
+        }
+    }
+}
+
+/// Copy a file in chunks of `buf.len()`, also between devices. `to` is created or truncated.
+/// Returns the number of bytes copied.
+pub fn copy(from: Path, to: Path, buf: &mut [u8]) -> Result<u32, VfsError> {
+    if from == to {
+        return Err(VfsError::InvalidPath);
+    }
+    if stat(from)?.is_dir {
+        return Err(VfsError::IsDirectory);
+    }
+    let mut src = File::open(from, OpenMode::Read)?;
+    let mut dst = File::open(to, OpenMode::Write)?;
+    loop {
+        match src.read(buf)? {
+            0 => break,
+            n => dst.write(&buf[..n])?,
+        };
+    }
+    let size = dst.size();
+    dst.close()?;
+    src.close()?;
+    Ok(size)
+}
+
+/// Rename on the same device, copy and remove between devices
+pub fn rename(from: Path, to: Path) -> Result<(), VfsError> {
+    if from.device() == to.device() {
+        return lookup(from)?.rename(from, to);
+    }
+    if stat(to).is_ok() {
+        return Err(VfsError::AlreadyExists);
+    }
+    copy(from, to, &mut [0u8; 512])?;
+    remove(from)
+}
+
+pub fn print_dir_entry<W: fmt::Write>(writer: &mut W, dir_entry: &DirEntry) -> fmt::Result {
+    write!(writer, "{:24}", dir_entry.name)?;
+    if let Some(modified) = dir_entry.modified {
+        write!(writer, " {modified}")?;
+    }
+    match dir_entry.metadata.is_dir {
+        true => writeln!(writer, "  <DIR>"),
+        false => writeln!(writer, "  {} bytes", dir_entry.metadata.size),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    Read,
+    /// Create or truncate
+    Write,
+    /// Create if it doesn't exist and start at the end
+    Append,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u32),
+    Current(i32),
+    End(i32),
+}
+
+/// A file opened through the VFS, the backend keeps it open until it's closed or dropped
+pub struct File {
+    fs: &'static dyn FileSystem,
+    handle: Handle,
+    mode: OpenMode,
+    offset: u32,
+    size: u32,
+}
+
+impl File {
+    pub fn open(path: Path, mode: OpenMode) -> Result<Self, VfsError> {
+        let fs = lookup(path)?;
+        let (handle, size) = fs.open(path, mode)?;
+        Ok(Self {
+            fs,
+            handle,
+            mode,
+            offset: if mode == OpenMode::Append { size } else { 0 },
+            size,
+        })
+    }
+
+    /// Close the file, with the error of writing what's left of it that dropping it ignores
+    pub fn close(self) -> Result<(), VfsError> {
+        let file = ManuallyDrop::new(self);
+        file.fs.close(file.handle)
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Read from the current offset, returns 0 at the end of the file
+    pub fn read(&mut self, data: &mut [u8]) -> Result<usize, VfsError> {
+        if self.mode != OpenMode::Read {
+            return Err(VfsError::WrongMode);
+        }
+        let n = self.fs.read(self.handle, data)?;
+        self.offset += n as u32;
+        Ok(n)
+    }
+
+    /// Write at the current offset, over what's there or at the end
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, VfsError> {
+        if self.mode == OpenMode::Read {
+            return Err(VfsError::WrongMode);
+        }
+        let n = self.fs.write(self.handle, data)?;
+        self.offset += n as u32;
+        self.size = self.size.max(self.offset);
+        Ok(n)
+    }
+
+    /// Move the offset, at most to the end of the file
+    pub fn seek(&mut self, pos: SeekFrom) -> Result<u32, VfsError> {
+        let offset = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.offset.checked_add_signed(n),
+            SeekFrom::End(n) => self.size.checked_add_signed(n),
+        }
+        .filter(|offset| *offset <= self.size)
+        .ok_or(VfsError::InvalidOffset)?;
+        self.fs.seek(self.handle, offset)?;
+        self.offset = offset;
+        Ok(offset)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let _ = self.fs.close(self.handle);
+    }
+}
@@ -0,0 +1,105 @@
+use {
+    super::{volume_path, DirEntry, FileSystem, Handle, Metadata, OpenMode, VfsError},
+    crate::fs::{path::Path, sdmmc_fs::with_sd_card},
+    chrono::{NaiveDate, NaiveDateTime},
+    h7_sdfs::FileHandle,
+};
+
+/// The FAT filesystem on the SD card
+pub struct SdCard;
+
+impl FileSystem for SdCard {
+    fn open(&self, path: Path, mode: OpenMode) -> Result<(Handle, u32), VfsError> {
+        let path = volume_path(path)?;
+        let mode = match mode {
+            OpenMode::Read => h7_sdfs::OpenMode::Read,
+            OpenMode::Write => h7_sdfs::OpenMode::Write,
+            OpenMode::Append => h7_sdfs::OpenMode::Append,
+        };
+        Ok(with_sd_card(|sdfs| {
+            let file = sdfs.open(&path, mode)?;
+            match sdfs.size(file) {
+                Ok(size) => Ok((Handle(file.0), size)),
+                Err(err) => {
+                    let _ = sdfs.close(file);
+                    Err(err)
+                }
+            }
+        })?)
+    }
+
+    fn read(&self, file: Handle, data: &mut [u8]) -> Result<usize, VfsError> {
+        Ok(with_sd_card(|sdfs| sdfs.read(FileHandle(file.0), data))?)
+    }
+
+    fn write(&self, file: Handle, data: &[u8]) -> Result<usize, VfsError> {
+        Ok(with_sd_card(|sdfs| sdfs.write(FileHandle(file.0), data))?)
+    }
+
+    fn seek(&self, file: Handle, offset: u32) -> Result<(), VfsError> {
+        Ok(with_sd_card(|sdfs| sdfs.seek(FileHandle(file.0), offset))?)
+    }
+
+    fn close(&self, file: Handle) -> Result<(), VfsError> {
+        Ok(with_sd_card(|sdfs| sdfs.close(FileHandle(file.0)))?)
+    }
+
+    fn read_dir(&self, path: Path, func: &mut dyn FnMut(&DirEntry)) -> Result<(), VfsError> {
+        let path = volume_path(path)?;
+        Ok(with_sd_card(|sdfs| {
+            sdfs.ls(&path, |e| {
+                if e.is_volume() || e.is_hidden() || e.name == "." || e.name == ".." {
+                    return;
+                }
+                func(&DirEntry {
+                    name: e.name,
+                    metadata: Metadata {
+                        is_dir: e.is_directory(),
+                        size: e.size,
+                    },
+                    modified: to_datetime(&e.modified()),
+                })
+            })
+        })?)
+    }
+
+    fn stat(&self, path: Path) -> Result<Metadata, VfsError> {
+        if path.parts().next().is_none() {
+            return Ok(Metadata {
+                is_dir: true,
+                size: 0,
+            });
+        }
+        let path = volume_path(path)?;
+        let entry = with_sd_card(|sdfs| sdfs.stat(&path))?;
+        Ok(Metadata {
+            is_dir: entry.attributes.is_directory(),
+            size: entry.size,
+        })
+    }
+
+    fn remove(&self, path: Path) -> Result<(), VfsError> {
+        let path = volume_path(path)?;
+        Ok(with_sd_card(|sdfs| {
+            match sdfs.stat(&path)?.attributes.is_directory() {
+                true => sdfs.rmdir(&path),
+                false => sdfs.delete_file(&path),
+            }
+        })?)
+    }
+
+    fn rename(&self, from: Path, to: Path) -> Result<(), VfsError> {
+        let (from, to) = (volume_path(from)?, volume_path(to)?);
+        let mut buf = [0u8; 512];
+        Ok(with_sd_card(|sdfs| sdfs.rename(&from, &to, &mut buf))?)
+    }
+}
+
+fn to_datetime(ts: &embedded_sdmmc::Timestamp) -> Option<NaiveDateTime> {
+    NaiveDate::from_ymd_opt(
+        1970 + ts.year_since_1970 as i32,
+        ts.zero_indexed_month as u32 + 1,
+        ts.zero_indexed_day as u32 + 1,
+    )?
+    .and_hms_opt(ts.hours as u32, ts.minutes as u32, ts.seconds as u32)
+}
@@ -38,30 +38,26 @@ mod semihosting {
 pub use semihosting::{init, set_log_level};
 
 #[cfg(not(feature = "semihosting"))]
-mod uart {
+mod terminal {
 
     use {
-        crate::{terminal::UART_TERMINAL_TX, utils::interrupt_free},
+        crate::terminal::sink,
         core::fmt::Write,
         log::{Log, Metadata, Record},
     };
 
-    static LOGGER: UartLogger = UartLogger;
+    static LOGGER: TerminalLogger = TerminalLogger;
 
-    pub struct UartLogger;
+    /// Logs to all enabled terminal sinks
+    pub struct TerminalLogger;
 
-    impl Write for UartLogger {
+    impl Write for TerminalLogger {
         fn write_str(&mut self, s: &str) -> core::fmt::Result {
-            interrupt_free(|cs| {
-                if let Some(tx) = &mut *UART_TERMINAL_TX.borrow(cs).borrow_mut() {
-                    tx.write_str(s)?;
-                }
-                Ok(())
-            })
+            sink::write_str(s)
         }
     }
 
-    impl Log for UartLogger {
+    impl Log for TerminalLogger {
         fn enabled(&self, metadata: &Metadata) -> bool {
             metadata.level() <= unsafe { super::LOG_LEVEL }
         }
@@ -70,7 +66,7 @@ mod uart {
             // haha rust go brrr
             // let this = self as *const Self as *mut Self;
             // let this = unsafe { &mut *this };
-            let this = &mut UartLogger;
+            let this = &mut TerminalLogger;
             let _ = write!(
                 this,
                 "{}",
@@ -99,4 +95,4 @@ mod uart {
 }
 
 #[cfg(not(feature = "semihosting"))]
-pub use uart::{init, set_log_level};
+pub use terminal::{init, set_log_level};
@@ -274,7 +274,7 @@ unsafe fn main() -> ! {
             ccdr.peripheral.SDMMC2,
             &ccdr.clocks,
         );
-        interrupt_free(|cs| fs::sdmmc_fs::SD_CARD.borrow(cs).replace(Some(fs::sdmmc_fs::SdmmcFs::new(sdcard))));
+        interrupt_free(|cs| fs::sdmmc_fs::SD_CARD.borrow(cs).replace(Some(fs::sdmmc_fs::SdmmcFs::new(fs::sdmmc_fs::H7Card(sdcard), TimeSource))));
     }
 
     // QSPI Flash
@@ -296,11 +296,27 @@ unsafe fn main() -> ! {
         );
         qspi_store.init().unwrap();
 
+        // Mount the filesystem if there is one, `nor format` creates it
+        let mut nor_fs = h7_norfs::NorFs::new(qspi_store);
+        if let Err(e) = nor_fs.mount() {
+            log::warn!("NOR flash: {e}");
+        }
+
         interrupt_free(|cs| {
-            fs::qspi_store::QSPI_STORE.borrow(cs).replace(Some(qspi_store));
+            fs::qspi_store::QSPI_STORE.borrow(cs).replace(Some(nor_fs));
         });
     }
 
+    // RAM disk, VFS
+    {
+        let memory = alloc::vec![0u8; fs::ram_disk::RAM_DISK_SIZE].leak();
+        let ram_disk = fs::ram_disk::new(memory.try_into().unwrap()).unwrap();
+        interrupt_free(|cs| fs::ram_disk::RAM_DISK.borrow(cs).replace(Some(ram_disk)));
+        if let Err(e) = fs::mount_all() {
+            log::error!("VFS: {e}");
+        }
+    }
+
     // Display config
     {
         let mut anx = Anx7625::new(
@@ -344,7 +360,8 @@ unsafe fn main() -> ! {
         let fb0: &'static mut _ = &mut *(framebuffer_start_addr as *mut _);
         let fb1: &'static mut _ = &mut *((framebuffer_start_addr + display::FRAME_BUFFER_SIZE) as *mut _);
         let display = h7_display::H7Display::new(fb0, fb1);
-        let gpu = display::Gpu::new(display, ltdc.split());
+        let console_cells = alloc::vec![h7_display::Cell::BLANK; display::CONSOLE_CELLS].leak();
+        let gpu = display::Gpu::new(display, ltdc.split(), console_cells);
 
         interrupt_free(|cs| {
             display::GPU.borrow(cs).replace(Some(gpu));
@@ -360,59 +377,26 @@ unsafe fn main() -> ! {
 
     let mut menu = terminal::menu::Menu::new(terminal::TerminalWriter, terminal::MENU);
 
-    let mut cmd_buf = [0u8; 1024];
-    let mut cmd_buf_len: usize = 0;
+    let mut editor = h7_shell::LineEditor::<1024, 8>::new("> ");
 
     // Main loop
     led_r.set_high();
     led_g.set_high();
     led_b.set_high();
-    let _ = write!(menu.writer(), "> ");
+    let _ = editor.start(menu.writer());
 
     loop {
-        match terminal::TERMINAL_INPUT_FIFO.dequeue() {
-            Some(10) => match core::str::from_utf8(&cmd_buf[0..cmd_buf_len]) {
-                Ok(s) => {
-                    let mut parts = s.split_whitespace().filter(|l| !l.trim().is_empty());
-
-                    if let Some(cmd) = parts.next() {
-                        // Collect up to 16 arguments
-                        let mut args = [""; 16];
-                        let mut args_len = 0;
-                        for arg in args.iter_mut() {
-                            match parts.next() {
-                                Some(s) => {
-                                    *arg = s;
-                                    args_len += 1;
-                                }
-                                None => break,
-                            }
-                        }
-                        // Run command
-                        if let Err(e) = menu.run(cmd, &args[0..args_len]) {
-                            let _ = writeln!(menu.writer(), "Error: {e}");
-                        }
-                        // Clear input
-                        delay.delay_ms(10u8); // Wait for interrupts
-                        while terminal::TERMINAL_INPUT_FIFO.dequeue().is_some() {}
-                        cmd_buf_len = 0;
+        if let Some(c) = terminal::TERMINAL_INPUT_FIFO.dequeue() {
+            if let Ok(Some(line)) = editor.feed(c, menu.writer(), &mut terminal::ShellCompleter) {
+                if !line.trim().is_empty() {
+                    // Run command
+                    if let Err(e) = menu.run_line(line) {
+                        let _ = writeln!(menu.writer(), "Error: {e}");
                     }
-                    let _ = write!(menu.writer(), "> ");
-                }
-                Err(e) => {
-                    let _ = writeln!(menu, "Error: {e}");
-                }
-            },
-            Some(c) => {
-                if cmd_buf_len < cmd_buf.len() {
-                    cmd_buf[cmd_buf_len] = c;
-                    cmd_buf_len += 1;
-                } else {
-                    let _ = writeln!(menu, "Error: Buffer full");
                 }
+                let _ = editor.start(menu.writer());
             }
-            None => {} // FIFO empty
-        };
+        }
 
         // Blink
         if let Some(dt) = TimeSource::get_date_time() {
@@ -1,5 +1,5 @@
 use {
-    crate::{terminal, utils::interrupt_free, Led},
+    crate::{display, terminal::sink, utils::interrupt_free, Led},
     core::{fmt::Write, panic::PanicInfo},
 };
 
@@ -7,20 +7,20 @@ struct PanicLogger;
 
 impl Write for PanicLogger {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        interrupt_free(|cs| {
-            if let Some(tx) = &mut *terminal::UART_TERMINAL_TX.borrow(cs).borrow_mut() {
-                write!(tx, "{s}")?
-            }
-            Ok(())
-        })
+        sink::write_str_uart(s)
     }
 }
 
 #[panic_handler]
 fn panic_handler(panic_info: &PanicInfo) -> ! {
-    // TODO: Render panic info to display
-
     let _ = writeln!(PanicLogger, "{panic_info}");
+    // We may have panicked in an interrupt that blocks the frame swap, draw the console now
+    interrupt_free(|cs| {
+        if let Ok(Some(gpu)) = display::GPU.borrow(cs).try_borrow_mut().as_deref_mut() {
+            gpu.set_app_owned(false);
+            gpu.swap();
+        }
+    });
     const LIMIT: usize = 10_000_000;
     const LIMIT_DC: usize = LIMIT / 2;
     unsafe {
@@ -1,10 +1,10 @@
 use {
-    super::utils::from_hex,
+    super::utils::{check_args_len, from_hex, iso_8601, write_utf8_lossy},
     crate::{
         fs::{
-            path::Path,
-            qspi_store::{mx25l::status as mx25l_status, QSPI_STORE},
-            sdmmc_fs,
+            path::{Path, PATH_LEN},
+            qspi_store::{mx25l::status as mx25l_status, with_nor_fs, QSPI_STORE},
+            vfs,
         },
         terminal::{
             commands::LABEL_WIDTH,
@@ -13,61 +13,51 @@ use {
         },
         utils::interrupt_free,
     },
+    alloc::vec::Vec,
     core::fmt::Write,
     fugit::RateExtU32,
+    h7_norfs::NorFsError,
+    h7_rpc::Value,
+    serde_json::json,
     stm32h7xx_hal as hal,
 };
 
 pub const LS: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "ls",
-    help: "ls <device>:<dir> - List files on a filesystem",
+    help: "ls [dir] - List files in a directory, the current directory by default",
     description: "List files",
     action: |m, args| {
-        let path = Path::new(args.first().unwrap_or(&""));
-        match path.device() {
-            Some("sdcard") => interrupt_free(|cs| {
-                match crate::fs::sdmmc_fs::SD_CARD
-                    .borrow(cs)
-                    .borrow_mut()
-                    .as_mut()
-                    .map(|sdfs| {
-                        sdfs.ls(path, |e| {
-                            let _ = sdmmc_fs::print_dir_entry(m.writer(), e);
-                        })
-                    }) {
-                    Some(Ok(_)) => Ok(()),
-                    Some(Err(e)) => {
-                        writeln!(m.writer(), "Error: {e}")?;
-                        Ok(())
-                    }
-                    None => {
-                        writeln!(m.writer(), "Error: SD Card controller not initialized")?;
-                        Ok(())
-                    }
-                }
-            }),
-            Some("nor") => {
-                writeln!(m.writer(), "Not implemented")?;
-                Ok(())
-            }
-            Some(device) => {
-                writeln!(m.writer(), "Unknown device '{device}'")?;
-                Ok(())
-            }
-            None => {
-                writeln!(m.writer(), "No device selected")?;
-                Ok(())
+        let path = m.resolve(args.first().unwrap_or(&""))?;
+        let rpc = m.is_rpc();
+        let mut entries = Vec::new();
+        vfs::read_dir(path.as_path(), |e| {
+            let _ = vfs::print_dir_entry(m.writer(), e);
+            if rpc {
+                entries.push(json!({
+                    "name": e.name,
+                    "dir": e.metadata.is_dir,
+                    "size": e.metadata.size,
+                    "modified": e.modified.as_ref().map(iso_8601),
+                }));
             }
-        }
+        })?;
+        m.put_data("entries", || Value::Array(entries));
+        Ok(())
     },
 };
 
 pub const MV: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "mv",
-    help: "mv <source> <destination> - Move a file or directory from source to destination",
-    description: "(TODO) Move a file or directory",
-    action: |m, _| {
-        writeln!(m.writer(), "todo")?;
+    help: "mv <source> <destination> - Move a file from source to destination",
+    description: "Move a file",
+    action: |m, args| {
+        check_args_len(2, args.len())?;
+        let (from, to) = (m.resolve(args[0])?, m.resolve(args[1])?);
+        let mut to_buf = heapless::String::new();
+        let to = destination(from.as_path(), to.as_path(), &mut to_buf)?;
+        if let Err(e) = vfs::rename(from.as_path(), to) {
+            writeln!(m.writer(), "Error: {e}")?;
+        }
         Ok(())
     },
 };
@@ -75,29 +65,62 @@ pub const MV: MenuItem<'static, TerminalWriter> = MenuItem::Command {
 pub const RM: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "rm",
     help: "rm <file> - Remove a file from a filesystem",
-    description: "(TODO) Remove a file from a filesystem",
-    action: |m, _| {
-        writeln!(m.writer(), "todo")?;
+    description: "Remove a file from a filesystem",
+    action: |m, args| {
+        check_args_len(1, args.len())?;
+        if let Err(e) = vfs::remove(m.resolve(args[0])?.as_path()) {
+            writeln!(m.writer(), "Error: {e}")?;
+        }
         Ok(())
     },
 };
 
 pub const CP: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "cp",
-    help: "cp <source> <destination> - Copy a file or directory from source to destination",
-    description: "(TODO) Copy a file or directory",
-    action: |m, _| {
-        writeln!(m.writer(), "todo")?;
+    help: "cp <source> <destination> - Copy a file from source to destination",
+    description: "Copy a file",
+    action: |m, args| {
+        check_args_len(2, args.len())?;
+        let (from, to) = (m.resolve(args[0])?, m.resolve(args[1])?);
+        let mut to_buf = heapless::String::new();
+        let to = destination(from.as_path(), to.as_path(), &mut to_buf)?;
+        let mut buf = [0u8; COPY_CHUNK];
+        match vfs::copy(from.as_path(), to, &mut buf) {
+            Ok(n) => writeln!(m.writer(), "Copied {n} bytes to {to}")?,
+            Err(e) => writeln!(m.writer(), "Error: {e}")?,
+        }
         Ok(())
     },
 };
 
 pub const CAT: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "cat",
-    help: "cat - Read and print a file to stdout",
-    description: "(TODO) Read and print a file to stdout",
-    action: |m, _| {
-        writeln!(m.writer(), "todo")?;
+    help: "cat <file> - Read and print a file to stdout",
+    description: "Read and print a file to stdout",
+    action: |m, args| {
+        check_args_len(1, args.len())?;
+        let path = m.resolve(args[0])?;
+        // Read in chunks so that files larger than RAM work, the file stays open in between
+        let mut buf = [0u8; 512];
+        // The chunk after the bytes of a utf-8 sequence split off the last one
+        let mut text = [0u8; 512 + 3];
+        let mut incomplete = 0;
+        let mut res = Ok(());
+        let read = vfs::read_chunks(path.as_path(), &mut buf, |chunk| {
+            let len = incomplete + chunk.len();
+            text[incomplete..len].copy_from_slice(chunk);
+            res = write_utf8_lossy(m.writer(), &text[..len]).map(|n| incomplete = n);
+            text.copy_within(len - incomplete..len, 0);
+            res.is_ok()
+        });
+        res?;
+        if let Err(e) = read {
+            writeln!(m.writer(), "Error: {e}")?;
+            return Ok(());
+        }
+        if incomplete > 0 {
+            write!(m.writer(), "\u{fffd}")?;
+        }
         Ok(())
     },
 };
@@ -107,24 +130,58 @@ pub const NOR: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     help: "nor <(i|info)|(m|mount)|(u|unmount)|(f|format)> - Info/Mount/Unmount/Format NOR-Flash filesystem",
     description: "Info/Mount/Unmount/Format NOR-Flash filesystem",
     action: |m, args| {
-        // writeln!(m.writer(), "todo")?;
-
         match args {
+            ["i" | "info"] => {
+                let (mounted, info) = with_nor_fs(|nor| Ok((nor.is_mounted(), nor.info())))
+                    .map_err(|_| MenuError::CommandError(None))?;
+                writeln!(m.writer(), "{:LABEL_WIDTH$} {}", "NOR-Flash mounted", mounted)?;
+                if let Ok(info) = &info {
+                    m.put_data("nor", || json!({
+                        "mounted": mounted,
+                        "size": info.capacity,
+                        "block_size": info.block_size,
+                        "available": info.available,
+                    }));
+                } else {
+                    m.put_data("nor", || json!({ "mounted": mounted }));
+                }
+                match info {
+                    Ok(info) => {
+                        writeln!(m.writer(), "{:LABEL_WIDTH$} {}MiB", "Size", info.capacity / (1024 * 1024))?;
+                        writeln!(m.writer(), "{:LABEL_WIDTH$} {} bytes", "Block size", info.block_size)?;
+                        writeln!(m.writer(), "{:LABEL_WIDTH$} {}KiB", "Available", info.available / 1024)?;
+                    }
+                    Err(NorFsError::NotMounted) => {}
+                    Err(e) => writeln!(m.writer(), "Error: {e}")?,
+                }
+            }
+            ["m" | "mount"] => match with_nor_fs(|nor| nor.mount()) {
+                Ok(_) => writeln!(m.writer(), "NOR-Flash mounted")?,
+                Err(e) => writeln!(m.writer(), "{e}")?,
+            },
+            ["u" | "unmount"] => match with_nor_fs(|nor| nor.unmount()) {
+                Ok(_) => writeln!(m.writer(), "NOR-Flash unmounted")?,
+                Err(e) => writeln!(m.writer(), "{e}")?,
+            },
+            ["f" | "format"] => match with_nor_fs(|nor| nor.format()) {
+                Ok(_) => writeln!(m.writer(), "NOR-Flash formatted, mount it with 'nor mount'")?,
+                Err(e) => writeln!(m.writer(), "{e}")?,
+            },
             ["dev", "ce"] => {
                 let result = interrupt_free(|cs| {
-                    QSPI_STORE.borrow(cs).borrow_mut().as_deref_mut().unwrap().chip_erase()
+                    QSPI_STORE.borrow(cs).borrow_mut().as_mut().unwrap().flash().chip_erase()
                 });
                 writeln!(m.writer(), "{result:?}")?;
             }
             ["dev", "reset"] => {
                 let result = interrupt_free(|cs| {
-                    QSPI_STORE.borrow(cs).borrow_mut().as_deref_mut().unwrap().reset()
+                    QSPI_STORE.borrow(cs).borrow_mut().as_mut().unwrap().flash().reset()
                 });
                 writeln!(m.writer(), "{result:?}")?;
             }
             ["dev", "id"] => {
                 let result = interrupt_free(|cs| {
-                    QSPI_STORE.borrow(cs).borrow_mut().as_deref_mut().unwrap().reaq_identification()
+                    QSPI_STORE.borrow(cs).borrow_mut().as_mut().unwrap().flash().reaq_identification()
                 });
                 match result {
                     Ok([mfn_id, mem_type, mem_density]) => {
@@ -137,7 +194,7 @@ pub const NOR: MenuItem<'static, TerminalWriter> = MenuItem::Command {
             },
             ["dev", "config"] => {
                 let result = interrupt_free(|cs| {
-                    QSPI_STORE.borrow(cs).borrow_mut().as_deref_mut().unwrap().read_config()
+                    QSPI_STORE.borrow(cs).borrow_mut().as_mut().unwrap().flash().read_config()
                 });
                 match result {
                     Ok(config) => {
@@ -157,10 +214,23 @@ pub const NOR: MenuItem<'static, TerminalWriter> = MenuItem::Command {
             },
             ["dev", "status"] => {
                 let result = interrupt_free(|cs| {
-                    QSPI_STORE.borrow(cs).borrow_mut().as_deref_mut().unwrap().read_status()
+                    QSPI_STORE.borrow(cs).borrow_mut().as_mut().unwrap().flash().read_status()
                 });
                 match result {
                     Ok(status) => {
+                        m.put_data("status", || {
+                            json!({
+                                "raw": status,
+                                "srwd": status & mx25l_status::SRWD != 0,
+                                "qe": status & mx25l_status::QE != 0,
+                                "bp3": status & mx25l_status::BP3 != 0,
+                                "bp2": status & mx25l_status::BP2 != 0,
+                                "bp1": status & mx25l_status::BP1 != 0,
+                                "bp0": status & mx25l_status::BP0 != 0,
+                                "wel": status & mx25l_status::WEL != 0,
+                                "wip": status & mx25l_status::WIP != 0,
+                            })
+                        });
                         writeln!(m.writer(), "Status: {status:08b}")?;
                         writeln!(
                             m.writer(),
@@ -188,7 +258,7 @@ pub const NOR: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                 for i in 0..length {
                     let mut data = [0u8; 1];
                     let result = interrupt_free(|cs| {
-                        QSPI_STORE.borrow(cs).borrow_mut().as_deref_mut().unwrap().read(address + i, &mut data)
+                        QSPI_STORE.borrow(cs).borrow_mut().as_mut().unwrap().flash().read(address + i, &mut data)
                     });
                     if (address + i) % OUTPUT_WIDTH == 0 && i != 0 {
                         writeln!(m.writer())?;
@@ -246,7 +316,7 @@ pub const NOR: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                     let byte = from_hex(*upper, *lower).unwrap();
                     write!(m.writer(), "0x{byte:02x} ")?;
                     let result = interrupt_free(|cs| {
-                        QSPI_STORE.borrow(cs).borrow_mut().as_deref_mut().unwrap().write(address + offset as u32, &[byte])
+                        QSPI_STORE.borrow(cs).borrow_mut().as_mut().unwrap().flash().write(address + offset as u32, &[byte])
                     });
 
                     if let Err(e) = result {
@@ -257,6 +327,12 @@ pub const NOR: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                 writeln!(m.writer())?;
             },
             _ => {
+                writeln!(m.writer(), "Expected:")?;
+                writeln!(m.writer(), "\ti | info - NOR-Flash filesystem info")?;
+                writeln!(m.writer(), "\tm | mount - Mount NOR-Flash filesystem")?;
+                writeln!(m.writer(), "\tu | unmount - Unmount NOR-Flash filesystem")?;
+                writeln!(m.writer(), "\tf | format - Create an empty filesystem, erases all files")?;
+                writeln!(m.writer(), "\tdev <ce|reset|id|config|status|read <addr> <len>|write <addr> <hex>> - Raw flash access")?;
                 return Err(MenuError::InvalidArgument)
             }
         }
@@ -279,6 +355,11 @@ pub const SDCARD: MenuItem<'static, TerminalWriter> = MenuItem::Command {
         }) {
             Some((mounted, size)) => {
                 writeln!(m.writer(), "{:LABEL_WIDTH$} {}", "SD Card mounted", mounted)?;
+                let size_data = size.as_ref().ok().copied();
+                m.put_data(
+                    "sdcard",
+                    || json!({ "mounted": mounted, "size": size_data }),
+                );
                 match size {
                     Ok(bytes) => {
                         writeln!(
@@ -310,7 +391,7 @@ pub const SDCARD: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                 .borrow(cs)
                 .borrow_mut()
                 .as_mut()
-                .map(|sdfs| sdfs.mount::<hal::delay::Delay, _>(freq.kHz(), 10, None))
+                .map(|sdfs| sdfs.mount::<hal::delay::Delay>(freq.kHz().into(), 10, None))
             {
                 Some(Ok(_)) => {
                     writeln!(m.writer(), "SD Card mounted")?;
@@ -366,3 +447,22 @@ pub const CURL: MenuItem<'static, TerminalWriter> = MenuItem::Command {
         Ok(())
     },
 };
+
+const COPY_CHUNK: usize = 4096;
+
+/// `to`, or `to/<file name of from>` if `to` is a directory
+fn destination<'b>(
+    from: Path,
+    to: Path<'b>,
+    buf: &'b mut heapless::String<PATH_LEN>,
+) -> Result<Path<'b>, MenuError> {
+    let is_dir = vfs::stat(to).is_ok_and(|m| m.is_dir);
+    match (is_dir, from.parts().last()) {
+        (true, Some(name)) => {
+            write!(buf, "{to}/{name}")
+                .map_err(|_| MenuError::CommandError(Some("Path too long")))?;
+            Ok(Path::new(buf.as_str()))
+        }
+        _ => Ok(to),
+    }
+}
@@ -1,5 +1,6 @@
 pub mod io;
 pub mod program;
+pub mod shell;
 pub mod sys;
 pub mod time;
 
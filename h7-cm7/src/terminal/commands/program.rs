@@ -1,63 +1,48 @@
 use {
     super::utils::*,
     crate::{
-        app,
-        fs::path::Path,
+        app::{self, AppLoad, LoadError, LoadedApp, Region, SigPolicy},
+        consts, display,
+        fs::{
+            path::{Path, PathBuf, PATH_LEN},
+            vfs::{self, OpenMode, VfsError},
+        },
         led::Led,
         terminal::{
-            menu::{MenuError, MenuItem},
-            TerminalWriter, TERMINAL_INPUT_FIFO,
+            commands::LABEL_WIDTH,
+            menu::{Menu, MenuError, MenuItem, MenuResult},
+            TerminalWriter, UartLink,
         },
-        utils::interrupt_free,
     },
     core::fmt::Write,
+    h7_appfmt::Fingerprint,
+    h7_xfer::{Packet, XferError},
+    serde_json::json,
 };
 
 pub const PLOAD: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "pload",
-    help: "pload <dev:/path/to/bin.h7> - Load a program into ram",
+    help: "pload <path/to/bin.h7> [axisram|sram|sdram] - Load a program into ram, relocatable programs anywhere",
     description: "Load a program into ram",
     action: |m, args| {
-        check_args_len(1, args.len())?;
-        let app_slice = app::app_slice();
-        app_slice.fill(0); // .bss
-        let path = Path::new(args[0]);
-        match path.device() {
-            Some("sdcard") => interrupt_free(|cs| {
-                match crate::fs::sdmmc_fs::SD_CARD
-                    .borrow(cs)
-                    .borrow_mut()
-                    .as_mut()
-                    .map(|sdfs| sdfs.read_file(path, app_slice))
-                {
-                    Some(Ok(len)) => {
-                        writeln!(m.writer(), "Program '{}' loaded ({} bytes)", args[0], len)?;
-                        app::print_info(m.writer(), &app_slice[..len])?;
-                        Ok(())
-                    }
-                    Some(Err(e)) => {
-                        writeln!(m.writer(), "Error: {e}")?;
-                        Ok(())
-                    }
-                    None => {
-                        writeln!(m.writer(), "Error: SD Card controller not initialized")?;
-                        Ok(())
-                    }
-                }
-            }),
-            Some("nor") => {
-                writeln!(m.writer(), "Not implemented")?;
-                Ok(())
-            }
-            Some(device) => {
-                writeln!(m.writer(), "Unknown device '{device}'")?;
-                Ok(())
-            }
-            None => {
-                writeln!(m.writer(), "No device selected")?;
-                Ok(())
+        let region = match args {
+            [_] => Region::AxiSram,
+            [_, region] => region
+                .parse::<Region>()
+                .map_err(|_| MenuError::InvalidArgument)?,
+            _ => return check_args_len(1, args.len()),
+        };
+        match app::load_file(m.resolve(args[0])?.as_path(), region) {
+            Ok((loaded, len)) => {
+                writeln!(m.writer(), "Program '{}' loaded ({} bytes)", args[0], len)?;
+                m.put_data("address", || json!(loaded.address));
+                m.put_data("signed", || json!(loaded.signature.is_ok()));
+                app::print_info(m.writer(), &loaded)?;
+                warn_unsigned(m, &loaded)?;
             }
+            Err(e) => writeln!(m.writer(), "Error: {e}")?,
         }
+        Ok(())
     },
 };
 
@@ -67,9 +52,15 @@ pub const PRUN: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     description: "Run program loaded in ram",
     action: |m, args| {
         check_args_len(0, args.len())?;
-        let app_slice = app::app_slice();
-        let app_fn = app::get_address(app_slice);
-        if app::check_address(app_fn).is_err() {
+        let loaded = app::loaded().ok_or(MenuError::CommandError(Some("No program loaded")))?;
+        // The policy may have changed since the app was loaded
+        if let Err(e) = loaded.check_signature() {
+            writeln!(m.writer(), "Error: {e}")?;
+            return Err(MenuError::CommandError(Some("Untrusted app")));
+        }
+        warn_unsigned(m, &loaded)?;
+        let app_fn = app::entry_point(&loaded);
+        if app::check_address(&loaded, app_fn).is_err() {
             return Err(MenuError::CommandError(Some("Invalid app address")));
         }
         writeln!(m.writer(), "Executing from {app_fn:p}")?;
@@ -101,12 +92,21 @@ pub const PRUN: MenuItem<'static, TerminalWriter> = MenuItem::Command {
 
             ret
         };
+        display::release_from_app();
         writeln!(
             m.writer(),
             "Exit: {} ({})",
             ret,
-            if ret == 0 { "ok" } else { "error" }
+            match ret {
+                0 => "ok",
+                h7_api::EXIT_INCOMPATIBLE_API => "incompatible api",
+                _ => "error",
+            }
         )?;
+        match app::close_leaked() {
+            0 => { /* App closed its files */ }
+            n => writeln!(m.writer(), "App left {n} files open")?,
+        }
         match app::free_leaked() {
             0 => { /* App did not leak memory */ }
             n => writeln!(m.writer(), "App leaked {n} bytes")?,
@@ -116,94 +116,137 @@ pub const PRUN: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     },
 };
 
-pub const UPLOAD: MenuItem<'static, TerminalWriter> = MenuItem::Command {
-    name: "upload",
-    help: "upload <hex> - Load program into RAM via serial. Data is sent in ascii hex.",
-    description: "Load program into RAM via serial. Data is sent in ascii hex.",
-    action: |m, args| {
-        let mut n = 0usize;
-        let app_slice = app::app_slice();
-        app_slice.fill(0);
-        match args {
-            [bin] => match bin.as_bytes().chunks(2).try_for_each(|s| match s.len() {
-                1 => Err((s[0], None)),
-                2 => {
-                    let b = from_hex(s[0], s[1]).ok_or((s[0], Some(s[1])))?;
-                    app_slice[n] = b;
-                    n += 1;
-                    Ok(())
-                }
-                _ => unreachable!(),
-            }) {
-                Ok(_) => {}
-                Err((a, None)) => {
-                    writeln!(
-                        m.writer(),
-                        "Invalid byte: '0x{} None' (half a byte missing)",
-                        a as char
-                    )?;
-                    return Err(MenuError::InvalidArgument);
-                }
-                Err((a, Some(b))) => {
-                    writeln!(
-                        m.writer(),
-                        "Invalid byte: '0x{} 0x{}'",
-                        a as char,
-                        b as char
-                    )?;
-                    return Err(MenuError::InvalidArgument);
-                }
-            },
-            _ => {
-                writeln!(m.writer(), "Waiting for data...")?;
-                let mut byte = None::<u8>;
-                loop {
-                    match (
-                        byte,
-                        //  interrupt_free(|cs| TERMINAL_INPUT_FIFO.borrow(cs).borrow_mut().pop()),
-                        TERMINAL_INPUT_FIFO.dequeue(),
-                    ) {
-                        (b, Some(b'\n')) => {
-                            if let Some(b) = b {
-                                writeln!(
-                                    m.writer(),
-                                    "Invalid byte: '0x{} None' (half a byte missing)",
-                                    b as char
-                                )?;
-                                return Err(MenuError::InvalidArgument);
-                            } else {
-                                break;
-                            }
-                        }
-                        (Some(x), Some(y)) => match from_hex(x, y) {
-                            Some(b) => {
-                                app_slice[n] = b;
-                                n += 1;
-                                byte = None;
-                            }
-                            None => {
-                                writeln!(
-                                    m.writer(),
-                                    "Invalid byte: '0x{} 0x{}'",
-                                    x as char,
-                                    y as char
-                                )?;
-                                return Err(MenuError::InvalidArgument);
-                            }
-                        },
-                        (None, n) => byte = n,
-                        _ => {}
-                    }
-                }
+pub const APPSIG: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "appsig",
+    help: "appsig [warn|enforce] - Show trusted keys or make the policy for apps not signed by one stricter",
+    description: "App signature policy",
+    action: |m, args| match args {
+        [] => {
+            writeln!(m.writer(), "{:LABEL_WIDTH$} {}", "policy", app::sig_policy().name())?;
+            m.put_data("policy", || json!(app::sig_policy().name()));
+            for key in consts::TRUSTED_KEYS {
+                writeln!(m.writer(), "{:LABEL_WIDTH$} {}", "trusted key", Fingerprint(key))?;
+            }
+            if consts::TRUSTED_KEYS.is_empty() {
+                writeln!(m.writer(), "No trusted keys, add them to h7-cm7/keys")?;
             }
+            Ok(())
         }
-        writeln!(m.writer(), "Read {n} bytes")?;
-        if n > 8 {
-            app::print_info(m.writer(), &app_slice[..n])?;
-        } else {
-            writeln!(m.writer(), "Not enough data")?;
+        [policy] => {
+            let policy = policy
+                .parse::<SigPolicy>()
+                .map_err(|_| MenuError::InvalidArgument)?;
+            app::set_sig_policy(policy).map_err(|_| {
+                MenuError::CommandError(Some("The policy can only be made stricter"))
+            })
         }
+        _ => check_args_len(1, args.len()),
+    },
+};
 
-        Ok(())
+/// Warn about an app that runs without a trusted signature
+fn warn_unsigned(m: &mut Menu<TerminalWriter>, loaded: &LoadedApp) -> MenuResult {
+    if let Ok(Some(e)) = loaded.check_signature() {
+        writeln!(m.writer(), "Warning: {e}")?;
+    }
+    Ok(())
+}
+
+pub const UPLOAD: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "upload",
+    help: "upload [destination] - Receive a file from `h7-uart-terminal send`, a program into RAM without a destination",
+    description: "Receive a program or file over serial",
+    action: |m, args| match args {
+        [] => upload_app(m),
+        [to] => upload_file(m, to),
+        _ => Err(MenuError::TooManyArgs(1, args.len() as u8)),
     },
 };
+
+/// Receive a program into RAM and load it as it arrives
+fn upload_app(m: &mut Menu<TerminalWriter>) -> MenuResult {
+    writeln!(m.writer(), "Waiting for data...")?;
+    let mut app = AppLoad::new(Region::AxiSram);
+    let mut error = None::<LoadError>;
+    let res = h7_xfer::receive(&mut UartLink, |packet| match packet {
+        Packet::Data(data) => app.feed(data).map_err(|e| {
+            error = Some(e);
+            XferError::Rejected
+        }),
+        _ => Ok(()),
+    });
+    let n = match (res, error) {
+        (_, Some(e)) => {
+            writeln!(m.writer(), "Error: {e}")?;
+            return Ok(());
+        }
+        (res, None) => res?,
+    };
+    writeln!(m.writer(), "Read {n} bytes")?;
+    m.put_data("size", || json!(n));
+    match app.finish() {
+        Ok(loaded) => {
+            app::print_info(m.writer(), &loaded)?;
+            warn_unsigned(m, &loaded)?;
+        }
+        Err(e) => writeln!(m.writer(), "Error: {e}")?,
+    }
+    Ok(())
+}
+
+/// Receive a file to `to`, or into `to` if it's a directory
+fn upload_file(m: &mut Menu<TerminalWriter>, to: &str) -> MenuResult {
+    let to = m.resolve(to)?;
+    let is_dir = vfs::stat(to.as_path()).is_ok_and(|m| m.is_dir);
+    writeln!(m.writer(), "Waiting for data...")?;
+    let mut target = None::<(PathBuf, vfs::File)>;
+    let mut error = None::<VfsError>;
+    let res = h7_xfer::receive(&mut UartLink, |packet| {
+        let res = match packet {
+            Packet::Start { name, .. } => {
+                let path = match is_dir {
+                    true => to
+                        .as_path()
+                        .join(Path::new(name))
+                        .map_err(|_| VfsError::PathTooLong(PATH_LEN)),
+                    false => Ok(to.clone()),
+                };
+                path.and_then(|path| {
+                    let file = vfs::File::open(path.as_path(), OpenMode::Write)?;
+                    target = Some((path, file));
+                    Ok(())
+                })
+            }
+            Packet::Data(data) => match target.as_mut() {
+                Some((_, file)) => file.write(data).map(|_| ()),
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        };
+        res.map_err(|e| {
+            error = Some(e);
+            XferError::Rejected
+        })
+    });
+    match (res, target) {
+        (Ok(n), Some((path, file))) => {
+            file.close()?;
+            writeln!(m.writer(), "Received {n} bytes to {path}")?;
+            m.put_data("path", || json!(path.as_str()));
+            m.put_data("size", || json!(n));
+            Ok(())
+        }
+        (res, target) => {
+            // Don't leave half a file behind
+            if let Some((path, file)) = target {
+                drop(file);
+                let _ = vfs::remove(path.as_path());
+            }
+            match (res, error) {
+                (_, Some(e)) => Err(e.into()),
+                (Err(e), None) => Err(e.into()),
+                (Ok(_), None) => Ok(()),
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use {
+    super::utils::check_args_len,
+    crate::{
+        fs::vfs,
+        terminal::{
+            menu::{MenuError, MenuItem, HOME},
+            rpc, TerminalWriter,
+        },
+    },
+    core::fmt::Write,
+    h7_shell::env::{NAME_LEN, VALUE_LEN},
+};
+
+pub const SET: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "set",
+    help: "set <name> <value> - Set a shell variable, use it as $name or ${name}",
+    description: "Set a shell variable",
+    action: |m, args| match args {
+        [name, value] => Ok(m.env().set(name, value)?),
+        _ => Err(MenuError::InvalidArgument),
+    },
+};
+
+pub const UNSET: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "unset",
+    help: "unset <name> - Remove a shell variable",
+    description: "Remove a shell variable",
+    action: |m, args| {
+        check_args_len(1, args.len())?;
+        if !m.env().unset(args[0]) {
+            writeln!(m.writer(), "'{}' is not set", args[0])?;
+        }
+        Ok(())
+    },
+};
+
+pub const ENV: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "env",
+    help: "env - List shell variables",
+    description: "List shell variables",
+    action: |m, args| {
+        check_args_len(0, args.len())?;
+        // The env can't be borrowed while writing, copy one variable at a time
+        for i in 0.. {
+            let mut line = heapless::String::<{ NAME_LEN + VALUE_LEN + 1 }>::new();
+            match m.env().iter().nth(i) {
+                Some((name, value)) => write!(line, "{name}={value}")?,
+                None => break,
+            }
+            writeln!(m.writer(), "{line}")?;
+        }
+        Ok(())
+    },
+};
+
+pub const ECHO: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "echo",
+    help: "echo [args...] - Print arguments",
+    description: "Print arguments",
+    action: |m, args| {
+        for (i, arg) in args.iter().enumerate() {
+            let sep = if i + 1 < args.len() { " " } else { "" };
+            write!(m.writer(), "{arg}{sep}")?;
+        }
+        writeln!(m.writer())?;
+        Ok(())
+    },
+};
+
+pub const CD: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "cd",
+    help: "cd [dir] - Change the current directory, to sdcard:/ without arguments",
+    description: "Change the current directory",
+    action: |m, args| {
+        if args.len() > 1 {
+            return Err(MenuError::TooManyArgs(1, args.len() as u8));
+        }
+        let dir = m.resolve(args.first().unwrap_or(&HOME))?;
+        match vfs::stat(dir.as_path()) {
+            Ok(metadata) if metadata.is_dir => m.set_cwd(dir),
+            Ok(_) => writeln!(m.writer(), "Error: '{dir}' is not a directory")?,
+            Err(e) => writeln!(m.writer(), "Error: {e}")?,
+        }
+        Ok(())
+    },
+};
+
+pub const PWD: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "pwd",
+    help: "pwd - Print the current directory",
+    description: "Print the current directory",
+    action: |m, args| {
+        check_args_len(0, args.len())?;
+        let cwd = m.cwd().clone();
+        writeln!(m.writer(), "{cwd}")?;
+        Ok(())
+    },
+};
+
+pub const RPC: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "rpc",
+    help: "rpc - Switch the terminal to JSON requests and responses for h7-ctl, until an `exit` request",
+    description: "Machine-readable mode for scripts",
+    action: |m, args| {
+        check_args_len(0, args.len())?;
+        rpc::serve(m)
+    },
+};
@@ -8,12 +8,14 @@ use {
         logger,
         terminal::{
             menu::{MenuError, MenuItem},
+            sink::{self, Sink},
             TerminalWriter, MENU,
         },
         utils::interrupt_free,
     },
     chrono::{Datelike, NaiveDate, Timelike},
     core::{fmt::Write, str::FromStr},
+    serde_json::json,
     stm32h7xx_hal as hal,
 };
 
@@ -161,11 +163,17 @@ pub const INFO: MenuItem<'static, TerminalWriter> = MenuItem::Command {
             // SAFETY: to_hex always returns valid hex
             let id_str = unsafe { core::str::from_utf8_unchecked(&id[0..id_len]) };
             writeln!(m.writer(), "{:LABEL_WIDTH$} {}", "Unique ID", id_str)?;
+            m.put_data("mcu", || json!({ "name": "STM32H747", "uid": id_str }));
             Ok(())
         }
         ["cpu"] => {
             writeln!(m.writer(), "{:LABEL_WIDTH$} Cortex-M7F", "Core")?;
-            match interrupt_free(crate::system::cpu_freq) {
+            let freq = interrupt_free(crate::system::cpu_freq);
+            let temp = interrupt_free(crate::system::cpu_temp);
+            let cycle_count = cortex_m::peripheral::DWT::cycle_count();
+            let icache = cortex_m::peripheral::SCB::icache_enabled();
+            let dcache = cortex_m::peripheral::SCB::dcache_enabled();
+            match freq {
                 Some(freq) => writeln!(
                     m.writer(),
                     "{:LABEL_WIDTH$} {}MHz",
@@ -174,7 +182,7 @@ pub const INFO: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                 )?,
                 None => writeln!(m.writer(), "{:LABEL_WIDTH$} unavailable", "Core frequency")?,
             }
-            match interrupt_free(crate::system::cpu_temp) {
+            match temp {
                 Some(temp) => writeln!(
                     m.writer(),
                     "{:LABEL_WIDTH$} {:.01}°C",
@@ -187,24 +195,29 @@ pub const INFO: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                     "Core temperature"
                 )?,
             }
-            writeln!(
-                m.writer(),
-                "{:LABEL_WIDTH$} {}",
-                "Cycle count",
-                cortex_m::peripheral::DWT::cycle_count()
-            )?;
+            writeln!(m.writer(), "{:LABEL_WIDTH$} {}", "Cycle count", cycle_count)?;
             writeln!(
                 m.writer(),
                 "{:LABEL_WIDTH$} {}",
                 "Instruction cache",
-                bool_to_enabled_disabled_str(cortex_m::peripheral::SCB::icache_enabled())
+                bool_to_enabled_disabled_str(icache)
             )?;
             writeln!(
                 m.writer(),
                 "{:LABEL_WIDTH$} {}",
                 "Data cache",
-                bool_to_enabled_disabled_str(cortex_m::peripheral::SCB::dcache_enabled())
+                bool_to_enabled_disabled_str(dcache)
             )?;
+            m.put_data("cpu", || {
+                json!({
+                    "core": "Cortex-M7F",
+                    "freq": freq.map(|f| f.raw()),
+                    "temp": temp,
+                    "cycle_count": cycle_count,
+                    "icache": icache,
+                    "dcache": dcache,
+                })
+            });
             Ok(())
         }
         ["ram"] => {
@@ -220,6 +233,12 @@ pub const INFO: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                 "External SDRAM",
                 crate::mem::sdram::SDRAM_SIZE / (1024 * 1024)
             )?;
+            m.put_data("ram", || {
+                json!({
+                    "internal": crate::system::ram_size(),
+                    "sdram": crate::mem::sdram::SDRAM_SIZE,
+                })
+            });
             Ok(())
         }
         ["flash"] => {
@@ -235,6 +254,12 @@ pub const INFO: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                 "External FLASH",
                 crate::fs::qspi_store::QSPI_FLASH_SIZE / (1024 * 1024)
             )?;
+            m.put_data("flash", || {
+                json!({
+                    "internal": crate::system::flash_size(),
+                    "external": crate::fs::qspi_store::QSPI_FLASH_SIZE,
+                })
+            });
             Ok(())
         }
         ["os"] => {
@@ -297,7 +322,9 @@ pub const INFO: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                 year = dt.year()
             )?;
 
-            match interrupt_free(|cs| *crate::time::BOOT_TIME.borrow(cs).borrow()) {
+            let compiled = dt;
+            let boot_time = interrupt_free(|cs| *crate::time::BOOT_TIME.borrow(cs).borrow());
+            match boot_time {
                 Some(dt) => {
                     writeln!(
                         m.writer(),
@@ -316,6 +343,18 @@ pub const INFO: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                     writeln!(m.writer(), "{:LABEL_WIDTH$} unavailable", "Boot time")?;
                 }
             }
+            m.put_data("os", || {
+                json!({
+                    "heap_used": crate::mem::ALLOCATOR.used(),
+                    "heap_size": crate::mem::HEAP_SIZE,
+                    "gpu_reserved": crate::display::FRAME_BUFFER_ALLOC_SIZE,
+                    "rustc": consts::RUSTC_VERSION,
+                    "version": consts::GIT_DESCRIBE,
+                    "debug": cfg!(debug_assertions),
+                    "compiled": iso_8601(&compiled),
+                    "boot_time": boot_time.as_ref().map(iso_8601),
+                })
+            });
 
             Ok(())
         }
@@ -388,6 +427,7 @@ pub const UPTIME: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                 write!(m.writer(), "Uptime: ")?;
                 crate::utils::write_pretty_duration(m.writer(), dur)?;
                 writeln!(m.writer())?;
+                m.put_data("uptime", || json!(dur.num_seconds()));
             }
             (Some(_), _) => {
                 writeln!(m.writer(), "Uptime: unavailable <boot time unavailable>")?;
@@ -427,6 +467,43 @@ pub const LEDCTL: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     },
 };
 
+pub const TERMCTL: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "termctl",
+    help: "termctl [<uart|display> <0|1>] - Enable/disable terminal outputs",
+    description: "Enable/disable terminal outputs",
+    action: |m, args| match args {
+        [] => {
+            for sink in Sink::ALL {
+                let state = if sink::is_enabled(sink) {
+                    "enabled"
+                } else {
+                    "disabled"
+                };
+                writeln!(m.writer(), "{:LABEL_WIDTH$} {state}", sink.name())?;
+            }
+            Ok(())
+        }
+        [name, state @ ("0" | "1")] => {
+            let sink = name
+                .parse::<Sink>()
+                .map_err(|_| MenuError::InvalidArgument)?;
+            let enable = *state == "1";
+            let others_enabled = Sink::ALL
+                .into_iter()
+                .any(|s| s != sink && sink::is_enabled(s));
+            if !enable && !others_enabled {
+                return Err(MenuError::CommandError(Some(
+                    "Can't disable the last terminal output",
+                )));
+            }
+            sink::set_enabled(sink, enable);
+            Ok(())
+        }
+        [_, _] => Err(MenuError::InvalidArgument),
+        _ => check_args_len(2, args.len()),
+    },
+};
+
 pub const CORECTL: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "corectl",
     help: "corectl - Start/stop the Cortex-M4 core",
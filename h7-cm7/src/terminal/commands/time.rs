@@ -72,17 +72,20 @@ pub const DATE: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                 writeln!(m.writer(), "Set time: date set {TIME_PARSE_FORMAT}")
             }
             [] => match TimeSource::get_date_time() {
-                Some(dt) => writeln!(
-                    m.writer(),
-                    "{weekday} {month} {day} {hh:02}:{mm:02}:{ss:02} {year}",
-                    weekday = dt.weekday(),
-                    month = month_to_str(dt.month()),
-                    day = dt.day(),
-                    hh = dt.hour(),
-                    mm = dt.minute(),
-                    ss = dt.second(),
-                    year = dt.year()
-                ),
+                Some(dt) => {
+                    m.put_data("date", || iso_8601(&dt));
+                    writeln!(
+                        m.writer(),
+                        "{weekday} {month} {day} {hh:02}:{mm:02}:{ss:02} {year}",
+                        weekday = dt.weekday(),
+                        month = month_to_str(dt.month()),
+                        day = dt.day(),
+                        hh = dt.hour(),
+                        mm = dt.minute(),
+                        ss = dt.second(),
+                        year = dt.year()
+                    )
+                }
                 None => writeln!(m.writer(), "Error: RTC not initialized"),
             },
             _ => writeln!(m.writer(), "Invalid usage"),
@@ -1,6 +1,7 @@
 use {
     crate::terminal::menu::{Menu, MenuError, MenuItem, MenuResult},
-    chrono::{Datelike, NaiveDate},
+    chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike},
+    h7_rpc::Value,
 };
 
 pub struct PaddedStr<'s, const PADDING: u8>(pub &'s str, pub usize);
@@ -109,6 +110,19 @@ pub fn bool_to_enabled_disabled_str(b: bool) -> &'static str {
     }
 }
 
+/// `dt` as `2023-01-31T12:00:00`, for RPC results
+pub fn iso_8601(dt: &NaiveDateTime) -> Value {
+    Value::String(alloc::format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        dt.year(),
+        dt.month(),
+        dt.day(),
+        dt.hour(),
+        dt.minute(),
+        dt.second()
+    ))
+}
+
 pub const fn nibble_to_char(nibble: u8, lowercase: bool) -> Option<u8> {
     match nibble & 0x0F {
         0..=9 => Some(nibble + 48),
@@ -159,3 +173,28 @@ pub const fn check_args_len(expected: u8, actual: usize) -> MenuResult {
         Ok(())
     }
 }
+
+/// Write `data` as text, invalid utf-8 is replaced with U+FFFD. Returns the length of an
+/// incomplete sequence at the end, which should be passed again with the next chunk.
+pub fn write_utf8_lossy<W: core::fmt::Write>(
+    w: &mut W,
+    mut data: &[u8],
+) -> Result<usize, core::fmt::Error> {
+    loop {
+        match core::str::from_utf8(data) {
+            Ok(s) => return w.write_str(s).map(|_| 0),
+            Err(e) => {
+                let (valid, rest) = data.split_at(e.valid_up_to());
+                // SAFETY: Checked by from_utf8
+                w.write_str(unsafe { core::str::from_utf8_unchecked(valid) })?;
+                match e.error_len() {
+                    Some(len) => {
+                        w.write_char('\u{fffd}')?;
+                        data = &rest[len..];
+                    }
+                    None => return Ok(rest.len()),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use {
+    super::{menu::MenuItem, TerminalWriter, MENU},
+    crate::fs::{path::Path, vfs},
+    core::fmt::Write,
+    h7_shell::Completer,
+};
+
+/// Tab completion for the shell, command names, devices and paths on them
+pub struct ShellCompleter;
+
+impl Completer for ShellCompleter {
+    fn complete(&mut self, before: &str, word: &str, candidate: &mut dyn FnMut(&str)) {
+        if before.is_empty() {
+            complete_command(MENU, word, candidate)
+        } else if let Some((device, path)) = word.split_once(':') {
+            complete_path(device, path, candidate)
+        } else {
+            for device in vfs::devices() {
+                let mut full = heapless::String::<16>::new();
+                if write!(full, "{device}:").is_ok() && full.starts_with(word) {
+                    candidate(&full);
+                }
+            }
+        }
+    }
+}
+
+fn complete_command(
+    items: &[MenuItem<TerminalWriter>],
+    word: &str,
+    candidate: &mut dyn FnMut(&str),
+) {
+    for item in items {
+        match item {
+            MenuItem::Command { name, .. } | MenuItem::Alias { alias: name, .. }
+                if name.starts_with(word) =>
+            {
+                candidate(name)
+            }
+            MenuItem::Group { commands, .. } => complete_command(commands, word, candidate),
+            _ => {}
+        }
+    }
+}
+
+fn complete_path(device: &str, path: &str, candidate: &mut dyn FnMut(&str)) {
+    // List the directory, match the start of the name case-insensitively since 8.3 names are upper case
+    let (dir, start) = match path.rfind('/') {
+        Some(n) => path.split_at(n + 1),
+        None => ("", path),
+    };
+    let mut dir_path = heapless::String::<256>::new();
+    if write!(dir_path, "{device}:{dir}").is_err() {
+        return;
+    }
+    let _ = vfs::read_dir(Path::new(dir_path.as_str()), |e| {
+        let matches = e.name.len() >= start.len()
+            && e.name.as_bytes()[..start.len()].eq_ignore_ascii_case(start.as_bytes());
+        if matches {
+            let mut full = heapless::String::<256>::new();
+            let slash = if e.metadata.is_dir { "/" } else { "" };
+            if write!(full, "{device}:{dir}{}{slash}", e.name).is_ok() {
+                candidate(&full);
+            }
+        }
+    });
+}
@@ -0,0 +1,41 @@
+use {
+    super::{TERMINAL_INPUT_FIFO, UART_TERMINAL_TX},
+    crate::utils::interrupt_free,
+    h7_xfer::{Link, XferError},
+    stm32h7xx_hal::prelude::*,
+};
+
+/// How long a read waits for a byte, `h7-uart-terminal` uses the same timeout
+const TIMEOUT_MS: u32 = 1000;
+/// Core clock cycles per ms at 480 MHz
+const CYCLES_PER_MS: u32 = 480_000;
+/// The FIFO holds 64 bytes, poll well before it fills up
+const POLLS_PER_MS: u32 = 10;
+
+/// The terminal UART as a binary link for file transfers. Writes go straight to the UART,
+/// control bytes don't belong on the display.
+pub struct UartLink;
+
+impl Link for UartLink {
+    fn read(&mut self) -> Option<u8> {
+        for _ in 0..TIMEOUT_MS * POLLS_PER_MS {
+            if let Some(byte) = TERMINAL_INPUT_FIFO.dequeue() {
+                return Some(byte);
+            }
+            cortex_m::asm::delay(CYCLES_PER_MS / POLLS_PER_MS);
+        }
+        None
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), XferError> {
+        // One byte per critical section, the other interrupts run while the UART sends
+        for &byte in data {
+            while !interrupt_free(|cs| {
+                let mut tx = UART_TERMINAL_TX.borrow(cs).borrow_mut();
+                let tx = tx.as_mut().ok_or(XferError::Link)?;
+                Ok(tx.write(byte).is_ok())
+            })? {}
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use crate::fs::{path::PathError, vfs::VfsError};
+
 #[derive(Debug)]
 pub enum MenuError {
     /// Too many arguments (expected, actual)
@@ -12,6 +14,14 @@ pub enum MenuError {
     CommandError(Option<&'static str>),
     /// Invalid Argument
     InvalidArgument,
+    /// Could not parse the command line
+    Shell(h7_shell::ShellError),
+    /// Could not resolve a path argument
+    Path(PathError),
+    /// Filesystem error
+    Vfs(VfsError),
+    /// Serial transfer failed
+    Xfer(h7_xfer::XferError),
 }
 
 impl From<core::fmt::Error> for MenuError {
@@ -20,6 +30,30 @@ impl From<core::fmt::Error> for MenuError {
     }
 }
 
+impl From<PathError> for MenuError {
+    fn from(err: PathError) -> Self {
+        Self::Path(err)
+    }
+}
+
+impl From<VfsError> for MenuError {
+    fn from(err: VfsError) -> Self {
+        Self::Vfs(err)
+    }
+}
+
+impl From<h7_xfer::XferError> for MenuError {
+    fn from(err: h7_xfer::XferError) -> Self {
+        Self::Xfer(err)
+    }
+}
+
+impl From<h7_shell::ShellError> for MenuError {
+    fn from(err: h7_shell::ShellError) -> Self {
+        Self::Shell(err)
+    }
+}
+
 impl core::fmt::Display for MenuError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
@@ -41,6 +75,10 @@ impl core::fmt::Display for MenuError {
             Self::CommandError(Some(err)) => write!(f, "Command error: {err}"),
             Self::CommandError(None) => write!(f, "Command error"),
             Self::InvalidArgument => write!(f, "Invalid argument"),
+            Self::Shell(e) => write!(f, "{e}"),
+            Self::Path(e) => write!(f, "{e}"),
+            Self::Vfs(e) => write!(f, "{e}"),
+            Self::Xfer(e) => write!(f, "{e}"),
         }
     }
 }
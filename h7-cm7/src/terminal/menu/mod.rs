@@ -1,6 +1,23 @@
+use {
+    super::sink,
+    crate::fs::path::{Path, PathBuf, PathError},
+    alloc::{string::String, vec::Vec},
+    h7_rpc::{Request, Response, Value},
+    serde_json::Map,
+};
+
 mod error;
 pub use error::{MenuError, MenuResult};
 
+/// Longest command line after expanding variables
+const LINE_LEN: usize = 1024;
+const MAX_ARGS: usize = 32;
+const MAX_VARS: usize = 16;
+/// Current directory of a new shell, and of `cd` without arguments
+pub const HOME: &str = "sdcard:/";
+
+pub type Env = h7_shell::Env<MAX_VARS>;
+
 pub type MenuAction<W> = fn(writer: &mut Menu<W>, args: &[&str]) -> MenuResult;
 
 pub enum MenuItem<'i, W: core::fmt::Write> {
@@ -23,19 +40,70 @@ pub enum MenuItem<'i, W: core::fmt::Write> {
 pub struct Menu<'m, W: core::fmt::Write> {
     writer: W,
     menu: &'m [MenuItem<'m, W>],
+    env: Env,
+    cwd: PathBuf,
+    /// Structured results of the running RPC request
+    data: Option<Map<String, Value>>,
 }
 
 impl<'m: 'i, 'i, W: core::fmt::Write> Menu<'m, W> {
     pub fn new(writer: W, menu: &'m [MenuItem<'i, W>]) -> Self {
-        Self { writer, menu }
+        Self {
+            writer,
+            menu,
+            env: Env::new(),
+            cwd: Path::new(HOME).normalize().unwrap_or_default(),
+            data: None,
+        }
     }
 
     pub fn writer(&mut self) -> &mut W {
         &mut self.writer
     }
+
+    /// Shell variables
+    pub fn env(&mut self) -> &mut Env {
+        &mut self.env
+    }
+
+    /// Current directory, relative paths in commands start here
+    pub fn cwd(&self) -> &PathBuf {
+        &self.cwd
+    }
+
+    pub fn set_cwd(&mut self, cwd: PathBuf) {
+        self.cwd = cwd;
+    }
+
+    /// `path` relative to the current directory, normalized
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, PathError> {
+        self.cwd.as_path().join(Path::new(path))
+    }
+
+    /// Running an RPC request, commands report their results with `put_data` too
+    pub fn is_rpc(&self) -> bool {
+        self.data.is_some()
+    }
+
+    /// Add `key` to the results of the RPC request, `value` is only called for one
+    pub fn put_data(&mut self, key: &str, value: impl FnOnce() -> Value) {
+        if let Some(data) = &mut self.data {
+            data.insert(key.into(), value());
+        }
+    }
 }
 
 impl<'m, W: core::fmt::Write> Menu<'m, W> {
+    /// Split `line` into a command and arguments, expanding variables, and run it
+    pub fn run_line(&mut self, line: &str) -> MenuResult {
+        let mut buf = [0u8; LINE_LEN];
+        let args = h7_shell::tokenize::<MAX_ARGS, MAX_VARS>(line, &self.env, &mut buf)?;
+        match args.split_first() {
+            Some((cmd, args)) => self.run(cmd, args),
+            None => Ok(()),
+        }
+    }
+
     pub fn run(&mut self, cmd: &str, args: &[&str]) -> MenuResult {
         fn run_impl<'m, W: core::fmt::Write>(
             menu: &mut Menu<'m, W>,
@@ -69,6 +137,18 @@ impl<'m, W: core::fmt::Write> Menu<'m, W> {
 
         run_impl(self, cmd, args, self.menu)
     }
+
+    /// Run `request` with its output and results captured for the response
+    pub fn run_request(&mut self, request: &Request) -> Response {
+        let args = request.args.iter().map(String::as_str).collect::<Vec<_>>();
+        self.data = Some(Map::new());
+        let (res, output) = sink::capture(|| self.run(&request.cmd, &args));
+        let data = self.data.take().map(Value::Object).unwrap_or_default();
+        match res {
+            Ok(()) => Response::ok(request.id, data, output),
+            Err(e) => Response::error(request.id, alloc::format!("{e}"), output),
+        }
+    }
 }
 
 impl<'m, W: core::fmt::Write> core::fmt::Write for Menu<'m, W> {
@@ -8,18 +8,19 @@ use {
 };
 
 mod commands;
+mod completion;
+mod link;
 pub mod menu;
+mod rpc;
+pub mod sink;
+
+pub use {completion::ShellCompleter, link::UartLink};
 
 pub struct TerminalWriter;
 
 impl core::fmt::Write for TerminalWriter {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        interrupt_free(|cs| {
-            if let Some(tx) = &mut *UART_TERMINAL_TX.borrow(cs).borrow_mut() {
-                write!(tx, "{s}")?
-            }
-            Ok(())
-        })
+        sink::write_str(s)
     }
 }
 
@@ -53,6 +54,19 @@ pub const MENU: &[MenuItem<TerminalWriter>] = &[
             commands::program::PLOAD,
             commands::program::PRUN,
             commands::program::UPLOAD,
+            commands::program::APPSIG,
+        ],
+    },
+    MenuItem::Group {
+        title: "Shell",
+        commands: &[
+            commands::shell::SET,
+            commands::shell::UNSET,
+            commands::shell::ENV,
+            commands::shell::ECHO,
+            commands::shell::CD,
+            commands::shell::PWD,
+            commands::shell::RPC,
         ],
     },
     MenuItem::Group {
@@ -69,6 +83,7 @@ pub const MENU: &[MenuItem<TerminalWriter>] = &[
             commands::sys::ETHCTL,
             commands::sys::UPTIME,
             commands::sys::LEDCTL,
+            commands::sys::TERMCTL,
             commands::sys::CORECTL,
         ],
     },
@@ -0,0 +1,65 @@
+//! RPC mode of the terminal UART, JSON lines as described in `h7-rpc`
+
+use {
+    super::{
+        menu::{Menu, MenuResult},
+        sink::{self, Sink},
+        TerminalWriter, UartLink, TERMINAL_INPUT_FIFO,
+    },
+    crate::utils::interrupt_free,
+    alloc::{format, string::String},
+    h7_rpc::{LineDecoder, Request, Response, Value, EXIT, READY_ID},
+    h7_xfer::Link,
+};
+
+/// Answer requests until `exit`. Text output goes to the display only in the meantime, or into
+/// the response of the running request. Output past what a response holds, and panic messages,
+/// still go out the UART, the client skips lines without a `{`.
+pub fn serve(m: &mut Menu<TerminalWriter>) -> MenuResult {
+    let uart_enabled = sink::is_enabled(Sink::Uart);
+    sink::set_enabled(Sink::Uart, false);
+    respond(&Response::ok(READY_ID, Value::Null, String::new()));
+
+    let mut decoder = LineDecoder::new();
+    loop {
+        let response = match decoder.push::<Request>(next_byte()) {
+            None => continue,
+            Some(Err(e)) => Response::error(READY_ID, format!("{e}"), String::new()),
+            // Sent by clients that don't know which mode we're in
+            Some(Ok(request)) if request.cmd == "rpc" => {
+                Response::ok(request.id, Value::Null, String::new())
+            }
+            Some(Ok(request)) if request.cmd == EXIT => {
+                respond(&Response::ok(request.id, Value::Null, String::new()));
+                break;
+            }
+            Some(Ok(request)) => m.run_request(&request),
+        };
+        respond(&response);
+    }
+
+    sink::set_enabled(Sink::Uart, uart_enabled);
+    Ok(())
+}
+
+/// Wait for the next byte from the UART, sleeping until an interrupt while there is none
+fn next_byte() -> u8 {
+    loop {
+        // With interrupts masked no byte can come in between the check and `wfi`, which still
+        // wakes up for the pending interrupt
+        let byte = interrupt_free(|_| {
+            TERMINAL_INPUT_FIFO.dequeue().or_else(|| {
+                cortex_m::asm::wfi();
+                None
+            })
+        });
+        if let Some(byte) = byte {
+            return byte;
+        }
+    }
+}
+
+/// Straight to the UART, past the sinks
+fn respond(response: &Response) {
+    let _ = UartLink.write(&h7_rpc::encode(response));
+}
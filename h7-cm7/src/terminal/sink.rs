@@ -0,0 +1,119 @@
+//! Terminal output fan-out. Everything written to the terminal (shell, log records, panic
+//! messages, app output) goes to every enabled sink.
+
+use {
+    super::UART_TERMINAL_TX,
+    crate::{display::GPU, utils::interrupt_free},
+    alloc::string::String,
+    core::{
+        cell::RefCell,
+        fmt::Write,
+        str::FromStr,
+        sync::atomic::{AtomicU8, Ordering},
+    },
+    critical_section::Mutex,
+};
+
+/// Most output kept by `capture`, the rest goes to the UART
+const MAX_CAPTURE: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sink {
+    /// USART1, the shell
+    Uart,
+    /// Text console on the DisplayPort monitor
+    Display,
+}
+
+impl Sink {
+    pub const ALL: [Sink; 2] = [Sink::Uart, Sink::Display];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Sink::Uart => "uart",
+            Sink::Display => "display",
+        }
+    }
+
+    const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl FromStr for Sink {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Sink::ALL
+            .into_iter()
+            .find(|sink| sink.name() == s)
+            .ok_or(())
+    }
+}
+
+static ENABLED: AtomicU8 = AtomicU8::new(Sink::Uart.bit() | Sink::Display.bit());
+
+/// Output of the running RPC request, instead of the UART
+static CAPTURE: Mutex<RefCell<Option<String>>> = Mutex::new(RefCell::new(None));
+
+pub fn is_enabled(sink: Sink) -> bool {
+    ENABLED.load(Ordering::Relaxed) & sink.bit() != 0
+}
+
+pub fn set_enabled(sink: Sink, enabled: bool) {
+    match enabled {
+        true => ENABLED.fetch_or(sink.bit(), Ordering::Relaxed),
+        false => ENABLED.fetch_and(!sink.bit(), Ordering::Relaxed),
+    };
+}
+
+/// Write `s` to all enabled sinks. A sink that is not initialized yet, or already borrowed
+/// because we panicked while writing to it, is skipped.
+pub fn write_str(s: &str) -> core::fmt::Result {
+    write(s, true)
+}
+
+/// Write `s` straight to the UART, past a running capture and the sink switch, and to the
+/// display if it's enabled. For panic messages, the captured output is never sent after a panic.
+pub fn write_str_uart(s: &str) -> core::fmt::Result {
+    write(s, false)
+}
+
+fn write(s: &str, capture: bool) -> core::fmt::Result {
+    interrupt_free(|cs| {
+        let uart = match CAPTURE.borrow(cs).try_borrow_mut().as_deref_mut() {
+            Ok(Some(output)) if capture => {
+                let mut len = MAX_CAPTURE.saturating_sub(output.len()).min(s.len());
+                while !s.is_char_boundary(len) {
+                    len -= 1;
+                }
+                output.push_str(&s[..len]);
+                // What doesn't fit goes out the UART rather than nowhere
+                &s[len..]
+            }
+            _ if !capture || is_enabled(Sink::Uart) => s,
+            _ => "",
+        };
+        let mut res = Ok(());
+        if !uart.is_empty() {
+            if let Ok(Some(tx)) = UART_TERMINAL_TX.borrow(cs).try_borrow_mut().as_deref_mut() {
+                res = tx.write_str(uart);
+            }
+        }
+        // Drawn on the next frame swap
+        if is_enabled(Sink::Display) {
+            if let Ok(Some(gpu)) = GPU.borrow(cs).try_borrow_mut().as_deref_mut() {
+                let _ = gpu.console().write_str(s);
+            }
+        }
+        res
+    })
+}
+
+/// Run `func` with the output that would go to the UART kept instead, returns it too
+pub fn capture<R>(func: impl FnOnce() -> R) -> (R, String) {
+    interrupt_free(|cs| CAPTURE.borrow(cs).replace(Some(String::new())));
+    let res = func();
+    let output = interrupt_free(|cs| CAPTURE.borrow(cs).take());
+    (res, output.unwrap_or_default())
+}
@@ -0,0 +1,119 @@
+use {
+    h7_rpc::{Client, Response, RpcError},
+    h7_xfer::IoLink,
+    std::{path::Path, time::Duration},
+};
+
+const BAUD: u32 = 115_200;
+/// Serial device if none is given
+const PORT_VAR: &str = "H7_PORT";
+/// Read timeout of the port, transfers need the firmware's timeout
+const READ_TIMEOUT: Duration = Duration::from_millis(1000);
+
+const USAGE: &str = "Usage:
+    h7-ctl [options] <command> [args...]   Run a shell command
+    h7-ctl [options] upload <file> [dest]  Send a file, a program into RAM without a destination
+    h7-ctl [options] run <file>            Send a program into RAM and run it
+
+Options:
+    -p <port>     Serial device, defaults to $H7_PORT
+    -b <baud>     Baud rate, 115200 by default
+    -t <seconds>  How long to wait for a response, 10 by default
+
+Prints the response as JSON, exits with 1 if the command failed.";
+
+type Port = serialport::TTYPort;
+
+fn main() {
+    let mut port = std::env::var(PORT_VAR).ok();
+    let mut baud = BAUD;
+    let mut timeout = None;
+    let mut args = Vec::new();
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        // Options only before the command, its arguments are passed on as they are
+        match (args.is_empty(), arg.as_str()) {
+            (true, "-p") => port = Some(iter.next().unwrap_or_else(|| usage())),
+            (true, "-b") => baud = parse(iter.next()),
+            (true, "-t") => timeout = Some(Duration::from_secs(parse(iter.next()))),
+            (true, "-h" | "--help") => usage(),
+            _ => args.push(arg),
+        }
+    }
+    let Some(port) = port else { usage() };
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let Some((cmd, args)) = args.split_first() else {
+        usage()
+    };
+
+    let res = serialport::new(&port, baud)
+        .timeout(READ_TIMEOUT)
+        .open_native()
+        .map_err(|e| format!("{port}: {e}"))
+        .and_then(|port| run(port, timeout, cmd, args));
+    match res {
+        Ok(response) => {
+            println!("{}", serde_json::to_string_pretty(&response).unwrap());
+            if !response.ok {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    std::process::exit(1);
+}
+
+fn parse<T: std::str::FromStr>(arg: Option<String>) -> T {
+    arg.and_then(|a| a.parse().ok()).unwrap_or_else(|| usage())
+}
+
+/// Run `cmd` in RPC mode and leave it again
+fn run(
+    port: Port,
+    timeout: Option<Duration>,
+    cmd: &str,
+    args: &[&str],
+) -> Result<Response, String> {
+    let mut client = Client::connect(port).map_err(|e| e.to_string())?;
+    if let Some(timeout) = timeout {
+        client.set_timeout(timeout);
+    }
+    let res = match (cmd, args) {
+        ("upload", [file]) => upload(&mut client, file, None),
+        ("upload", [file, to]) => upload(&mut client, file, Some(to)),
+        ("upload", _) => usage(),
+        ("run", [file]) => upload(&mut client, file, None).and_then(|response| match response.ok {
+            true => client.call("prun", &[]).map_err(|e| e.to_string()),
+            false => Ok(response),
+        }),
+        ("run", _) => usage(),
+        _ => client.call(cmd, args).map_err(|e| e.to_string()),
+    };
+    client.close().map_err(|e| e.to_string())?;
+    res
+}
+
+/// Start `upload` and send `file` while it waits
+fn upload(client: &mut Client<Port>, file: &str, to: Option<&str>) -> Result<Response, String> {
+    let data = std::fs::read(file).map_err(|e| format!("{file}: {e}"))?;
+    let name = Path::new(file)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or("Expected a file name")?;
+    let args = to.as_slice();
+    let id = client.send("upload", args).map_err(|e| e.to_string())?;
+    let sent = h7_xfer::send(&mut IoLink(client.io_mut()), name, &data, |_| {});
+    // The response says why if the board gave up
+    match (sent, client.wait(id)) {
+        (_, Ok(response)) => Ok(response),
+        (Err(e), Err(RpcError::Timeout)) => Err(e.to_string()),
+        (_, Err(e)) => Err(e.to_string()),
+    }
+}
@@ -0,0 +1,564 @@
+use {
+    crate::H7Display,
+    core::convert::Infallible,
+    embedded_graphics::{
+        mono_font::{MonoFont, MonoTextStyleBuilder},
+        pixelcolor::Rgb888,
+        prelude::*,
+        primitives::{PrimitiveStyle, Rectangle},
+        text::{Baseline, Text},
+    },
+};
+
+const TAB_WIDTH: usize = 8;
+const MAX_PARAMS: usize = 8;
+const DEFAULT_FG: u8 = 7;
+const DEFAULT_BG: u8 = 0;
+
+/// VGA colours used for the 16 ANSI colours, 0-7 normal and 8-15 bright
+const PALETTE: [Rgb888; 16] = [
+    Rgb888::new(0, 0, 0),
+    Rgb888::new(170, 0, 0),
+    Rgb888::new(0, 170, 0),
+    Rgb888::new(170, 85, 0),
+    Rgb888::new(0, 0, 170),
+    Rgb888::new(170, 0, 170),
+    Rgb888::new(0, 170, 170),
+    Rgb888::new(170, 170, 170),
+    Rgb888::new(85, 85, 85),
+    Rgb888::new(255, 85, 85),
+    Rgb888::new(85, 255, 85),
+    Rgb888::new(255, 255, 85),
+    Rgb888::new(85, 85, 255),
+    Rgb888::new(255, 85, 255),
+    Rgb888::new(85, 255, 255),
+    Rgb888::new(255, 255, 255),
+];
+
+/// One character on the console, colours are palette indices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub c: char,
+    pub fg: u8,
+    pub bg: u8,
+}
+
+impl Cell {
+    pub const BLANK: Self = Self { c: ' ', fg: DEFAULT_FG, bg: DEFAULT_BG };
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self::BLANK
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Parser {
+    Normal,
+    Escape,
+    Csi { params: [u16; MAX_PARAMS], len: usize, private: bool },
+}
+
+/// Full screen text console drawn on a [`H7Display`].
+///
+/// Text is written with [`core::fmt::Write`] and only updates the character grid, call
+/// [`TextConsole::draw`] to render the changes to the back buffer. `cells` holds the
+/// visible screen and the scrollback, lines that don't fit on the screen are kept there
+/// until they are overwritten.
+///
+/// Supported control characters: `\r`, `\n` (also returns the carriage), backspace, tab,
+/// and the ANSI escapes for colours (SGR 0, 1, 22, 30-37, 39, 40-47, 49, 90-97, 100-107),
+/// cursor movement (A, B, C, D, H, f, s, u), erasing (J, K) and cursor visibility (?25h/l).
+pub struct TextConsole<'b, 'f, COLOR: PixelColor> {
+    font: &'f MonoFont<'f>,
+    cells: &'b mut [Cell],
+    size: Size,
+    cols: usize,
+    rows: usize,
+    lines: usize,
+    /// Index in `cells` of the line at the top of the screen
+    top: usize,
+    /// Lines of scrollback above the screen
+    history: usize,
+    /// Lines the view is scrolled back, 0 follows the output
+    view: usize,
+    col: usize,
+    row: usize,
+    saved: (usize, usize),
+    fg: u8,
+    bg: u8,
+    bold: bool,
+    cursor_visible: bool,
+    parser: Parser,
+    /// First and last screen row to redraw
+    dirty: Option<(usize, usize)>,
+    redraw_all: bool,
+    /// Lines scrolled since the last draw
+    scrolled: usize,
+    cursor_drawn: Option<(usize, usize)>,
+    palette: [COLOR; 16],
+}
+
+impl<'b, 'f, COLOR: PixelColor + From<Rgb888>> TextConsole<'b, 'f, COLOR> {
+    /// Create a console covering `size` pixels. The number of lines is the length of
+    /// `cells` divided by the number of columns.
+    pub fn new(font: &'f MonoFont<'f>, cells: &'b mut [Cell], size: Size) -> Self {
+        let mut console = Self {
+            font,
+            cells,
+            size,
+            cols: 0,
+            rows: 0,
+            lines: 0,
+            top: 0,
+            history: 0,
+            view: 0,
+            col: 0,
+            row: 0,
+            saved: (0, 0),
+            fg: DEFAULT_FG,
+            bg: DEFAULT_BG,
+            bold: false,
+            cursor_visible: true,
+            parser: Parser::Normal,
+            dirty: None,
+            redraw_all: true,
+            scrolled: 0,
+            cursor_drawn: None,
+            palette: PALETTE.map(COLOR::from),
+        };
+        console.set_font(font);
+        console
+    }
+
+    /// Change the font. Clears the console since the grid changes size.
+    pub fn set_font(&mut self, font: &'f MonoFont<'f>) {
+        self.font = font;
+        self.cols = ((self.size.width / font.character_size.width) as usize).clamp(1, self.cells.len().max(1));
+        self.lines = self.cells.len() / self.cols;
+        self.rows = ((self.size.height / font.character_size.height) as usize).min(self.lines);
+        self.reset();
+    }
+
+    /// Replace one of the 16 palette colours
+    pub fn set_palette(&mut self, index: usize, color: COLOR) {
+        if let Some(c) = self.palette.get_mut(index) {
+            *c = color;
+            self.redraw_all = true;
+        }
+    }
+
+    /// Clear the screen and scrollback and reset colours and cursor
+    pub fn reset(&mut self) {
+        self.cells.fill(Cell::BLANK);
+        self.top = 0;
+        self.history = 0;
+        self.view = 0;
+        self.col = 0;
+        self.row = 0;
+        self.saved = (0, 0);
+        self.fg = DEFAULT_FG;
+        self.bg = DEFAULT_BG;
+        self.bold = false;
+        self.cursor_visible = true;
+        self.parser = Parser::Normal;
+        self.redraw_all = true;
+    }
+
+    /// Draw the whole screen on the next [`TextConsole::draw`], after something else drew over it
+    pub fn redraw(&mut self) {
+        self.redraw_all = true;
+    }
+
+    pub const fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub const fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Cursor position, (column, row)
+    pub const fn cursor(&self) -> (usize, usize) {
+        (self.col, self.row)
+    }
+
+    /// Character at a screen position, ignoring the scrollback view
+    pub fn cell(&self, col: usize, row: usize) -> Option<Cell> {
+        (col < self.cols && row < self.rows).then(|| self.cells[self.line_index(row) * self.cols + col])
+    }
+
+    /// Scroll the view back into the scrollback (positive) or towards the output (negative).
+    /// New output returns the view to the bottom.
+    pub fn scroll_view(&mut self, lines: isize) {
+        let view = self.view.saturating_add_signed(lines).min(self.history);
+        if view != self.view {
+            self.view = view;
+            self.redraw_all = true;
+        }
+    }
+
+    /// Render changes since the last call to the back buffer of `display`
+    pub fn draw<const WIDTH: usize, const HEIGHT: usize>(&mut self, display: &mut H7Display<'_, COLOR, WIDTH, HEIGHT>) -> Result<(), Infallible>
+    where
+        [(); WIDTH * HEIGHT]:,
+    {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let char_height = self.font.character_size.height as usize;
+        if self.scrolled >= self.rows {
+            self.redraw_all = true;
+        } else if self.scrolled > 0 && !self.redraw_all {
+            // Moving the pixels is a lot faster than drawing every character again
+            display.scroll((self.scrolled * char_height) as i32, self.palette[DEFAULT_BG as usize]);
+            self.cursor_drawn = self
+                .cursor_drawn
+                .and_then(|(col, row)| row.checked_sub(self.scrolled).map(|row| (col, row)));
+        }
+        self.scrolled = 0;
+
+        let cursor = (self.view == 0 && self.cursor_visible).then_some((self.col.min(self.cols - 1), self.row));
+        if cursor != self.cursor_drawn {
+            for (_, row) in [self.cursor_drawn, cursor].into_iter().flatten() {
+                self.mark(row);
+            }
+        }
+
+        let (first, last) = match (self.redraw_all, self.dirty) {
+            (true, _) => (0, self.rows.saturating_sub(1)),
+            (false, Some(range)) => range,
+            (false, None) => return Ok(()),
+        };
+        if self.redraw_all {
+            let fill = PrimitiveStyle::with_fill(self.palette[DEFAULT_BG as usize]);
+            Rectangle::new(Point::zero(), self.size).into_styled(fill).draw(display)?;
+        }
+        for row in first..=last.min(self.rows.saturating_sub(1)) {
+            self.draw_row(display, row, cursor)?;
+        }
+
+        self.cursor_drawn = cursor;
+        self.dirty = None;
+        self.redraw_all = false;
+        Ok(())
+    }
+
+    fn draw_row<D: DrawTarget<Color = COLOR, Error = Infallible>>(&self, display: &mut D, row: usize, cursor: Option<(usize, usize)>) -> Result<(), Infallible> {
+        let char_size = self.font.character_size;
+        // Rows in the scrollback wrap around the start of `cells`
+        let line = (self.line_index(row) + self.lines - self.view) % self.lines;
+        let y = row as i32 * char_size.height as i32;
+        for (col, cell) in self.cells[line * self.cols..(line + 1) * self.cols].iter().enumerate() {
+            let (mut fg, mut bg) = (self.palette[cell.fg as usize & 0xf], self.palette[cell.bg as usize & 0xf]);
+            if cursor == Some((col, row)) {
+                core::mem::swap(&mut fg, &mut bg);
+            }
+            let point = Point::new(col as i32 * char_size.width as i32, y);
+            if cell.c == ' ' {
+                display.fill_solid(&Rectangle::new(point, char_size), bg)?;
+            } else {
+                let style = MonoTextStyleBuilder::new().font(self.font).text_color(fg).background_color(bg).build();
+                let mut buf = [0u8; 4];
+                Text::with_baseline(cell.c.encode_utf8(&mut buf), point, style, Baseline::Top).draw(display)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn line_index(&self, row: usize) -> usize {
+        (self.top + row) % self.lines
+    }
+
+    fn mark(&mut self, row: usize) {
+        self.dirty = Some(match self.dirty {
+            Some((first, last)) => (first.min(row), last.max(row)),
+            None => (row, row),
+        });
+    }
+
+    fn blank(&self) -> Cell {
+        Cell { c: ' ', fg: self.fg, bg: self.bg }
+    }
+
+    /// Fill `cols` on screen `row` with blanks
+    fn erase(&mut self, row: usize, cols: core::ops::Range<usize>) {
+        let start = self.line_index(row) * self.cols;
+        let blank = self.blank();
+        self.cells[(start + cols.start)..(start + cols.end.min(self.cols))].fill(blank);
+        self.mark(row);
+    }
+
+    fn put(&mut self, c: char) {
+        if self.col >= self.cols {
+            self.col = 0;
+            self.line_feed();
+        }
+        let fg = if self.bold && self.fg < 8 { self.fg + 8 } else { self.fg };
+        let idx = self.line_index(self.row) * self.cols + self.col;
+        self.cells[idx] = Cell { c, fg, bg: self.bg };
+        self.mark(self.row);
+        self.col += 1;
+    }
+
+    fn line_feed(&mut self) {
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.top = (self.top + 1) % self.lines;
+            self.history = (self.history + 1).min(self.lines - self.rows);
+            self.scrolled += 1;
+            // The old first line is now the last line on screen
+            self.erase(self.rows - 1, 0..self.cols);
+            self.dirty = self.dirty.map(|(first, last)| (first.saturating_sub(1), last));
+        }
+    }
+
+    fn write(&mut self, c: char) {
+        match (self.parser, c) {
+            (Parser::Normal, '\x1b') => self.parser = Parser::Escape,
+            (Parser::Normal, '\r') => self.col = 0,
+            (Parser::Normal, '\n') => {
+                self.col = 0;
+                self.line_feed();
+            }
+            (Parser::Normal, '\x08') => self.col = self.col.min(self.cols - 1).saturating_sub(1),
+            (Parser::Normal, '\t') => self.col = ((self.col / TAB_WIDTH + 1) * TAB_WIDTH).min(self.cols - 1),
+            (Parser::Normal, c) if c.is_control() => {}
+            (Parser::Normal, c) => self.put(c),
+            (Parser::Escape, '[') => {
+                self.parser = Parser::Csi { params: [0; MAX_PARAMS], len: 0, private: false };
+            }
+            (Parser::Escape, c) => {
+                match c {
+                    '7' => self.saved = (self.col, self.row),
+                    '8' => (self.col, self.row) = self.saved,
+                    'c' => self.reset(),
+                    _ => {}
+                }
+                self.parser = Parser::Normal;
+            }
+            (Parser::Csi { mut params, mut len, private }, c) => match c {
+                '0'..='9' => {
+                    let p = &mut params[len.min(MAX_PARAMS - 1)];
+                    *p = p.saturating_mul(10).saturating_add(c as u16 - b'0' as u16);
+                    self.parser = Parser::Csi { params, len, private };
+                }
+                ';' => {
+                    len = (len + 1).min(MAX_PARAMS - 1);
+                    self.parser = Parser::Csi { params, len, private };
+                }
+                '?' => self.parser = Parser::Csi { params, len, private: true },
+                '\x40'..='\x7e' => {
+                    self.parser = Parser::Normal;
+                    self.csi(&params[..=len], private, c);
+                }
+                // Invalid sequence
+                _ => self.parser = Parser::Normal,
+            },
+        }
+    }
+
+    fn csi(&mut self, params: &[u16], private: bool, action: char) {
+        // Missing parameters and 0 default to 1 for movement
+        let n = (params[0] as usize).max(1);
+        match (private, action) {
+            (true, 'h') if params[0] == 25 => self.cursor_visible = true,
+            (true, 'l') if params[0] == 25 => self.cursor_visible = false,
+            (true, _) => {}
+            (false, 'A') => self.row = self.row.saturating_sub(n),
+            (false, 'B') => self.row = (self.row + n).min(self.rows - 1),
+            (false, 'C') => self.col = (self.col + n).min(self.cols - 1),
+            (false, 'D') => self.col = self.col.min(self.cols - 1).saturating_sub(n),
+            (false, 'H' | 'f') => {
+                self.row = (params[0] as usize).max(1).min(self.rows) - 1;
+                self.col = (params.get(1).copied().unwrap_or(0) as usize).max(1).min(self.cols) - 1;
+            }
+            (false, 'J') => {
+                let (col, row) = (self.col, self.row);
+                let rows = match params[0] {
+                    0 => {
+                        self.erase(row, col..self.cols);
+                        (row + 1)..self.rows
+                    }
+                    1 => {
+                        self.erase(row, 0..(col + 1));
+                        0..row
+                    }
+                    _ => 0..self.rows,
+                };
+                for r in rows {
+                    self.erase(r, 0..self.cols);
+                }
+            }
+            (false, 'K') => match params[0] {
+                0 => self.erase(self.row, self.col..self.cols),
+                1 => self.erase(self.row, 0..(self.col + 1)),
+                _ => self.erase(self.row, 0..self.cols),
+            },
+            (false, 'm') => params.iter().for_each(|p| self.sgr(*p)),
+            (false, 's') => self.saved = (self.col, self.row),
+            (false, 'u') => (self.col, self.row) = self.saved,
+            _ => {}
+        }
+    }
+
+    fn sgr(&mut self, param: u16) {
+        match param {
+            0 => {
+                self.fg = DEFAULT_FG;
+                self.bg = DEFAULT_BG;
+                self.bold = false;
+            }
+            1 => self.bold = true,
+            22 => self.bold = false,
+            30..=37 => self.fg = (param - 30) as u8,
+            39 => self.fg = DEFAULT_FG,
+            40..=47 => self.bg = (param - 40) as u8,
+            49 => self.bg = DEFAULT_BG,
+            90..=97 => self.fg = (param - 90 + 8) as u8,
+            100..=107 => self.bg = (param - 100 + 8) as u8,
+            _ => {}
+        }
+    }
+}
+
+impl<'b, 'f, COLOR: PixelColor + From<Rgb888>> core::fmt::Write for TextConsole<'b, 'f, COLOR> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        if self.rows == 0 {
+            return Err(core::fmt::Error);
+        }
+        if self.view != 0 {
+            self.view = 0;
+            self.redraw_all = true;
+        }
+        for c in s.chars() {
+            self.write(c);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, core::fmt::Write, embedded_graphics::mono_font::ascii::FONT_6X10};
+
+    /// 10 columns and 4 rows of 6x10 characters, 2 lines of scrollback
+    fn console(cells: &mut [Cell]) -> TextConsole<'_, 'static, Rgb888> {
+        TextConsole::new(&FONT_6X10, cells, Size::new(60, 40))
+    }
+
+    fn row(console: &TextConsole<Rgb888>, row: usize) -> String {
+        (0..console.cols()).map(|col| console.cell(col, row).unwrap().c).collect()
+    }
+
+    #[test]
+    fn wrap_and_scroll() {
+        let mut cells = [Cell::BLANK; 60];
+        let mut c = console(&mut cells);
+        assert_eq!((c.cols(), c.rows()), (10, 4));
+        c.write_str("0123456789ab\r\nc\nd").unwrap();
+        assert_eq!(row(&c, 0), "0123456789");
+        assert_eq!(row(&c, 1), "ab        ");
+        assert_eq!(c.cursor(), (1, 3));
+        // A full last line waits for the next character to wrap
+        c.write_str("\x1b[4;1Hefghijklmn").unwrap();
+        assert_eq!((c.cursor(), row(&c, 3).as_str()), ((10, 3), "efghijklmn"));
+        c.write_str("o").unwrap();
+        assert_eq!(row(&c, 0), "ab        ");
+        assert_eq!(row(&c, 2), "efghijklmn");
+        assert_eq!(row(&c, 3), "o         ");
+        c.write_str("\n\n").unwrap();
+        assert_eq!(row(&c, 0), "efghijklmn");
+        assert_eq!(row(&c, 3), "          ");
+        assert_eq!(c.cursor(), (0, 3));
+    }
+
+    #[test]
+    fn cursor_movement() {
+        let mut cells = [Cell::BLANK; 60];
+        let mut c = console(&mut cells);
+        let mut at = |s: &str| {
+            c.write_str(s).unwrap();
+            c.cursor()
+        };
+        assert_eq!(at("\x1b[2;3H"), (2, 1));
+        assert_eq!(at("\x1b[A"), (2, 0));
+        assert_eq!(at("\x1b[9A"), (2, 0));
+        assert_eq!(at("\x1b[2B"), (2, 2));
+        assert_eq!(at("\x1b[99B"), (2, 3));
+        assert_eq!(at("\x1b[5C"), (7, 3));
+        assert_eq!(at("\x1b[99C"), (9, 3));
+        assert_eq!(at("\x1b[4D"), (5, 3));
+        assert_eq!(at("\x1b[99D"), (0, 3));
+        assert_eq!(at("\x1b[99;99H"), (9, 3));
+        assert_eq!(at("\x1b[0;0f"), (0, 0));
+        assert_eq!(at("\x1b[3;4H\x1b[s\x1b[H\x1b[u"), (3, 2));
+        // Back from the pending wrap
+        assert_eq!(at("\x1b[1;10Hxy\x1b[D"), (0, 1));
+        assert_eq!(at("\x1b[1;10Hx\x1b[D"), (8, 0));
+    }
+
+    #[test]
+    fn erase() {
+        let mut cells = [Cell::BLANK; 60];
+        let mut c = console(&mut cells);
+        let fill = "\x1b[H0123456789abcdefghijABCDEFGHIJklmnopqrs";
+        let screen = |c: &TextConsole<Rgb888>| (0..4).map(|r| row(c, r)).collect::<Vec<_>>();
+
+        c.write_str(fill).unwrap();
+        c.write_str("\x1b[2;3H\x1b[K").unwrap();
+        assert_eq!(row(&c, 1), "ab        ");
+        c.write_str("\x1b[1K").unwrap();
+        assert_eq!(row(&c, 1), "          ");
+        c.write_str(fill).unwrap();
+        c.write_str("\x1b[2;3H\x1b[2K").unwrap();
+        assert_eq!(row(&c, 1), "          ");
+        assert_eq!(row(&c, 2), "ABCDEFGHIJ");
+
+        c.write_str(fill).unwrap();
+        c.write_str("\x1b[2;3H\x1b[J").unwrap();
+        assert_eq!(screen(&c), ["0123456789", "ab        ", "          ", "          "]);
+        c.write_str(fill).unwrap();
+        c.write_str("\x1b[3;3H\x1b[1J").unwrap();
+        assert_eq!(screen(&c), ["          ", "          ", "   DEFGHIJ", "klmnopqrs "]);
+        c.write_str("\x1b[2J").unwrap();
+        assert_eq!(screen(&c), ["          "; 4]);
+        // Erased with the background colour
+        c.write_str("\x1b[44m\x1b[2K").unwrap();
+        assert_eq!(c.cell(0, 2).unwrap().bg, 4);
+    }
+
+    #[test]
+    fn colours() {
+        let mut cells = [Cell::BLANK; 60];
+        let mut c = console(&mut cells);
+        c.write_str("\x1b[31;44ma\x1b[1mb\x1b[22mc\x1b[0md\x1b[92;105me\x1b[39;49mf\x1b[1;33mg\x1b[mh").unwrap();
+        let colours = (0..8).map(|col| c.cell(col, 0).unwrap()).map(|cell| (cell.c, cell.fg, cell.bg));
+        assert!(colours.eq([
+            ('a', 1, 4),
+            ('b', 9, 4),
+            ('c', 1, 4),
+            ('d', DEFAULT_FG, DEFAULT_BG),
+            ('e', 10, 13),
+            ('f', DEFAULT_FG, DEFAULT_BG),
+            ('g', 11, DEFAULT_BG),
+            ('h', DEFAULT_FG, DEFAULT_BG),
+        ]));
+    }
+
+    #[test]
+    fn split_escape() {
+        let mut cells = [Cell::BLANK; 60];
+        let mut c = console(&mut cells);
+        for s in ["a\x1b", "[3", "2", ";4", "4mb\x1b[2;", "5", "Hc"] {
+            c.write_str(s).unwrap();
+        }
+        assert_eq!(row(&c, 0), "ab        ");
+        assert_eq!(c.cell(1, 0).unwrap(), Cell { c: 'b', fg: 2, bg: 4 });
+        assert_eq!(c.cell(4, 1).unwrap().c, 'c');
+        assert_eq!(c.cursor(), (5, 1));
+    }
+}
@@ -6,7 +6,12 @@
 #![allow(mutable_transmutes)]
 #![feature(const_mut_refs)]
 
+mod console;
 mod display;
 mod framebuffer;
 
-pub use {display::H7Display, framebuffer::FrameBuffer};
+pub use {
+    console::{Cell, TextConsole},
+    display::H7Display,
+    framebuffer::FrameBuffer,
+};
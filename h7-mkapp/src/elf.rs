@@ -0,0 +1,194 @@
+//! Just enough of 32 bit little endian ARM ELF files to find the relocations of an app
+
+pub const MAGIC: &[u8] = b"\x7fELF";
+
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_RELA: u32 = 4;
+pub const SHT_NOBITS: u32 = 8;
+pub const SHT_REL: u32 = 9;
+
+pub const SHF_WRITE: u32 = 0x1;
+pub const SHF_ALLOC: u32 = 0x2;
+pub const SHF_EXECINSTR: u32 = 0x4;
+
+const EM_ARM: u16 = 40;
+const SECTION_HEADER_SIZE: usize = 40;
+const SYMBOL_SIZE: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElfError {
+    /// No ELF magic
+    NotElf,
+    /// Not a 32 bit little endian ARM file
+    Unsupported,
+    /// A header or section points past the end of the file
+    Truncated,
+}
+
+impl core::fmt::Display for ElfError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NotElf => write!(f, "Not an ELF file"),
+            Self::Unsupported => write!(f, "Not a 32 bit little endian ARM ELF file"),
+            Self::Truncated => write!(f, "ELF file is truncated"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub kind: u32,
+    pub flags: u32,
+    pub addr: u32,
+    pub offset: u32,
+    pub size: u32,
+    pub info: u32,
+}
+
+impl Section {
+    /// Part of the app in memory
+    pub fn is_alloc(&self) -> bool {
+        self.flags & SHF_ALLOC != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    /// Index of the section the symbol is defined in, or a special `SHN_*` index
+    pub shndx: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Rel {
+    /// Address of the relocated place, in linked files
+    pub offset: u32,
+    pub kind: u8,
+    pub symbol: u32,
+}
+
+pub struct Elf<'a> {
+    data: &'a [u8],
+    pub sections: Vec<Section>,
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if !data.starts_with(MAGIC) {
+            return Err(ElfError::NotElf);
+        }
+        // ELFCLASS32, ELFDATA2LSB
+        if data.get(4..6) != Some(&[1, 1]) || u16_at(data, 18)? != EM_ARM {
+            return Err(ElfError::Unsupported);
+        }
+        let shoff = u32_at(data, 32)? as usize;
+        let shnum = u16_at(data, 48)? as usize;
+        let shstrndx = u16_at(data, 50)? as usize;
+
+        let headers = (0..shnum)
+            .map(|i| {
+                let h = data
+                    .get(shoff + i * SECTION_HEADER_SIZE..)
+                    .ok_or(ElfError::Truncated)?;
+                Ok((
+                    u32_at(h, 0)?,
+                    Section {
+                        name: String::new(),
+                        kind: u32_at(h, 4)?,
+                        flags: u32_at(h, 8)?,
+                        addr: u32_at(h, 12)?,
+                        offset: u32_at(h, 16)?,
+                        size: u32_at(h, 20)?,
+                        info: u32_at(h, 28)?,
+                    },
+                ))
+            })
+            .collect::<Result<Vec<_>, ElfError>>()?;
+
+        let mut elf = Self {
+            data,
+            sections: Vec::new(),
+        };
+        let names = match headers.get(shstrndx) {
+            Some((_, strtab)) => elf.data(strtab)?,
+            None => &[],
+        };
+        elf.sections = headers
+            .into_iter()
+            .map(|(name, section)| Section {
+                name: c_str(names, name as usize),
+                ..section
+            })
+            .collect();
+        Ok(elf)
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    /// Contents of `section`, empty for .bss
+    pub fn data(&self, section: &Section) -> Result<&'a [u8], ElfError> {
+        if section.kind == SHT_NOBITS {
+            return Ok(&[]);
+        }
+        let start = section.offset as usize;
+        self.data
+            .get(start..start + section.size as usize)
+            .ok_or(ElfError::Truncated)
+    }
+
+    /// The symbol table, empty if the file is stripped
+    pub fn symbols(&self) -> Result<Vec<Symbol>, ElfError> {
+        let Some(symtab) = self.sections.iter().find(|s| s.kind == SHT_SYMTAB) else {
+            return Ok(Vec::new());
+        };
+        self.data(symtab)?
+            .chunks_exact(SYMBOL_SIZE)
+            .map(|s| {
+                Ok(Symbol {
+                    shndx: u16_at(s, 14)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Entries of a `SHT_REL` or `SHT_RELA` section
+    pub fn relocations(&self, section: &Section) -> Result<Vec<Rel>, ElfError> {
+        let size = match section.kind {
+            SHT_REL => 8,
+            SHT_RELA => 12,
+            _ => return Ok(Vec::new()),
+        };
+        self.data(section)?
+            .chunks_exact(size)
+            .map(|r| {
+                let info = u32_at(r, 4)?;
+                Ok(Rel {
+                    offset: u32_at(r, 0)?,
+                    kind: info as u8,
+                    symbol: info >> 8,
+                })
+            })
+            .collect()
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, ElfError> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or(ElfError::Truncated)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, ElfError> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(ElfError::Truncated)
+}
+
+fn c_str(data: &[u8], offset: usize) -> String {
+    let s = data.get(offset..).unwrap_or_default();
+    let len = s.iter().position(|b| *b == 0).unwrap_or(s.len());
+    String::from_utf8_lossy(&s[..len]).into_owned()
+}
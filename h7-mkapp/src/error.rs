@@ -0,0 +1,55 @@
+use {
+    crate::{elf::ElfError, image::LayoutError, reloc::RelocError},
+    h7_appfmt::AppFmtError,
+    std::io,
+};
+
+#[derive(Debug)]
+pub enum MkappError {
+    /// Reading or writing a file failed (path)
+    Io(String, io::Error),
+    Elf(ElfError),
+    Layout(LayoutError),
+    Reloc(RelocError),
+    /// Invalid app image
+    Format(AppFmtError),
+    /// Bad command line
+    Usage(String),
+}
+
+impl From<ElfError> for MkappError {
+    fn from(err: ElfError) -> Self {
+        Self::Elf(err)
+    }
+}
+
+impl From<LayoutError> for MkappError {
+    fn from(err: LayoutError) -> Self {
+        Self::Layout(err)
+    }
+}
+
+impl From<RelocError> for MkappError {
+    fn from(err: RelocError) -> Self {
+        Self::Reloc(err)
+    }
+}
+
+impl From<AppFmtError> for MkappError {
+    fn from(err: AppFmtError) -> Self {
+        Self::Format(err)
+    }
+}
+
+impl core::fmt::Display for MkappError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "{path}: {e}"),
+            Self::Elf(e) => write!(f, "{e}"),
+            Self::Layout(e) => write!(f, "{e}"),
+            Self::Reloc(e) => write!(f, "{e}"),
+            Self::Format(e) => write!(f, "{e}"),
+            Self::Usage(msg) => write!(f, "{msg}"),
+        }
+    }
+}
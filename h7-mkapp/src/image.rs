@@ -0,0 +1,329 @@
+//! The payload of an app from its ELF file, what `objcopy -O binary` would write, with the
+//! layout checked against what the loader expects
+
+use crate::elf::{Elf, ElfError, Section, SHF_EXECINSTR, SHF_WRITE, SHT_NOBITS};
+
+/// Output section of the `ENTRY_POINT` static in `h7-app.ld`
+const ENTRY_SECTION: &str = ".entry";
+/// Output section of the `H7_API_VERSION` static in `h7-app.ld`, not loaded
+const API_VERSION_SECTION: &str = ".h7_api_version";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub payload: Vec<u8>,
+    pub entry_address: u32,
+    /// Read-only sections, code and constants
+    pub text_size: u32,
+    /// Initialized writable sections
+    pub data_size: u32,
+    pub bss_size: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayoutError {
+    Elf(ElfError),
+    /// No loaded sections
+    Empty,
+    /// The entry point section is missing or too small for an address
+    NoEntry,
+    /// Another section comes before the entry point (section, address)
+    EntryNotFirst(String, u32),
+    /// The app does not start at the load address (address, load address)
+    LoadAddress(u32, u32),
+    /// Sections share memory (section, section)
+    Overlap(String, String),
+    /// The app does not fit in app memory (size, max)
+    TooLarge(usize, usize),
+    /// The entry point is not in code (address)
+    EntryNotCode(u32),
+    /// A binary without room for the entry point (size)
+    BinTooShort(usize),
+    /// The binary is not the payload of the ELF file passed with it
+    NotFromElf,
+    /// Neither the ELF nor the command line has the API version the app is built against
+    NoApiVersion,
+}
+
+impl From<ElfError> for LayoutError {
+    fn from(err: ElfError) -> Self {
+        Self::Elf(err)
+    }
+}
+
+impl core::fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Elf(e) => write!(f, "{e}"),
+            Self::Empty => write!(f, "No loaded sections, is it linked with h7-app.ld?"),
+            Self::NoEntry => write!(
+                f,
+                "No {ENTRY_SECTION} section with the entry point, is ENTRY_POINT defined and \
+                 linked with h7-app.ld?"
+            ),
+            Self::EntryNotFirst(name, addr) => write!(
+                f,
+                "{name} at 0x{addr:08x} comes before {ENTRY_SECTION}, the entry point has to be \
+                 the first word of the app"
+            ),
+            Self::LoadAddress(addr, load) => write!(
+                f,
+                "App starts at 0x{addr:08x}, expected the load address 0x{load:08x}"
+            ),
+            Self::Overlap(a, b) => write!(f, "Sections {a} and {b} overlap"),
+            Self::TooLarge(size, max) => {
+                write!(f, "App too large ({size} bytes with .bss, max {max})")
+            }
+            Self::EntryNotCode(addr) => {
+                write!(
+                    f,
+                    "Entry point 0x{addr:08x} is not in an executable section"
+                )
+            }
+            Self::BinTooShort(size) => {
+                write!(f, "Binary too short for the entry point ({size} bytes)")
+            }
+            Self::NotFromElf => write!(f, "The binary was not made from the ELF file"),
+            Self::NoApiVersion => write!(
+                f,
+                "No API version, is the app linked with h7-applib and h7-app.ld? Pass \
+                 --api-version otherwise"
+            ),
+        }
+    }
+}
+
+/// The API version the app is built against, exported by h7-applib
+pub fn api_version(elf: &Elf) -> Result<u32, LayoutError> {
+    let section = elf
+        .section(API_VERSION_SECTION)
+        .ok_or(LayoutError::NoApiVersion)?;
+    match elf.data(section)? {
+        [a, b, c, d] => Ok(u32::from_le_bytes([*a, *b, *c, *d])),
+        _ => Err(LayoutError::NoApiVersion),
+    }
+}
+
+impl Image {
+    /// A raw binary, starting with the `ENTRY_POINT` static. The section sizes are only known
+    /// from the ELF file, see [`Image::layout_from`].
+    pub fn from_bin(payload: Vec<u8>) -> Result<Self, LayoutError> {
+        if payload.len() < 4 {
+            return Err(LayoutError::BinTooShort(payload.len()));
+        }
+        Ok(Self {
+            entry_address: u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]),
+            text_size: 0,
+            data_size: 0,
+            bss_size: 0,
+            payload,
+        })
+    }
+
+    /// The section sizes of a binary from the ELF file it was made from
+    pub fn layout_from(
+        self,
+        elf: &Elf,
+        load_address: u32,
+        max_size: usize,
+    ) -> Result<Self, LayoutError> {
+        let image = Self::from_elf(elf, load_address, max_size)?;
+        match image.payload == self.payload {
+            true => Ok(image),
+            false => Err(LayoutError::NotFromElf),
+        }
+    }
+
+    /// Lay out the loaded sections of `elf` from `load_address`, the gaps are zeroed
+    pub fn from_elf(elf: &Elf, load_address: u32, max_size: usize) -> Result<Self, LayoutError> {
+        let mut sections: Vec<&Section> = elf
+            .sections
+            .iter()
+            .filter(|s| s.is_alloc() && s.size > 0)
+            .collect();
+        sections.sort_by_key(|s| s.addr);
+
+        let first = sections.first().ok_or(LayoutError::Empty)?;
+        let entry = elf
+            .section(ENTRY_SECTION)
+            .filter(|s| s.is_alloc() && s.size >= 4 && s.kind != SHT_NOBITS)
+            .ok_or(LayoutError::NoEntry)?;
+        if first.name != ENTRY_SECTION {
+            return Err(LayoutError::EntryNotFirst(first.name.clone(), first.addr));
+        }
+        if entry.addr != load_address {
+            return Err(LayoutError::LoadAddress(entry.addr, load_address));
+        }
+        for pair in sections.windows(2) {
+            if pair[0].addr + pair[0].size > pair[1].addr {
+                return Err(LayoutError::Overlap(
+                    pair[0].name.clone(),
+                    pair[1].name.clone(),
+                ));
+            }
+        }
+        let end = sections.iter().map(|s| s.addr + s.size).max().unwrap_or(0);
+        let memory_size = (end - load_address) as usize;
+        if memory_size > max_size {
+            return Err(LayoutError::TooLarge(memory_size, max_size));
+        }
+
+        let mut image = Image {
+            payload: Vec::new(),
+            entry_address: 0,
+            text_size: 0,
+            data_size: 0,
+            bss_size: 0,
+        };
+        for section in &sections {
+            match (section.kind, section.flags & SHF_WRITE != 0) {
+                (SHT_NOBITS, _) => continue,
+                (_, false) => image.text_size += section.size,
+                (_, true) => image.data_size += section.size,
+            }
+            let start = (section.addr - load_address) as usize;
+            image.payload.resize(start, 0);
+            image.payload.extend_from_slice(elf.data(section)?);
+        }
+        // Zeroed by the loader, from the end of the payload
+        image.bss_size = memory_size as u32 - image.payload.len() as u32;
+
+        // The ENTRY_POINT static
+        let entry_data = elf.data(entry)?;
+        image.entry_address =
+            u32::from_le_bytes([entry_data[0], entry_data[1], entry_data[2], entry_data[3]]);
+        let in_code = sections.iter().any(|s| {
+            s.flags & SHF_EXECINSTR != 0
+                && (s.addr..s.addr + s.size).contains(&(image.entry_address & !1))
+        });
+        if !in_code {
+            return Err(LayoutError::EntryNotCode(image.entry_address));
+        }
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            elf::SHT_PROGBITS,
+            test_elf::{elf, section, words},
+        },
+    };
+
+    const LOAD: u32 = 0x2400_0000;
+
+    fn app(entry: u32, sections: [(&'static str, u32); 3]) -> Vec<u8> {
+        let [(first, first_addr), (text, text_addr), (data, data_addr)] = sections;
+        elf(vec![
+            section(first, SHT_PROGBITS, first_addr, words(&[entry])),
+            section(text, SHT_PROGBITS, text_addr, words(&[0xbf00_4770])),
+            section(data, SHT_PROGBITS, data_addr, words(&[1, 2])),
+            section(".bss", SHT_NOBITS, data_addr + 8, Vec::new()),
+        ])
+    }
+
+    fn layout(entry: u32, sections: [(&'static str, u32); 3]) -> Result<Image, LayoutError> {
+        Image::from_elf(&Elf::parse(&app(entry, sections)).unwrap(), LOAD, 0x100)
+    }
+
+    #[test]
+    fn objcopy_layout() {
+        let image = layout(
+            LOAD + 9,
+            [
+                (".entry", LOAD),
+                (".text", LOAD + 8),
+                (".data", LOAD + 0x10),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            image,
+            Image {
+                payload: words(&[LOAD + 9, 0, 0xbf00_4770, 0, 1, 2]),
+                entry_address: LOAD + 9,
+                text_size: 8,
+                data_size: 8,
+                bss_size: 16,
+            }
+        );
+    }
+
+    #[test]
+    fn bin_layout() {
+        let sections = [
+            (".entry", LOAD),
+            (".text", LOAD + 8),
+            (".data", LOAD + 0x10),
+        ];
+        let elf_data = app(LOAD + 9, sections);
+        let elf = Elf::parse(&elf_data).unwrap();
+        let from_elf = layout(LOAD + 9, sections).unwrap();
+        let bin = Image::from_bin(from_elf.payload.clone()).unwrap();
+        assert_eq!((bin.text_size, bin.data_size, bin.bss_size), (0, 0, 0));
+        assert_eq!(bin.layout_from(&elf, LOAD, 0x100), Ok(from_elf.clone()));
+
+        let mut other = from_elf.payload;
+        other[8] ^= 1;
+        assert_eq!(
+            Image::from_bin(other)
+                .unwrap()
+                .layout_from(&elf, LOAD, 0x100),
+            Err(LayoutError::NotFromElf)
+        );
+    }
+
+    #[test]
+    fn api_version_section() {
+        let text = section(".text", SHT_PROGBITS, LOAD, words(&[0xbf00_4770]));
+        let elf_data = elf(vec![section(
+            ".h7_api_version",
+            SHT_PROGBITS,
+            0,
+            words(&[2]),
+        )]);
+        assert_eq!(api_version(&Elf::parse(&elf_data).unwrap()), Ok(2));
+        let elf_data = elf(vec![text]);
+        assert_eq!(
+            api_version(&Elf::parse(&elf_data).unwrap()),
+            Err(LayoutError::NoApiVersion)
+        );
+    }
+
+    #[test]
+    fn layout_errors() {
+        let ok = [
+            (".entry", LOAD),
+            (".text", LOAD + 8),
+            (".data", LOAD + 0x10),
+        ];
+        let entry = LOAD + 9;
+
+        assert_eq!(
+            layout(entry, [(".entry", LOAD + 4), (".text", LOAD + 8), ok[2]]),
+            Err(LayoutError::LoadAddress(LOAD + 4, LOAD))
+        );
+        assert_eq!(
+            layout(entry, [(".entry", LOAD + 8), (".text", LOAD), ok[2]]),
+            Err(LayoutError::EntryNotFirst(".text".into(), LOAD))
+        );
+        assert_eq!(
+            layout(entry, [(".rodata", LOAD), ok[1], ok[2]]),
+            Err(LayoutError::NoEntry)
+        );
+        assert_eq!(
+            layout(entry, [ok[0], ok[1], (".data", LOAD + 0xa)]),
+            Err(LayoutError::Overlap(".text".into(), ".data".into()))
+        );
+        assert_eq!(
+            layout(entry, [ok[0], ok[1], (".data", LOAD + 0x100)]),
+            Err(LayoutError::TooLarge(0x118, 0x100))
+        );
+        assert_eq!(
+            layout(LOAD + 0x11, ok),
+            Err(LayoutError::EntryNotCode(LOAD + 0x11))
+        );
+    }
+}
@@ -0,0 +1,422 @@
+//! What is in an app image, and whether the firmware would run it
+
+use {
+    crate::MkappError,
+    h7_appfmt::{
+        crc32, AppFmtError, AppHeader, Fingerprint, PublicKey, DEFAULT_APP_SIZE, MOVW_ALIGN,
+    },
+};
+
+/// An app image with the checks the firmware does before running it
+#[derive(Debug, Clone)]
+pub struct AppInfo {
+    pub header: AppHeader,
+    /// Size of the image
+    pub size: usize,
+    /// The body decompressed and relocated, its CRC and the app compatible with the firmware
+    pub load: Result<(), AppFmtError>,
+    /// The entry point, checked like `prun` does
+    pub entry: Result<&'static str, &'static str>,
+    /// The key the app is signed with, `None` if the image didn't load far enough to check
+    pub signature: Option<Result<PublicKey, AppFmtError>>,
+    /// Whether the signature was checked against trusted keys, or only its own key
+    pub trusted_keys: bool,
+}
+
+impl AppInfo {
+    /// Why the firmware would refuse the app, empty if it would run it. Unsigned apps are
+    /// only refused with trusted keys to check against.
+    pub fn errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if let Err(e) = self.load {
+            errors.push(e.to_string());
+        }
+        if let Err(e) = self.entry {
+            let addr = self.header.entry_address();
+            errors.push(format!("Entry point 0x{addr:08x} is {e}"));
+        }
+        match self.signature {
+            Some(Err(AppFmtError::Unsigned)) if !self.trusted_keys => {}
+            Some(Err(e)) => errors.push(e.to_string()),
+            _ => {}
+        }
+        errors
+    }
+}
+
+impl core::fmt::Display for AppInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let header = &self.header;
+        writeln!(f, "Name: {} {}", header.name(), header.version())?;
+        writeln!(
+            f,
+            "Address: 0x{addr:08x} ({entry}), API: v{api}, CRC: 0x{crc:08x}",
+            addr = header.entry_address(),
+            entry = self.entry.unwrap_or_else(|e| e),
+            api = header.api_version,
+            crc = header.payload_crc,
+        )?;
+        writeln!(
+            f,
+            "Size: 0x{size:x} (text 0x{text:x}, data 0x{data:x}, bss 0x{bss:x}), {relocs} relocations",
+            size = header.memory_size(),
+            text = header.text_size,
+            data = header.data_size,
+            bss = header.bss_size,
+            relocs = header.reloc_count,
+        )?;
+        write!(
+            f,
+            "Image: {} bytes, format v{}, load address 0x{:08x}",
+            self.size, header.format_version, header.load_address
+        )?;
+        match header.is_compressed() {
+            true => writeln!(
+                f,
+                ", payload compressed {} -> {} bytes",
+                header.payload_size, header.stored_size
+            )?,
+            false => writeln!(f)?,
+        }
+        match (self.signature, self.trusted_keys) {
+            (Some(Ok(key)), true) => writeln!(f, "Signature: trusted key {}", Fingerprint(&key))?,
+            (Some(Ok(key)), false) => writeln!(f, "Signature: key {}", Fingerprint(&key))?,
+            (Some(Err(e)), _) => writeln!(f, "Signature: {e}")?,
+            (None, _) => writeln!(f, "Signature: not checked")?,
+        }
+        match self.load {
+            Ok(()) => write!(f, "Load: ok"),
+            Err(e) => write!(f, "Load: {e}"),
+        }
+    }
+}
+
+/// Check an image like the firmware loads it. The signature is checked against the `trusted`
+/// keys, or only against the key in the image without any.
+pub fn inspect(image: &[u8], trusted: &[PublicKey]) -> Result<AppInfo, AppFmtError> {
+    let header = AppHeader::from_bytes(image)?;
+    let mut memory = vec![0; header.load_size()];
+    let (load, signature) = match h7_appfmt::load(image, &mut memory) {
+        Ok(loaded) => {
+            let crc = loaded.header.check_payload_crc(crc32(loaded.content()));
+            let keys = match (trusted, loaded.signature) {
+                ([], Some(block)) => vec![block.public_key],
+                _ => trusted.to_vec(),
+            };
+            let signature = loaded.verify_signature(&keys);
+            // The firmware moves relocatable apps by multiples of 64K
+            let load = crc
+                .and_then(|_| {
+                    let api = h7_api::API_VERSION;
+                    header.check_compatible(api, header.load_address, DEFAULT_APP_SIZE)
+                })
+                .and_then(|_| loaded.relocate(header.load_address.wrapping_add(MOVW_ALIGN)))
+                .map(|_| ());
+            (load, Some(signature))
+        }
+        Err(e) => (Err(e), None),
+    };
+    Ok(AppInfo {
+        header,
+        size: image.len(),
+        load,
+        entry: check_entry(&header),
+        signature,
+        trusted_keys: !trusted.is_empty(),
+    })
+}
+
+/// The entry point within the app and a valid arm or thumb address
+fn check_entry(header: &AppHeader) -> Result<&'static str, &'static str> {
+    let addr = header.entry_address();
+    let in_range = addr
+        .checked_sub(header.load_address)
+        .is_some_and(|offset| offset >= 4 && (offset as usize) < header.memory_size());
+    match (in_range, h7_appfmt::check_entry_alignment(addr)) {
+        (true, Ok(valid)) => Ok(valid),
+        (true, Err(_)) => Err("invalid"),
+        (false, _) => Err("out of range"),
+    }
+}
+
+/// Header fields, to compare images
+fn fields(header: &AppHeader) -> [(&'static str, String); 15] {
+    [
+        ("format version", header.format_version.to_string()),
+        ("header size", header.header_size.to_string()),
+        ("api version", header.api_version.to_string()),
+        ("load address", format!("0x{:08x}", header.load_address)),
+        ("entry offset", format!("0x{:x}", header.entry_offset)),
+        ("payload size", header.payload_size.to_string()),
+        ("text size", header.text_size.to_string()),
+        ("data size", header.data_size.to_string()),
+        ("bss size", header.bss_size.to_string()),
+        ("flags", format!("0x{:x}", header.flags)),
+        ("name", header.name().to_string()),
+        ("version", header.version().to_string()),
+        ("relocations", header.reloc_count.to_string()),
+        ("payload crc", format!("0x{:08x}", header.payload_crc)),
+        ("stored size", header.stored_size.to_string()),
+    ]
+}
+
+/// Differences between two images, in the header and in the body as loaded. Images that only
+/// differ in how they are stored, compressed or not, have the same body.
+pub fn diff(a: &[u8], b: &[u8]) -> Result<Vec<String>, MkappError> {
+    let (header_a, header_b) = (AppHeader::from_bytes(a)?, AppHeader::from_bytes(b)?);
+    let mut diffs: Vec<String> = fields(&header_a)
+        .into_iter()
+        .zip(fields(&header_b))
+        .filter(|((_, a), (_, b))| a != b)
+        .map(|((name, a), (_, b))| format!("{name}: {a} -> {b}"))
+        .collect();
+
+    let (mut memory_a, mut memory_b) =
+        (vec![0; header_a.load_size()], vec![0; header_b.load_size()]);
+    let (loaded_a, loaded_b) = (
+        h7_appfmt::load(a, &mut memory_a)?,
+        h7_appfmt::load(b, &mut memory_b)?,
+    );
+    let (content_a, content_b) = (loaded_a.content(), loaded_b.content());
+    let differ = content_a
+        .iter()
+        .zip(content_b)
+        .filter(|(a, b)| a != b)
+        .count();
+    if let Some(first) = content_a.iter().zip(content_b).position(|(a, b)| a != b) {
+        diffs.push(format!(
+            "body: {differ} bytes differ, the first at 0x{first:x}"
+        ));
+    }
+    if content_a.len() != content_b.len() {
+        diffs.push(format!(
+            "body size: {} -> {} bytes",
+            content_a.len(),
+            content_b.len()
+        ));
+    }
+
+    let key = |signature: Option<h7_appfmt::SignatureBlock>| match signature {
+        Some(block) => Fingerprint(&block.public_key).to_string(),
+        None => "unsigned".to_string(),
+    };
+    let (key_a, key_b) = (key(loaded_a.signature), key(loaded_b.signature));
+    if key_a != key_b {
+        diffs.push(format!("signature: {key_a} -> {key_b}"));
+    }
+    Ok(diffs)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::sign,
+        h7_appfmt::{public_key, HEADER_SIZE, SEED_SIZE},
+    };
+
+    const LOAD: u32 = 0x2400_0000;
+    const KEY: [u8; SEED_SIZE] = [9; SEED_SIZE];
+
+    fn image(entry: u32) -> Vec<u8> {
+        let mut payload = entry.to_le_bytes().to_vec();
+        payload.extend_from_slice(&[0x70, 0x47, 0x00, 0xbf]);
+        payload.extend_from_slice(&[0; 56]);
+        let mut header = AppHeader::new(1, LOAD, entry, &payload);
+        header.set_name("test");
+        (header.text_size, header.data_size, header.bss_size) = (8, 56, 0x40);
+        [header.to_bytes().as_slice(), &payload].concat()
+    }
+
+    fn compressed(image: &[u8]) -> Vec<u8> {
+        let mut header = AppHeader::from_bytes(image).unwrap();
+        let stored = lz4_flex::block::compress(header.payload(image).unwrap());
+        header.set_compressed(&stored);
+        [header.to_bytes().as_slice(), &stored].concat()
+    }
+
+    #[test]
+    fn valid() {
+        let image = image(LOAD + 5);
+        let info = inspect(&image, &[]).unwrap();
+        assert_eq!(info.load, Ok(()));
+        assert_eq!(info.entry, Ok("valid thumb"));
+        assert_eq!(info.signature, Some(Err(AppFmtError::Unsigned)));
+        assert!(info.errors().is_empty());
+
+        let text = info.to_string();
+        assert!(text.starts_with("Name: test \nAddress: 0x24000005 (valid thumb), API: v1"));
+        assert!(text.contains("Size: 0x80 (text 0x8, data 0x38, bss 0x40), 0 relocations"));
+
+        // Unsigned apps fail with trusted keys
+        let info = inspect(&image, &[public_key(&KEY)]).unwrap();
+        assert_eq!(info.errors(), ["App is not signed"]);
+
+        let compressed = compressed(&image);
+        let info = inspect(&compressed, &[]).unwrap();
+        assert!(info.errors().is_empty());
+        assert!(info.to_string().contains("payload compressed 64 -> "));
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            inspect(&[0; 10], &[]).err(),
+            Some(AppFmtError::TooShort(10))
+        );
+
+        let mut corrupt = image(LOAD + 5);
+        let end = corrupt.len() - 1;
+        corrupt[end] = 1;
+        let info = inspect(&corrupt, &[]).unwrap();
+        assert!(matches!(info.load, Err(AppFmtError::PayloadCrc { .. })));
+        assert_eq!(info.errors().len(), 1);
+
+        let truncated = &image(LOAD + 5)[..HEADER_SIZE + 16];
+        let info = inspect(truncated, &[]).unwrap();
+        assert!(matches!(
+            info.load,
+            Err(AppFmtError::PayloadTruncated { .. })
+        ));
+        assert_eq!(info.signature, None);
+
+        // Misaligned arm entry point
+        let info = inspect(&image(LOAD + 6), &[]).unwrap();
+        assert_eq!(info.entry, Err("invalid"));
+        assert_eq!(info.load, Err(AppFmtError::EntryAlignment(LOAD + 6)));
+        assert_eq!(info.errors().len(), 2);
+        // The header is not part of the app
+        let info = inspect(&image(LOAD + 1), &[]).unwrap();
+        assert_eq!(info.entry, Err("out of range"));
+    }
+
+    #[test]
+    fn signatures() {
+        let signed = sign::sign(&image(LOAD + 5), &KEY).unwrap();
+        let info = inspect(&signed, &[]).unwrap();
+        assert_eq!(info.signature, Some(Ok(public_key(&KEY))));
+        assert!(info.to_string().contains("Signature: key "));
+        let info = inspect(&signed, &[public_key(&KEY)]).unwrap();
+        assert!(info.to_string().contains("Signature: trusted key "));
+        assert!(info.errors().is_empty());
+
+        let info = inspect(&signed, &[public_key(&[1; SEED_SIZE])]).unwrap();
+        assert_eq!(
+            info.signature,
+            Some(Err(AppFmtError::UntrustedKey(public_key(&KEY))))
+        );
+        assert_eq!(info.errors().len(), 1);
+
+        // Tampered with, fails even without trusted keys
+        let mut tampered = signed.clone();
+        tampered[HEADER_SIZE + 4] = 0;
+        let info = inspect(&tampered, &[]).unwrap();
+        assert_eq!(info.signature, Some(Err(AppFmtError::BadSignature)));
+        assert!(!info.errors().is_empty());
+    }
+
+    #[test]
+    fn differences() {
+        let image = image(LOAD + 5);
+        assert!(diff(&image, &image).unwrap().is_empty());
+
+        // Only stored differently
+        let compressed = compressed(&image);
+        let stored = AppHeader::from_bytes(&compressed).unwrap().stored_size;
+        assert_eq!(
+            diff(&image, &compressed).unwrap(),
+            [
+                "flags: 0x0 -> 0x4".to_string(),
+                format!("stored size: 64 -> {stored}")
+            ]
+        );
+
+        let mut header = AppHeader::from_bytes(&image).unwrap();
+        header.set_name("other");
+        let mut payload = header.payload(&image).unwrap().to_vec();
+        payload[8] = 1;
+        payload[9] = 2;
+        let other = AppHeader::new(1, LOAD, LOAD + 5, &payload);
+        header.payload_crc = other.payload_crc;
+        let changed = [header.to_bytes().as_slice(), &payload].concat();
+        let signed = sign::sign(&changed, &KEY).unwrap();
+        assert_eq!(
+            diff(&image, &signed).unwrap(),
+            [
+                "flags: 0x0 -> 0x2".to_string(),
+                "name: test -> other".to_string(),
+                format!(
+                    "payload crc: 0x{:08x} -> 0x{:08x}",
+                    AppHeader::from_bytes(&image).unwrap().payload_crc,
+                    other.payload_crc
+                ),
+                "body: 2 bytes differ, the first at 0x8".to_string(),
+                format!("signature: unsigned -> {}", Fingerprint(&public_key(&KEY))),
+            ]
+        );
+    }
+
+    #[test]
+    fn differences_in_layout() {
+        let (image, moved) = (image(LOAD + 5), image(LOAD + 9));
+        let crc = |image: &[u8]| AppHeader::from_bytes(image).unwrap().payload_crc;
+
+        // The entry point is the first word of the payload
+        assert_eq!(
+            diff(&image, &moved).unwrap(),
+            [
+                "entry offset: 0x5 -> 0x9".to_string(),
+                format!(
+                    "payload crc: 0x{:08x} -> 0x{:08x}",
+                    crc(&image),
+                    crc(&moved)
+                ),
+                "body: 1 bytes differ, the first at 0x0".to_string(),
+            ]
+        );
+
+        // A longer payload, the rest of the body is the same
+        let mut header = AppHeader::from_bytes(&image).unwrap();
+        let mut payload = header.payload(&image).unwrap().to_vec();
+        payload.extend_from_slice(&[0; 16]);
+        header.payload_size = payload.len() as u32;
+        header.stored_size = payload.len() as u32;
+        header.bss_size = 0x30;
+        header.payload_crc = AppHeader::new(1, LOAD, LOAD + 5, &payload).payload_crc;
+        let longer = [header.to_bytes().as_slice(), &payload].concat();
+        assert_eq!(
+            diff(&longer, &image).unwrap(),
+            [
+                "payload size: 80 -> 64".to_string(),
+                "bss size: 48 -> 64".to_string(),
+                format!(
+                    "payload crc: 0x{:08x} -> 0x{:08x}",
+                    crc(&longer),
+                    crc(&image)
+                ),
+                "stored size: 80 -> 64".to_string(),
+                "body size: 80 -> 64 bytes".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn differences_invalid() {
+        let image = image(LOAD + 5);
+        assert!(matches!(
+            diff(&image, &[0; 10]),
+            Err(MkappError::Format(AppFmtError::TooShort(10)))
+        ));
+        assert!(matches!(
+            diff(&image, &image[..HEADER_SIZE + 16]),
+            Err(MkappError::Format(AppFmtError::PayloadTruncated { .. }))
+        ));
+        // Not checked against the CRC, the body is compared as is
+        let mut corrupt = image.clone();
+        corrupt[HEADER_SIZE + 8] = 1;
+        assert_eq!(
+            diff(&image, &corrupt).unwrap(),
+            ["body: 1 bytes differ, the first at 0x8"]
+        );
+    }
+}
@@ -0,0 +1,18 @@
+//! Making `.h7` app images from ELF files or binaries, and inspecting them. The `h7-mkapp`
+//! binary is the command line to this.
+
+pub mod elf;
+mod error;
+pub mod image;
+pub mod inspect;
+pub mod pack;
+pub mod reloc;
+pub mod sign;
+#[cfg(test)]
+mod test_elf;
+
+pub use {
+    error::MkappError,
+    inspect::{diff, inspect, AppInfo},
+    pack::{pack, PackOptions, Packed},
+};
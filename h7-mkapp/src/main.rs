@@ -1,54 +1,302 @@
-use std::{env, fs};
+use {
+    h7_appfmt::{AppHeader, Fingerprint, PublicKey},
+    h7_mkapp::{sign, MkappError, PackOptions},
+    std::{env, fs, path::Path, process::ExitCode},
+};
 
-const ARM_ADDR_ALIGN: u32 = 4;
-const THUMB_ADDR_ALIGN: u32 = 2;
-const THUMB_MASK: u32 = 0x0000_0001;
+const USAGE: &str = "\
+Usage:
+    h7-mkapp [pack] <input.elf|input.bin> <output.h7> [--name <name>] [--version <version>]
+             [--bss <bytes>] [--load-address <hex>] [--api-version <version>] [--relocs <input.elf>]
+             [--key <key>] [--compress]
+    h7-mkapp info [--trust <key.pub>]... <input.h7>
+    h7-mkapp verify [--trust <key.pub>]... <input.h7>...
+    h7-mkapp diff <a.h7> <b.h7>
+    h7-mkapp hex <input.h7> [output.hex]
+    h7-mkapp sign --key <key> <input.h7> [output.h7]
+    h7-mkapp keygen <key>";
 
-fn main() {
-    let mut args = env::args().skip(1);
-    let input = args.next().expect("No input");
-    let output = args.next().expect("No output");
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let res = match args.first().map(String::as_str) {
+        None | Some("help" | "--help" | "-h") => {
+            println!("{USAGE}");
+            Ok(ExitCode::SUCCESS)
+        }
+        Some("pack") => pack(&args[1..]),
+        Some("info") => info(&args[1..]),
+        Some("verify") => verify(&args[1..]),
+        Some("diff") => diff(&args[1..]),
+        Some("hex") => hex(&args[1..]),
+        Some("sign") => sign_command(&args[1..]),
+        Some("keygen") => keygen(&args[1..]),
+        _ => pack(&args),
+    };
+    res.unwrap_or_else(|e| {
+        match e {
+            MkappError::Usage(_) => eprintln!("{e}\n\n{USAGE}"),
+            _ => eprintln!("Error: {e}"),
+        }
+        ExitCode::FAILURE
+    })
+}
 
-    println!("input = {}", input);
-    println!("output = {}", output);
+/// Options and their values, and the positional arguments in between
+struct Args<'a> {
+    args: std::slice::Iter<'a, String>,
+    positional: Vec<&'a str>,
+}
 
-    let input_data = fs::read(&input).unwrap();
-    if input_data.len() < 4 {
-        panic!("Not ennough data");
-    }
-
-    let boot_address =
-        u32::from_le_bytes([input_data[0], input_data[1], input_data[2], input_data[3]]);
-    println!("Boot address: 0x{:08x} ({})", boot_address, {
-        // LSB is not part of the actual address,
-        // but rather indicate if the cpu should
-        // switch to arm or thumb mode.
-        // 0 = ARM, 1 = THUMB
-        let a = boot_address & !THUMB_MASK;
-        match (
-            boot_address & THUMB_MASK, // Thumb?
-            a % THUMB_ADDR_ALIGN,      // Valid Thumb alignment?
-            a % ARM_ADDR_ALIGN,        // Valid ARM alignment?
-        ) {
-            (1, 0, _) => "valid thumb",
-            (0, _, 0) => "valid arm",
-            _ => "invalid",
+impl<'a> Args<'a> {
+    fn new(args: &'a [String]) -> Self {
+        Self {
+            args: args.iter(),
+            positional: Vec::new(),
         }
+    }
+
+    /// The next option, positional arguments are collected on the way
+    fn option(&mut self) -> Option<&'a str> {
+        for arg in self.args.by_ref() {
+            match arg.starts_with("--") {
+                true => return Some(arg),
+                false => self.positional.push(arg),
+            }
+        }
+        None
+    }
+
+    fn value(&mut self, option: &str) -> Result<&'a str, MkappError> {
+        self.args
+            .next()
+            .map(String::as_str)
+            .ok_or_else(|| MkappError::Usage(format!("{option} requires a value")))
+    }
+}
+
+fn unknown(option: &str) -> MkappError {
+    MkappError::Usage(format!("Unknown option {option}"))
+}
+
+fn read(path: &str) -> Result<Vec<u8>, MkappError> {
+    fs::read(path).map_err(|e| MkappError::Io(path.to_string(), e))
+}
+
+fn write(path: &str, data: &[u8]) -> Result<(), MkappError> {
+    fs::write(path, data).map_err(|e| MkappError::Io(path.to_string(), e))
+}
+
+fn read_key(path: &str) -> Result<[u8; h7_appfmt::SEED_SIZE], MkappError> {
+    sign::read_key(Path::new(path)).map_err(|e| MkappError::Io(path.to_string(), e))
+}
+
+/// `--trust <key.pub>` options and the positional arguments
+fn trusted_keys(args: &[String]) -> Result<(Vec<PublicKey>, Vec<&str>), MkappError> {
+    let mut args = Args::new(args);
+    let mut trusted = Vec::new();
+    while let Some(option) = args.option() {
+        match option {
+            "--trust" => {
+                let path = args.value(option)?;
+                let key = sign::read_public_key(Path::new(path))
+                    .map_err(|e| MkappError::Io(path.to_string(), e))?;
+                trusted.push(key);
+            }
+            _ => return Err(unknown(option)),
+        }
+    }
+    Ok((trusted, args.positional))
+}
+
+fn pack(args: &[String]) -> Result<ExitCode, MkappError> {
+    let mut args = Args::new(args);
+    let mut options = PackOptions::default();
+    let mut name = None;
+    while let Some(option) = args.option() {
+        match option {
+            "--name" => name = Some(args.value(option)?.to_string()),
+            "--version" => options.version = args.value(option)?.to_string(),
+            "--bss" => {
+                options.bss_size = args
+                    .value(option)?
+                    .parse()
+                    .map_err(|_| MkappError::Usage("Invalid bss size".into()))?
+            }
+            "--load-address" => {
+                let addr = args.value(option)?;
+                options.load_address = u32::from_str_radix(addr.trim_start_matches("0x"), 16)
+                    .map_err(|_| MkappError::Usage("Invalid load address".into()))?
+            }
+            "--api-version" => {
+                options.api_version = Some(
+                    args.value(option)?
+                        .parse()
+                        .map_err(|_| MkappError::Usage("Invalid API version".into()))?,
+                )
+            }
+            "--relocs" => options.relocs = Some(read(args.value(option)?)?),
+            "--key" => options.key = Some(read_key(args.value(option)?)?),
+            "--compress" => options.compress = true,
+            _ => return Err(unknown(option)),
+        }
+    }
+    let [input, output] = args.positional[..] else {
+        return Err(MkappError::Usage("Expected an input and an output".into()));
+    };
+    options.name = name.unwrap_or_else(|| {
+        Path::new(input)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default()
     });
 
-    let mut output_data = vec![0u8; input_data.len() + 4];
-    // Convert address to big endian (the proper way...)
-    output_data[..4].copy_from_slice(&boot_address.to_be_bytes());
-    output_data[4..input_data.len()].copy_from_slice(&input_data[4..]);
-    let crc = crc::Crc::<u32>::new(&crc::CRC_32_MPEG_2);
-    let mut digest = crc.digest();
-    digest.update(&output_data[..input_data.len()]);
-    let crc_value = digest.finalize();
-    println!("CRC: 0x{:08x}", crc_value);
-    // Store CRC as big endian as well
-    output_data[input_data.len()..].copy_from_slice(&crc_value.to_be_bytes());
-
-    fs::write(&output, &output_data).unwrap();
-    println!("Size: {} bytes", output_data.len());
+    println!("input = {}", input);
+    println!("output = {}", output);
+
+    let packed = h7_mkapp::pack(&read(input)?, &options)?;
+    let header = &packed.header;
+    println!(
+        "Entry address: 0x{:08x} ({})",
+        header.entry_address(),
+        h7_appfmt::check_entry_alignment(header.entry_address()).unwrap_or("invalid")
+    );
+    if header.is_relocatable() {
+        println!("Relocations: {}", header.reloc_count);
+    }
+    println!("Name: {} {}", header.name(), header.version());
+    println!("API version: {}", header.api_version);
+    println!("Load address: 0x{:08x}", header.load_address);
+    println!(
+        "Sections: text {} bytes, data {} bytes, bss {} bytes",
+        header.text_size, header.data_size, header.bss_size
+    );
+    println!("CRC: 0x{:08x}", header.payload_crc);
+    match (options.compress, header.is_compressed()) {
+        (_, true) => println!(
+            "Compressed: {} -> {} bytes",
+            header.payload_size, header.stored_size
+        ),
+        (true, false) => println!("Compressed: no smaller, stored as is"),
+        (false, false) => {}
+    }
+    if header.is_signed() {
+        print_signature(&packed.image)?;
+    }
+
+    write(output, &packed.image)?;
+    println!("Size: {} bytes", packed.image.len());
     println!("Done");
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `info [--trust <key.pub>]... <input.h7>`
+fn info(args: &[String]) -> Result<ExitCode, MkappError> {
+    let (trusted, positional) = trusted_keys(args)?;
+    let [input] = positional[..] else {
+        return Err(MkappError::Usage("Expected an input".into()));
+    };
+    println!("{}", h7_mkapp::inspect(&read(input)?, &trusted)?);
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `verify [--trust <key.pub>]... <input.h7>...`, fails if any of the apps would be refused
+fn verify(args: &[String]) -> Result<ExitCode, MkappError> {
+    let (trusted, positional) = trusted_keys(args)?;
+    if positional.is_empty() {
+        return Err(MkappError::Usage("Expected an input".into()));
+    }
+    let mut valid = true;
+    for input in positional {
+        let errors = match h7_mkapp::inspect(&read(input)?, &trusted) {
+            Ok(info) => info.errors(),
+            Err(e) => vec![e.to_string()],
+        };
+        for e in &errors {
+            println!("{input}: {e}");
+        }
+        if errors.is_empty() {
+            println!("{input}: ok");
+        }
+        valid &= errors.is_empty();
+    }
+    Ok(match valid {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    })
+}
+
+/// `diff <a.h7> <b.h7>`, fails if they differ
+fn diff(args: &[String]) -> Result<ExitCode, MkappError> {
+    let [a, b] = args else {
+        return Err(MkappError::Usage("Expected two images".into()));
+    };
+    let diffs = h7_mkapp::diff(&read(a)?, &read(b)?)?;
+    diffs.iter().for_each(|diff| println!("{diff}"));
+    Ok(match diffs.is_empty() {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    })
+}
+
+/// `hex <input.h7> [output]`, the image in ASCII hex on one line, to stdout without an output
+fn hex(args: &[String]) -> Result<ExitCode, MkappError> {
+    let (input, output) = match args {
+        [input] => (input, None),
+        [input, output] => (input, Some(output)),
+        _ => return Err(MkappError::Usage("Expected an input".into())),
+    };
+    let image = read(input)?;
+    // Don't send what the firmware refuses
+    let errors = h7_mkapp::inspect(&image, &[])?.errors();
+    if !errors.is_empty() {
+        errors.iter().for_each(|e| eprintln!("{input}: {e}"));
+        return Ok(ExitCode::FAILURE);
+    }
+    let hex = sign::to_hex(&image) + "\n";
+    match output {
+        Some(output) => write(output, hex.as_bytes())?,
+        None => print!("{hex}"),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `sign --key <key> <input.h7> [output.h7]`, the input is signed in place without an output
+fn sign_command(args: &[String]) -> Result<ExitCode, MkappError> {
+    let mut args = Args::new(args);
+    let mut key = None;
+    while let Some(option) = args.option() {
+        match option {
+            "--key" => key = Some(read_key(args.value(option)?)?),
+            _ => return Err(unknown(option)),
+        }
+    }
+    let seed = key.ok_or_else(|| MkappError::Usage("No key, pass --key <key>".into()))?;
+    let (input, output) = match args.positional[..] {
+        [input] => (input, input),
+        [input, output] => (input, output),
+        _ => return Err(MkappError::Usage("Expected an input".into())),
+    };
+
+    let signed = sign::sign(&read(input)?, &seed)?;
+    write(output, &signed)?;
+    print_signature(&signed)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `keygen <key>`
+fn keygen(args: &[String]) -> Result<ExitCode, MkappError> {
+    let [path] = args else {
+        return Err(MkappError::Usage("Expected a key file".into()));
+    };
+    sign::keygen(Path::new(path)).map_err(|e| MkappError::Io(path.clone(), e))?;
+    println!("Key: {path}");
+    println!("Public key: {}", sign::public_path(Path::new(path)));
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_signature(image: &[u8]) -> Result<(), MkappError> {
+    let header = AppHeader::from_bytes(image)?;
+    let key = header.signature_block(image)?.public_key;
+    println!("Signed by: {} ({})", sign::to_hex(&key), Fingerprint(&key));
+    Ok(())
 }
@@ -0,0 +1,252 @@
+//! An app image from an ELF file or a binary
+
+use {
+    crate::{
+        elf,
+        elf::Elf,
+        image::{self, Image, LayoutError},
+        reloc, sign, MkappError,
+    },
+    h7_appfmt::{AppHeader, DEFAULT_APP_SIZE, DEFAULT_LOAD_ADDRESS, MOVW_ALIGN, SEED_SIZE},
+};
+
+#[derive(Debug, Clone)]
+pub struct PackOptions {
+    pub name: String,
+    pub version: String,
+    /// Least .bss size, the ELF may need more
+    pub bss_size: u32,
+    pub load_address: u32,
+    /// API version the app is built against, instead of the one in the ELF
+    pub api_version: Option<u32>,
+    /// The ELF file a binary was made from, for its section sizes and relocations
+    pub relocs: Option<Vec<u8>>,
+    /// Seed of the key to sign the app with
+    pub key: Option<[u8; SEED_SIZE]>,
+    /// LZ4 compress the payload, if that makes it smaller
+    pub compress: bool,
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            version: String::new(),
+            bss_size: 0,
+            load_address: DEFAULT_LOAD_ADDRESS,
+            api_version: None,
+            relocs: None,
+            key: None,
+            compress: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Packed {
+    pub header: AppHeader,
+    pub image: Vec<u8>,
+}
+
+/// Make an app image from an ELF file, or a binary starting with the entry point
+pub fn pack(input: &[u8], options: &PackOptions) -> Result<Packed, MkappError> {
+    let load_address = options.load_address;
+    // An ELF file is laid out here, its relocations are used if it was linked with them
+    let (image, relocs) = match input.starts_with(elf::MAGIC) {
+        true => {
+            let elf = Elf::parse(input)?;
+            let image = Image::from_elf(&elf, load_address, DEFAULT_APP_SIZE)?;
+            (image, reloc::has_relocations(&elf).then_some(input))
+        }
+        false => {
+            let image = Image::from_bin(input.to_vec())?;
+            let relocs = options.relocs.as_deref();
+            let image = match relocs {
+                Some(elf) => {
+                    image.layout_from(&Elf::parse(elf)?, load_address, DEFAULT_APP_SIZE)?
+                }
+                None => image,
+            };
+            (image, relocs)
+        }
+    };
+    let payload = &image.payload;
+
+    // Not the packer's version, repacking must not make the app require a newer host
+    let elf_data = match input.starts_with(elf::MAGIC) {
+        true => Some(input),
+        false => options.relocs.as_deref(),
+    };
+    let api_version = match (options.api_version, elf_data) {
+        (Some(version), _) => version,
+        (None, Some(elf_data)) => image::api_version(&Elf::parse(elf_data)?)?,
+        (None, None) => return Err(LayoutError::NoApiVersion.into()),
+    };
+
+    let mut header = AppHeader::new(api_version, load_address, image.entry_address, payload);
+    header.text_size = image.text_size;
+    header.data_size = image.data_size;
+    header.bss_size = image.bss_size.max(options.bss_size);
+    header.set_name(&options.name);
+    header.set_version(&options.version);
+
+    // The ELF the payload was made from, linked with `--emit-relocs`
+    let mut table = Vec::new();
+    if let Some(elf_data) = relocs {
+        let elf = Elf::parse(elf_data)?;
+        let relocations = reloc::relocations(&elf, load_address, payload)?;
+        // Relocated addresses may point into .bss, it has to be part of the app
+        let memory_size = reloc::app_end(&elf).saturating_sub(load_address);
+        header.bss_size = header
+            .bss_size
+            .max(memory_size.saturating_sub(payload.len() as u32));
+        table = relocations.iter().flat_map(|r| r.to_bytes()).collect();
+        header.set_relocations(payload, &table);
+        // Catch what the loader would refuse
+        header.relocate(&mut payload.clone(), &table, load_address + MOVW_ALIGN)?;
+    }
+
+    // Only worth it if it's smaller
+    let compressed = options
+        .compress
+        .then(|| lz4_flex::block::compress(payload))
+        .filter(|stored| stored.len() < payload.len());
+    if let Some(stored) = &compressed {
+        header.set_compressed(stored);
+    }
+    let stored = compressed.as_deref().unwrap_or(payload);
+
+    let mut data = Vec::with_capacity(h7_appfmt::HEADER_SIZE + stored.len() + table.len());
+    data.extend_from_slice(&header.to_bytes());
+    data.extend_from_slice(stored);
+    data.extend_from_slice(&table);
+    // Catch what the loader would refuse, and decompressor bugs
+    header.check_compatible(h7_api::API_VERSION, load_address, DEFAULT_APP_SIZE)?;
+    let mut memory = vec![0; header.load_size()];
+    let loaded = h7_appfmt::load(&data, &mut memory)?;
+    assert_eq!(&loaded.content()[..payload.len()], payload.as_slice());
+
+    if let Some(seed) = &options.key {
+        data = sign::sign(&data, seed)?;
+        header = AppHeader::from_bytes(&data)?;
+    }
+    Ok(Packed {
+        header,
+        image: data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            elf::SHT_PROGBITS,
+            inspect::inspect,
+            test_elf::{elf, section, words},
+        },
+        h7_appfmt::{public_key, AppFmtError, HEADER_SIZE},
+    };
+
+    const KEY: [u8; SEED_SIZE] = [5; SEED_SIZE];
+    const LOAD: u32 = DEFAULT_LOAD_ADDRESS;
+
+    /// Entry point, `bx lr` and something that compresses
+    fn bin() -> Vec<u8> {
+        let mut bin = vec![0x05, 0x00, 0x00, 0x24, 0x70, 0x47, 0x00, 0xbf];
+        bin.extend((0..0x800).map(|i| (i / 64) as u8));
+        bin
+    }
+
+    #[test]
+    fn bin_options() {
+        let options = PackOptions {
+            name: "hello".into(),
+            version: "1.2".into(),
+            bss_size: 0x100,
+            api_version: Some(1),
+            ..Default::default()
+        };
+        let packed = pack(&bin(), &options).unwrap();
+        assert_eq!(packed.image.len(), HEADER_SIZE + bin().len());
+        assert_eq!(packed.header.name(), "hello");
+        assert_eq!(packed.header.version(), "1.2");
+        assert_eq!(packed.header.bss_size, 0x100);
+        assert_eq!(packed.header.entry_address(), 0x2400_0005);
+        assert!(!packed.header.is_compressed() && !packed.header.is_signed());
+        assert!(inspect(&packed.image, &[]).unwrap().errors().is_empty());
+    }
+
+    #[test]
+    fn compressed_and_signed() {
+        let options = PackOptions {
+            key: Some(KEY),
+            compress: true,
+            api_version: Some(1),
+            ..Default::default()
+        };
+        let packed = pack(&bin(), &options).unwrap();
+        assert!(packed.header.is_compressed() && packed.header.is_signed());
+        assert!(packed.image.len() < bin().len());
+
+        let info = inspect(&packed.image, &[public_key(&KEY)]).unwrap();
+        assert_eq!(info.signature, Some(Ok(public_key(&KEY))));
+        assert!(info.errors().is_empty());
+
+        // Stored as is when it doesn't get smaller
+        let packed = pack(&bin()[..8], &options).unwrap();
+        assert!(!packed.header.is_compressed());
+    }
+
+    #[test]
+    fn errors() {
+        let options = PackOptions {
+            api_version: Some(1),
+            ..Default::default()
+        };
+        assert!(matches!(
+            pack(&[0x09, 0x00], &options),
+            Err(MkappError::Layout(LayoutError::BinTooShort(2)))
+        ));
+        // Entry point outside of the payload
+        assert!(matches!(
+            pack(&[0x09, 0x00, 0x00, 0x25], &options),
+            Err(MkappError::Format(AppFmtError::EntryOutOfRange(_)))
+        ));
+        let options = PackOptions {
+            relocs: Some(bin()),
+            ..Default::default()
+        };
+        assert!(matches!(pack(&bin(), &options), Err(MkappError::Elf(_))));
+        assert!(matches!(
+            pack(&bin(), &PackOptions::default()),
+            Err(MkappError::Layout(LayoutError::NoApiVersion))
+        ));
+    }
+
+    #[test]
+    fn api_version() {
+        let elf = elf(vec![
+            section(".entry", SHT_PROGBITS, LOAD, words(&[LOAD + 5])),
+            section(".text", SHT_PROGBITS, LOAD + 4, words(&[0xbf00_4770])),
+            section(".h7_api_version", SHT_PROGBITS, 0, words(&[1])),
+        ]);
+        let packed = pack(&elf, &PackOptions::default()).unwrap();
+        assert_eq!(packed.header.api_version, 1);
+        assert_eq!((packed.header.text_size, packed.header.data_size), (8, 0));
+
+        // A binary with the ELF it was made from
+        let options = PackOptions {
+            relocs: Some(elf.clone()),
+            ..Default::default()
+        };
+        let bin = words(&[LOAD + 5, 0xbf00_4770]);
+        assert_eq!(pack(&bin, &options).unwrap().header.api_version, 1);
+
+        let options = PackOptions {
+            api_version: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(pack(&elf, &options).unwrap().header.api_version, 2);
+    }
+}
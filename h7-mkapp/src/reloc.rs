@@ -0,0 +1,261 @@
+//! The relocation table of an app, from the relocations the linker keeps with `--emit-relocs`
+//! and the GOT
+
+use {
+    crate::elf::{Elf, ElfError, SHT_NOBITS, SHT_PROGBITS, SHT_REL, SHT_RELA},
+    h7_appfmt::{Relocation, RelocationKind},
+};
+
+const R_ARM_ABS32: u8 = 2;
+const R_ARM_TARGET1: u8 = 38;
+const R_ARM_ABS32_NOI: u8 = 55;
+const R_ARM_THM_MOVW_ABS_NC: u8 = 47;
+const R_ARM_THM_MOVT_ABS: u8 = 48;
+
+/// Relative to the place or the GOT, stay valid when the whole app moves
+const RELATIVE: [u8; 23] = [
+    0,   // R_ARM_NONE
+    3,   // R_ARM_REL32
+    10,  // R_ARM_THM_CALL
+    11,  // R_ARM_THM_PC8
+    25,  // R_ARM_BASE_PREL
+    26,  // R_ARM_GOT_BREL
+    27,  // R_ARM_PLT32
+    28,  // R_ARM_CALL
+    29,  // R_ARM_JUMP24
+    30,  // R_ARM_THM_JUMP24
+    40,  // R_ARM_V4BX
+    42,  // R_ARM_PREL31
+    45,  // R_ARM_MOVW_PREL_NC
+    46,  // R_ARM_MOVT_PREL
+    49,  // R_ARM_THM_MOVW_PREL_NC
+    50,  // R_ARM_THM_MOVT_PREL
+    51,  // R_ARM_THM_JUMP19
+    52,  // R_ARM_THM_JUMP6
+    53,  // R_ARM_THM_ALU_PREL_11_0
+    54,  // R_ARM_THM_PC12
+    96,  // R_ARM_GOT_PREL
+    102, // R_ARM_THM_JUMP11
+    103, // R_ARM_THM_JUMP8
+];
+
+/// First reserved section index, `SHN_ABS` and friends
+const SHN_LORESERVE: u16 = 0xff00;
+
+/// Sections of linker generated address tables
+const GOT_SECTIONS: [&str; 2] = [".got", ".got.plt"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelocError {
+    Elf(ElfError),
+    /// Relocation type that can't be moved (type, address)
+    Unsupported(u8, u32),
+    /// Relocated place is not in the binary (address)
+    OutsidePayload(u32),
+    /// Relocated word does not point into the app (address, value)
+    Target(u32, u32),
+}
+
+impl From<ElfError> for RelocError {
+    fn from(err: ElfError) -> Self {
+        Self::Elf(err)
+    }
+}
+
+impl core::fmt::Display for RelocError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Elf(e) => write!(f, "{e}"),
+            Self::Unsupported(kind, addr) => write!(
+                f,
+                "Unsupported relocation type {kind} at 0x{addr:08x}, is the app built for thumb?"
+            ),
+            Self::OutsidePayload(addr) => {
+                write!(f, "Relocation at 0x{addr:08x} is outside of the binary")
+            }
+            Self::Target(addr, value) => write!(
+                f,
+                "Relocation at 0x{addr:08x} points outside of the app (0x{value:08x})"
+            ),
+        }
+    }
+}
+
+/// Linked with `--emit-relocs`, the app can be relocated
+pub fn has_relocations(elf: &Elf) -> bool {
+    elf.sections
+        .iter()
+        .any(|s| s.kind == SHT_REL || s.kind == SHT_RELA)
+}
+
+/// End address of the app in memory, .bss included
+pub fn app_end(elf: &Elf) -> u32 {
+    elf.sections
+        .iter()
+        .filter(|s| s.is_alloc())
+        .map(|s| s.addr + s.size)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Places in `payload`, the binary of `elf` loaded at `load_address`, that hold absolute
+/// addresses in the app. Addresses outside the app, like the firmware's, stay.
+pub fn relocations(
+    elf: &Elf,
+    load_address: u32,
+    payload: &[u8],
+) -> Result<Vec<Relocation>, RelocError> {
+    let end = app_end(elf);
+    let in_app = |value: u32| value >= load_address && value <= end;
+    let offset = |addr: u32| match addr.checked_sub(load_address) {
+        Some(offset) if offset as usize + 4 <= payload.len() => Ok(offset),
+        _ => Err(RelocError::OutsidePayload(addr)),
+    };
+    let word = |offset: u32| {
+        let o = offset as usize;
+        u32::from_le_bytes([payload[o], payload[o + 1], payload[o + 2], payload[o + 3]])
+    };
+
+    let symbols = elf.symbols()?;
+    let mut relocations = Vec::new();
+    for section in elf
+        .sections
+        .iter()
+        .filter(|s| s.kind == SHT_REL || s.kind == SHT_RELA)
+    {
+        // Only relocations of the code and data, not of debug info
+        match elf.sections.get(section.info as usize) {
+            Some(target) if target.is_alloc() && target.kind != SHT_NOBITS => {}
+            _ => continue,
+        }
+        for rel in elf.relocations(section)? {
+            let kind = match rel.kind {
+                R_ARM_ABS32 | R_ARM_TARGET1 | R_ARM_ABS32_NOI => RelocationKind::Abs32,
+                R_ARM_THM_MOVW_ABS_NC => RelocationKind::ThumbMovw,
+                R_ARM_THM_MOVT_ABS => RelocationKind::ThumbMovt,
+                kind if RELATIVE.contains(&kind) => continue,
+                kind => return Err(RelocError::Unsupported(kind, rel.offset)),
+            };
+            let defined_in_app = symbols
+                .get(rel.symbol as usize)
+                .filter(|s| s.shndx != 0 && s.shndx < SHN_LORESERVE)
+                .and_then(|s| elf.sections.get(s.shndx as usize))
+                .is_some_and(|s| s.is_alloc());
+            if !defined_in_app {
+                continue;
+            }
+            let offset = offset(rel.offset)?;
+            if kind == RelocationKind::Abs32 && !in_app(word(offset)) {
+                return Err(RelocError::Target(rel.offset, word(offset)));
+            }
+            relocations.push(Relocation::new(kind, offset));
+        }
+    }
+
+    // Filled in by the linker, without relocations of their own
+    for got in GOT_SECTIONS
+        .iter()
+        .filter_map(|name| elf.section(name))
+        .filter(|s| s.is_alloc() && s.kind == SHT_PROGBITS)
+    {
+        for addr in (got.addr..got.addr + got.size).step_by(4) {
+            let offset = offset(addr)?;
+            if in_app(word(offset)) {
+                relocations.push(Relocation::new(RelocationKind::Abs32, offset));
+            }
+        }
+    }
+
+    relocations.sort();
+    relocations.dedup();
+    Ok(relocations)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            elf::SHT_SYMTAB,
+            test_elf::{elf, section, symbols, words, TestSection},
+        },
+    };
+
+    const LOAD: u32 = 0x2400_0000;
+
+    fn rel(offset: u32, kind: u8, symbol: u32) -> [u32; 2] {
+        [offset, symbol << 8 | kind as u32]
+    }
+
+    /// .text, .got and .bss of an app, the binary, and the relocations of .text
+    fn app(relocs: &[[u32; 2]]) -> (Vec<u8>, Vec<u8>) {
+        let text = words(&[LOAD + 5, 0xf240_0108, 0x0800_1234, LOAD + 0x14]);
+        let got = words(&[LOAD + 0x20, 0x0800_0000]);
+        let payload = [text.clone(), got.clone()].concat();
+        let elf = elf(vec![
+            section(".text", SHT_PROGBITS, LOAD, text),
+            section(".got", SHT_PROGBITS, LOAD + 0x10, got),
+            section(".bss", SHT_NOBITS, LOAD + 0x18, Vec::new()),
+            // Undefined, .text, absolute
+            section(".symtab", SHT_SYMTAB, 0, symbols(&[0, 1, 0xfff1])),
+            TestSection {
+                info: 1,
+                ..section(".rel.text", SHT_REL, 0, words(&relocs.concat()))
+            },
+        ]);
+        (elf, payload)
+    }
+
+    #[test]
+    fn abs32_and_got() {
+        let (elf, payload) = app(&[
+            rel(LOAD, R_ARM_ABS32, 1),
+            rel(LOAD + 4, R_ARM_THM_MOVW_ABS_NC, 1),
+            // Absolute symbol, stays
+            rel(LOAD + 8, R_ARM_ABS32, 2),
+            rel(LOAD + 12, R_ARM_TARGET1, 1),
+            rel(LOAD + 12, 30, 1),
+        ]);
+        let elf = Elf::parse(&elf).unwrap();
+        assert_eq!(app_end(&elf), LOAD + 0x28);
+        assert_eq!(
+            relocations(&elf, LOAD, &payload),
+            Ok(vec![
+                Relocation::new(RelocationKind::Abs32, 0),
+                Relocation::new(RelocationKind::ThumbMovw, 4),
+                Relocation::new(RelocationKind::Abs32, 12),
+                Relocation::new(RelocationKind::Abs32, 16),
+            ])
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let (elf, payload) = app(&[rel(LOAD, 44, 1)]);
+        let elf = Elf::parse(&elf).unwrap();
+        assert_eq!(
+            relocations(&elf, LOAD, &payload),
+            Err(RelocError::Unsupported(44, LOAD))
+        );
+
+        let (elf, payload) = app(&[rel(LOAD + 8, R_ARM_ABS32, 1)]);
+        let elf = Elf::parse(&elf).unwrap();
+        assert_eq!(
+            relocations(&elf, LOAD, &payload),
+            Err(RelocError::Target(LOAD + 8, 0x0800_1234))
+        );
+
+        let (elf, payload) = app(&[rel(LOAD + 0x40, R_ARM_ABS32, 1)]);
+        let elf = Elf::parse(&elf).unwrap();
+        assert_eq!(
+            relocations(&elf, LOAD, &payload),
+            Err(RelocError::OutsidePayload(LOAD + 0x40))
+        );
+
+        assert_eq!(
+            Elf::parse(b"\x7fELF\x02\x01").err(),
+            Some(ElfError::Unsupported)
+        );
+        assert_eq!(Elf::parse(b"MZ").err(), Some(ElfError::NotElf));
+    }
+}
@@ -0,0 +1,161 @@
+//! Ed25519 signatures of app images, and the key files they are made with
+//!
+//! A key is a text file with the 32 byte secret seed in hex, its public key is written next
+//! to it with a `.pub` extension, in the format the firmware build reads trusted keys from.
+
+use {
+    h7_appfmt::{
+        AppFmtError, AppHeader, PublicKey, SignatureBlock, FLAG_SIGNED, PUBLIC_KEY_SIZE, SEED_SIZE,
+    },
+    std::{fs, io, path::Path},
+};
+
+/// Sign `image`, replacing the signature it already has
+pub fn sign(image: &[u8], seed: &[u8; SEED_SIZE]) -> Result<Vec<u8>, AppFmtError> {
+    let mut header = AppHeader::from_bytes(image)?;
+    let body = header.body(image)?;
+    // The signature is of the payload as loaded
+    let mut memory = vec![0; header.load_size()];
+    let content = h7_appfmt::load(image, &mut memory)?.content().to_vec();
+    header.flags |= FLAG_SIGNED;
+
+    let mut signed = header.to_bytes().to_vec();
+    let block = SignatureBlock::sign(seed, &[&signed[..], &content].concat());
+    signed.extend_from_slice(body);
+    signed.extend_from_slice(&block.to_bytes());
+    Ok(signed)
+}
+
+/// Write a new key to `path` and its public key to `path.pub`
+pub fn keygen(path: &Path) -> io::Result<()> {
+    let mut seed = [0u8; SEED_SIZE];
+    io::Read::read_exact(&mut fs::File::open("/dev/urandom")?, &mut seed)?;
+
+    let mut file = fs::OpenOptions::new();
+    file.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o600);
+    io::Write::write_all(&mut file.open(path)?, (to_hex(&seed) + "\n").as_bytes())?;
+
+    let public = h7_appfmt::public_key(&seed);
+    fs::write(public_path(path), to_hex(&public) + "\n")
+}
+
+/// Read the seed of a key written by [`keygen`]
+pub fn read_key(path: &Path) -> io::Result<[u8; SEED_SIZE]> {
+    from_hex(&fs::read_to_string(path)?).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("not a {SEED_SIZE} byte hex key"),
+        )
+    })
+}
+
+/// Read a public key written by [`keygen`]
+pub fn read_public_key(path: &Path) -> io::Result<PublicKey> {
+    from_hex(&fs::read_to_string(path)?).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("not a {PUBLIC_KEY_SIZE} byte hex key"),
+        )
+    })
+}
+
+pub fn public_path(path: &Path) -> String {
+    format!("{}.pub", path.display())
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Parse exactly `N` bytes of hex, surrounding whitespace is ignored
+pub fn from_hex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    let s = s.trim();
+    if s.len() != N * 2 || !s.is_ascii() {
+        return None;
+    }
+    let mut out = [0u8; N];
+    for (i, b) in out.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        h7_appfmt::{public_key, HEADER_SIZE, SIGNATURE_BLOCK_SIZE},
+    };
+
+    const KEY: [u8; SEED_SIZE] = [7; SEED_SIZE];
+    const OTHER_KEY: [u8; SEED_SIZE] = [8; SEED_SIZE];
+
+    fn image() -> Vec<u8> {
+        let payload = [0x09, 0x00, 0x00, 0x24, 0x70, 0x47, 0x00, 0xbf];
+        let header = AppHeader::new(1, 0x2400_0000, 0x2400_0005, &payload);
+        [header.to_bytes().as_slice(), &payload].concat()
+    }
+
+    fn verify(image: &[u8], trusted: &[[u8; SEED_SIZE]]) -> Result<(), AppFmtError> {
+        let trusted: Vec<_> = trusted.iter().map(public_key).collect();
+        let mut memory = [0u8; 64];
+        let loaded = h7_appfmt::load(image, &mut memory)?;
+        loaded.verify_signature(&trusted).map(|_| ())
+    }
+
+    #[test]
+    fn sign_and_resign() {
+        let unsigned = image();
+        assert_eq!(verify(&unsigned, &[KEY]), Err(AppFmtError::Unsigned));
+
+        let signed = sign(&unsigned, &KEY).unwrap();
+        assert_eq!(signed.len(), unsigned.len() + SIGNATURE_BLOCK_SIZE);
+        assert_eq!(verify(&signed, &[KEY]), Ok(()));
+        // Still loads without checking the signature
+        let header = AppHeader::from_bytes(&signed).unwrap();
+        assert_eq!(header.payload(&signed), Ok(&unsigned[HEADER_SIZE..]));
+
+        let resigned = sign(&signed, &OTHER_KEY).unwrap();
+        assert_eq!(resigned.len(), signed.len());
+        assert_eq!(verify(&resigned, &[OTHER_KEY]), Ok(()));
+        assert_eq!(
+            verify(&resigned, &[KEY]),
+            Err(AppFmtError::UntrustedKey(public_key(&OTHER_KEY)))
+        );
+    }
+
+    #[test]
+    fn tampered() {
+        let signed = sign(&image(), &KEY).unwrap();
+
+        let mut payload = signed.clone();
+        payload[HEADER_SIZE + 4] = 0x00;
+        assert_eq!(verify(&payload, &[KEY]), Err(AppFmtError::BadSignature));
+
+        // Renamed, with a valid header CRC
+        let mut header = AppHeader::from_bytes(&signed).unwrap();
+        header.set_name("evil");
+        let mut renamed = signed.clone();
+        renamed[..HEADER_SIZE].copy_from_slice(&header.to_bytes());
+        assert_eq!(verify(&renamed, &[KEY]), Err(AppFmtError::BadSignature));
+
+        // The signature stripped, an unsigned app
+        let mut header = AppHeader::from_bytes(&signed).unwrap();
+        header.flags &= !FLAG_SIGNED;
+        let mut stripped = signed[..signed.len() - SIGNATURE_BLOCK_SIZE].to_vec();
+        stripped[..HEADER_SIZE].copy_from_slice(&header.to_bytes());
+        assert_eq!(verify(&stripped, &[KEY]), Err(AppFmtError::Unsigned));
+    }
+
+    #[test]
+    fn hex() {
+        let key = public_key(&KEY);
+        assert_eq!(from_hex(&(to_hex(&key) + "\n")), Some(key));
+        assert_eq!(from_hex::<2>("0aff"), Some([0x0a, 0xff]));
+        assert_eq!(from_hex::<2>("0aff00"), None);
+        assert_eq!(from_hex::<2>("0afg"), None);
+        assert_eq!(from_hex::<2>("0aé"), None);
+    }
+}
@@ -0,0 +1,88 @@
+//! Hand-built ELF files for tests
+
+use crate::elf::{SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, SHT_NOBITS};
+
+pub struct TestSection {
+    pub name: &'static str,
+    pub kind: u32,
+    pub flags: u32,
+    pub addr: u32,
+    pub data: Vec<u8>,
+    pub info: u32,
+}
+
+/// A section, loaded if it has an address. Code in .text, constants in .entry and .rodata,
+/// the rest is writable.
+pub fn section(name: &'static str, kind: u32, addr: u32, data: Vec<u8>) -> TestSection {
+    let flags = match (addr, name) {
+        (0, _) => 0,
+        (_, ".text") => SHF_ALLOC | SHF_EXECINSTR,
+        (_, ".entry" | ".rodata") => SHF_ALLOC,
+        _ => SHF_ALLOC | SHF_WRITE,
+    };
+    TestSection {
+        name,
+        kind,
+        flags,
+        addr,
+        data,
+        info: 0,
+    }
+}
+
+pub fn words(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|w| w.to_le_bytes()).collect()
+}
+
+/// A linked ELF file with `sections` after the null section and the names last
+pub fn elf(mut sections: Vec<TestSection>) -> Vec<u8> {
+    sections.push(section(".shstrtab", 3, 0, Vec::new()));
+    let mut names = vec![0u8];
+    let name_offsets: Vec<_> = sections
+        .iter()
+        .map(|s| {
+            let offset = names.len() as u32;
+            names.extend_from_slice(s.name.as_bytes());
+            names.push(0);
+            offset
+        })
+        .collect();
+    sections.last_mut().unwrap().data = names;
+
+    let mut out = vec![0u8; 52];
+    let mut headers = vec![0u8; 40];
+    for (s, name) in sections.iter().zip(name_offsets) {
+        // .bss of 16 bytes
+        let size = match s.kind {
+            SHT_NOBITS => 16,
+            _ => s.data.len() as u32,
+        };
+        let offset = out.len() as u32;
+        for field in [name, s.kind, s.flags, s.addr, offset, size, 0, s.info, 0, 0] {
+            headers.extend_from_slice(&field.to_le_bytes());
+        }
+        out.extend_from_slice(&s.data);
+    }
+    let shoff = out.len() as u32;
+    out.extend_from_slice(&headers);
+
+    let count = sections.len() as u16 + 1;
+    out[0..6].copy_from_slice(b"\x7fELF\x01\x01");
+    out[18..20].copy_from_slice(&40u16.to_le_bytes());
+    out[32..36].copy_from_slice(&shoff.to_le_bytes());
+    out[48..50].copy_from_slice(&count.to_le_bytes());
+    out[50..52].copy_from_slice(&(count - 1).to_le_bytes());
+    out
+}
+
+/// Symbols defined in the sections `shndx`
+pub fn symbols(shndx: &[u16]) -> Vec<u8> {
+    shndx
+        .iter()
+        .flat_map(|&shndx| {
+            let mut sym = [0u8; 16];
+            sym[14..16].copy_from_slice(&shndx.to_le_bytes());
+            sym
+        })
+        .collect()
+}
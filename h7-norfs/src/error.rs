@@ -0,0 +1,40 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NorFsError {
+    NotMounted,
+    AlreadyMounted,
+    /// No littlefs volume on the flash
+    NotFormatted,
+    /// Path too long or contains a nul byte
+    InvalidPath,
+    /// File does not fit in the buffer (file size)
+    BufferTooSmall(usize),
+    /// All the file handles are in use
+    TooManyOpenFiles,
+    /// The handle isn't an open file
+    NotOpen,
+    /// Unmounting with files open
+    FilesOpen,
+    Fs(littlefs2::io::Error),
+}
+
+impl From<littlefs2::io::Error> for NorFsError {
+    fn from(err: littlefs2::io::Error) -> Self {
+        Self::Fs(err)
+    }
+}
+
+impl core::fmt::Display for NorFsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NotMounted => write!(f, "Not Mounted"),
+            Self::AlreadyMounted => write!(f, "Already mounted"),
+            Self::NotFormatted => write!(f, "No filesystem, format first"),
+            Self::InvalidPath => write!(f, "Invalid path"),
+            Self::BufferTooSmall(size) => write!(f, "File too large ({size} bytes)"),
+            Self::TooManyOpenFiles => write!(f, "Too many open files"),
+            Self::NotOpen => write!(f, "File not open"),
+            Self::FilesOpen => write!(f, "Files are open"),
+            Self::Fs(e) => write!(f, "littlefs: {e:?}"),
+        }
+    }
+}
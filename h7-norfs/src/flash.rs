@@ -0,0 +1,132 @@
+/// A NOR flash chip.
+///
+/// Erased bits read as `1` and programming can only clear bits, so a region has to be erased
+/// before it is written again.
+pub trait Flash {
+    type Error: core::fmt::Debug;
+
+    /// Size of the chip in bytes
+    const CAPACITY: usize;
+    /// Smallest erasable unit, erases are aligned to it
+    const SECTOR_SIZE: usize;
+    /// Largest programmable unit, a program must not cross a page boundary
+    const PAGE_SIZE: usize;
+
+    fn read(&mut self, address: u32, data: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Program `data` at `address`, all of it inside one page
+    fn program_page(&mut self, address: u32, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Erase the sector starting at `address`
+    fn erase_sector(&mut self, address: u32) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RamFlashError {
+    OutOfBounds,
+    CrossesPage,
+    Unaligned,
+}
+
+/// RAM-backed flash model with NOR semantics, 4KiB sectors and 256 byte pages like the MX25L.
+/// Used by the tests and as a RAM disk.
+pub struct RamFlash<'a, const SIZE: usize> {
+    data: &'a mut [u8; SIZE],
+}
+
+impl<'a, const SIZE: usize> RamFlash<'a, SIZE> {
+    /// A fully erased chip in `data`
+    pub fn new(data: &'a mut [u8; SIZE]) -> Self {
+        data.fill(0xff);
+        Self { data }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    fn range(address: u32, len: usize) -> Result<core::ops::Range<usize>, RamFlashError> {
+        let start = address as usize;
+        match start.checked_add(len) {
+            Some(end) if end <= SIZE => Ok(start..end),
+            _ => Err(RamFlashError::OutOfBounds),
+        }
+    }
+}
+
+impl<const SIZE: usize> Flash for RamFlash<'_, SIZE> {
+    type Error = RamFlashError;
+
+    const CAPACITY: usize = SIZE;
+    const SECTOR_SIZE: usize = 4096;
+    const PAGE_SIZE: usize = 256;
+
+    fn read(&mut self, address: u32, data: &mut [u8]) -> Result<(), Self::Error> {
+        data.copy_from_slice(&self.data[Self::range(address, data.len())?]);
+        Ok(())
+    }
+
+    fn program_page(&mut self, address: u32, data: &[u8]) -> Result<(), Self::Error> {
+        let range = Self::range(address, data.len())?;
+        let page_end = (range.start / Self::PAGE_SIZE + 1) * Self::PAGE_SIZE;
+        if range.end > page_end {
+            return Err(RamFlashError::CrossesPage);
+        }
+        // Programming clears bits, it never sets them
+        for (cell, byte) in self.data[range].iter_mut().zip(data) {
+            *cell &= byte;
+        }
+        Ok(())
+    }
+
+    fn erase_sector(&mut self, address: u32) -> Result<(), Self::Error> {
+        if !(address as usize).is_multiple_of(Self::SECTOR_SIZE) {
+            return Err(RamFlashError::Unaligned);
+        }
+        self.data[Self::range(address, Self::SECTOR_SIZE)?].fill(0xff);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn program_clears_bits() {
+        let mut mem = [0; 8192];
+        let mut flash = RamFlash::new(&mut mem);
+        flash.program_page(0, &[0b1010_1010, 0x00]).unwrap();
+        flash.program_page(0, &[0b1100_1100, 0xff]).unwrap();
+        let mut data = [0u8; 3];
+        flash.read(0, &mut data).unwrap();
+        assert_eq!(data, [0b1000_1000, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn erase_sector() {
+        let mut mem = [0; 8192];
+        let mut flash = RamFlash::new(&mut mem);
+        flash.program_page(4095, &[0]).unwrap();
+        flash.program_page(4096, &[0]).unwrap();
+        flash.erase_sector(4096).unwrap();
+        assert_eq!(flash.data()[4095..4097], [0x00, 0xff]);
+        assert_eq!(flash.erase_sector(100), Err(RamFlashError::Unaligned));
+        assert_eq!(flash.erase_sector(8192), Err(RamFlashError::OutOfBounds));
+    }
+
+    #[test]
+    fn page_boundary() {
+        let mut mem = [0; 8192];
+        let mut flash = RamFlash::new(&mut mem);
+        flash.program_page(250, &[0; 6]).unwrap();
+        assert_eq!(
+            flash.program_page(250, &[0; 7]),
+            Err(RamFlashError::CrossesPage)
+        );
+        assert_eq!(
+            flash.read(8190, &mut [0; 4]),
+            Err(RamFlashError::OutOfBounds)
+        );
+    }
+}
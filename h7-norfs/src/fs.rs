@@ -0,0 +1,602 @@
+use {
+    crate::{Flash, NorFsError, NorStorage},
+    alloc::boxed::Box,
+    core::{cell::Cell, ptr::NonNull},
+    littlefs2::{
+        consts::PATH_MAX,
+        driver::Storage,
+        fs::{Allocation, File, FileAllocation, Filesystem, OpenOptions},
+        io::{self, SeekFrom, Write},
+        path::PathBuf,
+    },
+};
+
+/// Files that can be open at once on a volume
+pub const MAX_OPEN_FILES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub is_dir: bool,
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirEntry<'a> {
+    pub name: &'a str,
+    pub metadata: Metadata,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Info {
+    /// Size of the volume in bytes
+    pub capacity: usize,
+    pub block_size: usize,
+    /// Free space in bytes
+    pub available: usize,
+}
+
+/// A file opened with [`NorFs::open`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileHandle(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    Read,
+    /// Create or truncate
+    Write,
+    /// Create if it doesn't exist and start at the end
+    Append,
+}
+
+/// littlefs volume on a [`Flash`].
+///
+/// Paths are relative to the root of the volume, `""` and `"/"` are the root.
+///
+/// littlefs keeps pointers to its buffers and to the open files, so the mounted filesystem and
+/// the open files live in a [`Volume`] on the heap where they don't move with the `NorFs`.
+pub struct NorFs<F: Flash + 'static> {
+    storage: NorStorage<F>,
+    volume: Option<NonNull<Volume<F>>>,
+}
+
+// SAFETY: the volume belongs to the `NorFs` and is only used through `&mut self`
+unsafe impl<F: Flash + Send + 'static> Send for NorFs<F> {}
+
+/// A mounted volume. The filesystem borrows `alloc` and `storage`, the files borrow the
+/// filesystem and their allocation.
+struct Volume<F: Flash + 'static> {
+    files: [Option<File<'static, 'static, RawStorage<F>>>; MAX_OPEN_FILES],
+    fs: Option<Filesystem<'static, RawStorage<F>>>,
+    file_allocs: [FileAllocation<RawStorage<F>>; MAX_OPEN_FILES],
+    alloc: Allocation<RawStorage<F>>,
+    storage: RawStorage<F>,
+    /// The `NorFs`'s storage, set before each call into littlefs as the `NorFs` may have moved
+    nor: Cell<*mut NorStorage<F>>,
+}
+
+/// littlefs block device forwarding to the storage in [`Volume::nor`]
+struct RawStorage<F: Flash + 'static>(*const Cell<*mut NorStorage<F>>);
+
+impl<F: Flash + 'static> RawStorage<F> {
+    fn nor(&mut self) -> &mut NorStorage<F> {
+        // SAFETY: littlefs is only called from `NorFs` methods, after `NorFs::volume` has set
+        // the pointer to their `&mut self`
+        unsafe { &mut *(*self.0).get() }
+    }
+}
+
+impl<F: Flash + 'static> Storage for RawStorage<F> {
+    const READ_SIZE: usize = NorStorage::<F>::READ_SIZE;
+    const WRITE_SIZE: usize = NorStorage::<F>::WRITE_SIZE;
+    const BLOCK_SIZE: usize = NorStorage::<F>::BLOCK_SIZE;
+    const BLOCK_COUNT: usize = NorStorage::<F>::BLOCK_COUNT;
+    const BLOCK_CYCLES: isize = NorStorage::<F>::BLOCK_CYCLES;
+
+    type CACHE_SIZE = <NorStorage<F> as Storage>::CACHE_SIZE;
+    type LOOKAHEAD_SIZE = <NorStorage<F> as Storage>::LOOKAHEAD_SIZE;
+
+    fn read(&mut self, off: usize, buf: &mut [u8]) -> io::Result<usize> {
+        self.nor().read(off, buf)
+    }
+
+    fn write(&mut self, off: usize, data: &[u8]) -> io::Result<usize> {
+        self.nor().write(off, data)
+    }
+
+    fn erase(&mut self, off: usize, len: usize) -> io::Result<usize> {
+        self.nor().erase(off, len)
+    }
+}
+
+impl<F: Flash + 'static> NorFs<F> {
+    pub const fn new(flash: F) -> Self {
+        Self {
+            storage: NorStorage::new(flash),
+            volume: None,
+        }
+    }
+
+    /// Raw access to the flash, writing to it while mounted corrupts the volume
+    pub fn flash(&mut self) -> &mut F {
+        self.storage.flash()
+    }
+
+    pub fn is_mounted(&self) -> bool {
+        self.volume.is_some()
+    }
+
+    /// Erase the volume and create an empty one
+    pub fn format(&mut self) -> Result<(), NorFsError> {
+        if self.is_mounted() {
+            return Err(NorFsError::AlreadyMounted);
+        }
+        Ok(Filesystem::format(&mut self.storage)?)
+    }
+
+    pub fn mount(&mut self) -> Result<(), NorFsError> {
+        if self.is_mounted() {
+            return Err(NorFsError::AlreadyMounted);
+        }
+        if !Filesystem::is_mountable(&mut self.storage) {
+            return Err(NorFsError::NotFormatted);
+        }
+        let volume = Box::into_raw(Box::new(Volume {
+            files: Default::default(),
+            fs: None,
+            file_allocs: core::array::from_fn(|_| FileAllocation::new()),
+            alloc: Allocation::new(),
+            storage: RawStorage(core::ptr::null()),
+            nor: Cell::new(&mut self.storage),
+        }));
+        // SAFETY: the volume stays on the heap until `unmount` and nothing else borrows `alloc`
+        // and `storage`
+        unsafe {
+            (*volume).storage.0 = &(*volume).nor;
+            match Filesystem::mount(&mut (*volume).alloc, &mut (*volume).storage) {
+                Ok(fs) => (*volume).fs = Some(fs),
+                Err(err) => {
+                    drop(Box::from_raw(volume));
+                    return Err(err.into());
+                }
+            }
+        }
+        self.volume = NonNull::new(volume);
+        Ok(())
+    }
+
+    /// Unmount, the files have to be closed first
+    pub fn unmount(&mut self) -> Result<(), NorFsError> {
+        let volume = self.volume()?;
+        // SAFETY: the files were the last borrows of the volume
+        unsafe {
+            if (*volume).files.iter().any(Option::is_some) {
+                return Err(NorFsError::FilesOpen);
+            }
+            (*volume).fs = None;
+            drop(Box::from_raw(volume));
+        }
+        self.volume = None;
+        Ok(())
+    }
+
+    pub fn info(&mut self) -> Result<Info, NorFsError> {
+        let available = self.with_fs(|fs| fs.available_space())?;
+        Ok(Info {
+            capacity: F::CAPACITY,
+            block_size: F::SECTOR_SIZE,
+            available,
+        })
+    }
+
+    /// Call `f` for each entry in the directory at `path`, without `.` and `..`
+    pub fn ls(&mut self, path: &str, mut f: impl FnMut(&DirEntry)) -> Result<(), NorFsError> {
+        let path = lfs_path(path)?;
+        self.with_fs(|fs| {
+            fs.read_dir_and_then(&path, |dir| {
+                for entry in dir {
+                    let entry = entry?;
+                    let name = entry.file_name().as_ref();
+                    if name != "." && name != ".." {
+                        f(&DirEntry {
+                            name,
+                            metadata: metadata(&entry.metadata()),
+                        });
+                    }
+                }
+                Ok(())
+            })
+        })
+    }
+
+    pub fn stat(&mut self, path: &str) -> Result<Metadata, NorFsError> {
+        let path = lfs_path(path)?;
+        self.with_fs(|fs| fs.metadata(&path).map(|m| metadata(&m)))
+    }
+
+    pub fn exists(&mut self, path: &str) -> bool {
+        self.stat(path).is_ok()
+    }
+
+    /// Read from `offset` into `data`, returns the number of bytes read, 0 at the end of the file
+    pub fn read_at(
+        &mut self,
+        path: &str,
+        offset: usize,
+        data: &mut [u8],
+    ) -> Result<usize, NorFsError> {
+        let path = lfs_path(path)?;
+        self.with_fs(|fs| {
+            fs.open_file_and_then(&path, |file| {
+                file.seek(SeekFrom::Start(offset as u32))?;
+                let mut read = 0;
+                while read < data.len() {
+                    match file.read(&mut data[read..])? {
+                        0 => break,
+                        n => read += n,
+                    }
+                }
+                Ok(read)
+            })
+        })
+    }
+
+    /// Read a whole file into `data`, returns the file size
+    pub fn read_file(&mut self, path: &str, data: &mut [u8]) -> Result<usize, NorFsError> {
+        let size = self.stat(path)?.size;
+        if size > data.len() {
+            return Err(NorFsError::BufferTooSmall(size));
+        }
+        self.read_at(path, 0, &mut data[..size])
+    }
+
+    /// Create or truncate a file and write `data` to it
+    pub fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), NorFsError> {
+        let path = lfs_path(path)?;
+        self.with_fs(|fs| fs.write(&path, data))
+    }
+
+    /// Append `data` to a file, it's created if it doesn't exist
+    pub fn append_file(&mut self, path: &str, data: &[u8]) -> Result<(), NorFsError> {
+        let path = lfs_path(path)?;
+        self.with_fs(|fs| {
+            fs.open_file_with_options_and_then(
+                |o| o.write(true).create(true).append(true),
+                &path,
+                |file| file.write_all(data),
+            )
+        })
+    }
+
+    /// Open a file, reads and writes start at the beginning, or at the end with
+    /// [`OpenMode::Append`]
+    pub fn open(&mut self, path: &str, mode: OpenMode) -> Result<FileHandle, NorFsError> {
+        let path = lfs_path(path)?;
+        let volume = self.volume()?;
+        let mut options = OpenOptions::new();
+        match mode {
+            OpenMode::Read => options.read(true),
+            OpenMode::Write => options.write(true).create(true).truncate(true),
+            OpenMode::Append => options.write(true).create(true),
+        };
+        // SAFETY: the file borrows the filesystem and a free allocation in the volume, `unmount`
+        // refuses to free them until it's closed
+        unsafe {
+            let index = (*volume)
+                .files
+                .iter()
+                .position(Option::is_none)
+                .ok_or(NorFsError::TooManyOpenFiles)?;
+            let fs = (*volume).fs.as_ref().ok_or(NorFsError::NotMounted)?;
+            let file = options.open(fs, &mut (*volume).file_allocs[index], &path)?;
+            if mode == OpenMode::Append {
+                if let Err(err) = file.seek(SeekFrom::End(0)) {
+                    file.close().ok();
+                    return Err(err.into());
+                }
+            }
+            (*volume).files[index] = Some(file);
+            Ok(FileHandle(index))
+        }
+    }
+
+    /// Write what's left of the file to the flash and free its handle
+    pub fn close(&mut self, file: FileHandle) -> Result<(), NorFsError> {
+        let volume = self.volume()?;
+        // SAFETY: see `open`
+        let file = unsafe { (*volume).files.get_mut(file.0).and_then(Option::take) };
+        // SAFETY: opened in `open`
+        Ok(unsafe { file.ok_or(NorFsError::NotOpen)?.close() }?)
+    }
+
+    /// Read into `data` from the position of the file, returns the number of bytes read, 0 at
+    /// the end of the file
+    pub fn read(&mut self, file: FileHandle, data: &mut [u8]) -> Result<usize, NorFsError> {
+        let file = self.file(file)?;
+        let mut read = 0;
+        while read < data.len() {
+            match file.read(&mut data[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        Ok(read)
+    }
+
+    /// Write `data` at the position of the file
+    pub fn write(&mut self, file: FileHandle, data: &[u8]) -> Result<(), NorFsError> {
+        Ok(self.file(file)?.write_all(data)?)
+    }
+
+    /// Move the position of the file to `offset` from the start
+    pub fn seek(&mut self, file: FileHandle, offset: usize) -> Result<(), NorFsError> {
+        self.file(file)?.seek(SeekFrom::Start(offset as u32))?;
+        Ok(())
+    }
+
+    /// Size of an open file, including what hasn't been written to the flash yet
+    pub fn size(&mut self, file: FileHandle) -> Result<usize, NorFsError> {
+        Ok(self.file(file)?.len()?)
+    }
+
+    /// Remove a file or an empty directory
+    pub fn remove(&mut self, path: &str) -> Result<(), NorFsError> {
+        let path = lfs_path(path)?;
+        self.with_fs(|fs| match fs.metadata(&path)?.is_dir() {
+            true => fs.remove_dir(&path),
+            false => fs.remove(&path),
+        })
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<(), NorFsError> {
+        let (from, to) = (lfs_path(from)?, lfs_path(to)?);
+        self.with_fs(|fs| fs.rename(&from, &to))
+    }
+
+    pub fn create_dir(&mut self, path: &str) -> Result<(), NorFsError> {
+        let path = lfs_path(path)?;
+        self.with_fs(|fs| fs.create_dir(&path))
+    }
+
+    fn with_fs<R>(
+        &mut self,
+        f: impl FnOnce(&Filesystem<'_, RawStorage<F>>) -> io::Result<R>,
+    ) -> Result<R, NorFsError> {
+        let volume = self.volume()?;
+        // SAFETY: the filesystem lives until `unmount`
+        let fs = unsafe { (*volume).fs.as_ref() }.ok_or(NorFsError::NotMounted)?;
+        Ok(f(fs)?)
+    }
+
+    fn file(
+        &mut self,
+        file: FileHandle,
+    ) -> Result<&File<'static, 'static, RawStorage<F>>, NorFsError> {
+        let volume = self.volume()?;
+        // SAFETY: see `open`
+        unsafe { (*volume).files.get(file.0) }
+            .and_then(Option::as_ref)
+            .ok_or(NorFsError::NotOpen)
+    }
+
+    /// The mounted volume, pointed at the storage
+    fn volume(&mut self) -> Result<*mut Volume<F>, NorFsError> {
+        let volume = self.volume.ok_or(NorFsError::NotMounted)?.as_ptr();
+        // SAFETY: the volume lives until `unmount`
+        unsafe { (*volume).nor.set(&mut self.storage) };
+        Ok(volume)
+    }
+}
+
+impl<F: Flash + 'static> Drop for NorFs<F> {
+    /// Close the files so that what was written to them is on the flash, and free the volume
+    fn drop(&mut self) {
+        for file in 0..MAX_OPEN_FILES {
+            self.close(FileHandle(file)).ok();
+        }
+        self.unmount().ok();
+    }
+}
+
+fn lfs_path(path: &str) -> Result<PathBuf, NorFsError> {
+    let path = match path {
+        "" => "/",
+        path => path,
+    };
+    // PathBuf::from panics on these
+    if path.len() > PATH_MAX || path.contains('\0') {
+        return Err(NorFsError::InvalidPath);
+    }
+    Ok(PathBuf::from(path))
+}
+
+fn metadata(m: &littlefs2::fs::Metadata) -> Metadata {
+    Metadata {
+        is_dir: m.is_dir(),
+        size: m.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::RamFlash,
+        heapless::{String, Vec},
+    };
+
+    const SIZE: usize = 32 * 4096;
+    type TestFs = NorFs<RamFlash<'static, SIZE>>;
+
+    /// The volume outlives the `NorFs`, so the tests leak their flash
+    fn flash() -> RamFlash<'static, SIZE> {
+        RamFlash::new(Box::leak(Box::new([0; SIZE])))
+    }
+
+    fn mounted() -> TestFs {
+        let mut fs = TestFs::new(flash());
+        fs.format().unwrap();
+        fs.mount().unwrap();
+        fs
+    }
+
+    type Entry = (String<32>, Metadata);
+
+    fn ls(fs: &mut TestFs, path: &str) -> Vec<Entry, 8> {
+        let mut entries = Vec::new();
+        fs.ls(path, |e| entries.push(entry(e.name, e.metadata)).unwrap())
+            .unwrap();
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    fn entry(name: &str, metadata: Metadata) -> Entry {
+        (name.into(), metadata)
+    }
+
+    #[test]
+    fn mount() {
+        let mut fs = TestFs::new(flash());
+        assert_eq!(fs.ls("/", |_| {}), Err(NorFsError::NotMounted));
+        assert_eq!(fs.mount(), Err(NorFsError::NotFormatted));
+        fs.format().unwrap();
+        fs.mount().unwrap();
+        assert_eq!(fs.mount(), Err(NorFsError::AlreadyMounted));
+        assert_eq!(fs.format(), Err(NorFsError::AlreadyMounted));
+        fs.unmount().unwrap();
+        assert_eq!(fs.unmount(), Err(NorFsError::NotMounted));
+    }
+
+    #[test]
+    fn read_write() {
+        let mut fs = mounted();
+        fs.write_file("/hello.txt", b"Hello, world!").unwrap();
+        let mut buf = [0u8; 32];
+        assert_eq!(fs.read_file("hello.txt", &mut buf), Ok(13));
+        assert_eq!(&buf[..13], b"Hello, world!");
+        assert_eq!(fs.read_at("/hello.txt", 7, &mut buf[..3]), Ok(3));
+        assert_eq!(&buf[..3], b"wor");
+        assert_eq!(fs.read_at("/hello.txt", 13, &mut buf), Ok(0));
+        assert_eq!(
+            fs.read_file("/hello.txt", &mut buf[..4]),
+            Err(NorFsError::BufferTooSmall(13))
+        );
+        // Truncates
+        fs.write_file("/hello.txt", b"Hi").unwrap();
+        assert_eq!(fs.stat("/hello.txt").unwrap().size, 2);
+    }
+
+    #[test]
+    fn append_large_file() {
+        let mut fs = mounted();
+        // Spans several blocks
+        let chunk: [u8; 1000] = core::array::from_fn(|i| i as u8);
+        for _ in 0..10 {
+            fs.append_file("/big.bin", &chunk).unwrap();
+        }
+        assert_eq!(fs.stat("/big.bin").unwrap().size, 10_000);
+        let mut buf = [0u8; 1000];
+        assert_eq!(fs.read_at("/big.bin", 5000, &mut buf), Ok(1000));
+        assert_eq!(buf, chunk);
+    }
+
+    #[test]
+    fn directories() {
+        let mut fs = mounted();
+        fs.create_dir("/apps").unwrap();
+        fs.write_file("/apps/a.h7", &[1, 2, 3]).unwrap();
+        fs.write_file("/b.txt", b"b").unwrap();
+        let file = |size| Metadata {
+            is_dir: false,
+            size,
+        };
+        let dir = Metadata {
+            is_dir: true,
+            size: 0,
+        };
+        assert_eq!(
+            ls(&mut fs, "/"),
+            [entry("apps", dir), entry("b.txt", file(1))]
+        );
+        assert_eq!(ls(&mut fs, ""), ls(&mut fs, "/"));
+        assert_eq!(ls(&mut fs, "/apps"), [entry("a.h7", file(3))]);
+        assert!(matches!(
+            fs.remove("/apps"),
+            Err(NorFsError::Fs(io::Error::DirNotEmpty))
+        ));
+        fs.rename("/apps/a.h7", "/a.h7").unwrap();
+        fs.remove("/apps").unwrap();
+        fs.remove("/b.txt").unwrap();
+        assert!(!fs.exists("/b.txt"));
+        assert_eq!(ls(&mut fs, "/"), [entry("a.h7", file(3))]);
+    }
+
+    #[test]
+    fn persists() {
+        let mut fs = mounted();
+        fs.write_file("/keep", b"data").unwrap();
+        let available = fs.info().unwrap().available;
+        fs.unmount().unwrap();
+        fs.mount().unwrap();
+        let mut buf = [0u8; 4];
+        assert_eq!(fs.read_file("/keep", &mut buf), Ok(4));
+        assert_eq!(&buf, b"data");
+        assert_eq!(fs.info().unwrap().available, available);
+        assert!(available < SIZE);
+    }
+
+    #[test]
+    fn open_files() {
+        let mut fs = mounted();
+        let file = fs.open("/log.txt", OpenMode::Write).unwrap();
+        fs.write(file, b"Hello, world!").unwrap();
+        fs.seek(file, 7).unwrap();
+        fs.write(file, b"there").unwrap();
+        assert_eq!(fs.size(file), Ok(13));
+        // The handle survives the `NorFs` moving
+        let mut fs = Box::new(fs);
+        fs.seek(file, 13).unwrap();
+        fs.write(file, b"!!").unwrap();
+        assert_eq!(fs.unmount(), Err(NorFsError::FilesOpen));
+        fs.close(file).unwrap();
+        assert_eq!(fs.close(file), Err(NorFsError::NotOpen));
+
+        let mut buf = [0u8; 32];
+        let file = fs.open("/log.txt", OpenMode::Read).unwrap();
+        assert_eq!(fs.read(file, &mut buf), Ok(15));
+        assert_eq!(&buf[..15], b"Hello, there!!!");
+        fs.seek(file, 7).unwrap();
+        assert_eq!(fs.read(file, &mut buf[..5]), Ok(5));
+        assert_eq!(&buf[..5], b"there");
+        fs.close(file).unwrap();
+
+        let file = fs.open("/log.txt", OpenMode::Append).unwrap();
+        fs.write(file, b"?").unwrap();
+        fs.close(file).unwrap();
+        assert_eq!(fs.read_file("/log.txt", &mut buf), Ok(16));
+        assert_eq!(&buf[..16], b"Hello, there!!!?");
+
+        let files: [_; MAX_OPEN_FILES] =
+            core::array::from_fn(|_| fs.open("/log.txt", OpenMode::Read).unwrap());
+        assert_eq!(
+            fs.open("/log.txt", OpenMode::Read),
+            Err(NorFsError::TooManyOpenFiles)
+        );
+        for file in files {
+            fs.close(file).unwrap();
+        }
+        fs.unmount().unwrap();
+        assert_eq!(
+            fs.open("/log.txt", OpenMode::Read),
+            Err(NorFsError::NotMounted)
+        );
+    }
+
+    #[test]
+    fn invalid_path() {
+        let mut fs = mounted();
+        let long = [b'a'; PATH_MAX + 1];
+        let long = core::str::from_utf8(&long).unwrap();
+        assert_eq!(fs.stat(long), Err(NorFsError::InvalidPath));
+        assert_eq!(fs.stat("a\0b"), Err(NorFsError::InvalidPath));
+    }
+}
@@ -0,0 +1,16 @@
+#![no_std]
+
+extern crate alloc;
+
+mod error;
+mod flash;
+mod fs;
+mod storage;
+
+pub use {
+    error::NorFsError,
+    flash::{Flash, RamFlash, RamFlashError},
+    fs::{DirEntry, FileHandle, Info, Metadata, NorFs, OpenMode, MAX_OPEN_FILES},
+    littlefs2::io::Error as LfsError,
+    storage::NorStorage,
+};
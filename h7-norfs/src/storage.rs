@@ -0,0 +1,106 @@
+use {
+    crate::Flash,
+    littlefs2::{consts, driver::Storage, io},
+};
+
+/// littlefs2 block device on a [`Flash`], one block per sector
+pub struct NorStorage<F: Flash> {
+    flash: F,
+}
+
+impl<F: Flash> NorStorage<F> {
+    pub const fn new(flash: F) -> Self {
+        Self { flash }
+    }
+
+    pub fn flash(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Program `data` at `offset`, split at page boundaries
+    pub fn program(&mut self, offset: usize, data: &[u8]) -> Result<(), F::Error> {
+        let mut offset = offset;
+        let mut data = data;
+        while !data.is_empty() {
+            let len = data.len().min(F::PAGE_SIZE - offset % F::PAGE_SIZE);
+            self.flash.program_page(offset as u32, &data[..len])?;
+            offset += len;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    /// Erase the sectors in `offset..offset + len`, both have to be sector aligned
+    pub fn erase_range(&mut self, offset: usize, len: usize) -> Result<(), F::Error> {
+        debug_assert!(offset.is_multiple_of(F::SECTOR_SIZE) && len.is_multiple_of(F::SECTOR_SIZE));
+        for sector in (offset..offset + len).step_by(F::SECTOR_SIZE) {
+            self.flash.erase_sector(sector as u32)?;
+        }
+        Ok(())
+    }
+}
+
+impl<F: Flash> Storage for NorStorage<F> {
+    const READ_SIZE: usize = 16;
+    const WRITE_SIZE: usize = 16;
+    const BLOCK_SIZE: usize = F::SECTOR_SIZE;
+    const BLOCK_COUNT: usize = F::CAPACITY / F::SECTOR_SIZE;
+    // Wear leveling, move metadata after this many erases
+    const BLOCK_CYCLES: isize = 500;
+
+    // A multiple of WRITE_SIZE and a factor of BLOCK_SIZE
+    type CACHE_SIZE = consts::U256;
+    // In units of 8 bytes, tracks 1024 blocks per scan
+    type LOOKAHEAD_SIZE = consts::U16;
+
+    fn read(&mut self, off: usize, buf: &mut [u8]) -> io::Result<usize> {
+        self.flash
+            .read(off as u32, buf)
+            .map_err(|_| io::Error::Io)?;
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, off: usize, data: &[u8]) -> io::Result<usize> {
+        self.program(off, data).map_err(|_| io::Error::Io)?;
+        Ok(data.len())
+    }
+
+    fn erase(&mut self, off: usize, len: usize) -> io::Result<usize> {
+        self.erase_range(off, len).map_err(|_| io::Error::Io)?;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::RamFlash};
+
+    #[test]
+    fn program_across_pages() {
+        let mut mem = [0; 8192];
+        let mut storage = NorStorage::new(RamFlash::new(&mut mem));
+        let data = [0x5a; 600];
+        storage.program(200, &data).unwrap();
+        let flash = storage.into_inner();
+        assert!(flash.data()[..200].iter().all(|b| *b == 0xff));
+        assert_eq!(flash.data()[200..800], data);
+        assert!(flash.data()[800..].iter().all(|b| *b == 0xff));
+    }
+
+    #[test]
+    fn erase_sectors() {
+        let mut mem = [0; 3 * 4096];
+        let mut storage = NorStorage::new(RamFlash::new(&mut mem));
+        storage.program(0, &[0; 256]).unwrap();
+        storage.program(4096, &[0; 256]).unwrap();
+        storage.program(8192, &[0; 256]).unwrap();
+        storage.erase_range(4096, 8192).unwrap();
+        let flash = storage.into_inner();
+        assert!(flash.data()[..256].iter().all(|b| *b == 0));
+        assert!(flash.data()[4096..].iter().all(|b| *b == 0xff));
+    }
+}
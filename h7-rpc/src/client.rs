@@ -0,0 +1,228 @@
+use {
+    crate::{encode, LineDecoder, Request, Response, RpcError, EXIT, READY_ID},
+    std::{
+        io::{ErrorKind, Read, Write},
+        time::{Duration, Instant},
+    },
+};
+
+/// Time to wait for a response
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The host side, over a serial port with a read timeout
+pub struct Client<T> {
+    io: T,
+    decoder: LineDecoder,
+    next_id: u32,
+    timeout: Duration,
+}
+
+impl<T: Read + Write> Client<T> {
+    /// Switch the shell to RPC mode, works if it's in RPC mode already
+    pub fn connect(io: T) -> Result<Self, RpcError> {
+        let mut client = Self {
+            io,
+            decoder: LineDecoder::new(),
+            next_id: READY_ID + 1,
+            timeout: TIMEOUT,
+        };
+        // Ctrl-C drops a partly typed line. In RPC mode `rpc` is skipped and the request
+        // answered instead.
+        client.write(b"\x03rpc\n")?;
+        client.write(&encode(&Request {
+            id: READY_ID,
+            cmd: "rpc".into(),
+            args: Vec::new(),
+        }))?;
+        client.wait(READY_ID)?;
+        Ok(client)
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Run `cmd` and wait for its response
+    pub fn call(&mut self, cmd: &str, args: &[&str]) -> Result<Response, RpcError> {
+        let id = self.send(cmd, args)?;
+        self.wait(id)
+    }
+
+    /// Start `cmd`, returns the id to `wait` for
+    pub fn send(&mut self, cmd: &str, args: &[&str]) -> Result<u32, RpcError> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(READY_ID + 1);
+        self.write(&encode(&Request {
+            id,
+            cmd: cmd.into(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        }))?;
+        Ok(id)
+    }
+
+    /// The response to request `id`, other lines are skipped
+    pub fn wait(&mut self, id: u32) -> Result<Response, RpcError> {
+        let deadline = Instant::now() + self.timeout;
+        let mut byte = [0u8];
+        while Instant::now() < deadline {
+            match self.io.read(&mut byte) {
+                Ok(1) => {}
+                Ok(_) => continue,
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted) => {
+                    continue
+                }
+                Err(e) => return Err(RpcError::Io(e.to_string())),
+            }
+            match self.decoder.push::<Response>(byte[0]) {
+                Some(Ok(response)) if response.id == id => return Ok(response),
+                _ => {}
+            }
+        }
+        Err(RpcError::Timeout)
+    }
+
+    /// The port, for transfers started by a request
+    pub fn io_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+
+    /// Return the shell to text mode
+    pub fn close(mut self) -> Result<T, RpcError> {
+        self.call(EXIT, &[])?;
+        Ok(self.io)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), RpcError> {
+        self.io
+            .write_all(data)
+            .and_then(|_| self.io.flush())
+            .map_err(|e| RpcError::Io(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::Value,
+        serde_json::json,
+        std::{
+            io,
+            sync::mpsc::{channel, Receiver, Sender},
+            thread,
+        },
+    };
+
+    /// One end of an in-memory serial port, reads time out after 20 ms
+    struct Pipe {
+        rx: Receiver<u8>,
+        tx: Sender<u8>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.rx.recv_timeout(Duration::from_millis(20)) {
+                Ok(b) => {
+                    buf[0] = b;
+                    Ok(1)
+                }
+                Err(_) => Err(ErrorKind::TimedOut.into()),
+            }
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            for &b in buf {
+                self.tx
+                    .send(b)
+                    .map_err(|_| io::Error::from(ErrorKind::BrokenPipe))?;
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn pipe() -> (Pipe, Pipe) {
+        let (a_tx, b_rx) = channel();
+        let (b_tx, a_rx) = channel();
+        (Pipe { rx: a_rx, tx: a_tx }, Pipe { rx: b_rx, tx: b_tx })
+    }
+
+    /// A shell in text mode that echoes input until `rpc`, then answers requests like the
+    /// firmware. Returns the requests it got.
+    fn board(mut port: Pipe) -> thread::JoinHandle<Vec<Request>> {
+        thread::spawn(move || {
+            let mut line = Vec::new();
+            let mut byte = [0u8];
+            while port.read(&mut byte).is_ok() {
+                port.write_all(&byte).unwrap();
+                match byte[0] {
+                    b'\n' if line.ends_with(b"rpc") => break,
+                    b'\n' => line.clear(),
+                    b => line.push(b),
+                }
+            }
+            port.write_all(&encode(&Response::ok(READY_ID, Value::Null, String::new())))
+                .unwrap();
+
+            let mut decoder = LineDecoder::new();
+            let mut requests = Vec::new();
+            while port.read(&mut byte).is_ok() {
+                let Some(Ok(request)) = decoder.push::<Request>(byte[0]) else {
+                    continue;
+                };
+                let response = match request.cmd.as_str() {
+                    "date" => Response::ok(
+                        request.id,
+                        json!({"date": "2026-10-18T12:00:00"}),
+                        "Sun Oct 18 12:00:00 2026\n".into(),
+                    ),
+                    "rpc" | EXIT => Response::ok(request.id, Value::Null, String::new()),
+                    _ => Response::error(request.id, "Command not found".into(), String::new()),
+                };
+                port.write_all(&encode(&response)).unwrap();
+                let exit = request.cmd == EXIT;
+                requests.push(request);
+                if exit {
+                    break;
+                }
+            }
+            requests
+        })
+    }
+
+    #[test]
+    fn call() {
+        let (host, port) = pipe();
+        let board = board(port);
+        let mut client = Client::connect(host).unwrap();
+        let response = client.call("date", &[]).unwrap();
+        assert_eq!(response.data["date"], "2026-10-18T12:00:00");
+        assert_eq!(response.output, "Sun Oct 18 12:00:00 2026\n");
+
+        let response = client.call("nope", &["a"]).unwrap();
+        assert!(!response.ok);
+        assert_eq!(response.error.as_deref(), Some("Command not found"));
+        client.close().unwrap();
+
+        let cmds: Vec<_> = board.join().unwrap();
+        let cmds: Vec<_> = cmds.iter().map(|r| (r.id, r.cmd.as_str())).collect();
+        assert_eq!(cmds, [(0, "rpc"), (1, "date"), (2, "nope"), (3, EXIT)]);
+    }
+
+    #[test]
+    fn timeout() {
+        let (host, _port) = pipe();
+        let mut client = Client {
+            io: host,
+            decoder: LineDecoder::new(),
+            next_id: 1,
+            timeout: Duration::from_millis(100),
+        };
+        assert_eq!(client.call("date", &[]), Err(RpcError::Timeout));
+    }
+}
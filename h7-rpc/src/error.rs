@@ -0,0 +1,24 @@
+use alloc::string::String;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcError {
+    /// Line longer than `MAX_LINE`
+    TooLong,
+    /// Line is not a valid message
+    Json(String),
+    /// No response in time
+    Timeout,
+    /// Could not read or write the port
+    Io(String),
+}
+
+impl core::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::TooLong => write!(f, "Line too long"),
+            Self::Json(e) => write!(f, "Invalid message: {e}"),
+            Self::Timeout => write!(f, "Timed out"),
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
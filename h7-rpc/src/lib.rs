@@ -0,0 +1,195 @@
+//! Machine-readable mode of the firmware shell. `rpc` switches the terminal UART to JSON lines,
+//! one request and one response per line:
+//!
+//! ```text
+//! > {"id":1,"cmd":"ls","args":["sdcard:/"]}
+//! < {"id":1,"ok":true,"data":{"entries":[...]},"output":"..."}
+//! ```
+//!
+//! `data` holds what the command reports in a structured form, `output` its text output.
+//! The firmware answers `rpc` with a response with id `0`, and leaves the mode on `exit`.
+
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+extern crate alloc;
+
+#[cfg(any(test, feature = "std"))]
+mod client;
+mod error;
+
+#[cfg(any(test, feature = "std"))]
+pub use client::Client;
+pub use {error::RpcError, serde_json::Value};
+
+use {
+    alloc::{string::String, vec::Vec},
+    serde::{de::DeserializeOwned, Deserialize, Serialize},
+};
+
+/// Longest line in either direction
+pub const MAX_LINE: usize = 16 * 1024;
+/// Id of the response to `rpc`, and to lines that are not a request
+pub const READY_ID: u32 = 0;
+/// Request that leaves RPC mode
+pub const EXIT: &str = "exit";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Request {
+    pub id: u32,
+    pub cmd: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub id: u32,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub data: Value,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub output: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Response {
+    pub fn ok(id: u32, data: Value, output: String) -> Self {
+        Self {
+            id,
+            ok: true,
+            data,
+            output,
+            error: None,
+        }
+    }
+
+    pub fn error(id: u32, error: String, output: String) -> Self {
+        Self {
+            id,
+            ok: false,
+            data: Value::Null,
+            output,
+            error: Some(error),
+        }
+    }
+}
+
+/// `msg` as a line of JSON
+pub fn encode<T: Serialize>(msg: &T) -> Vec<u8> {
+    // Only fails for maps with non-string keys
+    let mut line = serde_json::to_vec(msg).unwrap_or_default();
+    line.push(b'\n');
+    line
+}
+
+/// Splits received bytes into lines and parses them. Anything before the first `{` of a line is
+/// skipped, the shell's echo and prompt, and so are empty lines.
+pub struct LineDecoder {
+    line: Vec<u8>,
+    overflow: bool,
+}
+
+impl LineDecoder {
+    pub const fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            overflow: false,
+        }
+    }
+
+    /// Add a byte, returns the message once a line is complete
+    pub fn push<T: DeserializeOwned>(&mut self, byte: u8) -> Option<Result<T, RpcError>> {
+        match byte {
+            b'\n' => {
+                let line = core::mem::take(&mut self.line);
+                if core::mem::take(&mut self.overflow) {
+                    return Some(Err(RpcError::TooLong));
+                }
+                let start = line.iter().position(|&b| b == b'{')?;
+                Some(
+                    serde_json::from_slice(&line[start..])
+                        .map_err(|e| RpcError::Json(alloc::format!("{e}"))),
+                )
+            }
+            b'\r' => None,
+            _ if self.line.len() >= MAX_LINE => {
+                self.overflow = true;
+                None
+            }
+            _ => {
+                self.line.push(byte);
+                None
+            }
+        }
+    }
+}
+
+impl Default for LineDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde_json::json};
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Vec<Result<T, RpcError>> {
+        let mut decoder = LineDecoder::new();
+        bytes.iter().filter_map(|&b| decoder.push(b)).collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let request = Request {
+            id: 7,
+            cmd: "ls".into(),
+            args: vec!["sdcard:/".into()],
+        };
+        assert_eq!(decode(&encode(&request)), [Ok(request)]);
+
+        let response = Response::ok(7, json!({"entries": []}), "out\n".into());
+        assert_eq!(decode(&encode(&response)), [Ok(response)]);
+        let response = Response::error(8, "Command not found".into(), String::new());
+        assert_eq!(decode(&encode(&response)), [Ok(response)]);
+    }
+
+    #[test]
+    fn compact_response() {
+        let line = encode(&Response::ok(1, Value::Null, String::new()));
+        assert_eq!(line, b"{\"id\":1,\"ok\":true}\n");
+    }
+
+    #[test]
+    fn skips_echo() {
+        let bytes = b"> rpc\r\n{\"id\":0,\"ok\":true}\r\n\r\n";
+        assert_eq!(
+            decode::<Response>(bytes),
+            [Ok(Response::ok(0, Value::Null, String::new()))]
+        );
+
+        // Arguments default to none
+        let bytes = b"\x03{\"id\":2,\"cmd\":\"info\"}\n";
+        let request = Request {
+            id: 2,
+            cmd: "info".into(),
+            args: Vec::new(),
+        };
+        assert_eq!(decode(bytes), [Ok(request)]);
+    }
+
+    #[test]
+    fn invalid() {
+        let res = decode::<Request>(b"{\"id\":1}\nrpc\n");
+        assert!(matches!(res[..], [Err(RpcError::Json(_))]));
+
+        // The decoder starts over after a long line
+        let mut bytes = vec![b'x'; MAX_LINE + 1];
+        bytes.extend_from_slice(b"\n{\"id\":3,\"cmd\":\"date\"}\n");
+        let res = decode::<Request>(&bytes);
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0], Err(RpcError::TooLong));
+        assert_eq!(res[1].as_ref().map(|r| r.id), Ok(3));
+    }
+}
@@ -0,0 +1,18 @@
+use embedded_sdmmc::BlockDevice;
+
+/// A card that has to be initialized before its blocks can be used, the SD card on the SDMMC
+/// peripheral in the firmware
+pub trait Card: Sized {
+    type BlockDevice: BlockDevice;
+    /// Settings for `init`, the bus clock of an SD card
+    type Config: Copy;
+
+    fn init(
+        &mut self,
+        config: Self::Config,
+    ) -> Result<(), <Self::BlockDevice as BlockDevice>::Error>;
+
+    fn into_block_device(self) -> Self::BlockDevice;
+
+    fn from_block_device(device: Self::BlockDevice) -> Self;
+}
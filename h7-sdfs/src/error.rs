@@ -0,0 +1,52 @@
+/// Errors of [`SdmmcFs`](crate::SdmmcFs), `E` is the error of the block device
+#[derive(Debug)]
+pub enum SdmmcFsError<E: core::fmt::Debug> {
+    NotFound,
+    AlreadyMounted,
+    NotMounted,
+    /// The firmware has no SD card controller
+    NotInitialized,
+    AlreadyExists,
+    NotADirectory,
+    /// Removing a directory that still has entries
+    DirNotEmpty,
+    /// Path to the root where a file or directory is expected, or source and destination are the same
+    InvalidPath,
+    InvalidOffset(u32),
+    /// The handle isn't an open file
+    NotOpen,
+    /// Unmounting with files open
+    FilesOpen,
+    /// Operation not supported by embedded-sdmmc
+    Unsupported(&'static str),
+    Sdmmc(embedded_sdmmc::Error<E>),
+    /// The card failed to initialize
+    Card(E),
+}
+
+impl<E: core::fmt::Debug> From<embedded_sdmmc::Error<E>> for SdmmcFsError<E> {
+    fn from(err: embedded_sdmmc::Error<E>) -> Self {
+        Self::Sdmmc(err)
+    }
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for SdmmcFsError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "Not Found"),
+            Self::AlreadyMounted => write!(f, "Already mounted"),
+            Self::NotMounted => write!(f, "Not Mounted"),
+            Self::NotInitialized => write!(f, "SD Card controller not initialized"),
+            Self::AlreadyExists => write!(f, "Already exists"),
+            Self::NotADirectory => write!(f, "Not a directory"),
+            Self::DirNotEmpty => write!(f, "Directory not empty"),
+            Self::InvalidPath => write!(f, "Invalid path"),
+            Self::InvalidOffset(offset) => write!(f, "Invalid offset {offset}"),
+            Self::NotOpen => write!(f, "File not open"),
+            Self::FilesOpen => write!(f, "Files are open"),
+            Self::Unsupported(what) => write!(f, "{what} is not supported"),
+            Self::Sdmmc(e) => write!(f, "Sdmmc: {e:?}"),
+            Self::Card(e) => write!(f, "Card: {e:?}"),
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! The FAT and directory entries on the block device.
+//!
+//! embedded-sdmmc 0.5 keeps its clusters and its FAT code to itself, so directories are made
+//! and removed here: the partition's boot sector is read for where the FATs and clusters are,
+//! and entries are patched in place at the block and offset of their [`DirEntry`].
+
+use {
+    crate::{
+        lfn::{self, ATTR_DIRECTORY, ATTR_LONG_NAME, DELETED, DIR_ENTRY_SIZE},
+        SdmmcFsError,
+    },
+    embedded_sdmmc::{Block, BlockDevice, BlockIdx, DirEntry},
+};
+
+/// First partition entry in the MBR
+const PARTITION_ENTRY: usize = 446;
+const BLOCK_SIZE: u32 = Block::LEN as u32;
+/// Clusters 0 and 1 are reserved, cluster 2 is the first in the data area
+const FIRST_CLUSTER: u32 = 2;
+/// Fewer clusters than this is FAT12
+const MIN_FAT16_CLUSTERS: u32 = 4085;
+/// Fewer clusters than this is FAT16
+const MIN_FAT32_CLUSTERS: u32 = 65525;
+/// Free cluster count in the FAT32 FSInfo sector
+const FSINFO_FREE_COUNT: usize = 488;
+const FSINFO_UNKNOWN: u32 = 0xffff_ffff;
+
+type Error<D> = SdmmcFsError<<D as BlockDevice>::Error>;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FatType {
+    Fat16,
+    Fat32,
+}
+
+/// Where the FATs and the clusters of the first partition are
+pub struct Fat {
+    fat_type: FatType,
+    /// First block of the first FAT
+    fat_start: u32,
+    /// Blocks per FAT
+    fat_blocks: u32,
+    fats: u32,
+    /// First block of cluster 2
+    data_start: u32,
+    blocks_per_cluster: u32,
+    clusters: u32,
+    /// The FAT32 FSInfo sector
+    info_block: Option<u32>,
+}
+
+impl Fat {
+    /// Read the boot sector of the first partition
+    pub fn read<D: BlockDevice>(device: &D) -> Result<Self, Error<D>> {
+        let mut block = [Block::new()];
+        read_block(device, &mut block, 0)?;
+        let lba = le32(&block[0].contents, PARTITION_ENTRY + 8);
+        read_block(device, &mut block, lba)?;
+        let bpb = &block[0].contents;
+
+        if le16(bpb, 11) as u32 != BLOCK_SIZE {
+            return Err(embedded_sdmmc::Error::BadBlockSize(le16(bpb, 11)).into());
+        }
+        let blocks_per_cluster = bpb[13] as u32;
+        let reserved = le16(bpb, 14) as u32;
+        let fats = bpb[16] as u32;
+        let root_blocks = (le16(bpb, 17) as u32 * DIR_ENTRY_SIZE as u32).div_ceil(BLOCK_SIZE);
+        let total_blocks = match le16(bpb, 19) {
+            0 => le32(bpb, 32),
+            n => n as u32,
+        };
+        let fat_blocks = match le16(bpb, 22) {
+            0 => le32(bpb, 36),
+            n => n as u32,
+        };
+        let data_start = reserved + fats * fat_blocks + root_blocks;
+        if blocks_per_cluster == 0 || total_blocks < data_start {
+            return Err(embedded_sdmmc::Error::FormatError("Bad boot sector").into());
+        }
+        let clusters = (total_blocks - data_start) / blocks_per_cluster;
+        let fat_type = match clusters {
+            c if c < MIN_FAT16_CLUSTERS => return Err(SdmmcFsError::Unsupported("FAT12")),
+            c if c < MIN_FAT32_CLUSTERS => FatType::Fat16,
+            _ => FatType::Fat32,
+        };
+
+        Ok(Self {
+            fat_type,
+            fat_start: lba + reserved,
+            fat_blocks,
+            fats,
+            data_start: lba + data_start,
+            blocks_per_cluster,
+            clusters,
+            info_block: (fat_type == FatType::Fat32).then(|| lba + le16(bpb, 48) as u32),
+        })
+    }
+
+    pub fn cluster_block(&self, cluster: u32) -> BlockIdx {
+        BlockIdx(self.data_start + (cluster - FIRST_CLUSTER) * self.blocks_per_cluster)
+    }
+
+    /// Take the first free cluster, marked as the end of its chain and zeroed
+    pub fn alloc<D: BlockDevice>(&self, device: &D) -> Result<u32, Error<D>> {
+        let entries_per_block = BLOCK_SIZE / self.entry_size();
+        let end = FIRST_CLUSTER + self.clusters;
+        let mut block = [Block::new()];
+        for b in 0..self.fat_blocks {
+            let first = b * entries_per_block;
+            if first >= end {
+                break;
+            }
+            read_block(device, &mut block, self.fat_start + b)?;
+            let free = (first.max(FIRST_CLUSTER)..end.min(first + entries_per_block))
+                .find(|c| self.value(&block[0].contents, *c) == 0);
+            if let Some(cluster) = free {
+                self.set(device, cluster, self.end_of_chain())?;
+                self.zero_cluster(device, cluster)?;
+                self.add_free(device, -1)?;
+                return Ok(cluster);
+            }
+        }
+        Err(embedded_sdmmc::Error::NotEnoughSpace.into())
+    }
+
+    /// Mark the chain starting at `cluster` free
+    pub fn free_chain<D: BlockDevice>(&self, device: &D, mut cluster: u32) -> Result<(), Error<D>> {
+        let mut freed = 0;
+        while (FIRST_CLUSTER..FIRST_CLUSTER + self.clusters).contains(&cluster) {
+            let next = self.get(device, cluster)?;
+            self.set(device, cluster, 0)?;
+            freed += 1;
+            // A chain can't be longer than the volume, a loop is a broken FAT
+            if freed > self.clusters {
+                return Err(embedded_sdmmc::Error::BadCluster.into());
+            }
+            cluster = next;
+        }
+        self.add_free(device, freed as i32)
+    }
+
+    fn zero_cluster<D: BlockDevice>(&self, device: &D, cluster: u32) -> Result<(), Error<D>> {
+        let start = self.cluster_block(cluster).0;
+        (start..start + self.blocks_per_cluster).try_for_each(|b| {
+            device
+                .write(&[Block::new()], BlockIdx(b))
+                .map_err(|e| embedded_sdmmc::Error::DeviceError(e).into())
+        })
+    }
+
+    fn entry_size(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat16 => 2,
+            FatType::Fat32 => 4,
+        }
+    }
+
+    fn end_of_chain(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat16 => 0xffff,
+            FatType::Fat32 => 0x0fff_ffff,
+        }
+    }
+
+    /// The FAT entry of `cluster` in the FAT block it falls in
+    fn value(&self, block: &[u8], cluster: u32) -> u32 {
+        let offset = ((cluster * self.entry_size()) % BLOCK_SIZE) as usize;
+        match self.fat_type {
+            FatType::Fat16 => le16(block, offset) as u32,
+            FatType::Fat32 => le32(block, offset) & 0x0fff_ffff,
+        }
+    }
+
+    fn get<D: BlockDevice>(&self, device: &D, cluster: u32) -> Result<u32, Error<D>> {
+        let mut block = [Block::new()];
+        let fat_block = cluster * self.entry_size() / BLOCK_SIZE;
+        read_block(device, &mut block, self.fat_start + fat_block)?;
+        Ok(self.value(&block[0].contents, cluster))
+    }
+
+    /// Set the entry of `cluster` in every FAT
+    fn set<D: BlockDevice>(&self, device: &D, cluster: u32, value: u32) -> Result<(), Error<D>> {
+        let mut block = [Block::new()];
+        let fat_block = cluster * self.entry_size() / BLOCK_SIZE;
+        let offset = ((cluster * self.entry_size()) % BLOCK_SIZE) as usize;
+        for fat in 0..self.fats {
+            let idx = self.fat_start + fat * self.fat_blocks + fat_block;
+            read_block(device, &mut block, idx)?;
+            let data = &mut block[0].contents;
+            match self.fat_type {
+                FatType::Fat16 => {
+                    data[offset..offset + 2].copy_from_slice(&(value as u16).to_le_bytes())
+                }
+                FatType::Fat32 => {
+                    // The top 4 bits are reserved
+                    let value = (le32(data, offset) & 0xf000_0000) | value;
+                    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+                }
+            }
+            write_block(device, &block, idx)?;
+        }
+        Ok(())
+    }
+
+    /// Keep the FAT32 free cluster count right, if it's known
+    fn add_free<D: BlockDevice>(&self, device: &D, clusters: i32) -> Result<(), Error<D>> {
+        let Some(idx) = self.info_block else {
+            return Ok(());
+        };
+        let mut block = [Block::new()];
+        read_block(device, &mut block, idx)?;
+        let data = &mut block[0].contents;
+        let count = le32(data, FSINFO_FREE_COUNT);
+        if count == FSINFO_UNKNOWN {
+            return Ok(());
+        }
+        let count = count.wrapping_add_signed(clusters);
+        data[FSINFO_FREE_COUNT..FSINFO_FREE_COUNT + 4].copy_from_slice(&count.to_le_bytes());
+        write_block(device, &block, idx)
+    }
+}
+
+/// The raw 32 bytes of `entry`
+pub fn read_entry<D: BlockDevice>(
+    device: &D,
+    entry: &DirEntry,
+) -> Result<[u8; DIR_ENTRY_SIZE], Error<D>> {
+    let mut block = [Block::new()];
+    read_block(device, &mut block, entry.entry_block.0)?;
+    let offset = entry.entry_offset as usize;
+    let mut raw = [0; DIR_ENTRY_SIZE];
+    raw.copy_from_slice(&block[0].contents[offset..offset + DIR_ENTRY_SIZE]);
+    Ok(raw)
+}
+
+/// Overwrite `entry` with `raw`
+pub fn write_entry<D: BlockDevice>(
+    device: &D,
+    entry: &DirEntry,
+    raw: &[u8; DIR_ENTRY_SIZE],
+) -> Result<(), Error<D>> {
+    let mut block = [Block::new()];
+    read_block(device, &mut block, entry.entry_block.0)?;
+    let offset = entry.entry_offset as usize;
+    block[0].contents[offset..offset + DIR_ENTRY_SIZE].copy_from_slice(raw);
+    write_block(device, &block, entry.entry_block.0)
+}
+
+/// Mark `entry` deleted, with the long name entries in front of it in the same block. `raw` is
+/// the entry as it was before, embedded-sdmmc may have marked it already.
+pub fn delete_entry<D: BlockDevice>(
+    device: &D,
+    entry: &DirEntry,
+    raw: &[u8; DIR_ENTRY_SIZE],
+) -> Result<(), Error<D>> {
+    let mut block = [Block::new()];
+    read_block(device, &mut block, entry.entry_block.0)?;
+    let data = &mut block[0].contents;
+    let offset = entry.entry_offset as usize;
+    let checksum = lfn::checksum(raw);
+    data[offset] = DELETED;
+    for start in (0..offset).step_by(DIR_ENTRY_SIZE).rev() {
+        let raw = &mut data[start..start + DIR_ENTRY_SIZE];
+        if raw[11] != ATTR_LONG_NAME || raw[0] == DELETED || raw[13] != checksum {
+            break;
+        }
+        raw[0] = DELETED;
+    }
+    write_block(device, &block, entry.entry_block.0)
+}
+
+/// The first cluster in a raw directory entry, 0 for an empty file
+pub fn entry_cluster(raw: &[u8]) -> u32 {
+    (le16(raw, 20) as u32) << 16 | le16(raw, 26) as u32
+}
+
+pub fn set_entry_cluster(raw: &mut [u8], cluster: u32) {
+    raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+}
+
+pub fn set_entry_size(raw: &mut [u8], size: u32) {
+    raw[28..32].copy_from_slice(&size.to_le_bytes());
+}
+
+/// `.` or `..` in a new directory, with the times of the directory's own entry
+pub fn dot_entry(dir: &[u8; DIR_ENTRY_SIZE], name: &[u8], cluster: u32) -> [u8; DIR_ENTRY_SIZE] {
+    let mut raw = *dir;
+    raw[..11].fill(b' ');
+    raw[..name.len()].copy_from_slice(name);
+    raw[11] = ATTR_DIRECTORY;
+    set_entry_cluster(&mut raw, cluster);
+    raw[28..32].fill(0);
+    raw
+}
+
+fn read_block<D: BlockDevice>(
+    device: &D,
+    block: &mut [Block; 1],
+    idx: u32,
+) -> Result<(), Error<D>> {
+    device
+        .read(block, BlockIdx(idx), "fat")
+        .map_err(|e| embedded_sdmmc::Error::DeviceError(e).into())
+}
+
+fn write_block<D: BlockDevice>(device: &D, block: &[Block; 1], idx: u32) -> Result<(), Error<D>> {
+    device
+        .write(block, BlockIdx(idx))
+        .map_err(|e| embedded_sdmmc::Error::DeviceError(e).into())
+}
+
+fn le16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn le32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
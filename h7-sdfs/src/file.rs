@@ -0,0 +1,82 @@
+use {
+    crate::Card,
+    embedded_sdmmc::{Block, BlockCount, BlockDevice, BlockIdx},
+    std::{
+        fs::{File, OpenOptions},
+        io::{self, Read, Seek, SeekFrom, Write},
+        path::Path,
+    },
+};
+
+const BLOCK_SIZE: u64 = 512;
+
+/// A disk image file as a block device, for testing on the host. The image needs a partition
+/// table like an SD card.
+pub struct FileBlockDevice {
+    file: File,
+}
+
+impl FileBlockDevice {
+    /// Open an image for reading and writing
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(Self { file })
+    }
+
+    pub fn into_inner(self) -> File {
+        self.file
+    }
+
+    fn seek(&self, idx: BlockIdx) -> io::Result<&File> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(idx.0 as u64 * BLOCK_SIZE))?;
+        Ok(file)
+    }
+}
+
+impl BlockDevice for FileBlockDevice {
+    type Error = io::Error;
+
+    fn read(
+        &self,
+        blocks: &mut [Block],
+        start_block_idx: BlockIdx,
+        _reason: &str,
+    ) -> Result<(), Self::Error> {
+        let mut file = self.seek(start_block_idx)?;
+        blocks
+            .iter_mut()
+            .try_for_each(|block| file.read_exact(&mut block.contents))
+    }
+
+    fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+        let mut file = self.seek(start_block_idx)?;
+        blocks
+            .iter()
+            .try_for_each(|block| file.write_all(&block.contents))
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, Self::Error> {
+        Ok(BlockCount(
+            (self.file.metadata()?.len() / BLOCK_SIZE) as u32,
+        ))
+    }
+}
+
+/// The image is always ready
+impl Card for FileBlockDevice {
+    type BlockDevice = Self;
+    type Config = ();
+
+    fn init(&mut self, _config: ()) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn into_block_device(self) -> Self {
+        self
+    }
+
+    fn from_block_device(device: Self) -> Self {
+        device
+    }
+}
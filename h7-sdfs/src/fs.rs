@@ -0,0 +1,1461 @@
+use {
+    crate::{
+        fat::{self, Fat},
+        lfn::{self, DirParser, Entry, Parsed, ATTR_DIRECTORY, DIR_ENTRY_SIZE},
+        Card, SdmmcFsError,
+    },
+    embedded_hal::blocking::delay::DelayMs,
+    embedded_sdmmc::{
+        Block, BlockDevice, BlockIdx, Controller, DirEntry, Directory, File, Mode as FileOpenMode,
+        ShortFileName, TimeSource, Volume, VolumeIdx,
+    },
+    heapless::{String, Vec},
+};
+
+/// Directory blocks read per pass when listing long names
+const DIR_BLOCK_WINDOW: usize = 16;
+
+type DeviceError<C> = <<C as Card>::BlockDevice as BlockDevice>::Error;
+type Error<C> = SdmmcFsError<DeviceError<C>>;
+
+enum SdmmcState<C: Card, T: TimeSource, const MAX_OPEN_DIRS: usize, const MAX_OPEN_FILES: usize> {
+    Controller(Controller<C::BlockDevice, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>),
+    Card(C, T),
+    /// Only while mounting or unmounting, the volume is not mounted
+    MidSwap,
+}
+
+/// A file opened with [`SdmmcFs::open`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileHandle(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    Read,
+    /// Create or truncate
+    Write,
+    /// Create if it doesn't exist and start at the end
+    Append,
+}
+
+/// An open file and its directory entry
+struct OpenFile {
+    file: File,
+    entry: DirEntry,
+}
+
+/// The FAT volume on the first partition of a card.
+///
+/// Paths are on the volume, `/apps/hello.h7`, the leading `/` is optional. Names are looked up
+/// with their long names, new files get 8.3 names.
+pub struct SdmmcFs<C: Card, T: TimeSource, const MAX_OPEN_DIRS: usize, const MAX_OPEN_FILES: usize>
+{
+    state: SdmmcState<C, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+    files: [Option<OpenFile>; MAX_OPEN_FILES],
+}
+
+impl<C: Card, T: TimeSource, const MAX_OPEN_DIRS: usize, const MAX_OPEN_FILES: usize>
+    SdmmcFs<C, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>
+{
+    pub fn new(card: C, time_source: T) -> Self {
+        Self {
+            state: SdmmcState::Card(card, time_source),
+            files: core::array::from_fn(|_| None),
+        }
+    }
+
+    pub fn is_mounted(&self) -> bool {
+        matches!(self.state, SdmmcState::Controller(_))
+    }
+
+    pub fn card_size(&mut self) -> Result<u64, Error<C>> {
+        match self.state {
+            SdmmcState::Controller(ref mut c) => {
+                let blocks = c
+                    .device()
+                    .num_blocks()
+                    .map_err(embedded_sdmmc::Error::DeviceError)?
+                    .0;
+                Ok(blocks as u64 * 512)
+            }
+            SdmmcState::Card(..) | SdmmcState::MidSwap => Err(SdmmcFsError::NotMounted),
+        }
+    }
+
+    /// Initialize the card, trying `n_retry` times with `delay` ms in between
+    pub fn mount<D: DelayMs<u16>>(
+        &mut self,
+        config: C::Config,
+        n_retry: u8,
+        mut delay: Option<(u16, &mut D)>,
+    ) -> Result<(), Error<C>> {
+        match &mut self.state {
+            SdmmcState::Controller(_) => return Err(SdmmcFsError::AlreadyMounted),
+            SdmmcState::Card(card, _) => {
+                for i in (0..n_retry).rev() {
+                    match card.init(config) {
+                        Ok(_) => {
+                            if let SdmmcState::Card(card, time_source) =
+                                core::mem::replace(&mut self.state, SdmmcState::MidSwap)
+                            {
+                                self.state = SdmmcState::Controller(Controller::<
+                                    _,
+                                    _,
+                                    MAX_OPEN_DIRS,
+                                    MAX_OPEN_FILES,
+                                >::new_with_limits(
+                                    card.into_block_device(),
+                                    time_source,
+                                ));
+                            }
+                            return Ok(());
+                        }
+                        Err(e) => {
+                            if i == 0 {
+                                return Err(SdmmcFsError::Card(e));
+                            } else {
+                                log::warn!("SD Card mount failed, retrying...");
+                                if let Some((time, ref mut delay)) = delay {
+                                    delay.delay_ms(time);
+                                }
+                                continue;
+                            }
+                        }
+                    }
+                }
+            }
+            SdmmcState::MidSwap => {}
+        };
+        Err(SdmmcFsError::NotMounted)
+    }
+
+    /// Useless on the H7 until https://github.com/stm32-rs/stm32h7xx-hal/issues/145 is fixed.
+    /// The files have to be closed first.
+    pub fn unmount(&mut self) -> Result<(), Error<C>> {
+        if self.files.iter().any(Option::is_some) {
+            return Err(SdmmcFsError::FilesOpen);
+        }
+        match core::mem::replace(&mut self.state, SdmmcState::MidSwap) {
+            SdmmcState::Controller(c) => {
+                let (device, time_source) = c.free();
+                self.state = SdmmcState::Card(C::from_block_device(device), time_source);
+                Ok(())
+            }
+            state => {
+                self.state = state;
+                Err(SdmmcFsError::NotMounted)
+            }
+        }
+    }
+
+    /// Open a file, it stays open until [`close`](Self::close) so reads and writes go on from
+    /// where the last one stopped. A file can only be open once. Files opened for writing get
+    /// their first cluster here, new ones need an 8.3 name.
+    pub fn open(&mut self, path: &str, mode: OpenMode) -> Result<FileHandle, Error<C>> {
+        let index = self
+            .files
+            .iter()
+            .position(Option::is_none)
+            .ok_or(embedded_sdmmc::Error::TooManyOpenFiles)?;
+        let mode = match mode {
+            OpenMode::Read => FileOpenMode::ReadOnly,
+            OpenMode::Write => FileOpenMode::ReadWriteTruncate,
+            OpenMode::Append => FileOpenMode::ReadWriteAppend,
+        };
+        if mode != FileOpenMode::ReadOnly {
+            self.prepare_write(path)?;
+        }
+        let entry = self.stat(path)?;
+        let file = self.with_volume(|controller, volume| {
+            match controller.open_dir_entry(volume, entry.clone(), mode) {
+                // What it says for files too
+                Err(embedded_sdmmc::Error::DirAlreadyOpen) => {
+                    Err(embedded_sdmmc::Error::FileAlreadyOpen.into())
+                }
+                file => Ok(file?),
+            }
+        })?;
+        self.files[index] = Some(OpenFile { file, entry });
+        Ok(FileHandle(index))
+    }
+
+    pub fn close(&mut self, file: FileHandle) -> Result<(), Error<C>> {
+        let (controller, slot) = self.file_slot(file)?;
+        let open = slot.take().ok_or(SdmmcFsError::NotOpen)?;
+        let volume = controller.get_volume(VolumeIdx(0))?;
+        Ok(controller.close_file(&volume, open.file)?)
+    }
+
+    /// Read into `data` from the position of the file, returns the number of bytes read, 0 at
+    /// the end of the file
+    pub fn read(&mut self, file: FileHandle, data: &mut [u8]) -> Result<usize, Error<C>> {
+        let (controller, slot) = self.file_slot(file)?;
+        let open = slot.as_mut().ok_or(SdmmcFsError::NotOpen)?;
+        let volume = controller.get_volume(VolumeIdx(0))?;
+        Ok(controller.read(&volume, &mut open.file, data)?)
+    }
+
+    /// Write `data` at the position of the file, returns the number of bytes written
+    pub fn write(&mut self, file: FileHandle, data: &[u8]) -> Result<usize, Error<C>> {
+        let (controller, slot) = self.file_slot(file)?;
+        let open = slot.as_mut().ok_or(SdmmcFsError::NotOpen)?;
+        let mut volume = controller.get_volume(VolumeIdx(0))?;
+        let length = open.file.length();
+        let offset = length - open.file.left();
+
+        // embedded-sdmmc only reads a block before writing to it when the write starts inside
+        // it, a write that ends inside a block it starts at the beginning of loses the rest of
+        // the block. That's read here first and written again after `data`.
+        let end = offset.saturating_add(data.len() as u32);
+        let block_start = end - end % Block::LEN_U32;
+        let mut tail = Block::new();
+        let mut tail_len = 0;
+        if end < length && end != block_start && block_start >= offset {
+            tail_len = (length.min(block_start + Block::LEN_U32) - end) as usize;
+            seek(&mut open.file, end)?;
+            controller.read(&volume, &mut open.file, &mut tail.contents[..tail_len])?;
+            seek(&mut open.file, offset)?;
+        }
+        let written = controller.write(&mut volume, &mut open.file, data)?;
+        if written == data.len() && tail_len > 0 {
+            controller.write(&mut volume, &mut open.file, &tail.contents[..tail_len])?;
+        }
+        let end = offset + written as u32;
+        if open.file.length() == length.max(end) && tail_len == 0 {
+            return Ok(written);
+        }
+
+        // It also adds all that's written to the length, also what overwrote the file. The
+        // entry gets the real length and the file is opened again with it.
+        let OpenFile { file, mut entry } = slot.take().ok_or(SdmmcFsError::NotOpen)?;
+        controller.close_file(&volume, file)?;
+        entry.size = length.max(end);
+        let device = &*controller.device();
+        let mut raw = fat::read_entry(device, &entry)?;
+        fat::set_entry_size(&mut raw, entry.size);
+        fat::write_entry(device, &entry, &raw)?;
+        let mut file =
+            controller.open_dir_entry(&mut volume, entry.clone(), FileOpenMode::ReadWriteAppend)?;
+        seek(&mut file, end)?;
+        *slot = Some(OpenFile { file, entry });
+        Ok(written)
+    }
+
+    /// Move the position of the file to `offset` from the start, at most to the end
+    pub fn seek(&mut self, file: FileHandle, offset: u32) -> Result<(), Error<C>> {
+        let (_, slot) = self.file_slot(file)?;
+        seek(
+            &mut slot.as_mut().ok_or(SdmmcFsError::NotOpen)?.file,
+            offset,
+        )
+    }
+
+    pub fn size(&mut self, file: FileHandle) -> Result<u32, Error<C>> {
+        let (_, slot) = self.file_slot(file)?;
+        Ok(slot.as_ref().ok_or(SdmmcFsError::NotOpen)?.file.length())
+    }
+
+    /// Read from `offset` into `data`, returns the number of bytes read, 0 at the end of the file.
+    /// Used to stream files that don't fit in RAM.
+    pub fn read_file_at(
+        &mut self,
+        path: &str,
+        offset: u32,
+        data: &mut [u8],
+    ) -> Result<usize, Error<C>> {
+        self.find_file(path, FileOpenMode::ReadOnly, |controller, volume, file| {
+            if offset >= file.length() {
+                return Ok(0);
+            }
+            file.seek_from_start(offset)
+                .map_err(|_| SdmmcFsError::InvalidOffset(offset))?;
+            controller
+                .read(volume, file, data)
+                .map_err(SdmmcFsError::from)
+        })?
+    }
+
+    /// Call `func` with the file in chunks of `buf.len()` until it returns false, the file stays
+    /// open in between. Returns the number of bytes read.
+    pub fn read_chunks(
+        &mut self,
+        path: &str,
+        buf: &mut [u8],
+        mut func: impl FnMut(&[u8]) -> bool,
+    ) -> Result<u32, Error<C>> {
+        self.find_file(path, FileOpenMode::ReadOnly, |controller, volume, file| {
+            let mut len = 0;
+            loop {
+                match controller
+                    .read(volume, file, buf)
+                    .map_err(SdmmcFsError::from)?
+                {
+                    0 => return Ok(len),
+                    n => {
+                        len += n as u32;
+                        if !func(&buf[..n]) {
+                            return Ok(len);
+                        }
+                    }
+                }
+            }
+        })?
+    }
+
+    /// Create or truncate the file and write `data`
+    pub fn write_file(&mut self, path: &str, data: &[u8]) -> Result<usize, Error<C>> {
+        self.write_path(path, FileOpenMode::ReadWriteCreateOrTruncate, data)
+    }
+
+    /// Append `data` to the file, creating it if it doesn't exist
+    pub fn append_file(&mut self, path: &str, data: &[u8]) -> Result<usize, Error<C>> {
+        self.write_path(path, FileOpenMode::ReadWriteCreateOrAppend, data)
+    }
+
+    fn write_path(
+        &mut self,
+        path: &str,
+        mode: FileOpenMode,
+        data: &[u8],
+    ) -> Result<usize, Error<C>> {
+        self.prepare_write(path)?;
+        self.find_file(path, mode, |controller, volume, file| {
+            controller.write(volume, file, data)
+        })?
+        .map_err(SdmmcFsError::from)
+    }
+
+    /// Delete the entry and free its clusters, embedded-sdmmc only marks the 8.3 entry deleted
+    pub fn delete_file(&mut self, path: &str) -> Result<(), Error<C>> {
+        self.find_entry(path, |controller, volume, dir, name| {
+            let entry = controller.find_directory_entry(volume, dir, name)?;
+            let raw = fat::read_entry(controller.device(), &entry)?;
+            // Fails for directories and open files
+            controller.delete_file_in_dir(volume, dir, name)?;
+
+            let device = &*controller.device();
+            fat::delete_entry(device, &entry, &raw)?;
+            Fat::read(device)?.free_chain(device, fat::entry_cluster(&raw))
+        })?
+    }
+
+    /// Directory entry of a file or directory, the root directory has none
+    pub fn stat(&mut self, path: &str) -> Result<DirEntry, Error<C>> {
+        self.find_entry(path, |controller, volume, dir, name| {
+            controller.find_directory_entry(volume, dir, name)
+        })?
+        .map_err(SdmmcFsError::from)
+    }
+
+    pub fn exists(&mut self, path: &str) -> Result<bool, Error<C>> {
+        if parts(path).next().is_none() {
+            // Root
+            return self.with_root_dir(|_, _, _| Ok(true));
+        }
+        match self.stat(path) {
+            Ok(_) => Ok(true),
+            Err(SdmmcFsError::Sdmmc(embedded_sdmmc::Error::FileNotFound)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Copy a file in chunks of `buf.len()`, `to` is created or truncated.
+    /// Returns the number of bytes copied.
+    pub fn copy_file(&mut self, from: &str, to: &str, buf: &mut [u8]) -> Result<u32, Error<C>> {
+        if parts(from).eq(parts(to)) {
+            return Err(SdmmcFsError::InvalidPath);
+        }
+        if self.stat(from)?.attributes.is_directory() {
+            return Err(SdmmcFsError::Unsupported("Copying directories"));
+        }
+        self.write_file(to, &[])?;
+        // Both files stay open for the whole copy, seeking to an offset walks the cluster chain
+        self.with_root_dir(|controller, volume, root_dir| {
+            let mut src = open_file(
+                controller,
+                volume,
+                root_dir,
+                FileOpenMode::ReadOnly,
+                &mut parts(from),
+            )
+            .ok_or(SdmmcFsError::NotFound)??;
+            let res = open_file(
+                controller,
+                volume,
+                root_dir,
+                FileOpenMode::ReadWriteAppend,
+                &mut parts(to),
+            )
+            .ok_or(SdmmcFsError::NotFound)
+            .and_then(|dst| {
+                let mut dst = dst?;
+                let mut copied = 0;
+                let res = loop {
+                    match controller.read(volume, &mut src, buf) {
+                        Ok(0) => break Ok(copied),
+                        Ok(n) => match controller.write(volume, &mut dst, &buf[..n]) {
+                            Ok(n) => copied += n as u32,
+                            Err(e) => break Err(e),
+                        },
+                        Err(e) => break Err(e),
+                    }
+                };
+                controller.close_file(volume, dst)?;
+                Ok(res?)
+            });
+            controller.close_file(volume, src)?;
+            res
+        })
+    }
+
+    /// embedded-sdmmc can't edit directory entries, so this copies the file and deletes the
+    /// original. Directories can't be renamed.
+    pub fn rename(&mut self, from: &str, to: &str, buf: &mut [u8]) -> Result<(), Error<C>> {
+        if self.exists(to)? {
+            return Err(SdmmcFsError::AlreadyExists);
+        }
+        self.copy_file(from, to, buf)?;
+        self.delete_file(from)
+    }
+
+    /// Create a directory with an 8.3 name in an existing directory.
+    ///
+    /// embedded-sdmmc creates the entry as an empty file, it's turned into a directory here with
+    /// a new cluster for `.` and `..`.
+    pub fn mkdir(&mut self, path: &str) -> Result<(), Error<C>> {
+        let name = parts(path).last().ok_or(SdmmcFsError::InvalidPath)?;
+        if ShortFileName::create_from_str(name).is_err() {
+            return Err(SdmmcFsError::Unsupported(
+                "Creating directories with long names",
+            ));
+        }
+        if self.exists(path)? {
+            return Err(SdmmcFsError::AlreadyExists);
+        }
+        self.find_entry(path, |controller, volume, dir, name| {
+            let file =
+                controller.open_file_in_dir(volume, dir, name, FileOpenMode::ReadWriteCreate)?;
+            controller.close_file(volume, file)?;
+            let entry = controller.find_directory_entry(volume, dir, name)?;
+            let parent = dir_cluster(controller, volume, dir)?;
+
+            let device = &*controller.device();
+            let fat = Fat::read(device)?;
+            let cluster = fat.alloc(device)?;
+            let mut raw = fat::read_entry(device, &entry)?;
+            raw[11] = ATTR_DIRECTORY;
+            fat::set_entry_cluster(&mut raw, cluster);
+
+            let mut block = [Block::new()];
+            block[0].contents[..DIR_ENTRY_SIZE]
+                .copy_from_slice(&fat::dot_entry(&raw, b".", cluster));
+            block[0].contents[DIR_ENTRY_SIZE..2 * DIR_ENTRY_SIZE]
+                .copy_from_slice(&fat::dot_entry(&raw, b"..", parent));
+            device
+                .write(&block, fat.cluster_block(cluster))
+                .map_err(embedded_sdmmc::Error::DeviceError)?;
+            fat::write_entry(device, &entry, &raw)
+        })?
+    }
+
+    /// Remove an empty directory
+    pub fn rmdir(&mut self, path: &str) -> Result<(), Error<C>> {
+        self.find_entry(path, |controller, volume, dir, name| {
+            let entry = controller.find_directory_entry(volume, dir, name)?;
+            if !entry.attributes.is_directory() {
+                return Err(SdmmcFsError::NotADirectory);
+            }
+            let sub_dir = controller.open_dir(volume, dir, name)?;
+            let mut empty = true;
+            let res = controller.iterate_dir(volume, &sub_dir, |e| empty &= is_dot(&e.name));
+            controller.close_dir(volume, sub_dir);
+            res?;
+            if !empty {
+                return Err(SdmmcFsError::DirNotEmpty);
+            }
+
+            let device = &*controller.device();
+            let raw = fat::read_entry(device, &entry)?;
+            fat::delete_entry(device, &entry, &raw)?;
+            Fat::read(device)?.free_chain(device, fat::entry_cluster(&raw))
+        })?
+    }
+
+    /// Call `func` for each entry in the directory, with long names
+    pub fn ls(&mut self, path: &str, mut func: impl FnMut(&Entry)) -> Result<(), Error<C>> {
+        self.find_dir(path, |controller, volume, dir| {
+            iterate_dir_lfn(controller, volume, dir, &mut func)
+        })?
+    }
+
+    /// Run `func` on the first volume
+    fn with_volume<R>(
+        &mut self,
+        func: impl FnOnce(
+            &mut Controller<C::BlockDevice, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+            &mut Volume,
+        ) -> Result<R, Error<C>>,
+    ) -> Result<R, Error<C>> {
+        match self.state {
+            SdmmcState::Controller(ref mut controller) => {
+                let mut volume = controller.get_volume(VolumeIdx(0))?;
+                func(controller, &mut volume)
+            }
+            SdmmcState::Card(..) | SdmmcState::MidSwap => Err(SdmmcFsError::NotMounted),
+        }
+    }
+
+    /// The controller and the slot of `file` in the open files
+    #[allow(clippy::type_complexity)]
+    fn file_slot(
+        &mut self,
+        file: FileHandle,
+    ) -> Result<
+        (
+            &mut Controller<C::BlockDevice, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+            &mut Option<OpenFile>,
+        ),
+        Error<C>,
+    > {
+        match self.state {
+            SdmmcState::Controller(ref mut controller) => {
+                let slot = self.files.get_mut(file.0).ok_or(SdmmcFsError::NotOpen)?;
+                Ok((controller, slot))
+            }
+            SdmmcState::Card(..) | SdmmcState::MidSwap => Err(SdmmcFsError::NotMounted),
+        }
+    }
+
+    /// Run `func` on the root directory of the first volume
+    fn with_root_dir<R>(
+        &mut self,
+        func: impl FnOnce(
+            &mut Controller<C::BlockDevice, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+            &mut Volume,
+            &Directory,
+        ) -> Result<R, Error<C>>,
+    ) -> Result<R, Error<C>> {
+        match self.state {
+            SdmmcState::Controller(ref mut controller) => {
+                let mut volume = controller.get_volume(VolumeIdx(0))?;
+                let root_dir = controller.open_root_dir(&volume)?;
+
+                let res = func(controller, &mut volume, &root_dir);
+
+                controller.close_dir(&volume, root_dir);
+                res
+            }
+            SdmmcState::Card(..) | SdmmcState::MidSwap => Err(SdmmcFsError::NotMounted),
+        }
+    }
+
+    /// Create the file if it doesn't exist and give it its first cluster, before it's opened for
+    /// writing.
+    ///
+    /// embedded-sdmmc closes files by their first cluster. An empty file that gets one on its
+    /// first write can't be closed, and no other empty file can be opened after it.
+    fn prepare_write(&mut self, path: &str) -> Result<(), Error<C>> {
+        let name = parts(path).last().ok_or(SdmmcFsError::InvalidPath)?;
+        let parent_len = parts(path).count() - 1;
+        self.with_root_dir(|controller, volume, root_dir| {
+            let mut parent = parts(path).take(parent_len).peekable();
+            find_dir(controller, volume, root_dir, &mut parent, |c, v, d| {
+                let name = match short_name(c, v, d, name) {
+                    // embedded-sdmmc can only create 8.3 entries
+                    Err(SdmmcFsError::Sdmmc(embedded_sdmmc::Error::FileNotFound)) => {
+                        return Err(SdmmcFsError::Unsupported("Creating files with long names"))
+                    }
+                    name => name?,
+                };
+                alloc_first_cluster(c, v, d, &name)
+            })
+            .ok_or(SdmmcFsError::NotFound)??
+        })
+    }
+
+    fn find_dir<R>(
+        &mut self,
+        path: &str,
+        func: impl FnMut(
+            &mut Controller<C::BlockDevice, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+            &mut Volume,
+            &Directory,
+        ) -> R,
+    ) -> Result<R, Error<C>> {
+        self.with_root_dir(|controller, volume, root_dir| {
+            find_dir(controller, volume, root_dir, &mut parts(path), func)
+                .ok_or(SdmmcFsError::NotFound)?
+        })
+    }
+
+    fn find_file<R>(
+        &mut self,
+        path: &str,
+        mode: FileOpenMode,
+        func: impl FnMut(
+            &mut Controller<C::BlockDevice, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+            &mut Volume,
+            &mut File,
+        ) -> R,
+    ) -> Result<R, Error<C>> {
+        self.with_root_dir(|controller, volume, root_dir| {
+            find_file(controller, volume, root_dir, mode, &mut parts(path), func)
+                .ok_or(SdmmcFsError::NotFound)?
+        })
+    }
+
+    /// Run `func` on the parent directory of `path` and the 8.3 name of the entry in it
+    fn find_entry<R>(
+        &mut self,
+        path: &str,
+        mut func: impl FnMut(
+            &mut Controller<C::BlockDevice, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+            &mut Volume,
+            &Directory,
+            &str,
+        ) -> R,
+    ) -> Result<R, Error<C>> {
+        let name = parts(path).last().ok_or(SdmmcFsError::InvalidPath)?;
+        let parent_len = parts(path).count() - 1;
+        self.with_root_dir(|controller, volume, root_dir| {
+            let mut parent = parts(path).take(parent_len).peekable();
+            find_dir(controller, volume, root_dir, &mut parent, |c, v, d| {
+                let name = short_name(c, v, d, name)?;
+                Ok(func(c, v, d, &name))
+            })
+            .ok_or(SdmmcFsError::NotFound)??
+        })
+    }
+}
+
+/// Parts of a path on the volume, the root is `""` or `/`
+fn parts(path: &str) -> core::iter::Peekable<impl Iterator<Item = &str>> {
+    path.split('/')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .peekable()
+}
+
+fn find_dir<
+    'p,
+    R,
+    D: BlockDevice,
+    T: TimeSource,
+    const MAX_OPEN_DIRS: usize,
+    const MAX_OPEN_FILES: usize,
+>(
+    controller: &mut Controller<D, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+    volume: &mut Volume,
+    dir: &Directory,
+    path_iter: &mut core::iter::Peekable<impl Iterator<Item = &'p str>>,
+    mut func: impl FnMut(
+        &mut Controller<D, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+        &mut Volume,
+        &Directory,
+    ) -> R,
+) -> Option<Result<R, SdmmcFsError<D::Error>>> {
+    if let Some(name) = path_iter.next() {
+        let name = match short_name(controller, volume, dir, name) {
+            Ok(name) => name,
+            Err(e) => return Some(Err(e)),
+        };
+        match controller.open_dir(volume, dir, &name) {
+            Ok(new_dir) => {
+                log::trace!("OPENED DIR: {}", name);
+                let res = find_dir(controller, volume, &new_dir, path_iter, func);
+                controller.close_dir(volume, new_dir);
+                log::trace!("CLOSED DIR: {}", name);
+                res
+            }
+            Err(e) => Some(Err(SdmmcFsError::from(e))),
+        }
+    } else {
+        Some(Ok(func(controller, volume, dir)))
+    }
+}
+
+fn find_file<
+    'p,
+    R,
+    D: BlockDevice,
+    T: TimeSource,
+    const MAX_OPEN_DIRS: usize,
+    const MAX_OPEN_FILES: usize,
+>(
+    controller: &mut Controller<D, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+    volume: &mut Volume,
+    dir: &Directory,
+    mode: FileOpenMode,
+    path_iter: &mut core::iter::Peekable<impl Iterator<Item = &'p str>>,
+    mut func: impl FnMut(
+        &mut Controller<D, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+        &mut Volume,
+        &mut File,
+    ) -> R,
+) -> Option<Result<R, SdmmcFsError<D::Error>>> {
+    let mut file = match open_file(controller, volume, dir, mode, path_iter)? {
+        Ok(file) => file,
+        Err(e) => return Some(Err(e)),
+    };
+    let ret = func(controller, volume, &mut file);
+    if let Err(e) = controller.close_file(volume, file) {
+        return Some(Err(SdmmcFsError::from(e)));
+    };
+    log::trace!("CLOSED FILE");
+    Some(Ok(ret))
+}
+
+/// Open the file at the path in `dir`, the directories on the way are closed again
+fn open_file<
+    'p,
+    D: BlockDevice,
+    T: TimeSource,
+    const MAX_OPEN_DIRS: usize,
+    const MAX_OPEN_FILES: usize,
+>(
+    controller: &mut Controller<D, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+    volume: &mut Volume,
+    dir: &Directory,
+    mode: FileOpenMode,
+    path_iter: &mut core::iter::Peekable<impl Iterator<Item = &'p str>>,
+) -> Option<Result<File, SdmmcFsError<D::Error>>> {
+    let name = path_iter.next()?;
+    let name = match short_name(controller, volume, dir, name) {
+        Ok(name) => name,
+        // embedded-sdmmc can only create 8.3 entries
+        Err(SdmmcFsError::Sdmmc(embedded_sdmmc::Error::FileNotFound))
+            if path_iter.peek().is_none() && !matches!(mode, FileOpenMode::ReadOnly) =>
+        {
+            return Some(Err(SdmmcFsError::Unsupported(
+                "Creating files with long names",
+            )))
+        }
+        Err(e) => return Some(Err(e)),
+    };
+    if path_iter.peek().is_some() {
+        match controller.open_dir(volume, dir, &name) {
+            Ok(new_dir) => {
+                log::trace!("OPENED DIR: {}", name);
+                let res = open_file(controller, volume, &new_dir, mode, path_iter);
+                controller.close_dir(volume, new_dir);
+                log::trace!("CLOSED DIR: {}", name);
+                res
+            }
+            Err(e) => Some(Err(SdmmcFsError::from(e))),
+        }
+    } else {
+        log::trace!("OPENED FILE: {}", name);
+        Some(
+            controller
+                .open_file_in_dir(volume, dir, &name, mode)
+                .map_err(SdmmcFsError::from),
+        )
+    }
+}
+
+/// Create the file `name` in `dir` if it doesn't exist, and give it a cluster if it has none
+fn alloc_first_cluster<
+    D: BlockDevice,
+    T: TimeSource,
+    const MAX_OPEN_DIRS: usize,
+    const MAX_OPEN_FILES: usize,
+>(
+    controller: &mut Controller<D, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+    volume: &mut Volume,
+    dir: &Directory,
+    name: &str,
+) -> Result<(), SdmmcFsError<D::Error>> {
+    let entry = match controller.find_directory_entry(volume, dir, name) {
+        Err(embedded_sdmmc::Error::FileNotFound) => {
+            // Created with cluster 0, closing it still finds it by that
+            let file =
+                controller.open_file_in_dir(volume, dir, name, FileOpenMode::ReadWriteCreate)?;
+            controller.close_file(volume, file)?;
+            controller.find_directory_entry(volume, dir, name)?
+        }
+        entry => entry?,
+    };
+    if entry.attributes.is_directory() {
+        return Err(embedded_sdmmc::Error::OpenedDirAsFile.into());
+    }
+    if entry.attributes.is_read_only() {
+        return Err(embedded_sdmmc::Error::ReadOnly.into());
+    }
+
+    let device = &*controller.device();
+    let mut raw = fat::read_entry(device, &entry)?;
+    if fat::entry_cluster(&raw) == 0 {
+        fat::set_entry_cluster(&mut raw, Fat::read(device)?.alloc(device)?);
+        fat::write_entry(device, &entry, &raw)?;
+    }
+    Ok(())
+}
+
+/// Move the position of `file` to `offset`. Only seeking from the start goes back to the first
+/// cluster when it has to, the others keep walking the chain from where the file was.
+fn seek<E: core::fmt::Debug>(file: &mut File, offset: u32) -> Result<(), SdmmcFsError<E>> {
+    file.seek_from_start(offset)
+        .map_err(|_| SdmmcFsError::InvalidOffset(offset))
+}
+
+/// `.` or `..`
+fn is_dot(name: &ShortFileName) -> bool {
+    name.extension().is_empty() && matches!(name.base_name(), b"." | b"..")
+}
+
+/// The first cluster of `dir` from its `.` entry, 0 for the root directory which has none
+fn dir_cluster<
+    D: BlockDevice,
+    T: TimeSource,
+    const MAX_OPEN_DIRS: usize,
+    const MAX_OPEN_FILES: usize,
+>(
+    controller: &mut Controller<D, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+    volume: &Volume,
+    dir: &Directory,
+) -> Result<u32, SdmmcFsError<D::Error>> {
+    let mut dot = None;
+    controller.iterate_dir(volume, dir, |e| {
+        if dot.is_none() && e.name.base_name() == b"." && e.name.extension().is_empty() {
+            dot = Some(e.clone());
+        }
+    })?;
+    match dot {
+        Some(dot) => Ok(fat::entry_cluster(&fat::read_entry(
+            controller.device(),
+            &dot,
+        )?)),
+        None => Ok(0),
+    }
+}
+
+/// The 8.3 name of the entry `name` in `dir`. Valid 8.3 names are used as is, long names are
+/// looked up case-insensitively.
+fn short_name<
+    D: BlockDevice,
+    T: TimeSource,
+    const MAX_OPEN_DIRS: usize,
+    const MAX_OPEN_FILES: usize,
+>(
+    controller: &mut Controller<D, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+    volume: &Volume,
+    dir: &Directory,
+    name: &str,
+) -> Result<String<12>, SdmmcFsError<D::Error>> {
+    let mut short = String::new();
+    if ShortFileName::create_from_str(name).is_ok() && short.push_str(name).is_ok() {
+        return Ok(short);
+    }
+    iterate_dir_lfn(controller, volume, dir, |e| {
+        if short.is_empty() && lfn::name_eq(e.name, name) {
+            let _ = short.push_str(e.short_name);
+        }
+    })?;
+    match short.is_empty() {
+        true => Err(embedded_sdmmc::Error::FileNotFound.into()),
+        false => Ok(short),
+    }
+}
+
+/// Call `func` for each entry in `dir`, with its long name.
+///
+/// embedded-sdmmc skips long name entries and doesn't tell where the blocks of a directory
+/// are, so they are collected from the 8.3 entries it lists and read raw, `DIR_BLOCK_WINDOW`
+/// blocks per pass. Long names in blocks without any 8.3 entry are missed, those entries
+/// show their 8.3 name.
+fn iterate_dir_lfn<
+    D: BlockDevice,
+    T: TimeSource,
+    const MAX_OPEN_DIRS: usize,
+    const MAX_OPEN_FILES: usize,
+>(
+    controller: &mut Controller<D, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+    volume: &Volume,
+    dir: &Directory,
+    mut func: impl FnMut(&Entry),
+) -> Result<(), SdmmcFsError<D::Error>> {
+    let mut parser = DirParser::new();
+    let mut block = [Block::new()];
+    let mut skip = 0;
+    loop {
+        let mut blocks = Vec::<BlockIdx, DIR_BLOCK_WINDOW>::new();
+        let (mut seen, mut last, mut more) = (0, None, false);
+        controller.iterate_dir(volume, dir, |e| {
+            if last != Some(e.entry_block) {
+                last = Some(e.entry_block);
+                seen += 1;
+                if seen > skip && blocks.push(e.entry_block).is_err() {
+                    more = true;
+                }
+            }
+        })?;
+
+        for idx in &blocks {
+            controller
+                .device()
+                .read(&mut block, *idx, "lfn")
+                .map_err(embedded_sdmmc::Error::DeviceError)?;
+            for raw in block[0].contents.chunks(DIR_ENTRY_SIZE) {
+                match parser.push(raw) {
+                    Parsed::End => return Ok(()),
+                    Parsed::Skip => {}
+                    Parsed::Entry(e) => func(&e),
+                }
+            }
+        }
+        if !more {
+            return Ok(());
+        }
+        skip += blocks.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::FileBlockDevice,
+        embedded_sdmmc::Timestamp,
+        fatfs::{FatType, FormatVolumeOptions, FsOptions},
+        std::{
+            io::{self, Cursor, Read, Write},
+            path::PathBuf,
+            vec::Vec as StdVec,
+        },
+    };
+
+    /// First block of the partition, after the MBR
+    const PARTITION_START: u32 = 1;
+    /// 8 MB, enough clusters for FAT16
+    const PARTITION_BLOCKS: u32 = 16 * 1024;
+    const LONG_FILE_LEN: usize = 2000;
+
+    struct Clock;
+
+    impl TimeSource for Clock {
+        fn get_timestamp(&self) -> Timestamp {
+            Timestamp {
+                year_since_1970: 53,
+                zero_indexed_month: 0,
+                zero_indexed_day: 0,
+                hours: 12,
+                minutes: 0,
+                seconds: 0,
+            }
+        }
+    }
+
+    struct NoDelay;
+
+    impl DelayMs<u16> for NoDelay {
+        fn delay_ms(&mut self, _ms: u16) {}
+    }
+
+    type TestFs = SdmmcFs<FileBlockDevice, Clock, 4, 4>;
+
+    /// An image file removed when the test ends
+    struct Image(PathBuf);
+
+    impl Drop for Image {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn long_file() -> StdVec<u8> {
+        (0..LONG_FILE_LEN).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// A card with an MBR and a FAT16 partition:
+    ///
+    /// ```text
+    /// /HELLO.TXT
+    /// /A long file name.txt
+    /// /apps/game.h7
+    /// /apps/Nested Directory/deep.txt
+    /// ```
+    fn image(name: &str) -> Image {
+        let mut partition = Cursor::new(vec![0u8; PARTITION_BLOCKS as usize * 512]);
+        fatfs::format_volume(
+            &mut partition,
+            FormatVolumeOptions::new()
+                .fat_type(FatType::Fat16)
+                .bytes_per_cluster(512),
+        )
+        .unwrap();
+        {
+            let fs = fatfs::FileSystem::new(&mut partition, FsOptions::new()).unwrap();
+            let root = fs.root_dir();
+            root.create_file("HELLO.TXT")
+                .unwrap()
+                .write_all(b"Hello, world!\n")
+                .unwrap();
+            root.create_file("A long file name.txt")
+                .unwrap()
+                .write_all(&long_file())
+                .unwrap();
+            let apps = root.create_dir("apps").unwrap();
+            apps.create_file("game.h7")
+                .unwrap()
+                .write_all(b"H7")
+                .unwrap();
+            apps.create_dir("Nested Directory")
+                .unwrap()
+                .create_file("deep.txt")
+                .unwrap()
+                .write_all(b"deep")
+                .unwrap();
+        }
+
+        let mut disk = vec![0u8; PARTITION_START as usize * 512];
+        let entry = &mut disk[446..462];
+        entry[4] = 0x06; // FAT16
+        entry[8..12].copy_from_slice(&PARTITION_START.to_le_bytes());
+        entry[12..16].copy_from_slice(&PARTITION_BLOCKS.to_le_bytes());
+        disk[510..512].copy_from_slice(&[0x55, 0xaa]);
+        disk.extend(partition.into_inner());
+
+        let path = std::env::temp_dir().join(format!("h7-sdfs-{}-{name}.img", std::process::id()));
+        std::fs::write(&path, disk).unwrap();
+        Image(path)
+    }
+
+    fn mounted(image: &Image) -> TestFs {
+        let mut fs = TestFs::new(FileBlockDevice::open(&image.0).unwrap(), Clock);
+        fs.mount::<NoDelay>((), 1, None).unwrap();
+        fs
+    }
+
+    /// Names and sizes of the visible entries, sorted
+    fn ls(fs: &mut TestFs, path: &str) -> StdVec<(std::string::String, bool, u32)> {
+        let mut entries = StdVec::new();
+        fs.ls(path, |e| {
+            if !(e.is_volume() || e.is_hidden() || e.name == "." || e.name == "..") {
+                entries.push((e.name.to_string(), e.is_directory(), e.size));
+            }
+        })
+        .unwrap();
+        entries.sort();
+        entries
+    }
+
+    fn read(fs: &mut TestFs, path: &str) -> StdVec<u8> {
+        let mut data = StdVec::new();
+        let mut buf = [0u8; 300];
+        loop {
+            match fs.read_file_at(path, data.len() as u32, &mut buf).unwrap() {
+                0 => return data,
+                n => data.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    /// The partition of the image, to check it with fatfs
+    fn partition(image: &Image) -> fatfs::FileSystem<Cursor<StdVec<u8>>> {
+        let disk = std::fs::read(&image.0).unwrap();
+        let partition = Cursor::new(disk[PARTITION_START as usize * 512..].to_vec());
+        fatfs::FileSystem::new(partition, FsOptions::new()).unwrap()
+    }
+
+    fn is_not_found(e: &SdmmcFsError<io::Error>) -> bool {
+        matches!(e, SdmmcFsError::Sdmmc(embedded_sdmmc::Error::FileNotFound))
+    }
+
+    #[test]
+    fn ls_long_names() {
+        let image = image("ls");
+        let mut fs = mounted(&image);
+        assert_eq!(
+            ls(&mut fs, "/"),
+            [
+                (
+                    "A long file name.txt".to_string(),
+                    false,
+                    LONG_FILE_LEN as u32
+                ),
+                ("HELLO.TXT".to_string(), false, 14),
+                ("apps".to_string(), true, 0),
+            ]
+        );
+        assert_eq!(ls(&mut fs, ""), ls(&mut fs, "/"));
+    }
+
+    #[test]
+    fn read_file() {
+        let image = image("read");
+        let mut fs = mounted(&image);
+        assert_eq!(read(&mut fs, "/HELLO.TXT"), b"Hello, world!\n");
+        // Long and short names, any case
+        assert_eq!(read(&mut fs, "/A long file name.txt"), long_file());
+        assert_eq!(read(&mut fs, "/a LONG file NAME.TXT"), long_file());
+        assert_eq!(read(&mut fs, "hello.txt"), b"Hello, world!\n");
+
+        let mut buf = [0u8; 512];
+        let n = fs
+            .read_file_at("/A long file name.txt", 1800, &mut buf)
+            .unwrap();
+        assert_eq!(buf[..n], long_file()[1800..]);
+        let n = fs
+            .read_file_at("/A long file name.txt", LONG_FILE_LEN as u32, &mut buf)
+            .unwrap();
+        assert_eq!(n, 0);
+
+        let mut data = StdVec::new();
+        let len = fs
+            .read_chunks("/A long file name.txt", &mut buf, |chunk| {
+                data.extend_from_slice(chunk);
+                true
+            })
+            .unwrap();
+        assert_eq!((len, data), (LONG_FILE_LEN as u32, long_file()));
+        // Stops when told to
+        let mut chunks = 0;
+        let len = fs
+            .read_chunks("/A long file name.txt", &mut buf, |_| {
+                chunks += 1;
+                false
+            })
+            .unwrap();
+        assert_eq!((len, chunks), (512, 1));
+    }
+
+    #[test]
+    fn nested_dirs() {
+        let image = image("nested");
+        let mut fs = mounted(&image);
+        assert_eq!(
+            ls(&mut fs, "/apps"),
+            [
+                ("Nested Directory".to_string(), true, 0),
+                ("game.h7".to_string(), false, 2),
+            ]
+        );
+        assert_eq!(
+            ls(&mut fs, "/apps/Nested Directory/"),
+            [("deep.txt".to_string(), false, 4)]
+        );
+        assert_eq!(read(&mut fs, "/apps/Nested Directory/deep.txt"), b"deep");
+        assert!(fs
+            .stat("/apps/nested directory")
+            .unwrap()
+            .attributes
+            .is_directory());
+        assert!(fs.exists("/").unwrap());
+        assert!(fs.exists("/apps/game.h7").unwrap());
+    }
+
+    #[test]
+    fn not_found() {
+        let image = image("not-found");
+        let mut fs = mounted(&image);
+        let mut buf = [0u8; 16];
+        assert!(is_not_found(
+            &fs.read_file_at("/missing.txt", 0, &mut buf).unwrap_err()
+        ));
+        assert!(is_not_found(
+            &fs.read_file_at("/no such dir/deep.txt", 0, &mut buf)
+                .unwrap_err()
+        ));
+        assert!(is_not_found(&fs.ls("/nothing", |_| {}).unwrap_err()));
+        assert!(is_not_found(&fs.stat("/apps/missing.h7").unwrap_err()));
+        assert!(!fs.exists("/apps/Missing Directory").unwrap());
+        assert!(matches!(fs.stat("/"), Err(SdmmcFsError::InvalidPath)));
+    }
+
+    #[test]
+    fn unmounted() {
+        let image = image("unmounted");
+        let mut fs = TestFs::new(FileBlockDevice::open(&image.0).unwrap(), Clock);
+        assert!(!fs.is_mounted());
+        assert!(matches!(fs.ls("/", |_| {}), Err(SdmmcFsError::NotMounted)));
+        assert!(matches!(
+            fs.read_file_at("/HELLO.TXT", 0, &mut [0; 4]),
+            Err(SdmmcFsError::NotMounted)
+        ));
+        assert!(matches!(fs.card_size(), Err(SdmmcFsError::NotMounted)));
+        assert!(matches!(fs.unmount(), Err(SdmmcFsError::NotMounted)));
+
+        fs.mount::<NoDelay>((), 1, None).unwrap();
+        assert!(fs.is_mounted());
+        assert_eq!(
+            fs.card_size().unwrap(),
+            (PARTITION_START + PARTITION_BLOCKS) as u64 * 512
+        );
+        assert!(matches!(
+            fs.mount::<NoDelay>((), 1, None),
+            Err(SdmmcFsError::AlreadyMounted)
+        ));
+
+        fs.unmount().unwrap();
+        assert!(matches!(fs.exists("/"), Err(SdmmcFsError::NotMounted)));
+        fs.mount::<NoDelay>((), 1, None).unwrap();
+        assert_eq!(read(&mut fs, "/apps/game.h7"), b"H7");
+    }
+
+    #[test]
+    fn write_and_delete() {
+        let image = image("write");
+        let mut fs = mounted(&image);
+        fs.write_file("/apps/NEW.TXT", b"abc").unwrap();
+        fs.append_file("/apps/NEW.TXT", b"def").unwrap();
+        assert_eq!(read(&mut fs, "/apps/NEW.TXT"), b"abcdef");
+
+        let mut buf = [0u8; 7];
+        assert_eq!(
+            fs.copy_file("/HELLO.TXT", "/COPY.TXT", &mut buf).unwrap(),
+            14
+        );
+        assert_eq!(read(&mut fs, "/COPY.TXT"), b"Hello, world!\n");
+        // Over a few clusters
+        assert_eq!(
+            fs.copy_file("/A long file name.txt", "/apps/LONG.TXT", &mut buf)
+                .unwrap(),
+            LONG_FILE_LEN as u32
+        );
+        assert_eq!(read(&mut fs, "/apps/LONG.TXT"), long_file());
+        fs.rename("/COPY.TXT", "/apps/MOVED.TXT", &mut buf).unwrap();
+        assert!(!fs.exists("/COPY.TXT").unwrap());
+        assert!(matches!(
+            fs.rename("/HELLO.TXT", "/apps/MOVED.TXT", &mut buf),
+            Err(SdmmcFsError::AlreadyExists)
+        ));
+
+        fs.delete_file("/apps/NEW.TXT").unwrap();
+        assert!(!fs.exists("/apps/NEW.TXT").unwrap());
+        assert!(matches!(
+            fs.write_file("/A new long name.txt", b""),
+            Err(SdmmcFsError::Unsupported(_))
+        ));
+
+        // Still readable after a remount
+        fs.unmount().unwrap();
+        fs.mount::<NoDelay>((), 1, None).unwrap();
+        assert_eq!(read(&mut fs, "/apps/MOVED.TXT"), b"Hello, world!\n");
+    }
+
+    fn has_open_handles(fs: &TestFs) -> bool {
+        match &fs.state {
+            SdmmcState::Controller(c) => c.has_open_handles(),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn empty_files() {
+        let image = image("empty");
+        let mut fs = mounted(&image);
+        fs.write_file("/EMPTY.TXT", &[]).unwrap();
+        // More files than can be open at once, each was empty when it was opened
+        for name in ["/A.TXT", "/B.TXT", "/C.TXT", "/D.TXT", "/E.TXT"] {
+            fs.append_file(name, b"x").unwrap();
+            assert!(!has_open_handles(&fs));
+        }
+        fs.append_file("/EMPTY.TXT", b"now").unwrap();
+        assert_eq!(read(&mut fs, "/EMPTY.TXT"), b"now");
+        fs.write_file("/EMPTY.TXT", &[]).unwrap();
+        fs.append_file("/EMPTY.TXT", b"again").unwrap();
+        assert_eq!(read(&mut fs, "/EMPTY.TXT"), b"again");
+        fs.copy_file("/E.TXT", "/F.TXT", &mut [0; 16]).unwrap();
+        assert_eq!(read(&mut fs, "/F.TXT"), b"x");
+        assert!(!has_open_handles(&fs));
+        assert!(matches!(
+            fs.append_file("/apps", b"x"),
+            Err(SdmmcFsError::Sdmmc(embedded_sdmmc::Error::OpenedDirAsFile))
+        ));
+    }
+
+    #[test]
+    fn open_files() {
+        let image = image("open");
+        let mut fs = mounted(&image);
+        let log = fs.open("/LOG.TXT", OpenMode::Write).unwrap();
+        assert_eq!(fs.write(log, b"Hello, world!").unwrap(), 13);
+        fs.seek(log, 7).unwrap();
+        assert_eq!(fs.write(log, b"there").unwrap(), 5);
+        assert_eq!(fs.size(log).unwrap(), 13);
+        assert_eq!(fs.write(log, b"!!").unwrap(), 2);
+        assert!(matches!(
+            fs.seek(log, 16),
+            Err(SdmmcFsError::InvalidOffset(16))
+        ));
+        assert!(matches!(fs.unmount(), Err(SdmmcFsError::FilesOpen)));
+        assert!(matches!(
+            fs.open("/LOG.TXT", OpenMode::Read),
+            Err(SdmmcFsError::Sdmmc(embedded_sdmmc::Error::FileAlreadyOpen))
+        ));
+
+        // Overwrite across clusters, reading from a second file in between
+        let long = fs.open("/LONG.TXT", OpenMode::Write).unwrap();
+        let hello = fs.open("/HELLO.TXT", OpenMode::Read).unwrap();
+        let mut expected = long_file();
+        for chunk in expected.chunks(300) {
+            fs.write(long, chunk).unwrap();
+        }
+        let mut buf = [0u8; 5];
+        assert_eq!(fs.read(hello, &mut buf).unwrap(), 5);
+        assert_eq!(&buf, b"Hello");
+        fs.seek(long, 400).unwrap();
+        fs.write(long, &[0x55; 700]).unwrap();
+        expected[400..1100].fill(0x55);
+        assert_eq!(fs.size(long).unwrap(), LONG_FILE_LEN as u32);
+        fs.write(long, b"more").unwrap();
+        fs.seek(long, 0).unwrap();
+        fs.write(long, b"start").unwrap();
+        expected[..5].copy_from_slice(b"start");
+        for file in [log, long, hello] {
+            fs.close(file).unwrap();
+        }
+        assert!(matches!(fs.close(log), Err(SdmmcFsError::NotOpen)));
+        assert!(!has_open_handles(&fs));
+
+        let append = fs.open("/LOG.TXT", OpenMode::Append).unwrap();
+        fs.write(append, b"?").unwrap();
+        fs.close(append).unwrap();
+        assert_eq!(read(&mut fs, "/LOG.TXT"), b"Hello, there!!?");
+        let file = fs.open("/LOG.TXT", OpenMode::Read).unwrap();
+        fs.seek(file, 7).unwrap();
+        assert_eq!(fs.read(file, &mut buf).unwrap(), 5);
+        assert_eq!(&buf, b"there");
+        fs.close(file).unwrap();
+
+        let mut data = StdVec::new();
+        let fat = partition(&image);
+        fat.root_dir()
+            .open_file("LONG.TXT")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        expected[1100..1104].copy_from_slice(b"more");
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn mkdir_and_rmdir() {
+        let image = image("mkdir");
+        let free_clusters = partition(&image).stats().unwrap().free_clusters();
+        let mut fs = mounted(&image);
+        fs.mkdir("/NEWDIR").unwrap();
+        fs.mkdir("/apps/Nested Directory/SUB").unwrap();
+        fs.write_file("/NEWDIR/FILE.TXT", b"in a new dir").unwrap();
+        assert_eq!(
+            ls(&mut fs, "/NEWDIR"),
+            [("FILE.TXT".to_string(), false, 12)]
+        );
+        assert!(fs.stat("/newdir").unwrap().attributes.is_directory());
+        assert!(matches!(
+            fs.mkdir("/apps"),
+            Err(SdmmcFsError::AlreadyExists)
+        ));
+        assert!(matches!(
+            fs.mkdir("/A new long name"),
+            Err(SdmmcFsError::Unsupported(_))
+        ));
+        assert!(is_not_found(&fs.mkdir("/missing/NEWDIR").unwrap_err()));
+
+        // Another implementation sees the same directories
+        {
+            let fat = partition(&image);
+            let new_dir = fat.root_dir().open_dir("NEWDIR").unwrap();
+            let names: StdVec<_> = new_dir.iter().map(|e| e.unwrap().file_name()).collect();
+            assert_eq!(names, [".", "..", "FILE.TXT"]);
+            let mut data = StdVec::new();
+            new_dir
+                .open_file("FILE.TXT")
+                .unwrap()
+                .read_to_end(&mut data)
+                .unwrap();
+            assert_eq!(data, b"in a new dir");
+            let sub = fat
+                .root_dir()
+                .open_dir("apps/Nested Directory/SUB")
+                .unwrap();
+            let parent = sub.open_dir("..").unwrap();
+            assert!(parent.open_file("deep.txt").is_ok());
+        }
+
+        assert!(matches!(
+            fs.rmdir("/NEWDIR"),
+            Err(SdmmcFsError::DirNotEmpty)
+        ));
+        assert!(matches!(
+            fs.rmdir("/HELLO.TXT"),
+            Err(SdmmcFsError::NotADirectory)
+        ));
+        fs.delete_file("/NEWDIR/FILE.TXT").unwrap();
+        fs.rmdir("/NEWDIR").unwrap();
+        fs.rmdir("/apps/Nested Directory/SUB").unwrap();
+        assert!(!fs.exists("/NEWDIR").unwrap());
+        assert_eq!(
+            ls(&mut fs, "/apps/Nested Directory"),
+            [("deep.txt".to_string(), false, 4)]
+        );
+        // With its long name
+        fs.delete_file("/apps/Nested Directory/deep.txt").unwrap();
+        fs.rmdir("/apps/Nested Directory").unwrap();
+        assert_eq!(ls(&mut fs, "/apps"), [("game.h7".to_string(), false, 2)]);
+
+        let fat = partition(&image);
+        let names: StdVec<_> = fat
+            .root_dir()
+            .open_dir("apps")
+            .unwrap()
+            .iter()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, [".", "..", "game.h7"]);
+        // The clusters of the directories and deep.txt are free again
+        assert_eq!(fat.stats().unwrap().free_clusters(), free_clusters + 2);
+    }
+
+    /// Fails `init` a number of times before it comes up
+    struct FlakyCard {
+        failures: u8,
+        device: FileBlockDevice,
+    }
+
+    impl Card for FlakyCard {
+        type BlockDevice = FileBlockDevice;
+        type Config = ();
+
+        fn init(&mut self, _config: ()) -> Result<(), io::Error> {
+            match self.failures.checked_sub(1) {
+                Some(failures) => {
+                    self.failures = failures;
+                    Err(io::ErrorKind::TimedOut.into())
+                }
+                None => Ok(()),
+            }
+        }
+
+        fn into_block_device(self) -> FileBlockDevice {
+            self.device
+        }
+
+        fn from_block_device(device: FileBlockDevice) -> Self {
+            Self {
+                failures: 0,
+                device,
+            }
+        }
+    }
+
+    #[test]
+    fn mount_retries() {
+        let image = image("retries");
+        let card = |failures| FlakyCard {
+            failures,
+            device: FileBlockDevice::open(&image.0).unwrap(),
+        };
+
+        let mut fs = SdmmcFs::<_, _, 4, 4>::new(card(2), Clock);
+        fs.mount((), 3, Some((10, &mut NoDelay))).unwrap();
+        assert!(fs.exists("/HELLO.TXT").unwrap());
+
+        let mut fs = SdmmcFs::<_, _, 4, 4>::new(card(3), Clock);
+        assert!(matches!(
+            fs.mount::<NoDelay>((), 3, None),
+            Err(SdmmcFsError::Card(_))
+        ));
+        assert!(!fs.is_mounted());
+    }
+}
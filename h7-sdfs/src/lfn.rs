@@ -0,0 +1,433 @@
+//! VFAT long file names.
+//!
+//! embedded-sdmmc skips the long name entries in front of each 8.3 entry. [`DirParser`] reads
+//! the raw 32 byte directory entries instead and pairs each 8.3 entry with its long name.
+
+use {embedded_sdmmc::Timestamp, heapless::String};
+
+pub const DIR_ENTRY_SIZE: usize = 32;
+/// Longest name in UTF-16 code units
+pub const LFN_MAX: usize = 255;
+/// [`LFN_MAX`] code units as UTF-8
+const LFN_UTF8_MAX: usize = LFN_MAX * 3;
+
+const CHARS_PER_ENTRY: usize = 13;
+/// Byte offsets of the UTF-16 characters in a long name entry
+const CHAR_OFFSETS: [usize; CHARS_PER_ENTRY] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const MAX_SEQUENCE: u8 = (LFN_MAX / CHARS_PER_ENTRY + 1) as u8;
+const LAST_LONG_ENTRY: u8 = 0x40;
+
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_VOLUME: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_LONG_NAME: u8 = 0x0f;
+
+const END_OF_DIR: u8 = 0x00;
+pub const DELETED: u8 = 0xe5;
+/// A first byte of 0xe5 in the name is stored as 0x05
+const KANJI_E5: u8 = 0x05;
+
+/// NT flags for 8.3 names that are all lower case, used instead of a long name
+const NT_LOWER_BASE: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+
+/// Checksum of the 11 bytes of an 8.3 name, stored in each of its long name entries
+pub fn checksum(short_name: &[u8]) -> u8 {
+    short_name[..11]
+        .iter()
+        .fold(0u8, |sum, b| sum.rotate_right(1).wrapping_add(*b))
+}
+
+/// Case-insensitive name comparison, FAT names are not case sensitive
+pub fn name_eq(a: &str, b: &str) -> bool {
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .eq(b.chars().flat_map(char::to_lowercase))
+}
+
+/// A file or directory, an 8.3 entry and its long name
+pub struct Entry<'a> {
+    /// The long name, or the 8.3 name if there is none
+    pub name: &'a str,
+    /// The 8.3 name as used by embedded-sdmmc, `README.TXT`
+    pub short_name: &'a str,
+    pub attributes: u8,
+    pub size: u32,
+    /// FAT date and time
+    pub mtime: (u16, u16),
+}
+
+impl Entry<'_> {
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.attributes & ATTR_HIDDEN != 0
+    }
+
+    pub fn is_volume(&self) -> bool {
+        self.attributes & ATTR_VOLUME != 0
+    }
+
+    pub fn modified(&self) -> Timestamp {
+        let (date, time) = self.mtime;
+        Timestamp {
+            year_since_1970: ((date >> 9) + 10) as u8,
+            zero_indexed_month: (((date >> 5) & 0x0f) as u8).saturating_sub(1),
+            zero_indexed_day: ((date & 0x1f) as u8).saturating_sub(1),
+            hours: (time >> 11) as u8,
+            minutes: ((time >> 5) & 0x3f) as u8,
+            seconds: ((time & 0x1f) * 2) as u8,
+        }
+    }
+}
+
+pub enum Parsed<'a> {
+    /// No more entries in the directory
+    End,
+    /// Deleted or part of a long name
+    Skip,
+    Entry(Entry<'a>),
+}
+
+/// Parses the entries of a directory in order, across blocks
+pub struct DirParser {
+    units: [u16; MAX_SEQUENCE as usize * CHARS_PER_ENTRY],
+    /// Sequence number of the last long name entry, 0 if there is no long name
+    sequence: u8,
+    checksum: u8,
+    short_name: String<12>,
+    long_name: String<LFN_UTF8_MAX>,
+}
+
+impl DirParser {
+    pub const fn new() -> Self {
+        Self {
+            units: [0; MAX_SEQUENCE as usize * CHARS_PER_ENTRY],
+            sequence: 0,
+            checksum: 0,
+            short_name: String::new(),
+            long_name: String::new(),
+        }
+    }
+
+    /// Parse the next 32 byte entry
+    pub fn push(&mut self, raw: &[u8]) -> Parsed<'_> {
+        match (raw[0], raw[11]) {
+            (END_OF_DIR, _) => {
+                self.sequence = 0;
+                Parsed::End
+            }
+            (DELETED, _) => {
+                self.sequence = 0;
+                Parsed::Skip
+            }
+            (_, ATTR_LONG_NAME) => {
+                self.push_long(raw);
+                Parsed::Skip
+            }
+            _ => Parsed::Entry(self.push_short(raw)),
+        }
+    }
+
+    /// Long name entries are stored last part first, sequence number n holds characters
+    /// (n - 1) * 13.. of the name
+    fn push_long(&mut self, raw: &[u8]) {
+        let sequence = raw[0] & !LAST_LONG_ENTRY;
+        let valid = match raw[0] & LAST_LONG_ENTRY != 0 {
+            true => {
+                self.checksum = raw[13];
+                true
+            }
+            false => self.sequence == sequence + 1 && self.checksum == raw[13],
+        };
+        if !valid || sequence == 0 || sequence > MAX_SEQUENCE {
+            self.sequence = 0;
+            return;
+        }
+        let start = (sequence as usize - 1) * CHARS_PER_ENTRY;
+        for (i, offset) in CHAR_OFFSETS.iter().enumerate() {
+            self.units[start + i] = u16::from_le_bytes([raw[*offset], raw[offset + 1]]);
+        }
+        if raw[0] & LAST_LONG_ENTRY != 0 {
+            // Only the last entry is padded, make sure the name ends
+            if let Some(unit) = self.units.get_mut(start + CHARS_PER_ENTRY) {
+                *unit = 0;
+            }
+        }
+        self.sequence = sequence;
+    }
+
+    fn push_short(&mut self, raw: &[u8]) -> Entry<'_> {
+        let has_long_name = self.sequence == 1 && self.checksum == checksum(raw);
+        self.sequence = 0;
+
+        self.short_name.clear();
+        let (base, ext) = (&raw[..8], &raw[8..11]);
+        push_short_part(&mut self.short_name, base, raw[12] & NT_LOWER_BASE != 0);
+        if ext.iter().any(|b| *b != b' ') {
+            let _ = self.short_name.push('.');
+            push_short_part(&mut self.short_name, ext, raw[12] & NT_LOWER_EXT != 0);
+        }
+
+        self.long_name.clear();
+        if has_long_name {
+            let units = self.units.iter().copied().take_while(|u| *u != 0);
+            for c in char::decode_utf16(units) {
+                let _ = self
+                    .long_name
+                    .push(c.unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+        }
+
+        Entry {
+            name: match self.long_name.is_empty() {
+                true => &self.short_name,
+                false => &self.long_name,
+            },
+            short_name: &self.short_name,
+            attributes: raw[11],
+            size: u32::from_le_bytes([raw[28], raw[29], raw[30], raw[31]]),
+            mtime: (
+                u16::from_le_bytes([raw[24], raw[25]]),
+                u16::from_le_bytes([raw[22], raw[23]]),
+            ),
+        }
+    }
+}
+
+impl Default for DirParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Append the space padded part of an 8.3 name. Bytes outside of ASCII depend on the code
+/// page and show up as `?`.
+fn push_short_part(name: &mut String<12>, part: &[u8], lower: bool) {
+    for (i, b) in part.iter().enumerate() {
+        let b = match (i, *b) {
+            (0, KANJI_E5) => DELETED,
+            (_, b) => b,
+        };
+        let c = match b {
+            b' ' => continue,
+            b if b.is_ascii() && lower => b.to_ascii_lowercase() as char,
+            b if b.is_ascii() => b as char,
+            _ => '?',
+        };
+        let _ = name.push(c);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Directory entries the way a host writes them, a long name followed by its 8.3 entry
+    struct DirBuilder {
+        data: [u8; 2048],
+        len: usize,
+    }
+
+    impl DirBuilder {
+        fn new() -> Self {
+            Self {
+                data: [0; 2048],
+                len: 0,
+            }
+        }
+
+        fn raw(&mut self, raw: [u8; DIR_ENTRY_SIZE]) -> &mut Self {
+            self.data[self.len..self.len + DIR_ENTRY_SIZE].copy_from_slice(&raw);
+            self.len += DIR_ENTRY_SIZE;
+            self
+        }
+
+        fn short(&mut self, short_name: &[u8; 11], attributes: u8, size: u32) -> &mut Self {
+            let mut raw = [0; DIR_ENTRY_SIZE];
+            raw[..11].copy_from_slice(short_name);
+            raw[11] = attributes;
+            // 2023-05-17 13:37:42
+            raw[22..24].copy_from_slice(&((13 << 11) | (37 << 5) | 21u16).to_le_bytes());
+            raw[24..26].copy_from_slice(&((43 << 9) | (5 << 5) | 17u16).to_le_bytes());
+            raw[28..].copy_from_slice(&size.to_le_bytes());
+            self.raw(raw)
+        }
+
+        fn long(&mut self, name: &str, short_name: &[u8; 11]) -> &mut Self {
+            let mut units = [0xffffu16; 260];
+            let mut len = 0;
+            for u in name.encode_utf16() {
+                units[len] = u;
+                len += 1;
+            }
+            if len % CHARS_PER_ENTRY != 0 {
+                units[len] = 0;
+            }
+            let count = len.div_ceil(CHARS_PER_ENTRY);
+            for sequence in (1..=count).rev() {
+                let mut raw = [0; DIR_ENTRY_SIZE];
+                raw[0] = sequence as u8;
+                if sequence == count {
+                    raw[0] |= LAST_LONG_ENTRY;
+                }
+                raw[11] = ATTR_LONG_NAME;
+                raw[13] = checksum(short_name);
+                let chars = &units[(sequence - 1) * CHARS_PER_ENTRY..];
+                for (i, offset) in CHAR_OFFSETS.iter().enumerate() {
+                    raw[*offset..offset + 2].copy_from_slice(&chars[i].to_le_bytes());
+                }
+                self.raw(raw);
+            }
+            self
+        }
+
+        fn file(&mut self, name: &str, short_name: &[u8; 11], size: u32) -> &mut Self {
+            self.long(name, short_name).short(short_name, 0x20, size)
+        }
+
+        /// Names of the parsed entries, `long|short`
+        fn parse(&self, blocks: usize) -> [String<300>; 8] {
+            let mut names: [String<300>; 8] = Default::default();
+            let mut n = 0;
+            let mut parser = DirParser::new();
+            // Parse in blocks of `blocks` entries like the firmware does with 512 byte blocks
+            for block in self.data.chunks(blocks * DIR_ENTRY_SIZE) {
+                for raw in block.chunks(DIR_ENTRY_SIZE) {
+                    match parser.push(raw) {
+                        Parsed::End => return names,
+                        Parsed::Skip => {}
+                        Parsed::Entry(e) => {
+                            names[n].push_str(e.name).unwrap();
+                            names[n].push('|').unwrap();
+                            names[n].push_str(e.short_name).unwrap();
+                            n += 1;
+                        }
+                    }
+                }
+            }
+            names
+        }
+    }
+
+    #[test]
+    fn checksums() {
+        // sum = ((sum & 1) << 7) + (sum >> 1) + byte, from the FAT specification
+        assert_eq!(checksum(b"LONGFI~1TXT"), 0xd4);
+        assert_eq!(checksum(b"README  TXT"), 0x73);
+    }
+
+    #[test]
+    fn short_names() {
+        let mut dir = DirBuilder::new();
+        dir.short(b"README  TXT", 0x20, 10)
+            .short(b"DOCS       ", ATTR_DIRECTORY, 0)
+            .short(b"\x05BC     BIN", 0x20, 1);
+        let names = dir.parse(16);
+        assert_eq!(names[0], "README.TXT|README.TXT");
+        assert_eq!(names[1], "DOCS|DOCS");
+        assert_eq!(names[2], "?BC.BIN|?BC.BIN");
+        assert_eq!(names[3], "");
+    }
+
+    #[test]
+    fn nt_lower_case() {
+        let mut raw = [0; DIR_ENTRY_SIZE];
+        raw[..11].copy_from_slice(b"README  TXT");
+        raw[12] = NT_LOWER_BASE;
+        let mut dir = DirBuilder::new();
+        dir.raw(raw);
+        assert_eq!(dir.parse(16)[0], "readme.TXT|readme.TXT");
+    }
+
+    #[test]
+    fn long_names() {
+        let mut dir = DirBuilder::new();
+        dir.file("Long file name.txt", b"LONGFI~1TXT", 1)
+            // Exactly 13 characters, no terminator
+            .file("thirteen.char", b"THIRTE~1CHA", 2)
+            .file("Ünïcödé ♥.md", b"NCD~1   MD ", 3)
+            .short(b"PLAIN   TXT", 0x20, 4);
+        let names = dir.parse(16);
+        assert_eq!(names[0], "Long file name.txt|LONGFI~1.TXT");
+        assert_eq!(names[1], "thirteen.char|THIRTE~1.CHA");
+        assert_eq!(names[2], "Ünïcödé ♥.md|NCD~1.MD");
+        assert_eq!(names[3], "PLAIN.TXT|PLAIN.TXT");
+    }
+
+    #[test]
+    fn long_name_across_blocks() {
+        let long = "A very long file name that needs four entries.data";
+        let mut dir = DirBuilder::new();
+        dir.short(b"FIRST      ", 0x20, 0)
+            .short(b"SECOND     ", 0x20, 0)
+            .file(long, b"AVERYL~1DAT", 0);
+        // Blocks of 4 entries, the long name starts in the first block
+        let names = dir.parse(4);
+        assert_eq!(names[2].split('|').next(), Some(long));
+    }
+
+    #[test]
+    fn max_length_name() {
+        let mut long = String::<300>::new();
+        for _ in 0..LFN_MAX {
+            long.push('x').unwrap();
+        }
+        let mut dir = DirBuilder::new();
+        dir.file(&long, b"XXXXXX~1   ", 0);
+        assert_eq!(dir.parse(16)[0].split('|').next(), Some(long.as_str()));
+    }
+
+    #[test]
+    fn orphaned_long_names() {
+        let mut dir = DirBuilder::new();
+        // Checksum of a different 8.3 name, eg. renamed by a tool that doesn't know LFN
+        dir.long("Stale name.txt", b"OTHER   TXT")
+            .short(b"RENAMED TXT", 0x20, 0)
+            // Deleted entry between the long name and the 8.3 entry
+            .long("Deleted.txt", b"DELETED TXT");
+        let mut deleted = [0; DIR_ENTRY_SIZE];
+        deleted[0] = DELETED;
+        dir.raw(deleted).short(b"DELETED TXT", 0x20, 0);
+        let names = dir.parse(16);
+        assert_eq!(names[0], "RENAMED.TXT|RENAMED.TXT");
+        assert_eq!(names[1], "DELETED.TXT|DELETED.TXT");
+    }
+
+    #[test]
+    fn metadata() {
+        let mut dir = DirBuilder::new();
+        dir.short(b"DOCS       ", ATTR_DIRECTORY | ATTR_HIDDEN, 0)
+            .short(b"A       BIN", 0x20, 1234);
+        let mut parser = DirParser::new();
+        match parser.push(&dir.data[..DIR_ENTRY_SIZE]) {
+            Parsed::Entry(e) => {
+                assert!(e.is_directory() && e.is_hidden() && !e.is_volume());
+                let ts = e.modified();
+                assert_eq!(
+                    (
+                        ts.year_since_1970,
+                        ts.zero_indexed_month,
+                        ts.zero_indexed_day
+                    ),
+                    (53, 4, 16)
+                );
+                assert_eq!((ts.hours, ts.minutes, ts.seconds), (13, 37, 42));
+            }
+            _ => panic!("Expected an entry"),
+        }
+        match parser.push(&dir.data[DIR_ENTRY_SIZE..2 * DIR_ENTRY_SIZE]) {
+            Parsed::Entry(e) => assert_eq!((e.is_directory(), e.size), (false, 1234)),
+            _ => panic!("Expected an entry"),
+        }
+    }
+
+    #[test]
+    fn case_insensitive() {
+        assert!(name_eq("Long File Name.TXT", "long file name.txt"));
+        assert!(name_eq("ÜBER.md", "über.MD"));
+        assert!(!name_eq("a.txt", "b.txt"));
+    }
+}
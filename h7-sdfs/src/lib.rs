@@ -0,0 +1,18 @@
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+mod card;
+mod error;
+mod fat;
+#[cfg(any(test, feature = "std"))]
+mod file;
+mod fs;
+mod lfn;
+
+#[cfg(any(test, feature = "std"))]
+pub use file::FileBlockDevice;
+pub use {
+    card::Card,
+    error::SdmmcFsError,
+    fs::{FileHandle, OpenMode, SdmmcFs},
+    lfn::Entry,
+};
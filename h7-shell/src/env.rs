@@ -0,0 +1,122 @@
+use {
+    crate::ShellError,
+    heapless::{String, Vec},
+};
+
+pub const NAME_LEN: usize = 32;
+pub const VALUE_LEN: usize = 128;
+
+/// Shell variables, up to `N` of them
+#[derive(Debug, Clone, Default)]
+pub struct Env<const N: usize> {
+    vars: Vec<(String<NAME_LEN>, String<VALUE_LEN>), N>,
+}
+
+impl<const N: usize> Env<N> {
+    pub const fn new() -> Self {
+        Self { vars: Vec::new() }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Set or replace a variable
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), ShellError> {
+        if !is_valid_name(name) {
+            return Err(ShellError::InvalidName);
+        }
+        let mut new_value = String::new();
+        new_value
+            .push_str(value)
+            .map_err(|_| ShellError::VariableTooLong(VALUE_LEN))?;
+        if let Some((_, v)) = self.vars.iter_mut().find(|(n, _)| n == name) {
+            *v = new_value;
+            return Ok(());
+        }
+        let mut new_name = String::new();
+        new_name
+            .push_str(name)
+            .map_err(|_| ShellError::VariableTooLong(NAME_LEN))?;
+        self.vars
+            .push((new_name, new_value))
+            .map_err(|_| ShellError::EnvFull(N))
+    }
+
+    /// Remove a variable, returns `false` if it was not set
+    pub fn unset(&mut self, name: &str) -> bool {
+        match self.vars.iter().position(|(n, _)| n == name) {
+            Some(i) => {
+                self.vars.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Variables in the order they were first set
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+}
+
+/// Letters, digits and `_`, not starting with a digit
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_get() {
+        let mut env = Env::<2>::new();
+        assert_eq!(env.get("A"), None);
+        env.set("A", "1").unwrap();
+        env.set("_b2", "two words").unwrap();
+        assert_eq!(env.get("A"), Some("1"));
+        assert_eq!(env.get("_b2"), Some("two words"));
+        // Replacing doesn't need room
+        env.set("A", "").unwrap();
+        assert_eq!(env.get("A"), Some(""));
+        assert_eq!(env.set("C", "3"), Err(ShellError::EnvFull(2)));
+    }
+
+    #[test]
+    fn unset() {
+        let mut env = Env::<4>::new();
+        env.set("A", "1").unwrap();
+        env.set("B", "2").unwrap();
+        assert!(env.unset("A"));
+        assert!(!env.unset("A"));
+        assert_eq!(env.iter().collect::<Vec<_, 4>>(), [("B", "2")]);
+    }
+
+    #[test]
+    fn invalid() {
+        let mut env = Env::<4>::new();
+        for name in ["", "1A", "A-B", "A B", "Å"] {
+            assert_eq!(env.set(name, ""), Err(ShellError::InvalidName), "{name}");
+        }
+        let long = [b'A'; NAME_LEN + 1];
+        let long = core::str::from_utf8(&long).unwrap();
+        assert_eq!(
+            env.set(long, ""),
+            Err(ShellError::VariableTooLong(NAME_LEN))
+        );
+        let long = [b'x'; VALUE_LEN + 1];
+        let long = core::str::from_utf8(&long).unwrap();
+        assert_eq!(
+            env.set("A", long),
+            Err(ShellError::VariableTooLong(VALUE_LEN))
+        );
+    }
+}
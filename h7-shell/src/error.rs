@@ -0,0 +1,34 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellError {
+    /// Quote opened but not closed
+    UnterminatedQuote(char),
+    /// Line ends with `\`
+    TrailingBackslash,
+    /// `${` without `}`
+    UnterminatedVariable,
+    /// More arguments than fit (max)
+    TooManyArgs(usize),
+    /// Expanded line does not fit in the buffer (max)
+    LineTooLong(usize),
+    /// Variable names are letters, digits and `_`, and don't start with a digit
+    InvalidName,
+    /// No room for another variable (max)
+    EnvFull(usize),
+    /// Variable name or value is too long (max)
+    VariableTooLong(usize),
+}
+
+impl core::fmt::Display for ShellError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::UnterminatedQuote(q) => write!(f, "Missing closing {q}"),
+            Self::TrailingBackslash => write!(f, "Trailing \\"),
+            Self::UnterminatedVariable => write!(f, "Missing closing }}"),
+            Self::TooManyArgs(max) => write!(f, "Too many arguments (max {max})"),
+            Self::LineTooLong(max) => write!(f, "Line too long (max {max} bytes)"),
+            Self::InvalidName => write!(f, "Invalid variable name"),
+            Self::EnvFull(max) => write!(f, "Too many variables (max {max})"),
+            Self::VariableTooLong(max) => write!(f, "Variable too long (max {max} bytes)"),
+        }
+    }
+}
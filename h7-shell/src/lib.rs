@@ -0,0 +1,13 @@
+#![no_std]
+
+pub mod env;
+mod error;
+mod line_editor;
+mod parser;
+
+pub use {
+    env::Env,
+    error::ShellError,
+    line_editor::{Completer, LineEditor},
+    parser::tokenize,
+};
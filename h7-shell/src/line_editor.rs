@@ -0,0 +1,614 @@
+use {
+    core::fmt::{self, Write},
+    heapless::{Deque, String, Vec},
+};
+
+/// Source of tab completions
+pub trait Completer {
+    /// Call `candidate` with every completion of `word`, the word under the cursor.
+    /// `before` is the line up to `word`, empty if `word` is the command.
+    fn complete(&mut self, before: &str, word: &str, candidate: &mut dyn FnMut(&str));
+}
+
+/// No completions
+impl Completer for () {
+    fn complete(&mut self, _: &str, _: &str, _: &mut dyn FnMut(&str)) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// Got `ESC`
+    Esc,
+    /// `ESC [` and the numeric parameter so far
+    Csi(u16),
+    /// `ESC O`
+    Ss3,
+}
+
+/// VT100 line editor with a history ring of `H` lines, each up to `N` bytes.
+///
+/// Feed it input bytes one at a time, it echoes to the terminal and returns the line when
+/// enter is pressed.
+pub struct LineEditor<const N: usize, const H: usize> {
+    prompt: &'static str,
+    line: Vec<u8, N>,
+    /// Byte offset in `line`, always on a char boundary
+    cursor: usize,
+    /// Incomplete utf-8 sequence
+    pending: Vec<u8, 4>,
+    escape: Escape,
+    history: Deque<String<N>, H>,
+    /// Index in `history` while browsing, 0 is the newest
+    history_pos: Option<usize>,
+    /// The line being edited before browsing the history
+    saved: String<N>,
+    last_cr: bool,
+    done: bool,
+}
+
+impl<const N: usize, const H: usize> LineEditor<N, H> {
+    pub const fn new(prompt: &'static str) -> Self {
+        Self {
+            prompt,
+            line: Vec::new(),
+            cursor: 0,
+            pending: Vec::new(),
+            escape: Escape::None,
+            history: Deque::new(),
+            history_pos: None,
+            saved: String::new(),
+            last_cr: false,
+            done: false,
+        }
+    }
+
+    /// Print the prompt for a new line
+    pub fn start<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        self.clear();
+        out.write_str(self.prompt)
+    }
+
+    /// The line being edited
+    pub fn line(&self) -> &str {
+        core::str::from_utf8(&self.line).unwrap_or_default()
+    }
+
+    /// History, newest first
+    pub fn history(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().rev().map(|l| l.as_str())
+    }
+
+    /// Handle one byte of input. Returns the line when enter is pressed, the line is
+    /// cleared on the next call.
+    pub fn feed<W: Write>(
+        &mut self,
+        byte: u8,
+        out: &mut W,
+        completer: &mut impl Completer,
+    ) -> Result<Option<&str>, fmt::Error> {
+        if self.done {
+            self.clear();
+        }
+        let last_cr = core::mem::replace(&mut self.last_cr, byte == b'\r');
+
+        match self.escape {
+            Escape::Esc => {
+                self.escape = match byte {
+                    b'[' => Escape::Csi(0),
+                    b'O' => Escape::Ss3,
+                    _ => Escape::None,
+                };
+                match byte {
+                    b'b' => self.word_left(out)?,
+                    b'f' => self.word_right(out)?,
+                    _ => {}
+                }
+                return Ok(None);
+            }
+            Escape::Csi(param) => {
+                self.escape = match byte {
+                    b'0'..=b'9' => Escape::Csi(
+                        param
+                            .saturating_mul(10)
+                            .saturating_add((byte - b'0') as u16),
+                    ),
+                    // Only the first parameter is used
+                    b';' => Escape::Csi(param),
+                    _ => Escape::None,
+                };
+                if let 0x40..=0x7e = byte {
+                    self.csi(byte, param, out)?;
+                }
+                return Ok(None);
+            }
+            Escape::Ss3 => {
+                self.escape = Escape::None;
+                self.csi(byte, 0, out)?;
+                return Ok(None);
+            }
+            Escape::None => {}
+        }
+
+        match byte {
+            // \r\n is one line ending
+            b'\n' if last_cr => {}
+            b'\r' | b'\n' => {
+                self.pending.clear();
+                self.end(out)?;
+                out.write_char('\n')?;
+                self.push_history();
+                self.done = true;
+                return Ok(Some(self.line()));
+            }
+            0x1b => self.escape = Escape::Esc,
+            // Ctrl-A / Ctrl-E
+            0x01 => self.home(out)?,
+            0x05 => self.end(out)?,
+            // Ctrl-B / Ctrl-F
+            0x02 => {
+                self.left(out)?;
+            }
+            0x06 => {
+                self.right(out)?;
+            }
+            // Ctrl-P / Ctrl-N
+            0x10 => self.history_prev(out)?,
+            0x0e => self.history_next(out)?,
+            // Ctrl-C, drop the line
+            0x03 => {
+                out.write_str("^C\n")?;
+                self.start(out)?;
+            }
+            // Ctrl-D, delete under cursor
+            0x04 => self.delete(out)?,
+            // Backspace / DEL
+            0x08 | 0x7f => {
+                if self.left(out)? {
+                    self.delete(out)?;
+                }
+            }
+            // Ctrl-K, delete to end of line
+            0x0b => {
+                self.line.truncate(self.cursor);
+                out.write_str("\x1b[K")?;
+            }
+            // Ctrl-L, clear screen
+            0x0c => {
+                out.write_str("\x1b[2J\x1b[H")?;
+                self.refresh(out)?;
+            }
+            // Ctrl-U, delete to start of line
+            0x15 => {
+                self.remove(0..self.cursor);
+                self.cursor = 0;
+                self.refresh(out)?;
+            }
+            // Ctrl-W, delete previous word
+            0x17 => {
+                let end = self.cursor;
+                self.cursor = self.word_start(self.cursor);
+                self.remove(self.cursor..end);
+                self.refresh(out)?;
+            }
+            b'\t' => self.complete(out, completer)?,
+            0x00..=0x1f => {}
+            _ => {
+                if self.pending.push(byte).is_err() {
+                    self.pending.clear();
+                }
+                let mut buf = [0u8; 4];
+                let len = self.pending.len();
+                buf[..len].copy_from_slice(&self.pending);
+                match core::str::from_utf8(&buf[..len]) {
+                    Ok(s) => {
+                        self.pending.clear();
+                        self.insert(s, out)?;
+                    }
+                    // Wait for the rest of the sequence
+                    Err(e) if e.error_len().is_none() => {}
+                    Err(_) => self.pending.clear(),
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn clear(&mut self) {
+        self.line.clear();
+        self.cursor = 0;
+        self.pending.clear();
+        self.escape = Escape::None;
+        self.history_pos = None;
+        self.done = false;
+    }
+
+    fn csi<W: Write>(&mut self, byte: u8, param: u16, out: &mut W) -> fmt::Result {
+        match (byte, param) {
+            (b'A', _) => self.history_prev(out),
+            (b'B', _) => self.history_next(out),
+            (b'C', _) => self.right(out).map(|_| ()),
+            (b'D', _) => self.left(out).map(|_| ()),
+            (b'H', _) | (b'~', 1 | 7) => self.home(out),
+            (b'F', _) | (b'~', 4 | 8) => self.end(out),
+            (b'~', 3) => self.delete(out),
+            _ => Ok(()),
+        }
+    }
+
+    /// Insert `s` at the cursor, rings the bell if the line is full
+    fn insert<W: Write>(&mut self, s: &str, out: &mut W) -> fmt::Result {
+        if self.line.len() + s.len() > N {
+            return out.write_char('\x07');
+        }
+        for (i, b) in s.bytes().enumerate() {
+            let _ = self.line.insert(self.cursor + i, b);
+        }
+        self.cursor += s.len();
+        out.write_str(s)?;
+        match self.cursor < self.line.len() {
+            true => self.redraw_tail(out),
+            false => Ok(()),
+        }
+    }
+
+    fn remove(&mut self, range: core::ops::Range<usize>) {
+        let len = self.line.len();
+        self.line.copy_within(range.end.., range.start);
+        self.line.truncate(len - range.len());
+    }
+
+    /// Delete the char under the cursor
+    fn delete<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        match self.line()[self.cursor..].chars().next() {
+            Some(c) => {
+                self.remove(self.cursor..self.cursor + c.len_utf8());
+                self.redraw_tail(out)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn left<W: Write>(&mut self, out: &mut W) -> Result<bool, fmt::Error> {
+        match self.line()[..self.cursor].chars().next_back() {
+            Some(c) => {
+                self.cursor -= c.len_utf8();
+                out.write_str("\x1b[D")?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn right<W: Write>(&mut self, out: &mut W) -> Result<bool, fmt::Error> {
+        match self.line()[self.cursor..].chars().next() {
+            Some(c) => {
+                self.cursor += c.len_utf8();
+                out.write_str("\x1b[C")?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn home<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        self.move_to(0, out)
+    }
+
+    fn end<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        self.move_to(self.line.len(), out)
+    }
+
+    fn word_left<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        self.move_to(self.word_start(self.cursor), out)
+    }
+
+    fn word_right<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        let line = self.line();
+        let rest = &line[self.cursor..];
+        let skip = rest.len() - rest.trim_start_matches(' ').len();
+        let end = rest[skip..]
+            .find(' ')
+            .map_or(line.len(), |n| self.cursor + skip + n);
+        self.move_to(end, out)
+    }
+
+    /// Start of the word before `pos`, skipping spaces
+    fn word_start(&self, pos: usize) -> usize {
+        let before = self.line()[..pos].trim_end_matches(' ');
+        before.rfind(' ').map_or(0, |n| n + 1)
+    }
+
+    fn move_to<W: Write>(&mut self, pos: usize, out: &mut W) -> fmt::Result {
+        let line = self.line();
+        let (n, dir) = match pos < self.cursor {
+            true => (line[pos..self.cursor].chars().count(), 'D'),
+            false => (line[self.cursor..pos].chars().count(), 'C'),
+        };
+        self.cursor = pos;
+        if n > 0 {
+            write!(out, "\x1b[{n}{dir}")?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite everything after the cursor and put the cursor back
+    fn redraw_tail<W: Write>(&self, out: &mut W) -> fmt::Result {
+        let tail = &self.line()[self.cursor..];
+        out.write_str(tail)?;
+        out.write_str("\x1b[K")?;
+        match tail.chars().count() {
+            0 => Ok(()),
+            n => write!(out, "\x1b[{n}D"),
+        }
+    }
+
+    /// Rewrite the prompt and the whole line
+    fn refresh<W: Write>(&self, out: &mut W) -> fmt::Result {
+        out.write_char('\r')?;
+        out.write_str(self.prompt)?;
+        out.write_str(&self.line()[..self.cursor])?;
+        self.redraw_tail(out)
+    }
+
+    fn set_line<W: Write>(&mut self, s: &str, out: &mut W) -> fmt::Result {
+        self.line.clear();
+        let _ = self.line.extend_from_slice(s.as_bytes());
+        self.cursor = self.line.len();
+        self.refresh(out)
+    }
+
+    fn push_history(&mut self) {
+        let line = self.line().trim();
+        if line.is_empty() || self.history.back().is_some_and(|l| l == line) {
+            return;
+        }
+        let mut entry = String::new();
+        let _ = entry.push_str(line);
+        if self.history.is_full() {
+            self.history.pop_front();
+        }
+        let _ = self.history.push_back(entry);
+    }
+
+    fn history_prev<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        let pos = self.history_pos.map_or(0, |p| p + 1);
+        if pos >= self.history.len() {
+            return Ok(());
+        }
+        if self.history_pos.is_none() {
+            self.saved.clear();
+            let line = core::str::from_utf8(&self.line).unwrap_or_default();
+            let _ = self.saved.push_str(line);
+        }
+        self.history_pos = Some(pos);
+        self.show_history(out)
+    }
+
+    fn history_next<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        match self.history_pos {
+            None => Ok(()),
+            Some(0) => {
+                self.history_pos = None;
+                let saved = core::mem::take(&mut self.saved);
+                self.set_line(&saved, out)
+            }
+            Some(pos) => {
+                self.history_pos = Some(pos - 1);
+                self.show_history(out)
+            }
+        }
+    }
+
+    fn show_history<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        let mut entry = String::<N>::new();
+        if let Some(pos) = self.history_pos {
+            if let Some(l) = self.history.iter().rev().nth(pos) {
+                entry = l.clone();
+            }
+        }
+        self.set_line(&entry, out)
+    }
+
+    fn complete<W: Write>(&mut self, out: &mut W, completer: &mut impl Completer) -> fmt::Result {
+        let start = self.line()[..self.cursor].rfind(' ').map_or(0, |n| n + 1);
+        let mut line = String::<N>::new();
+        let _ = line.push_str(&self.line()[..self.cursor]);
+        let (before, word) = (line[..start].trim_start(), &line[start..]);
+
+        // Longest common prefix of all candidates
+        let mut prefix = String::<N>::new();
+        let mut count = 0;
+        completer.complete(before, word, &mut |c| {
+            if count == 0 {
+                let _ = prefix.push_str(c);
+            } else {
+                let common = prefix
+                    .chars()
+                    .zip(c.chars())
+                    .take_while(|(a, b)| a == b)
+                    .map(|(a, _)| a.len_utf8())
+                    .sum();
+                prefix.truncate(common);
+            }
+            count += 1;
+        });
+        // Complete the argument unless it's a directory or a device
+        if count == 1 && !prefix.ends_with(['/', ':']) {
+            let _ = prefix.push(' ');
+        }
+
+        match count {
+            0 => out.write_char('\x07'),
+            1 if prefix == word => Ok(()),
+            _ if count == 1 || prefix.len() > word.len() => {
+                // The completion is written over the word
+                let end = self.cursor;
+                self.move_to(start, out)?;
+                self.remove(start..end);
+                self.insert(&prefix, out)
+            }
+            // Ambiguous, list the candidates
+            _ => {
+                let (mut res, mut col) = (Ok(()), 0);
+                out.write_char('\n')?;
+                completer.complete(before, word, &mut |c| {
+                    col += 1;
+                    let sep = if col % 4 == 0 { "\n" } else { "" };
+                    res = res.and_then(|_| write!(out, "{c:19} {sep}"));
+                });
+                res?;
+                if col % 4 != 0 {
+                    out.write_char('\n')?;
+                }
+                self.refresh(out)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use {super::*, std::string::String as StdString, std::vec::Vec as StdVec};
+
+    type Editor = LineEditor<64, 4>;
+
+    struct Words(&'static [&'static str]);
+
+    impl Completer for Words {
+        fn complete(&mut self, _: &str, word: &str, candidate: &mut dyn FnMut(&str)) {
+            self.0
+                .iter()
+                .filter(|w| w.starts_with(word))
+                .for_each(|w| candidate(w));
+        }
+    }
+
+    /// Feed `input`, returns the lines entered and the output
+    fn run(
+        editor: &mut Editor,
+        input: &[u8],
+        completer: &mut impl Completer,
+    ) -> (StdVec<StdString>, StdString) {
+        let mut out = StdString::new();
+        let mut lines = StdVec::new();
+        for b in input {
+            if let Some(line) = editor.feed(*b, &mut out, completer).unwrap() {
+                lines.push(line.into());
+            }
+        }
+        (lines, out)
+    }
+
+    fn lines(editor: &mut Editor, input: &[u8]) -> StdVec<StdString> {
+        run(editor, input, &mut ()).0
+    }
+
+    #[test]
+    fn enter() {
+        let mut e = Editor::new("> ");
+        assert_eq!(lines(&mut e, b"ls sdcard:/\r"), ["ls sdcard:/"]);
+        assert_eq!(lines(&mut e, b"a\nb\r\nc\r"), ["a", "b", "c"]);
+        assert_eq!(lines(&mut e, b"\r"), [""]);
+    }
+
+    #[test]
+    fn echo() {
+        let mut e = Editor::new("> ");
+        let (_, out) = run(&mut e, b"hi\r", &mut ());
+        assert_eq!(out, "hi\n");
+        let (_, out) = run(&mut e, b"ac\x1b[Db", &mut ());
+        assert_eq!(out, "ac\x1b[Dbc\x1b[K\x1b[1D");
+    }
+
+    #[test]
+    fn backspace() {
+        let mut e = Editor::new("> ");
+        assert_eq!(lines(&mut e, b"lss\x7f\r"), ["ls"]);
+        assert_eq!(lines(&mut e, b"ab\x08\x08\x08c\r"), ["c"]);
+    }
+
+    #[test]
+    fn cursor_movement() {
+        let mut e = Editor::new("> ");
+        assert_eq!(lines(&mut e, b"ac\x1b[Db\r"), ["abc"]);
+        assert_eq!(lines(&mut e, b"bc\x01a\x05d\r"), ["abcd"]);
+        assert_eq!(lines(&mut e, b"bc\x1b[Ha\x1b[Fd\r"), ["abcd"]);
+        assert_eq!(lines(&mut e, b"bc\x1b[1~a\x1b[4~d\r"), ["abcd"]);
+        assert_eq!(lines(&mut e, b"abc\x1b[D\x1b[D\x1b[3~\r"), ["ac"]);
+        assert_eq!(lines(&mut e, b"ab\x1b[C\x1b[C\x1b[D\x1b[Cc\r"), ["abc"]);
+    }
+
+    #[test]
+    fn kill() {
+        let mut e = Editor::new("> ");
+        assert_eq!(lines(&mut e, b"hello world\x15bye\r"), ["bye"]);
+        assert_eq!(lines(&mut e, b"cat sdcard:/a  \x17b\r"), ["cat b"]);
+        assert_eq!(lines(&mut e, b"hello world\x01\x1bf\x0b\r"), ["hello"]);
+        assert_eq!(lines(&mut e, b"drop\x03keep\r"), ["keep"]);
+    }
+
+    #[test]
+    fn utf8() {
+        let mut e = Editor::new("> ");
+        assert_eq!(lines(&mut e, "åäö\x7f\x1b[Dx\r".as_bytes()), ["åxä"]);
+    }
+
+    #[test]
+    fn full_line() {
+        let mut e = Editor::new("> ");
+        let (lines, out) = run(&mut e, &[b'x'; 70], &mut ());
+        assert!(lines.is_empty());
+        assert_eq!(e.line().len(), 64);
+        assert!(out.ends_with('\x07'));
+    }
+
+    #[test]
+    fn history() {
+        let mut e = Editor::new("> ");
+        lines(&mut e, b"one\rtwo\rtwo\r  \rthree\r");
+        assert_eq!(e.history().collect::<StdVec<_>>(), ["three", "two", "one"]);
+        assert_eq!(lines(&mut e, b"\x1b[A\x1b[A\x1b[A\x1b[A\x1b[B\r"), ["two"]);
+        // Browsing back down restores the edited line
+        assert_eq!(lines(&mut e, b"new\x1b[A\x1b[B\r"), ["new"]);
+        assert_eq!(lines(&mut e, b"\x10\x10\x0e!\r"), ["new!"]);
+    }
+
+    #[test]
+    fn history_ring() {
+        let mut e = Editor::new("> ");
+        lines(&mut e, b"1\r2\r3\r4\r5\r6\r");
+        assert_eq!(e.history().collect::<StdVec<_>>(), ["6", "5", "4", "3"]);
+    }
+
+    #[test]
+    fn complete() {
+        let mut words = Words(&["help", "ls", "ledctl", "sdcard:", "sdcard:/APPS/"]);
+        let mut e = Editor::new("> ");
+        let (lines, out) = run(&mut e, b"he\t\r", &mut words);
+        assert_eq!(lines, ["help "]);
+        assert_eq!(out, "he\x1b[2Dhelp \n");
+        // In the middle of the line
+        let (lines, out) = run(&mut e, b"ls sd x\x1b[D\x1b[D\t\r", &mut words);
+        assert_eq!(lines, ["ls sdcard: x"]);
+        assert_eq!(
+            out,
+            "ls sd x\x1b[D\x1b[D\x1b[2Dsdcard: x\x1b[K\x1b[2D\x1b[2C\n"
+        );
+        // Common prefix, then the list of candidates
+        let (lines, out) = run(&mut e, b"l\t\r", &mut words);
+        assert_eq!(lines, ["l"]);
+        assert!(out.contains("ls") && out.contains("ledctl"));
+        assert_eq!(run(&mut e, b"ls sd\t\r", &mut words).0, ["ls sdcard:"]);
+        assert_eq!(
+            run(&mut e, b"ls sdcard:/A\t\r", &mut words).0,
+            ["ls sdcard:/APPS/"]
+        );
+        // Nothing to complete
+        let (lines, out) = run(&mut e, b"x\t\r", &mut words);
+        assert_eq!(lines, ["x"]);
+        assert!(out.contains('\x07'));
+    }
+}
@@ -0,0 +1,236 @@
+use {
+    crate::{
+        env::{Env, NAME_LEN},
+        ShellError,
+    },
+    core::{iter::Peekable, str::Chars},
+    heapless::{String, Vec},
+};
+
+/// Split `line` into at most `A` arguments, written to `buf`.
+///
+/// * Arguments are separated by whitespace
+/// * `'...'` is taken literally
+/// * `"..."` expands variables, `\"`, `\\` and `\$` are escapes, other backslashes are kept
+/// * `\` outside of quotes escapes the next character
+/// * `$NAME` and `${NAME}` expand to the value from `env`, or nothing if it's not set.
+///   Values are not split into several arguments.
+///
+/// An unquoted variable that expands to nothing is not an argument, `""` is an empty one.
+pub fn tokenize<'b, const A: usize, const N: usize>(
+    line: &str,
+    env: &Env<N>,
+    buf: &'b mut [u8],
+) -> Result<Vec<&'b str, A>, ShellError> {
+    let mut out = Output { buf, len: 0 };
+    // Arguments as ranges in buf
+    let mut ranges = Vec::<(usize, usize), A>::new();
+    // Start of the current argument
+    let mut start = None;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(start) = start.take() {
+                    ranges
+                        .push((start, out.len))
+                        .map_err(|_| ShellError::TooManyArgs(A))?;
+                }
+            }
+            '\'' => {
+                start.get_or_insert(out.len);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => out.push(c)?,
+                        None => return Err(ShellError::UnterminatedQuote('\'')),
+                    }
+                }
+            }
+            '"' => {
+                start.get_or_insert(out.len);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next_if(|c| matches!(c, '"' | '\\' | '$')) {
+                            Some(c) => out.push(c)?,
+                            None => out.push('\\')?,
+                        },
+                        Some('$') => expand(&mut chars, env, &mut out)?,
+                        Some(c) => out.push(c)?,
+                        None => return Err(ShellError::UnterminatedQuote('"')),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => {
+                    start.get_or_insert(out.len);
+                    out.push(c)?;
+                }
+                None => return Err(ShellError::TrailingBackslash),
+            },
+            '$' => {
+                let before = out.len;
+                expand(&mut chars, env, &mut out)?;
+                if out.len > before {
+                    start.get_or_insert(before);
+                }
+            }
+            c => {
+                start.get_or_insert(out.len);
+                out.push(c)?;
+            }
+        }
+    }
+    if let Some(start) = start {
+        ranges
+            .push((start, out.len))
+            .map_err(|_| ShellError::TooManyArgs(A))?;
+    }
+
+    // Only whole chars are written to buf
+    let buf = &*out.buf;
+    Ok(ranges
+        .iter()
+        .map(|(s, e)| core::str::from_utf8(&buf[*s..*e]).unwrap_or_default())
+        .collect())
+}
+
+struct Output<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl Output<'_> {
+    fn push(&mut self, c: char) -> Result<(), ShellError> {
+        self.push_str(c.encode_utf8(&mut [0; 4]))
+    }
+
+    fn push_str(&mut self, s: &str) -> Result<(), ShellError> {
+        let end = self.len + s.len();
+        if end > self.buf.len() {
+            return Err(ShellError::LineTooLong(self.buf.len()));
+        }
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Expand the variable after a `$`. A `$` that is not followed by a name is kept.
+fn expand<const N: usize>(
+    chars: &mut Peekable<Chars>,
+    env: &Env<N>,
+    out: &mut Output,
+) -> Result<(), ShellError> {
+    let braced = chars.next_if_eq(&'{').is_some();
+    let mut name = String::<NAME_LEN>::new();
+    if let Some(c) = chars.next_if(|c| c.is_ascii_alphabetic() || *c == '_') {
+        let _ = name.push(c);
+        while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+            name.push(c)
+                .map_err(|_| ShellError::VariableTooLong(NAME_LEN))?;
+        }
+    }
+    if braced && chars.next() != Some('}') {
+        return Err(ShellError::UnterminatedVariable);
+    }
+    match (braced, name.is_empty()) {
+        (true, true) => Err(ShellError::InvalidName),
+        (false, true) => out.push('$'),
+        _ => out.push_str(env.get(&name).unwrap_or_default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env() -> Env<4> {
+        let mut env = Env::new();
+        env.set("DIR", "sdcard:/apps").unwrap();
+        env.set("SPACE", "a b").unwrap();
+        env.set("EMPTY", "").unwrap();
+        env
+    }
+
+    fn args<'b>(line: &str, buf: &'b mut [u8]) -> Result<Vec<&'b str, 8>, ShellError> {
+        tokenize::<8, 4>(line, &env(), buf)
+    }
+
+    #[track_caller]
+    fn assert_args(line: &str, expected: &[&str]) {
+        let mut buf = [0u8; 128];
+        assert_eq!(args(line, &mut buf).unwrap(), expected, "{line}");
+    }
+
+    #[track_caller]
+    fn assert_err(line: &str, expected: ShellError) {
+        let mut buf = [0u8; 128];
+        assert_eq!(args(line, &mut buf), Err(expected), "{line}");
+    }
+
+    #[test]
+    fn whitespace() {
+        assert_args("", &[]);
+        assert_args("   \t ", &[]);
+        assert_args("ls", &["ls"]);
+        assert_args("  cp  a\tb ", &["cp", "a", "b"]);
+    }
+
+    #[test]
+    fn quotes() {
+        assert_args("cat 'sdcard:/my file.txt'", &["cat", "sdcard:/my file.txt"]);
+        assert_args("echo \"a  b\" c", &["echo", "a  b", "c"]);
+        assert_args("echo a'b c'\"d\"e", &["echo", "ab cde"]);
+        assert_args("echo '' \"\"", &["echo", "", ""]);
+        assert_args("echo '\"' \"'\"", &["echo", "\"", "'"]);
+        assert_args("echo 'å ä'", &["echo", "å ä"]);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_args("echo a\\ b", &["echo", "a b"]);
+        assert_args("echo \\'\\\"\\\\\\$", &["echo", "'\"\\$"]);
+        assert_args("echo \"\\\"\\\\\\$\\n\"", &["echo", "\"\\$\\n"]);
+        assert_args("echo '\\n'", &["echo", "\\n"]);
+        assert_args("echo \\ ", &["echo", " "]);
+    }
+
+    #[test]
+    fn variables() {
+        assert_args("ls $DIR", &["ls", "sdcard:/apps"]);
+        assert_args("ls ${DIR}/bin", &["ls", "sdcard:/apps/bin"]);
+        assert_args("ls \"$DIR/x y\"", &["ls", "sdcard:/apps/x y"]);
+        assert_args("echo $SPACE", &["echo", "a b"]);
+        assert_args("echo '$DIR' \\$DIR", &["echo", "$DIR", "$DIR"]);
+        assert_args("echo $ $1 a$", &["echo", "$", "$1", "a$"]);
+    }
+
+    #[test]
+    fn empty_variables() {
+        assert_args("echo $EMPTY $UNSET", &["echo"]);
+        assert_args("echo \"$EMPTY\" a$UNSET", &["echo", "", "a"]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_err("echo 'a", ShellError::UnterminatedQuote('\''));
+        assert_err("echo \"a", ShellError::UnterminatedQuote('"'));
+        assert_err("echo \"a\\", ShellError::UnterminatedQuote('"'));
+        assert_err("echo a\\", ShellError::TrailingBackslash);
+        assert_err("echo ${DIR", ShellError::UnterminatedVariable);
+        assert_err("echo ${}", ShellError::InvalidName);
+        assert_err("echo ${1}", ShellError::UnterminatedVariable);
+        assert_err("1 2 3 4 5 6 7 8 9", ShellError::TooManyArgs(8));
+    }
+
+    #[test]
+    fn buffer_full() {
+        let mut buf = [0u8; 8];
+        assert_eq!(args("12345678", &mut buf).unwrap(), ["12345678"]);
+        assert_eq!(args("123456789", &mut buf), Err(ShellError::LineTooLong(8)));
+        assert_eq!(args("ls $DIR", &mut buf), Err(ShellError::LineTooLong(8)));
+    }
+}
@@ -0,0 +1,377 @@
+use {
+    crate::{
+        console_cells, draw_console, host, new_console, poll_app, Vram, FPS_TARGET, HEIGHT, WIDTH,
+    },
+    embedded_graphics::pixelcolor::{Rgb888, RgbColor},
+    h7_api::AppEntryPoint,
+    std::{
+        collections::VecDeque,
+        fs,
+        io::{BufWriter, Write},
+        path::{Path, PathBuf},
+        time::{Duration, Instant},
+    },
+};
+
+/// One line of an input script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// `type <text>` or `key <name>`, bytes for the app to read with getc
+    Input(Vec<u8>),
+    /// `wait <frames>`
+    Wait(u32),
+    /// `wait-exit`, wait until the app has exited
+    WaitExit,
+    /// `snapshot <path>`, save the front buffer as png or ppm
+    Snapshot(PathBuf),
+    /// `stdout <path>`, save the app output so far
+    Stdout(PathBuf),
+}
+
+/// Input script for headless runs. One step per line, empty lines and lines starting
+/// with `#` are ignored. `type` supports `\n`, `\r`, `\t`, `\\`, `\e` and `\xNN` escapes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    steps: VecDeque<Step>,
+}
+
+impl Script {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let src = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::parse(&src).map_err(|e| format!("{}:{e}", path.display()))
+    }
+
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut steps = VecDeque::new();
+        for (n, line) in src.lines().enumerate() {
+            let line = line.trim_start();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (cmd, arg) = line.split_once(' ').unwrap_or((line, ""));
+            let step = match cmd {
+                "type" => unescape(arg).map(Step::Input),
+                "key" => key(arg.trim()).map(|k| Step::Input(k.to_vec())),
+                "wait" => arg
+                    .trim()
+                    .parse()
+                    .map(Step::Wait)
+                    .map_err(|_| format!("invalid frame count '{}'", arg.trim())),
+                "wait-exit" => Ok(Step::WaitExit),
+                "snapshot" if !arg.trim().is_empty() => Ok(Step::Snapshot(arg.trim().into())),
+                "stdout" if !arg.trim().is_empty() => Ok(Step::Stdout(arg.trim().into())),
+                "snapshot" | "stdout" => Err(format!("{cmd} requires a path")),
+                _ => Err(format!("unknown command '{cmd}'")),
+            };
+            steps.push_back(step.map_err(|e| format!("{}: {e}", n + 1))?);
+        }
+        Ok(Self { steps })
+    }
+
+    pub fn push(&mut self, step: Step) {
+        self.steps.push_back(step)
+    }
+}
+
+fn key(name: &str) -> Result<&'static [u8], String> {
+    Ok(match name {
+        "enter" => b"\r",
+        "backspace" => b"\x08",
+        "tab" => b"\t",
+        "escape" => b"\x1b",
+        "up" => b"\x1b[A",
+        "down" => b"\x1b[B",
+        "right" => b"\x1b[C",
+        "left" => b"\x1b[D",
+        _ => return Err(format!("unknown key '{name}'")),
+    })
+}
+
+fn unescape(s: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push(b'\n'),
+            Some('r') => out.push(b'\r'),
+            Some('t') => out.push(b'\t'),
+            Some('e') => out.push(0x1b),
+            Some('\\') => out.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let b = u8::from_str_radix(&hex, 16)
+                    .map_err(|_| format!("invalid escape '\\x{hex}'"))?;
+                out.push(b);
+            }
+            Some(c) => return Err(format!("invalid escape '\\{c}'")),
+            None => return Err("trailing '\\'".into()),
+        }
+    }
+    Ok(out)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub script: Script,
+    /// Stop after this many frames
+    pub frames: Option<u32>,
+    /// Save the front buffer here when done
+    pub snapshot: Option<PathBuf>,
+    /// Save the app output here when done
+    pub stdout: Option<PathBuf>,
+}
+
+/// Run `app` without a window, feeding it input from the script. Stops when the app has
+/// exited and the script is done, or after `options.frames`.
+/// Returns the exit code of the app, `None` if there is no app or it was still running.
+pub fn run(app: Option<AppEntryPoint>, options: Options) -> Result<Option<i32>, String> {
+    let Options {
+        script: Script { mut steps },
+        frames,
+        snapshot,
+        stdout,
+    } = options;
+
+    let _vram = Vram::new();
+    let mut cells = console_cells();
+    let mut console = new_console(&mut cells);
+    let mut captured = Vec::new();
+    let mut exit_code = None;
+    let mut wait = 0;
+    let mut wait_exit = false;
+    let mut frame = 0;
+
+    if let Some(app) = app {
+        host::run(app);
+    }
+
+    loop {
+        let sof = Instant::now();
+        let mut gpu = host::GPU.lock().unwrap();
+        let display = gpu.as_mut().unwrap();
+
+        wait_exit &= exit_code.is_none();
+        while wait == 0 && !wait_exit {
+            match steps.pop_front() {
+                Some(Step::Input(bytes)) => host::push_input(&bytes),
+                Some(Step::Wait(frames)) => wait = frames,
+                Some(Step::WaitExit) => wait_exit = app.is_some() && exit_code.is_none(),
+                Some(Step::Snapshot(path)) => write_snapshot(&**display.front_buffer(), &path)?,
+                Some(Step::Stdout(path)) => write_file(&path, &captured)?,
+                None => break,
+            }
+        }
+
+        captured.extend_from_slice(poll_app(&mut console, &mut exit_code).as_bytes());
+        draw_console(&mut console, display);
+        display.swap_buffers();
+        drop(gpu);
+
+        frame += 1;
+        wait = wait.saturating_sub(1);
+        let script_done = steps.is_empty() && wait == 0 && !wait_exit;
+        let app_done = app.is_none() || exit_code.is_some();
+        if (script_done && app_done) || frames.is_some_and(|max| frame >= max) {
+            break;
+        }
+
+        let diff = Instant::now() - sof;
+        if diff < Duration::SECOND / FPS_TARGET {
+            std::thread::sleep((Duration::SECOND / FPS_TARGET) - diff);
+        }
+    }
+
+    // Output written during the last frame
+    {
+        let mut gpu = host::GPU.lock().unwrap();
+        let display = gpu.as_mut().unwrap();
+        captured.extend_from_slice(poll_app(&mut console, &mut exit_code).as_bytes());
+        draw_console(&mut console, display);
+        if let Some(path) = snapshot {
+            display.swap_buffers();
+            write_snapshot(&**display.front_buffer(), &path)?;
+        }
+    }
+    if let Some(path) = stdout {
+        write_file(&path, &captured)?;
+    }
+
+    Ok(exit_code)
+}
+
+fn write_file(path: &Path, data: &[u8]) -> Result<(), String> {
+    fs::write(path, data).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Save RGB565 pixels as RGB888, png or binary ppm depending on the extension
+fn write_snapshot(pixels: &[crate::PixelColor], path: &Path) -> Result<(), String> {
+    let rgb = pixels
+        .iter()
+        .flat_map(|p| {
+            let c = Rgb888::from(*p);
+            [c.r(), c.g(), c.b()]
+        })
+        .collect::<Vec<u8>>();
+
+    let file = fs::File::create(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut w = BufWriter::new(file);
+    let res = match path.extension().and_then(|e| e.to_str()) {
+        Some("png") => {
+            let mut encoder = png::Encoder::new(&mut w, WIDTH as u32, HEIGHT as u32);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder
+                .write_header()
+                .and_then(|mut png| png.write_image_data(&rgb))
+                .map_err(|e| e.to_string())
+        }
+        Some("ppm") => write!(w, "P6\n{WIDTH} {HEIGHT}\n255\n")
+            .and_then(|_| w.write_all(&rgb))
+            .map_err(|e| e.to_string()),
+        _ => Err("snapshot must be .png or .ppm".into()),
+    };
+    res.and_then(|_| w.flush().map_err(|e| e.to_string()))
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, h7_api::H7Api};
+
+    fn steps(src: &str) -> Vec<Step> {
+        Script::parse(src).unwrap().steps.into()
+    }
+
+    #[test]
+    fn parse() {
+        let src = "# comment\n\n  type ls\\r\nkey up\nwait 3\nwait-exit\nsnapshot out.ppm \nstdout  out.txt\n";
+        assert_eq!(
+            steps(src),
+            [
+                Step::Input(b"ls\r".to_vec()),
+                Step::Input(b"\x1b[A".to_vec()),
+                Step::Wait(3),
+                Step::WaitExit,
+                Step::Snapshot("out.ppm".into()),
+                Step::Stdout("out.txt".into()),
+            ]
+        );
+        // Everything after `type ` is sent, also spaces
+        assert_eq!(steps("type  a b "), [Step::Input(b" a b ".to_vec())]);
+        assert_eq!(steps("type"), [Step::Input(Vec::new())]);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            unescape(r"a\n\r\t\\\e\x41\x7fä").unwrap(),
+            b"a\n\r\t\\\x1bA\x7f\xc3\xa4"
+        );
+        assert_eq!(unescape(r"\q").unwrap_err(), r"invalid escape '\q'");
+        assert_eq!(unescape(r"\xg1").unwrap_err(), r"invalid escape '\xg1'");
+        assert_eq!(unescape(r"\x").unwrap_err(), r"invalid escape '\x'");
+        assert_eq!(unescape("a\\").unwrap_err(), "trailing '\\'");
+    }
+
+    #[test]
+    fn keys() {
+        let keys: [(_, &[u8]); 8] = [
+            ("enter", b"\r"),
+            ("backspace", b"\x08"),
+            ("tab", b"\t"),
+            ("escape", b"\x1b"),
+            ("up", b"\x1b[A"),
+            ("down", b"\x1b[B"),
+            ("right", b"\x1b[C"),
+            ("left", b"\x1b[D"),
+        ];
+        for (name, bytes) in keys {
+            assert_eq!(key(name).unwrap(), bytes);
+        }
+        assert_eq!(key("f1").unwrap_err(), "unknown key 'f1'");
+        assert_eq!(key("Enter").unwrap_err(), "unknown key 'Enter'");
+    }
+
+    #[test]
+    fn malformed() {
+        let err = |src| Script::parse(src).unwrap_err();
+        assert_eq!(err("wait 1\nkey f1"), "2: unknown key 'f1'");
+        assert_eq!(err("# wait\nwait x"), "2: invalid frame count 'x'");
+        assert_eq!(err("wait"), "1: invalid frame count ''");
+        assert_eq!(err("wait -1"), "1: invalid frame count '-1'");
+        assert_eq!(err("snapshot  "), "1: snapshot requires a path");
+        assert_eq!(err("stdout"), "1: stdout requires a path");
+        assert_eq!(err("typo hello"), "1: unknown command 'typo'");
+        assert_eq!(err("type \\z"), "1: invalid escape '\\z'");
+    }
+
+    /// Writes a line, draws a square once it gets a `d` and exits with 3 on a `q`
+    extern "C" fn app(api: *const H7Api) -> i32 {
+        let api = unsafe { &*api };
+        let puts = |s: &str| (api.puts)(s.as_ptr(), s.len());
+        puts("Hello, \x1b[32mheadless\x1b[0m\n");
+        while (api.getc)() != b'd' {
+            std::thread::yield_now();
+        }
+        (api.square_fill)(100, 100, 299, 199, 0xf800);
+        (api.square)(120, 120, 279, 179, 4, 0x07ff);
+        puts("Done\n");
+        while (api.getc)() != b'q' {
+            std::thread::yield_now();
+        }
+        3
+    }
+
+    /// Pixels of a binary ppm
+    fn read_ppm(path: &Path) -> Vec<u8> {
+        let data = fs::read(path).unwrap();
+        let header = format!("P6\n{WIDTH} {HEIGHT}\n255\n");
+        assert!(data.starts_with(header.as_bytes()));
+        data[header.len()..].to_vec()
+    }
+
+    fn read_png(path: &Path) -> Vec<u8> {
+        let mut reader = png::Decoder::new(fs::File::open(path).unwrap())
+            .read_info()
+            .unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (WIDTH as u32, HEIGHT as u32));
+        pixels
+    }
+
+    #[test]
+    fn snapshot() {
+        let out = std::env::temp_dir().join(format!("h7-sim-{}", std::process::id()));
+        fs::create_dir_all(&out).unwrap();
+        let drawn = out.join("drawn.ppm");
+        let script = format!(
+            "wait 2\ntype xd\nwait 2\nsnapshot {}\ntype q\nwait-exit\n",
+            drawn.display()
+        );
+        let options = Options {
+            script: Script::parse(&script).unwrap(),
+            frames: Some(600),
+            snapshot: Some(out.join("exited.ppm")),
+            stdout: Some(out.join("stdout.txt")),
+        };
+        assert_eq!(run(Some(app), options), Ok(Some(3)));
+
+        let stdout = fs::read_to_string(out.join("stdout.txt")).unwrap();
+        assert!(stdout.starts_with("Hello, \x1b[32mheadless\x1b[0m\nDone\n"));
+        let expected = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/snapshot.png");
+        // The console stays off the square while the app runs and draws over it after
+        let drawn = read_ppm(&drawn);
+        assert!(drawn == read_png(&expected));
+        let pixel = (150 * WIDTH + 200) * 3;
+        let exited = read_ppm(&out.join("exited.ppm"));
+        assert_ne!(drawn[pixel..pixel + 3], exited[pixel..pixel + 3]);
+        fs::remove_dir_all(&out).unwrap();
+    }
+}
@@ -0,0 +1,554 @@
+use {
+    crate::{Display, PixelColor, FONT, HEIGHT, WIDTH},
+    embedded_graphics::{
+        pixelcolor::raw::RawU16,
+        prelude::*,
+        primitives::{Line, PrimitiveStyle, Rectangle},
+    },
+    h7_api::{
+        AppEntryPoint, Capability, DirEntry, FileStat, FsError, H7Api, OpenMode, Whence,
+        API_VERSION,
+    },
+    std::{
+        alloc::Layout,
+        collections::{BTreeMap, VecDeque},
+        convert::Infallible,
+        fs::{self, File},
+        io::{self, Read, Seek, SeekFrom, Write},
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        },
+    },
+};
+
+pub static API: H7Api = H7Api {
+    size: core::mem::size_of::<H7Api>(),
+    version: API_VERSION,
+    capabilities: Capability::Alloc.bit()
+        | Capability::Io.bit()
+        | Capability::Gpu.bit()
+        | Capability::Fs.bit(),
+    // Sys, Mem
+    alloc,
+    free,
+    panic,
+    // IO
+    getc,
+    putc,
+    puts,
+    // GPU
+    screen_width_px,
+    screen_height_px,
+    screen_width_char,
+    screen_height_char,
+    dot,
+    line,
+    square,
+    square_fill,
+    // FS, files in a host directory, see `set_fs_root`
+    open,
+    read,
+    write,
+    seek,
+    close,
+    read_dir,
+    stat,
+};
+
+pub static GPU: Mutex<Option<Display>> = Mutex::new(None);
+
+// Keyboard input waiting to be read by the app
+static INPUT: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+// App output waiting to be written to the console
+static OUTPUT: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+static EXIT: Mutex<Option<AppExit>> = Mutex::new(None);
+// Set when the app draws, the console stays off the display until the app exits
+static APP_OWNS_DISPLAY: AtomicBool = AtomicBool::new(false);
+
+/// Files the app can have open at once, same as the firmware
+const MAX_OPEN_FILES: usize = 8;
+
+struct HostFile {
+    file: File,
+    mode: OpenMode,
+}
+
+static FS_ROOT: Mutex<Option<PathBuf>> = Mutex::new(None);
+// Files opened by the app, the handle is the index
+static FILES: Mutex<[Option<HostFile>; MAX_OPEN_FILES]> =
+    Mutex::new([const { None }; MAX_OPEN_FILES]);
+
+// Keep track of app allocations so that we can free leaked application memory
+static APP_ALLOCATIONS: Mutex<BTreeMap<usize, Layout>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppExit {
+    Exited(i32),
+    Panicked(String),
+}
+
+impl AppExit {
+    pub fn code(&self) -> i32 {
+        match self {
+            Self::Exited(code) => *code,
+            Self::Panicked(_) => -1,
+        }
+    }
+}
+
+impl core::fmt::Display for AppExit {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Exited(0) => write!(f, "Exited with code 0 (ok)"),
+            Self::Exited(code) if *code == h7_api::EXIT_INCOMPATIBLE_API => {
+                write!(f, "Exited with code {code} (incompatible api)")
+            }
+            Self::Exited(code) => write!(f, "Exited with code {code} (error)"),
+            Self::Panicked(msg) => write!(f, "App panicked: {msg}"),
+        }
+    }
+}
+
+/// Start the app on its own thread, see [`take_exit`]
+pub fn run(entry: AppEntryPoint) -> std::thread::JoinHandle<()> {
+    std::thread::Builder::new()
+        .name("h7-app".into())
+        .spawn(move || {
+            let code = entry(&API);
+            set_exit(AppExit::Exited(code));
+        })
+        .expect("Failed to spawn app thread")
+}
+
+/// How the app ended, `None` while it's still running. The display goes back to the console
+/// once the app has ended.
+pub fn take_exit() -> Option<AppExit> {
+    let exit = EXIT.lock().unwrap().take();
+    if exit.is_some() {
+        APP_OWNS_DISPLAY.store(false, Ordering::Relaxed);
+    }
+    exit
+}
+
+/// Whether the app has drawn on the display and the console shouldn't draw over it
+pub fn app_owns_display() -> bool {
+    APP_OWNS_DISPLAY.load(Ordering::Relaxed)
+}
+
+fn set_exit(exit: AppExit) {
+    EXIT.lock().unwrap().replace(exit);
+}
+
+pub fn push_input(bytes: &[u8]) {
+    INPUT.lock().unwrap().extend(bytes);
+}
+
+/// App output since the last call. An incomplete utf-8 sequence at the end is kept for
+/// the next call.
+pub fn take_output() -> String {
+    let mut output = OUTPUT.lock().unwrap();
+    let valid = match std::str::from_utf8(&output) {
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        _ => output.len(),
+    };
+    let rest = output.split_off(valid);
+    let text = String::from_utf8_lossy(&output).into_owned();
+    *output = rest;
+    text
+}
+
+pub fn free_leaked() -> usize {
+    let mut leaked = 0;
+    let mut allocations = APP_ALLOCATIONS.lock().unwrap();
+    for (ptr, layout) in allocations.iter().map(|(k, v)| ((*k) as *mut u8, *v)) {
+        leaked += layout.size();
+        unsafe { std::alloc::dealloc(ptr, layout) };
+    }
+    allocations.clear();
+    leaked
+}
+
+extern "C" fn alloc(size: usize, align: usize) -> *mut u8 {
+    match Layout::from_size_align(size, align) {
+        Ok(layout) if layout.size() > 0 => {
+            let ptr = unsafe { std::alloc::alloc(layout) };
+            if !ptr.is_null() {
+                APP_ALLOCATIONS.lock().unwrap().insert(ptr as usize, layout);
+            }
+            ptr
+        }
+        _ => core::ptr::null_mut(),
+    }
+}
+
+extern "C" fn free(ptr: *mut u8) {
+    if let Some(layout) = APP_ALLOCATIONS.lock().unwrap().remove(&(ptr as usize)) {
+        unsafe { std::alloc::dealloc(ptr, layout) }
+    }
+}
+
+extern "C" fn panic(start: *const u8, len: usize) -> ! {
+    let s = unsafe { core::slice::from_raw_parts(start, len) };
+    set_exit(AppExit::Panicked(String::from_utf8_lossy(s).into_owned()));
+    // Unwinding out of an extern "C" fn aborts the whole sim, park the app thread instead
+    loop {
+        std::thread::park();
+    }
+}
+
+// IO
+
+extern "C" fn getc() -> u8 {
+    INPUT.lock().unwrap().pop_front().unwrap_or(0)
+}
+
+extern "C" fn putc(c: u8) -> i32 {
+    write_output(&[c])
+}
+
+extern "C" fn puts(start: *const u8, len: usize) -> i32 {
+    let s = unsafe { core::slice::from_raw_parts(start, len) };
+    match core::str::from_utf8(s) {
+        Ok(s) => write_output(s.as_bytes()),
+        _ => -1,
+    }
+}
+
+fn write_output(bytes: &[u8]) -> i32 {
+    OUTPUT.lock().unwrap().extend_from_slice(bytes);
+    // Mirror to stdout to make debugging easier
+    let mut stdout = std::io::stdout();
+    match stdout.write_all(bytes).and_then(|_| stdout.flush()) {
+        Ok(_) => 0,
+        _ => -1,
+    }
+}
+
+// GPU
+
+/// Run `f` on the display, 0 on success, -1 if the display is not initialized
+fn draw(f: impl FnOnce(&mut Display) -> Result<(), Infallible>) -> i32 {
+    match GPU.lock().unwrap().as_mut() {
+        Some(display) => {
+            APP_OWNS_DISPLAY.store(true, Ordering::Relaxed);
+            f(display).map(|_| 0).unwrap_or(-1)
+        }
+        None => -1,
+    }
+}
+
+fn point(x: u32, y: u32) -> Point {
+    Point::new(x as i32, y as i32)
+}
+
+fn color(raw: u16) -> PixelColor {
+    PixelColor::from(RawU16::new(raw))
+}
+
+extern "C" fn screen_width_px() -> u32 {
+    WIDTH as u32
+}
+
+extern "C" fn screen_height_px() -> u32 {
+    HEIGHT as u32
+}
+
+extern "C" fn screen_width_char() -> u32 {
+    WIDTH as u32 / FONT.character_size.width
+}
+
+extern "C" fn screen_height_char() -> u32 {
+    HEIGHT as u32 / FONT.character_size.height
+}
+
+extern "C" fn dot(x1: u32, y1: u32, c: u16) -> i32 {
+    draw(|d| embedded_graphics::Pixel(point(x1, y1), color(c)).draw(d))
+}
+
+extern "C" fn line(x1: u32, y1: u32, x2: u32, y2: u32, stroke: u32, c: u16) -> i32 {
+    draw(|d| {
+        Line::new(point(x1, y1), point(x2, y2))
+            .into_styled(PrimitiveStyle::with_stroke(color(c), stroke))
+            .draw(d)
+    })
+}
+
+extern "C" fn square(x1: u32, y1: u32, x2: u32, y2: u32, stroke: u32, c: u16) -> i32 {
+    draw(|d| {
+        Rectangle::with_corners(point(x1, y1), point(x2, y2))
+            .into_styled(PrimitiveStyle::with_stroke(color(c), stroke))
+            .draw(d)
+    })
+}
+
+extern "C" fn square_fill(x1: u32, y1: u32, x2: u32, y2: u32, c: u16) -> i32 {
+    draw(|d| {
+        d.fill_solid(
+            &Rectangle::with_corners(point(x1, y1), point(x2, y2)),
+            color(c),
+        )
+    })
+}
+
+// FS
+
+/// Directory the devices are in, `sdcard:/a.txt` is `<dir>/sdcard/a.txt`. The current directory
+/// if not set.
+pub fn set_fs_root(dir: PathBuf) {
+    FS_ROOT.lock().unwrap().replace(dir);
+}
+
+fn fs_error(err: io::Error) -> FsError {
+    match err.kind() {
+        io::ErrorKind::NotFound => FsError::NotFound,
+        io::ErrorKind::AlreadyExists => FsError::AlreadyExists,
+        io::ErrorKind::IsADirectory => FsError::IsDirectory,
+        io::ErrorKind::StorageFull => FsError::NoSpace,
+        _ => FsError::Io,
+    }
+}
+
+/// Return value of a file function, the result or a negative [`FsError`]
+fn fs_result(result: Result<i32, FsError>) -> i32 {
+    result.unwrap_or_else(FsError::code)
+}
+
+/// Host path of a `device:/path` from the app, `..` can't leave the device
+fn host_path(start: *const u8, len: usize) -> Result<PathBuf, FsError> {
+    let s = unsafe { core::slice::from_raw_parts(start, len) };
+    let s = core::str::from_utf8(s).map_err(|_| FsError::InvalidPath)?;
+    let (device, path) = s.split_once(':').ok_or(FsError::InvalidPath)?;
+    if device.is_empty() || device.contains(['/', '\\']) || device.starts_with('.') {
+        return Err(FsError::InvalidPath);
+    }
+    let root = FS_ROOT.lock().unwrap().clone().unwrap_or_default();
+    let mut host_path = root.join(device);
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => return Err(FsError::InvalidPath),
+            part => host_path.push(part),
+        }
+    }
+    Ok(host_path)
+}
+
+/// Run `func` on the open file `handle`
+fn with_file(
+    handle: i32,
+    func: impl FnOnce(&mut HostFile) -> Result<i32, FsError>,
+) -> Result<i32, FsError> {
+    let mut files = FILES.lock().unwrap();
+    let file = usize::try_from(handle)
+        .ok()
+        .and_then(|i| files.get_mut(i))
+        .and_then(Option::as_mut)
+        .ok_or(FsError::BadHandle)?;
+    func(file)
+}
+
+extern "C" fn open(path: *const u8, path_len: usize, mode: u32) -> i32 {
+    let Some(mode) = OpenMode::from_u32(mode) else {
+        return FsError::Unsupported.code();
+    };
+    fs_result(host_path(path, path_len).and_then(|path| {
+        let mut files = FILES.lock().unwrap();
+        // Find a free handle first, opening for writing truncates the file
+        let (handle, slot) = files
+            .iter_mut()
+            .enumerate()
+            .find(|(_, file)| file.is_none())
+            .ok_or(FsError::TooManyOpenFiles)?;
+        if path.is_dir() {
+            return Err(FsError::IsDirectory);
+        }
+        let mut file = match mode {
+            OpenMode::Read => File::open(&path),
+            OpenMode::Write => File::create(&path),
+            // Not `append`, the app can seek back and write over what's there
+            OpenMode::Append => File::options()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path),
+        }
+        .map_err(fs_error)?;
+        if mode == OpenMode::Append {
+            file.seek(SeekFrom::End(0)).map_err(fs_error)?;
+        }
+        slot.replace(HostFile { file, mode });
+        Ok(handle as i32)
+    }))
+}
+
+extern "C" fn read(handle: i32, buf: *mut u8, len: usize) -> i32 {
+    let buf = unsafe { core::slice::from_raw_parts_mut(buf, len.min(i32::MAX as usize)) };
+    fs_result(with_file(handle, |file| match file.mode {
+        OpenMode::Read => Ok(file.file.read(buf).map_err(fs_error)? as i32),
+        _ => Err(FsError::WrongMode),
+    }))
+}
+
+extern "C" fn write(handle: i32, buf: *const u8, len: usize) -> i32 {
+    let buf = unsafe { core::slice::from_raw_parts(buf, len.min(i32::MAX as usize)) };
+    fs_result(with_file(handle, |file| match file.mode {
+        OpenMode::Read => Err(FsError::WrongMode),
+        _ => {
+            file.file.write_all(buf).map_err(fs_error)?;
+            Ok(buf.len() as i32)
+        }
+    }))
+}
+
+extern "C" fn seek(handle: i32, offset: i32, whence: u32) -> i32 {
+    fs_result(with_file(handle, |file| {
+        let size = file.file.metadata().map_err(fs_error)?.len();
+        let base = match Whence::from_u32(whence) {
+            Some(Whence::Start) => 0,
+            Some(Whence::Current) => file.file.stream_position().map_err(fs_error)?,
+            Some(Whence::End) => size,
+            None => return Err(FsError::Unsupported),
+        };
+        // Not past the end, like on the device
+        let offset = base
+            .checked_add_signed(offset as i64)
+            .filter(|offset| *offset <= size)
+            .ok_or(FsError::InvalidOffset)?;
+        file.file.seek(SeekFrom::Start(offset)).map_err(fs_error)?;
+        i32::try_from(offset).map_err(|_| FsError::InvalidOffset)
+    }))
+}
+
+extern "C" fn close(handle: i32) -> i32 {
+    let file = usize::try_from(handle)
+        .ok()
+        .and_then(|i| FILES.lock().unwrap().get_mut(i).and_then(Option::take));
+    match file {
+        Some(file) => fs_result(file.file.sync_all().map(|_| 0).map_err(fs_error)),
+        None => FsError::BadHandle.code(),
+    }
+}
+
+/// Close the files the app left open, returns how many there were
+pub fn close_leaked() -> usize {
+    let mut files = FILES.lock().unwrap();
+    files.iter_mut().filter_map(Option::take).count()
+}
+
+fn file_stat(metadata: &fs::Metadata) -> FileStat {
+    FileStat {
+        size: metadata.len().min(u32::MAX as u64) as u32,
+        is_dir: metadata.is_dir(),
+    }
+}
+
+extern "C" fn read_dir(path: *const u8, path_len: usize, index: u32, entry: *mut DirEntry) -> i32 {
+    let entry = unsafe { entry.as_mut() };
+    fs_result(host_path(path, path_len).and_then(|path| {
+        // Sorted, the host doesn't promise an order between calls
+        let mut entries = fs::read_dir(path)
+            .map_err(fs_error)?
+            .filter_map(Result::ok)
+            .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|e| e.file_name());
+        match entries.get(index as usize) {
+            Some(e) => {
+                if let Some(entry) = entry {
+                    entry.stat = file_stat(&e.metadata().map_err(fs_error)?);
+                    entry.set_name(&e.file_name().to_string_lossy());
+                }
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }))
+}
+
+extern "C" fn stat(path: *const u8, path_len: usize, stat: *mut FileStat) -> i32 {
+    let stat = unsafe { stat.as_mut() };
+    fs_result(host_path(path, path_len).and_then(|path| {
+        let metadata = fs::metadata(path).map_err(fs_error)?;
+        if let Some(stat) = stat {
+            *stat = file_stat(&metadata);
+        }
+        Ok(0)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(func: extern "C" fn(*const u8, usize, u32) -> i32, path: &str, arg: u32) -> i32 {
+        func(path.as_ptr(), path.len(), arg)
+    }
+
+    #[test]
+    fn files() {
+        let root = std::env::temp_dir().join(format!("h7-sim-fs-{}", std::process::id()));
+        fs::create_dir_all(root.join("ram/dir")).unwrap();
+        set_fs_root(root.clone());
+
+        let file = call(open, "ram:/a.txt", OpenMode::Write as u32);
+        assert!(file >= 0);
+        assert_eq!(write(file, b"hello".as_ptr(), 5), 5);
+        assert_eq!(seek(file, 1, Whence::Start as u32), 1);
+        assert_eq!(write(file, b"a".as_ptr(), 1), 1);
+        assert_eq!(
+            read(file, [0u8; 1].as_mut_ptr(), 1),
+            FsError::WrongMode.code()
+        );
+        assert_eq!(
+            seek(file, 1, Whence::End as u32),
+            FsError::InvalidOffset.code()
+        );
+        assert_eq!(close(file), 0);
+        assert_eq!(close(file), FsError::BadHandle.code());
+
+        let file = call(open, "ram:/dir/../a.txt", OpenMode::Append as u32);
+        assert_eq!(file, FsError::InvalidPath.code());
+        let file = call(open, "ram:a.txt", OpenMode::Append as u32);
+        assert_eq!(write(file, b"!".as_ptr(), 1), 1);
+        assert_eq!(close(file), 0);
+        assert_eq!(fs::read(root.join("ram/a.txt")).unwrap(), b"hallo!");
+
+        let file = call(open, "ram:/a.txt", OpenMode::Read as u32);
+        let mut buf = [0u8; 16];
+        assert_eq!(seek(file, -3, Whence::End as u32), 3);
+        assert_eq!(read(file, buf.as_mut_ptr(), buf.len()), 3);
+        assert_eq!(&buf[..3], b"lo!");
+        assert_eq!(read(file, buf.as_mut_ptr(), buf.len()), 0);
+        assert_eq!(close_leaked(), 1);
+
+        let mut entry = DirEntry::new();
+        let path = "ram:/";
+        assert_eq!(read_dir(path.as_ptr(), path.len(), 0, &mut entry), 1);
+        assert_eq!((entry.name(), entry.stat.size), ("a.txt", 6));
+        assert_eq!(read_dir(path.as_ptr(), path.len(), 1, &mut entry), 1);
+        assert_eq!((entry.name(), entry.stat.is_dir), ("dir", true));
+        assert_eq!(read_dir(path.as_ptr(), path.len(), 2, &mut entry), 0);
+
+        let mut file_stat = FileStat::default();
+        let path = "ram:/dir";
+        assert_eq!(stat(path.as_ptr(), path.len(), &mut file_stat), 0);
+        assert!(file_stat.is_dir);
+        assert_eq!(
+            call(open, path, OpenMode::Read as u32),
+            FsError::IsDirectory.code()
+        );
+        let path = "ram:/missing";
+        assert_eq!(
+            stat(path.as_ptr(), path.len(), &mut file_stat),
+            FsError::NotFound.code()
+        );
+        assert_eq!(
+            call(open, "a.txt", OpenMode::Read as u32),
+            FsError::InvalidPath.code()
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+#![feature(
+    generic_const_exprs,
+    const_trait_impl,
+    duration_constants,
+    const_mut_refs
+)]
+
+use {
+    core::fmt::Write,
+    embedded_graphics::{mono_font::MonoFont, pixelcolor::Rgb565, prelude::*},
+    h7_display::{Cell, FrameBuffer, H7Display, TextConsole},
+    std::{
+        alloc::{alloc, dealloc, Layout},
+        mem::{align_of, size_of},
+    },
+};
+
+pub mod headless;
+mod host;
+#[cfg(feature = "sdl")]
+mod window;
+
+pub use host::{set_fs_root, AppExit};
+#[cfg(feature = "sdl")]
+pub use window::run;
+
+const FPS_TARGET: u32 = 60;
+const WIDTH: usize = 1024;
+const HEIGHT: usize = 768;
+type PixelColor = Rgb565;
+type Display = H7Display<'static, PixelColor, WIDTH, HEIGHT>;
+type Console<'b> = TextConsole<'b, 'static, PixelColor>;
+
+/// Console font, same as the firmware
+const FONT: MonoFont = embedded_graphics::mono_font::ascii::FONT_8X13;
+/// Lines kept by the console, screen and scrollback
+const CONSOLE_LINES: usize = 1000;
+
+/// Framebuffer memory. The display is handed to the app through `host::GPU` while this is alive.
+struct Vram {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+impl Vram {
+    fn new() -> Self {
+        let (ptr, layout, front_buffer, back_buffer) = unsafe {
+            const FBSZ: usize = size_of::<FrameBuffer<PixelColor, WIDTH, HEIGHT>>();
+            const FBAL: usize = align_of::<FrameBuffer<PixelColor, WIDTH, HEIGHT>>();
+            // This assertion makes sure that consecutive framebuffers will be properly aligned.
+            assert_eq!(FBSZ % FBAL, 0);
+            let layout = Layout::from_size_align(FBSZ * 2, FBAL).unwrap();
+            let vram_ptr = alloc(layout);
+            let front_buffer = &mut *(vram_ptr as *mut _);
+            let back_buffer = &mut *(vram_ptr.add(FBSZ) as *mut _);
+            (vram_ptr, layout, front_buffer, back_buffer)
+        };
+        println!("vram_layout: {layout:?}");
+        host::GPU
+            .lock()
+            .unwrap()
+            .replace(Display::new(front_buffer, back_buffer));
+        Self { ptr, layout }
+    }
+}
+
+impl Drop for Vram {
+    fn drop(&mut self) {
+        // The app may still be running, take the display away from it before freeing vram
+        host::GPU.lock().unwrap().take();
+        unsafe { dealloc(self.ptr, self.layout) };
+    }
+}
+
+fn console_cells() -> Vec<Cell> {
+    vec![Cell::BLANK; WIDTH / FONT.character_size.width as usize * CONSOLE_LINES]
+}
+
+fn new_console(cells: &mut [Cell]) -> Console<'_> {
+    TextConsole::new(&FONT, cells, Size::new(WIDTH as u32, HEIGHT as u32))
+}
+
+/// Write app output to the console and report when the app exits. Returns everything
+/// written to the console.
+fn poll_app(console: &mut Console, exit_code: &mut Option<i32>) -> String {
+    let mut output = host::take_output();
+    let _ = console.write_str(&output);
+
+    if let Some(exit) = host::take_exit() {
+        // Draw over what the app left on the display
+        console.redraw();
+        let leaked = host::free_leaked();
+        let msg = match host::close_leaked() {
+            0 => format!("\n{exit}, {leaked} bytes leaked\n"),
+            files => format!("\n{exit}, {leaked} bytes leaked, {files} files left open\n"),
+        };
+        print!("{msg}");
+        let _ = console.write_str(&msg);
+        output.push_str(&msg);
+        exit_code.replace(exit.code());
+    }
+
+    output
+}
+
+/// Draw the console, unless the app has drawn on the display
+fn draw_console(console: &mut Console, display: &mut Display) {
+    if !host::app_owns_display() {
+        console.draw(display).unwrap();
+    }
+}
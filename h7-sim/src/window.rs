@@ -0,0 +1,164 @@
+use {
+    crate::{
+        console_cells, draw_console, host, new_console, poll_app, Vram, FPS_TARGET, HEIGHT, WIDTH,
+    },
+    core::fmt::Write,
+    h7_api::AppEntryPoint,
+    sdl2::{event::Event, keyboard::Keycode, pixels::PixelFormatEnum},
+    std::{
+        mem::size_of,
+        time::{Duration, Instant},
+    },
+};
+
+const PROMPT: &str = "[root@h7] ";
+
+/// Bytes sent to the app for keys that don't produce text
+fn key_bytes(keycode: Keycode) -> Option<&'static [u8]> {
+    Some(match keycode {
+        Keycode::Return | Keycode::KpEnter => b"\r",
+        Keycode::Backspace => b"\x08",
+        Keycode::Tab => b"\t",
+        Keycode::Up => b"\x1b[A",
+        Keycode::Down => b"\x1b[B",
+        Keycode::Right => b"\x1b[C",
+        Keycode::Left => b"\x1b[D",
+        _ => return None,
+    })
+}
+
+/// Open the simulator window and run `app` on its own thread until the window is closed.
+/// Returns the exit code of the app, `None` if there is no app or it was still running.
+pub fn run(app: Option<AppEntryPoint>) -> Result<Option<i32>, String> {
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+
+    let window = video_subsystem
+        .window(env!("CARGO_PKG_NAME"), WIDTH as u32, HEIGHT as u32)
+        .position_centered()
+        .vulkan()
+        // .opengl()
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let textute_creator = canvas.texture_creator();
+    let mut texture = textute_creator
+        .create_texture_streaming(PixelFormatEnum::RGB565, WIDTH as u32, HEIGHT as u32)
+        .unwrap();
+
+    canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
+    canvas.clear();
+    canvas.present();
+    let mut event_pump = sdl_context.event_pump()?;
+
+    let _vram = Vram::new();
+    let mut cells = console_cells();
+    let mut console = new_console(&mut cells);
+    let mut app_running = false;
+    let mut exit_code = None;
+    // Local echo while there is no app
+    let mut line = String::new();
+    video_subsystem.text_input().start();
+    if let Some(app) = app {
+        host::run(app);
+        app_running = true;
+    } else {
+        let _ = write!(console, "{PROMPT}");
+    }
+
+    'running: loop {
+        let sof = Instant::now();
+        let mut gpu = host::GPU.lock().unwrap();
+        let display = gpu.as_mut().unwrap();
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(Keycode::PageUp),
+                    ..
+                } => console.scroll_view(console.rows() as isize / 2),
+                Event::KeyDown {
+                    keycode: Some(Keycode::PageDown),
+                    ..
+                } => console.scroll_view(-(console.rows() as isize) / 2),
+                // Keyboard input goes to the app while it's running
+                Event::TextInput { text, .. } if app_running => host::push_input(text.as_bytes()),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } if app_running => {
+                    if let Some(bytes) = key_bytes(keycode) {
+                        host::push_input(bytes)
+                    }
+                }
+                Event::TextInput { text, .. } => {
+                    line.push_str(&text);
+                    let _ = write!(console, "{text}");
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Return | Keycode::KpEnter),
+                    ..
+                } => {
+                    line.clear();
+                    let _ = write!(console, "\n{PROMPT}");
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } if line.pop().is_some() => {
+                    let _ = write!(console, "\x08 \x08");
+                }
+                _ => {}
+            }
+        }
+
+        poll_app(&mut console, &mut exit_code);
+        app_running &= exit_code.is_none();
+        draw_console(&mut console, display);
+
+        // Copy our front buffer to the SDL texture and commit
+        // some unsafe crimes while we're at it.
+        let front = display.front_buffer();
+
+        texture
+            .with_lock(None, |buffer, _| {
+                buffer.copy_from_slice(unsafe {
+                    core::slice::from_raw_parts(
+                        front.as_ptr() as *const u8,
+                        front.len() * size_of::<crate::PixelColor>(),
+                    )
+                });
+            })
+            .unwrap();
+
+        // Copy SDL texture to canvas
+        canvas.copy(&texture, None, None).unwrap();
+
+        // Swap sdl2 buffers
+        canvas.present();
+
+        // Swap our own buffer
+        display.swap_buffers();
+        // Let the app draw while we sleep
+        drop(gpu);
+
+        let diff = Instant::now() - sof;
+        // let fps = 1_000_000f64 / diff.as_micros() as f64;
+        // eprintln!("FT: {:.02}ms, FPS: {fps:.02}", diff.as_secs_f64() * 1000.0);
+
+        if diff < Duration::SECOND / FPS_TARGET {
+            std::thread::sleep((Duration::SECOND / FPS_TARGET) - diff);
+        }
+
+        // let diff = Instant::now() - sof;
+        // let fps = 1_000_000f64 / diff.as_micros() as f64;
+        // eprintln!("FT: {:.02}ms, FPS: {fps:.02}", diff.as_secs_f64() * 1000.0);
+    }
+
+    Ok(exit_code)
+}
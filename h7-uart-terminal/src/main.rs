@@ -1,62 +1,86 @@
-#[cfg(all(feature = "rpi", not(feature = "linux")))]
-use rppal::uart::{Parity, Uart};
+mod port;
+mod raw;
+mod send;
+mod terminal;
 
-#[cfg(all(feature = "linux", not(feature = "rpi")))]
-use std::io::ErrorKind;
+use {
+    port::{Config, Port},
+    send::{send, SEND_TIMEOUT},
+    std::io::{Read, Write},
+    terminal::Terminal,
+};
 
-use std::io::{Read, Write};
+const BAUD: u32 = 115_200;
+/// Serial device if none is given
+const PORT_VAR: &str = "H7_PORT";
+
+const USAGE: &str = "Usage:
+    h7-uart-terminal [-b <baud>] [-l <log file>] [port]
+    h7-uart-terminal [-b <baud>] send <file> [destination]
+
+The port defaults to $H7_PORT.";
 
 fn main() {
-    #[cfg(all(feature = "rpi", not(feature = "linux")))]
-    let mut uart = Uart::new(115_200, Parity::None, 8, 1).unwrap();
-    #[cfg(all(feature = "linux", not(feature = "rpi")))]
-    let mut uart = {
-        let port = std::env::args().nth(1).expect("Expected serial device");
-        serialport::new(port, 115_200).open_native().unwrap()
+    let mut baud = BAUD;
+    let mut log = None;
+    let mut args = Vec::new();
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-b" | "--baud" => match iter.next().and_then(|b| b.parse().ok()) {
+                Some(b) => baud = b,
+                None => usage(),
+            },
+            "-l" | "--log" => match iter.next() {
+                Some(path) => log = Some(path),
+                None => usage(),
+            },
+            "-h" | "--help" => usage(),
+            _ => args.push(arg),
+        }
+    }
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let config = |path: Option<&str>| Config {
+        path: path
+            .map(String::from)
+            .or_else(|| std::env::var(PORT_VAR).ok()),
+        baud,
     };
 
-    let (tx, rx) = std::sync::mpsc::channel::<String>();
+    let res = match args.as_slice() {
+        ["send", file] => send_file(config(None), file, None),
+        ["send", file, to] => send_file(config(None), file, Some(to)),
+        ["send", ..] => usage(),
+        [port] => run_terminal(config(Some(port)), log.as_deref()),
+        [] => run_terminal(config(None), log.as_deref()),
+        _ => usage(),
+    };
+    if let Err(e) = res {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+}
 
-    std::thread::spawn(move || loop {
-        let mut s = String::new();
-        match std::io::stdin().read_line(&mut s) {
-            Ok(_) => tx.send(s).unwrap(),
-            Err(e) => eprintln!("Error: {:#?}", e),
-        }
-    });
-
-    loop {
-        let mut buf = [0u8; 256];
-        match uart.read(&mut buf) {
-            Ok(0) => {}
-            Ok(len) => {
-                // println!("Data: {:?}", &buf[0..len]);
-                match core::str::from_utf8(&buf[0..len]) {
-                    Ok(s) => {
-                        print!("{}", s);
-                        std::io::stdout().flush().unwrap();
-                    }
-                    Err(e) => eprintln!("Error: {:#?}", e),
-                }
-            }
-            #[cfg(all(feature = "linux", not(feature = "rpi")))]
-            Err(e) if e.kind() == ErrorKind::TimedOut => {}
-            #[cfg(all(feature = "linux", not(feature = "rpi")))]
-            Err(e) if e.kind() == ErrorKind::BrokenPipe => {
-                eprintln!("Broken pipe");
-                break;
-            }
-            Err(e) => eprintln!("Error: {:#?}", e),
-        }
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    std::process::exit(1);
+}
 
-        match rx.try_recv() {
-            Ok(s) => {
-                let _ = uart.write(s.as_bytes()).unwrap();
-            }
-            Err(std::sync::mpsc::TryRecvError::Empty) => {}
-            Err(e) => eprintln!("Error: {:#?}", e),
-        }
+fn run_terminal(config: Config, log: Option<&str>) -> Result<(), String> {
+    Terminal::new(config, log)
+        .and_then(|mut terminal| terminal.run())
+        .map_err(|e| e.to_string())
+}
+
+fn send_file(config: Config, file: &str, to: Option<&str>) -> Result<(), String> {
+    let mut port = Port::open(&config, SEND_TIMEOUT).map_err(|e| e.to_string())?;
+    let res = send(&mut port, file, to);
 
-        std::thread::sleep(std::time::Duration::from_millis(10));
+    // What the board says about it, until it goes quiet
+    let mut buf = [0u8; 256];
+    while let Ok(len @ 1..) = port.read(&mut buf) {
+        print!("{}", String::from_utf8_lossy(&buf[..len]));
     }
+    let _ = std::io::stdout().flush();
+    res.map_err(|e| e.to_string())
 }
@@ -0,0 +1,102 @@
+//! The serial port, a device or pseudo-terminal on Linux or the Pi's UART with `rpi`
+
+#[cfg(all(feature = "rpi", not(feature = "linux")))]
+use rppal::uart::{Parity, Uart};
+#[cfg(all(feature = "linux", not(feature = "rpi")))]
+use serialport::SerialPort;
+
+use std::{
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+pub struct Config {
+    /// Device path, the Pi's primary UART if `None` with `rpi`
+    pub path: Option<String>,
+    pub baud: u32,
+}
+
+impl Config {
+    pub fn name(&self) -> &str {
+        self.path.as_deref().unwrap_or("UART")
+    }
+}
+
+#[cfg(all(feature = "linux", not(feature = "rpi")))]
+pub struct Port(serialport::TTYPort);
+
+#[cfg(all(feature = "rpi", not(feature = "linux")))]
+pub struct Port(Uart);
+
+impl Port {
+    /// Open the port, reads time out after `timeout`
+    #[cfg(all(feature = "linux", not(feature = "rpi")))]
+    pub fn open(config: &Config, timeout: Duration) -> io::Result<Self> {
+        let path = config
+            .path
+            .as_deref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Expected serial device"))?;
+        let port = serialport::new(path, config.baud)
+            .timeout(timeout)
+            .open_native()?;
+        Ok(Self(port))
+    }
+
+    #[cfg(all(feature = "rpi", not(feature = "linux")))]
+    pub fn open(config: &Config, timeout: Duration) -> io::Result<Self> {
+        let mut uart = match &config.path {
+            Some(path) => Uart::with_path(path, config.baud, Parity::None, 8, 1),
+            None => Uart::new(config.baud, Parity::None, 8, 1),
+        }
+        .map_err(io::Error::other)?;
+        uart.set_write_mode(true).map_err(io::Error::other)?;
+        let mut port = Self(uart);
+        port.set_timeout(timeout)?;
+        Ok(port)
+    }
+
+    #[cfg(all(feature = "linux", not(feature = "rpi")))]
+    pub fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        Ok(self.0.set_timeout(timeout)?)
+    }
+
+    #[cfg(all(feature = "rpi", not(feature = "linux")))]
+    pub fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.0.set_read_mode(0, timeout).map_err(io::Error::other)
+    }
+}
+
+/// A timeout is `Ok(0)` or an error of kind `TimedOut`
+impl Read for Port {
+    #[cfg(all(feature = "linux", not(feature = "rpi")))]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+
+    #[cfg(all(feature = "rpi", not(feature = "linux")))]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(io::Error::other)
+    }
+}
+
+impl Write for Port {
+    #[cfg(all(feature = "linux", not(feature = "rpi")))]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    #[cfg(all(feature = "rpi", not(feature = "linux")))]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf).map_err(io::Error::other)
+    }
+
+    #[cfg(all(feature = "linux", not(feature = "rpi")))]
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+
+    #[cfg(all(feature = "rpi", not(feature = "linux")))]
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.drain().map_err(io::Error::other)
+    }
+}
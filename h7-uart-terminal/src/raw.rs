@@ -0,0 +1,33 @@
+//! Raw mode for the local terminal, every key goes to the board as is
+
+use std::mem::MaybeUninit;
+
+/// Restores the terminal settings when dropped
+pub struct RawMode(libc::termios);
+
+impl RawMode {
+    /// Put stdin in raw mode, `None` if it isn't a terminal
+    pub fn enable() -> Option<Self> {
+        unsafe {
+            let mut termios = MaybeUninit::uninit();
+            if libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) != 0 {
+                return None;
+            }
+            let saved = termios.assume_init();
+            let mut raw = saved;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+            Some(Self(saved))
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0);
+        }
+    }
+}
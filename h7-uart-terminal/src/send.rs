@@ -0,0 +1,63 @@
+use {
+    crate::port::Port,
+    h7_xfer::{IoLink, XferError},
+    std::{
+        fmt,
+        io::{self, Write},
+        path::Path,
+        time::Duration,
+    },
+};
+
+/// Read timeout while sending, the firmware waits as long
+pub const SEND_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Debug)]
+pub enum SendError {
+    Io(io::Error),
+    Xfer(XferError),
+}
+
+impl From<io::Error> for SendError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<XferError> for SendError {
+    fn from(e: XferError) -> Self {
+        Self::Xfer(e)
+    }
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Xfer(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Start `upload` on the board and send `file` to it, `to` is a file or directory on the board,
+/// without it the file is loaded as a program. The port needs a read timeout of `SEND_TIMEOUT`.
+pub fn send(port: &mut Port, file: &str, to: Option<&str>) -> Result<(), SendError> {
+    let data = std::fs::read(file)?;
+    let name = Path::new(file)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Expected a file name"))?;
+
+    let command = match to {
+        Some(to) => format!("upload {to}\n"),
+        None => "upload\n".to_string(),
+    };
+    port.write_all(command.as_bytes())?;
+
+    // Carriage returns as stderr may be in raw mode
+    let res = h7_xfer::send(&mut IoLink(&mut *port), name, &data, |n| {
+        eprint!("\rSent {n}/{} bytes", data.len());
+    });
+    eprint!("\r\n");
+    Ok(res?)
+}
@@ -0,0 +1,391 @@
+//! Interactive terminal. Keys go to the board unchanged and its output is written to stdout as
+//! is. `Ctrl-]` opens a local menu.
+
+use {
+    crate::{
+        port::{Config, Port},
+        raw::RawMode,
+        send::{send, SEND_TIMEOUT},
+    },
+    std::{
+        collections::VecDeque,
+        fs::{File, OpenOptions},
+        io::{self, ErrorKind, Read, Write},
+        sync::mpsc::{channel, Receiver, TryRecvError},
+        thread,
+        time::{Duration, Instant},
+    },
+};
+
+/// Ctrl-]
+const ESCAPE: u8 = 0x1d;
+const CTRL_C: u8 = 0x03;
+const ESC: u8 = 0x1b;
+const BACKSPACE: u8 = 0x7f;
+
+/// Read timeout of the port, how long a key may wait
+const POLL: Duration = Duration::from_millis(10);
+/// Time between attempts to open a lost port
+const RECONNECT: Duration = Duration::from_millis(1000);
+
+const MENU: &str = "s: send file, l: start/stop log, q: quit, Ctrl-]: send Ctrl-]";
+
+/// Keys from stdin, read on a thread
+struct Input {
+    rx: Receiver<Vec<u8>>,
+    pending: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Input {
+    fn spawn(mut source: impl Read + Send + 'static) -> Self {
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            loop {
+                match source.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        if tx.send(buf[..n].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(_) => break,
+                }
+            }
+        });
+        Self {
+            rx,
+            pending: VecDeque::new(),
+            closed: false,
+        }
+    }
+
+    /// The next key if there is one
+    fn try_byte(&mut self) -> Option<u8> {
+        if self.pending.is_empty() && !self.closed {
+            match self.rx.try_recv() {
+                Ok(bytes) => self.pending.extend(bytes),
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => self.closed = true,
+            }
+        }
+        self.pending.pop_front()
+    }
+
+    /// Wait for the next key, `None` once stdin is closed
+    fn byte(&mut self) -> Option<u8> {
+        if self.pending.is_empty() && !self.closed {
+            match self.rx.recv() {
+                Ok(bytes) => self.pending.extend(bytes),
+                Err(_) => self.closed = true,
+            }
+        }
+        self.pending.pop_front()
+    }
+}
+
+pub struct Terminal {
+    config: Config,
+    port: Option<Port>,
+    input: Input,
+    output: Box<dyn Write>,
+    log: Option<File>,
+    last_attempt: Instant,
+}
+
+impl Terminal {
+    pub fn new(config: Config, log: Option<&str>) -> io::Result<Self> {
+        Self::with_io(config, log, io::stdin(), io::stdout())
+    }
+
+    /// Keys from `input` and the board's output to `output` instead of stdin and stdout
+    fn with_io(
+        config: Config,
+        log: Option<&str>,
+        input: impl Read + Send + 'static,
+        output: impl Write + 'static,
+    ) -> io::Result<Self> {
+        let port = Port::open(&config, POLL)?;
+        let log = log.map(open_log).transpose()?;
+        Ok(Self {
+            config,
+            port: Some(port),
+            input: Input::spawn(input),
+            output: Box::new(output),
+            log,
+            last_attempt: Instant::now(),
+        })
+    }
+
+    /// Run until quit from the menu
+    pub fn run(&mut self) -> io::Result<()> {
+        let _raw = RawMode::enable();
+        status(&format!(
+            "Connected to {} at {} baud, Ctrl-] for the menu",
+            self.config.name(),
+            self.config.baud
+        ));
+        let mut buf = [0u8; 256];
+        loop {
+            self.receive(&mut buf)?;
+
+            let mut keys = Vec::new();
+            while let Some(key) = self.input.try_byte() {
+                if key != ESCAPE {
+                    keys.push(key);
+                    continue;
+                }
+                self.write(&keys);
+                keys.clear();
+                if !self.menu()? {
+                    return Ok(());
+                }
+            }
+            self.write(&keys);
+        }
+    }
+
+    /// Copy output from the board to the output and the log, or try to reconnect
+    fn receive(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let Some(port) = &mut self.port else {
+            if self.last_attempt.elapsed() < RECONNECT {
+                thread::sleep(POLL);
+                return Ok(());
+            }
+            self.last_attempt = Instant::now();
+            if let Ok(port) = Port::open(&self.config, POLL) {
+                self.port = Some(port);
+                status(&format!("Reconnected to {}", self.config.name()));
+            }
+            return Ok(());
+        };
+        match port.read(buf) {
+            Ok(0) => {}
+            Ok(len) => {
+                self.output.write_all(&buf[..len])?;
+                self.output.flush()?;
+                if let Some(log) = &mut self.log {
+                    log.write_all(&buf[..len])?;
+                }
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+            Err(e) => self.disconnected(e),
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, keys: &[u8]) {
+        let Some(port) = &mut self.port else {
+            return;
+        };
+        if let Err(e) = port.write_all(keys).and_then(|_| port.flush()) {
+            self.disconnected(e);
+        }
+    }
+
+    fn disconnected(&mut self, e: io::Error) {
+        self.port = None;
+        self.last_attempt = Instant::now();
+        status(&format!("Disconnected: {e}, reconnecting"));
+    }
+
+    /// Handle a menu key, returns `false` to quit
+    fn menu(&mut self) -> io::Result<bool> {
+        status(MENU);
+        match self.input.byte() {
+            Some(b'q') | None => return Ok(false),
+            Some(ESCAPE) => self.write(&[ESCAPE]),
+            Some(b's') => self.send_file(),
+            Some(b'l') => self.toggle_log(),
+            Some(_) => {}
+        }
+        Ok(true)
+    }
+
+    fn send_file(&mut self) {
+        let Some(file) = self.prompt("File: ").filter(|f| !f.is_empty()) else {
+            return;
+        };
+        let Some(to) = self.prompt("Destination, empty to load it as a program: ") else {
+            return;
+        };
+        let to = Some(to.as_str()).filter(|to| !to.is_empty());
+        let Some(port) = &mut self.port else {
+            status("Not connected");
+            return;
+        };
+        let res = port
+            .set_timeout(SEND_TIMEOUT)
+            .map_err(Into::into)
+            .and_then(|_| send(port, &file, to));
+        if let Err(e) = port.set_timeout(POLL) {
+            self.disconnected(e);
+        }
+        match res {
+            Ok(()) => status(&format!("Sent {file}")),
+            Err(e) => status(&format!("Error: {e}")),
+        }
+    }
+
+    fn toggle_log(&mut self) {
+        if self.log.take().is_some() {
+            status("Log closed");
+            return;
+        }
+        let Some(path) = self.prompt("Log to: ").filter(|p| !p.is_empty()) else {
+            return;
+        };
+        match open_log(&path) {
+            Ok(log) => {
+                self.log = Some(log);
+                status(&format!("Logging to {path}"));
+            }
+            Err(e) => status(&format!("Error: {path}: {e}")),
+        }
+    }
+
+    /// Read a line with local echo, `None` if cancelled with Ctrl-C or Esc
+    fn prompt(&mut self, prompt: &str) -> Option<String> {
+        let mut stderr = io::stderr();
+        let mut line = String::new();
+        let _ = write!(stderr, "{prompt}");
+        loop {
+            match self.input.byte()? {
+                b'\r' | b'\n' => {
+                    let _ = write!(stderr, "\r\n");
+                    return Some(line);
+                }
+                CTRL_C | ESC => {
+                    let _ = write!(stderr, "\r\n");
+                    return None;
+                }
+                BACKSPACE | 0x08 if !line.is_empty() => {
+                    line.pop();
+                    let _ = write!(stderr, "\x08 \x08");
+                }
+                b if b.is_ascii() && !b.is_ascii_control() => {
+                    line.push(b as char);
+                    let _ = write!(stderr, "{}", b as char);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn open_log(path: &str) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// A line from the terminal itself, between the board's output
+fn status(msg: &str) {
+    eprint!("\r\n[{msg}]\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{
+            os::fd::FromRawFd,
+            sync::{Arc, Mutex},
+        },
+    };
+
+    /// Keys typed in the test
+    struct Keys(Receiver<Vec<u8>>, VecDeque<u8>);
+
+    impl Read for Keys {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.1.is_empty() {
+                match self.0.recv() {
+                    Ok(keys) => self.1.extend(keys),
+                    Err(_) => return Ok(0),
+                }
+            }
+            let n = buf.len().min(self.1.len());
+            buf[..n]
+                .iter_mut()
+                .for_each(|b| *b = self.1.pop_front().unwrap());
+            Ok(n)
+        }
+    }
+
+    /// What the terminal writes to stdout
+    #[derive(Clone, Default)]
+    struct Screen(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Screen {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A pseudo-terminal, the controller end and the device end with its path. Reading the
+    /// controller fails while the device isn't open.
+    fn pty() -> (File, File, String) {
+        let (mut controller, mut device) = (0, 0);
+        unsafe {
+            let res = libc::openpty(
+                &mut controller,
+                &mut device,
+                core::ptr::null_mut(),
+                core::ptr::null(),
+                core::ptr::null(),
+            );
+            assert_eq!(res, 0, "openpty failed");
+            let path = std::fs::read_link(format!("/proc/self/fd/{device}")).unwrap();
+            (
+                File::from_raw_fd(controller),
+                File::from_raw_fd(device),
+                path.display().to_string(),
+            )
+        }
+    }
+
+    #[test]
+    fn pass_through() {
+        let (mut board, _device, path) = pty();
+        let (keys, rx) = channel();
+        let screen = Screen::default();
+        let output = screen.clone();
+        let terminal = thread::spawn(move || {
+            let config = Config {
+                path: Some(path),
+                baud: 115_200,
+            };
+            Terminal::with_io(config, None, Keys(rx, VecDeque::new()), output)?.run()
+        });
+
+        // Everything but Ctrl-] goes to the board as is
+        let typed = b"ls\r\x1b[A\x1b[3~\x03\x00\x7f\xff\xc3\xa4".to_vec();
+        keys.send(typed.clone()).unwrap();
+        let mut received = vec![0; typed.len()];
+        board.read_exact(&mut received).unwrap();
+        assert_eq!(received, typed);
+
+        // And everything from the board to the screen
+        let mut output = b"\x1b[2J\x1b[H\x1b[31mred\x1b[0m\r\n".to_vec();
+        output.extend(0..=255u8);
+        board.write_all(&output).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while screen.0.lock().unwrap().len() < output.len() && Instant::now() < deadline {
+            thread::sleep(POLL);
+        }
+        assert!(*screen.0.lock().unwrap() == output);
+
+        // Ctrl-] Ctrl-] sends one, Ctrl-] q quits
+        keys.send(vec![ESCAPE, ESCAPE, ESCAPE, b'q']).unwrap();
+        let mut escape = [0];
+        board.read_exact(&mut escape).unwrap();
+        assert_eq!(escape, [ESCAPE]);
+        terminal.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,36 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XferError {
+    /// Frame with a bad CRC, length or kind
+    Corrupt,
+    /// No answer after `MAX_RETRIES`
+    Timeout,
+    /// The other side aborted the transfer
+    Cancelled,
+    /// The receiver could not store a packet
+    Rejected,
+    /// Packet before the start or after the end
+    UnexpectedPacket,
+    /// More or less data than announced (announced, received)
+    SizeMismatch(u32, u32),
+    /// File or name does not fit
+    TooLarge,
+    /// Could not write to the link
+    Link,
+}
+
+impl core::fmt::Display for XferError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Corrupt => write!(f, "Corrupt frame"),
+            Self::Timeout => write!(f, "Timed out"),
+            Self::Cancelled => write!(f, "Transfer cancelled"),
+            Self::Rejected => write!(f, "Receiver rejected the data"),
+            Self::UnexpectedPacket => write!(f, "Unexpected packet"),
+            Self::SizeMismatch(announced, received) => {
+                write!(f, "Expected {announced} bytes, got {received}")
+            }
+            Self::TooLarge => write!(f, "Too large"),
+            Self::Link => write!(f, "Link error"),
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Frames on the wire:
+//!
+//! ```text
+//! | SOH | kind | seq u16 | len u16 | payload | crc32 |
+//! ```
+//!
+//! Numbers are big endian, the CRC covers everything between `SOH` and the CRC.
+
+use crate::{XferError, MAX_FRAME, MAX_PAYLOAD};
+
+const SOH: u8 = 0x01;
+pub(crate) const HEADER_LEN: usize = 6;
+pub(crate) const CRC_LEN: usize = 4;
+
+const KIND_START: u8 = 1;
+const KIND_DATA: u8 = 2;
+const KIND_END: u8 = 3;
+const KIND_ABORT: u8 = 4;
+
+const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packet<'a> {
+    /// First packet, the size of the file and its name on the sender
+    Start {
+        size: u32,
+        name: &'a str,
+    },
+    Data(&'a [u8]),
+    /// All data sent, the receiver acknowledges once it's stored
+    End,
+    /// The sender gave up
+    Abort,
+}
+
+impl<'a> Packet<'a> {
+    fn kind(&self) -> u8 {
+        match self {
+            Self::Start { .. } => KIND_START,
+            Self::Data(_) => KIND_DATA,
+            Self::End => KIND_END,
+            Self::Abort => KIND_ABORT,
+        }
+    }
+
+    fn parse(kind: u8, payload: &'a [u8]) -> Result<Self, XferError> {
+        match (kind, payload.len()) {
+            (KIND_START, 4..) => Ok(Self::Start {
+                size: u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]),
+                name: core::str::from_utf8(&payload[4..]).map_err(|_| XferError::Corrupt)?,
+            }),
+            (KIND_DATA, _) => Ok(Self::Data(payload)),
+            (KIND_END, 0) => Ok(Self::End),
+            (KIND_ABORT, 0) => Ok(Self::Abort),
+            _ => Err(XferError::Corrupt),
+        }
+    }
+}
+
+/// Write the frame for `packet` to `out`, returns its length
+pub fn encode(seq: u16, packet: &Packet, out: &mut [u8; MAX_FRAME]) -> Result<usize, XferError> {
+    let payload_len = match packet {
+        Packet::Start { name, .. } => 4 + name.len(),
+        Packet::Data(data) => data.len(),
+        Packet::End | Packet::Abort => 0,
+    };
+    if payload_len > MAX_PAYLOAD {
+        return Err(XferError::TooLarge);
+    }
+    out[0] = SOH;
+    out[1] = packet.kind();
+    out[2..4].copy_from_slice(&seq.to_be_bytes());
+    out[4..6].copy_from_slice(&(payload_len as u16).to_be_bytes());
+    let payload = &mut out[HEADER_LEN..HEADER_LEN + payload_len];
+    match packet {
+        Packet::Start { size, name } => {
+            payload[..4].copy_from_slice(&size.to_be_bytes());
+            payload[4..].copy_from_slice(name.as_bytes());
+        }
+        Packet::Data(data) => payload.copy_from_slice(data),
+        Packet::End | Packet::Abort => {}
+    }
+    let end = HEADER_LEN + payload_len;
+    let crc = CRC.checksum(&out[1..end]);
+    out[end..end + CRC_LEN].copy_from_slice(&crc.to_be_bytes());
+    Ok(end + CRC_LEN)
+}
+
+/// Reassembles frames from received bytes, anything outside a frame is skipped
+pub struct Decoder {
+    buf: [u8; MAX_FRAME],
+    len: usize,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME],
+            len: 0,
+        }
+    }
+
+    /// Drop a partly received frame
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    /// Add a byte, returns the sequence number and packet once a frame is complete
+    pub fn push(&mut self, byte: u8) -> Option<Result<(u16, Packet<'_>), XferError>> {
+        if self.len == 0 && byte != SOH {
+            return None;
+        }
+        self.buf[self.len] = byte;
+        self.len += 1;
+        if self.len < HEADER_LEN {
+            return None;
+        }
+        let payload_len = u16::from_be_bytes([self.buf[4], self.buf[5]]) as usize;
+        if payload_len > MAX_PAYLOAD {
+            self.len = 0;
+            return Some(Err(XferError::Corrupt));
+        }
+        let end = HEADER_LEN + payload_len;
+        if self.len < end + CRC_LEN {
+            return None;
+        }
+        self.len = 0;
+
+        let crc = u32::from_be_bytes([
+            self.buf[end],
+            self.buf[end + 1],
+            self.buf[end + 2],
+            self.buf[end + 3],
+        ]);
+        if crc != CRC.checksum(&self.buf[1..end]) {
+            return Some(Err(XferError::Corrupt));
+        }
+        let seq = u16::from_be_bytes([self.buf[2], self.buf[3]]);
+        Some(Packet::parse(self.buf[1], &self.buf[HEADER_LEN..end]).map(|p| (seq, p)))
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(decoder: &mut Decoder, bytes: &[u8]) -> Vec<Result<(u16, String), XferError>> {
+        let mut frames = Vec::new();
+        for &b in bytes {
+            if let Some(res) = decoder.push(b) {
+                frames.push(res.map(|(seq, p)| (seq, format!("{p:?}"))));
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut frame = [0u8; MAX_FRAME];
+        let mut decoder = Decoder::new();
+        for (seq, packet) in [
+            (
+                0,
+                Packet::Start {
+                    size: 1234,
+                    name: "hello.h7",
+                },
+            ),
+            (1, Packet::Data(&[1, 2, 3])),
+            (2, Packet::Data(&[0xaa; MAX_PAYLOAD])),
+            (3, Packet::End),
+            (0xffff, Packet::Abort),
+        ] {
+            let n = encode(seq, &packet, &mut frame).unwrap();
+            let frames = decode(&mut decoder, &frame[..n]);
+            assert_eq!(frames, [Ok((seq, format!("{packet:?}")))]);
+        }
+    }
+
+    #[test]
+    fn skips_noise() {
+        let mut frame = [0u8; MAX_FRAME];
+        let n = encode(7, &Packet::Data(b"data"), &mut frame).unwrap();
+        let mut bytes = b"upload sdcard:/\r\n> ".to_vec();
+        bytes.extend_from_slice(&frame[..n]);
+        let frames = decode(&mut Decoder::new(), &bytes);
+        assert_eq!(frames, [Ok((7, format!("{:?}", Packet::Data(b"data"))))]);
+    }
+
+    #[test]
+    fn corrupt() {
+        let mut frame = [0u8; MAX_FRAME];
+        let n = encode(1, &Packet::Data(b"data"), &mut frame).unwrap();
+        let mut decoder = Decoder::new();
+        frame[HEADER_LEN + 1] ^= 0x10;
+        assert_eq!(decode(&mut decoder, &frame[..n]), [Err(XferError::Corrupt)]);
+
+        // Length field out of range
+        let n = encode(1, &Packet::End, &mut frame).unwrap();
+        frame[4] = 0xff;
+        assert_eq!(decode(&mut decoder, &frame[..n]), [Err(XferError::Corrupt)]);
+
+        // The decoder starts over after an error
+        let n = encode(2, &Packet::End, &mut frame).unwrap();
+        assert_eq!(decode(&mut decoder, &frame[..n]), [Ok((2, "End".into()))]);
+    }
+
+    #[test]
+    fn too_large() {
+        let mut frame = [0u8; MAX_FRAME];
+        let data = [0u8; MAX_PAYLOAD + 1];
+        assert_eq!(
+            encode(0, &Packet::Data(&data), &mut frame),
+            Err(XferError::TooLarge)
+        );
+    }
+}
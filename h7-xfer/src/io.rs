@@ -0,0 +1,111 @@
+use {
+    crate::{Link, XferError},
+    std::io::{Read, Write},
+};
+
+/// A link over a reader and writer that time out, a serial port with a read timeout.
+/// Reads that fail or return nothing count as timeouts.
+pub struct IoLink<T>(pub T);
+
+impl<T: Read + Write> Link for IoLink<T> {
+    fn read(&mut self) -> Option<u8> {
+        let mut byte = [0u8];
+        match self.0.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), XferError> {
+        self.0
+            .write_all(data)
+            .and_then(|_| self.0.flush())
+            .map_err(|_| XferError::Link)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{receive, send, Packet, MAX_PAYLOAD},
+        std::{
+            fs::File,
+            io,
+            os::fd::{AsRawFd, FromRawFd},
+            thread,
+        },
+    };
+
+    /// One end of a pseudo-terminal, reads time out after 100 ms like a serial port
+    struct Pty(File);
+
+    impl Read for Pty {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut fd = libc::pollfd {
+                fd: self.0.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            match unsafe { libc::poll(&mut fd, 1, 100) } {
+                1 => self.0.read(buf),
+                _ => Err(io::ErrorKind::TimedOut.into()),
+            }
+        }
+    }
+
+    impl Write for Pty {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    /// A raw pseudo-terminal pair, the controller and the device end
+    fn pty_pair() -> (Pty, Pty) {
+        let (mut controller, mut device) = (0, 0);
+        unsafe {
+            let res = libc::openpty(
+                &mut controller,
+                &mut device,
+                core::ptr::null_mut(),
+                core::ptr::null(),
+                core::ptr::null(),
+            );
+            assert_eq!(res, 0, "openpty failed");
+            let mut termios = core::mem::zeroed();
+            assert_eq!(libc::tcgetattr(device, &mut termios), 0);
+            libc::cfmakeraw(&mut termios);
+            assert_eq!(libc::tcsetattr(device, libc::TCSANOW, &termios), 0);
+            (
+                Pty(File::from_raw_fd(controller)),
+                Pty(File::from_raw_fd(device)),
+            )
+        }
+    }
+
+    #[test]
+    fn send_over_pty() {
+        let data: Vec<u8> = (0..3 * MAX_PAYLOAD + 100)
+            .map(|i| (i % 256) as u8)
+            .collect();
+        let (host, mut board) = pty_pair();
+        let rx = thread::spawn(move || {
+            // The shell echoes the command before the receiver starts
+            board.write_all(b"upload sdcard:/\r\n").unwrap();
+            let mut received = Vec::new();
+            receive(&mut IoLink(board), |p| {
+                if let Packet::Data(d) = p {
+                    received.extend_from_slice(d);
+                }
+                Ok(())
+            })
+            .map(|_| received)
+        });
+        send(&mut IoLink(host), "app.h7", &data, |_| {}).unwrap();
+        assert_eq!(rx.join().unwrap(), Ok(data));
+    }
+}
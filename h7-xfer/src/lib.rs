@@ -0,0 +1,31 @@
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+mod error;
+mod frame;
+#[cfg(any(test, feature = "std"))]
+mod io;
+mod transfer;
+
+#[cfg(any(test, feature = "std"))]
+pub use io::IoLink;
+pub use {
+    error::XferError,
+    frame::{encode, Decoder, Packet},
+    transfer::{receive, send, Link},
+};
+
+/// Receiver is waiting for the first frame
+pub const READY: u8 = 0x05;
+/// Frame received, followed by its sequence number
+pub const ACK: u8 = 0x06;
+/// Frame corrupted, send it again
+pub const NAK: u8 = 0x15;
+/// Transfer aborted by the receiver
+pub const CAN: u8 = 0x18;
+
+/// Largest payload of a frame
+pub const MAX_PAYLOAD: usize = 1024;
+/// Largest frame on the wire
+pub const MAX_FRAME: usize = frame::HEADER_LEN + MAX_PAYLOAD + frame::CRC_LEN;
+/// Attempts per frame, and link timeouts before giving up
+pub const MAX_RETRIES: u8 = 10;
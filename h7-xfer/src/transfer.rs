@@ -0,0 +1,371 @@
+//! Stop-and-wait transfer. The sender waits for `READY`, then sends one frame at a time and
+//! repeats it until it's acknowledged with `ACK` and the frame's sequence number. A lost `ACK`
+//! makes the sender repeat a frame the receiver already has, the receiver acknowledges it again
+//! without passing it on.
+
+use crate::{
+    Decoder, Packet, XferError, ACK, CAN, MAX_FRAME, MAX_PAYLOAD, MAX_RETRIES, NAK, READY,
+};
+
+/// A byte stream to the other side, a UART or a serial port
+pub trait Link {
+    /// The next byte, `None` if nothing arrived within the link's timeout
+    fn read(&mut self) -> Option<u8>;
+
+    fn write(&mut self, data: &[u8]) -> Result<(), XferError>;
+}
+
+/// Send `data` as `name`. `progress` is called with the number of bytes acknowledged so far.
+pub fn send<L: Link>(
+    link: &mut L,
+    name: &str,
+    data: &[u8],
+    mut progress: impl FnMut(usize),
+) -> Result<(), XferError> {
+    let size = u32::try_from(data.len()).map_err(|_| XferError::TooLarge)?;
+    wait_ready(link)?;
+
+    let mut frame = [0u8; MAX_FRAME];
+    let res = send_packets(link, &mut frame, name, size, data, &mut progress);
+    match res {
+        Err(XferError::Cancelled) | Ok(()) => res,
+        Err(e) => {
+            // Best effort, the receiver gives up on its own too
+            if let Ok(n) = crate::encode(0, &Packet::Abort, &mut frame) {
+                let _ = link.write(&frame[..n]);
+            }
+            Err(e)
+        }
+    }
+}
+
+fn send_packets<L: Link>(
+    link: &mut L,
+    frame: &mut [u8; MAX_FRAME],
+    name: &str,
+    size: u32,
+    data: &[u8],
+    progress: &mut impl FnMut(usize),
+) -> Result<(), XferError> {
+    let mut seq = 0u16;
+    send_packet(link, frame, seq, &Packet::Start { size, name })?;
+    let mut sent = 0;
+    for chunk in data.chunks(MAX_PAYLOAD) {
+        seq = seq.wrapping_add(1);
+        send_packet(link, frame, seq, &Packet::Data(chunk))?;
+        sent += chunk.len();
+        progress(sent);
+    }
+    send_packet(link, frame, seq.wrapping_add(1), &Packet::End)
+}
+
+fn wait_ready<L: Link>(link: &mut L) -> Result<(), XferError> {
+    let mut timeouts = 0;
+    while timeouts < MAX_RETRIES {
+        match link.read() {
+            Some(READY) => return Ok(()),
+            Some(CAN) => return Err(XferError::Cancelled),
+            // Echo and output of the command that started the receiver
+            Some(_) => {}
+            None => timeouts += 1,
+        }
+    }
+    Err(XferError::Timeout)
+}
+
+fn send_packet<L: Link>(
+    link: &mut L,
+    frame: &mut [u8; MAX_FRAME],
+    seq: u16,
+    packet: &Packet,
+) -> Result<(), XferError> {
+    let n = crate::encode(seq, packet, frame)?;
+    for _ in 0..MAX_RETRIES {
+        link.write(&frame[..n])?;
+        loop {
+            match link.read() {
+                Some(ACK) => match (link.read(), link.read()) {
+                    (Some(hi), Some(lo)) if u16::from_be_bytes([hi, lo]) == seq => return Ok(()),
+                    // Late ACK of an earlier frame
+                    _ => continue,
+                },
+                Some(CAN) => return Err(XferError::Cancelled),
+                Some(NAK) | None => break,
+                Some(_) => continue,
+            }
+        }
+    }
+    Err(XferError::Timeout)
+}
+
+/// Receive a file, `handler` gets each packet once and in order, from `Start` to `End`.
+/// An error from the handler cancels the transfer. Returns the number of bytes received.
+pub fn receive<L: Link>(
+    link: &mut L,
+    mut handler: impl FnMut(Packet) -> Result<(), XferError>,
+) -> Result<u32, XferError> {
+    let mut decoder = Decoder::new();
+    let mut expected = 0u16;
+    let mut size = None;
+    let mut received = 0u32;
+    let mut timeouts = 0;
+    link.write(&[READY])?;
+    loop {
+        let Some(byte) = link.read() else {
+            decoder.reset();
+            timeouts += 1;
+            // The sender retries for MAX_RETRIES timeouts, wait for it to give up first
+            if timeouts > 2 * MAX_RETRIES {
+                let _ = link.write(&[CAN]);
+                return Err(XferError::Timeout);
+            }
+            if size.is_none() {
+                link.write(&[READY])?;
+            }
+            continue;
+        };
+        timeouts = 0;
+        let (seq, packet) = match decoder.push(byte) {
+            None => continue,
+            Some(Ok(frame)) => frame,
+            Some(Err(_)) => {
+                link.write(&[NAK])?;
+                continue;
+            }
+        };
+        if packet == Packet::Abort {
+            return Err(XferError::Cancelled);
+        }
+        if size.is_some() && seq == expected.wrapping_sub(1) {
+            ack(link, seq)?;
+            continue;
+        }
+        if seq != expected {
+            link.write(&[NAK])?;
+            continue;
+        }
+
+        let res = match (packet, size) {
+            (Packet::Start { size: s, .. }, None) => {
+                size = Some(s);
+                handler(packet)
+            }
+            (Packet::Data(data), Some(s)) => match received.checked_add(data.len() as u32) {
+                Some(n) if n <= s => {
+                    received = n;
+                    handler(packet)
+                }
+                _ => Err(XferError::SizeMismatch(
+                    s,
+                    received.saturating_add(data.len() as u32),
+                )),
+            },
+            (Packet::End, Some(s)) if received == s => handler(packet),
+            (Packet::End, Some(s)) => Err(XferError::SizeMismatch(s, received)),
+            _ => Err(XferError::UnexpectedPacket),
+        };
+        if let Err(e) = res {
+            let _ = link.write(&[CAN]);
+            return Err(e);
+        }
+        ack(link, seq)?;
+        if packet == Packet::End {
+            return Ok(received);
+        }
+        expected = expected.wrapping_add(1);
+    }
+}
+
+fn ack<L: Link>(link: &mut L, seq: u16) -> Result<(), XferError> {
+    let [hi, lo] = seq.to_be_bytes();
+    link.write(&[ACK, hi, lo])
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{
+            sync::mpsc::{channel, Receiver, Sender},
+            thread,
+            time::Duration,
+        },
+    };
+
+    /// One end of an in-memory link, writes with an index in `corrupt` get a flipped bit and
+    /// ones in `drop` are lost
+    struct ChannelLink {
+        rx: Receiver<u8>,
+        tx: Sender<u8>,
+        writes: usize,
+        corrupt: Vec<usize>,
+        drop: Vec<usize>,
+    }
+
+    impl Link for ChannelLink {
+        fn read(&mut self) -> Option<u8> {
+            self.rx.recv_timeout(Duration::from_millis(20)).ok()
+        }
+
+        fn write(&mut self, data: &[u8]) -> Result<(), XferError> {
+            let n = self.writes;
+            self.writes += 1;
+            if self.drop.contains(&n) {
+                return Ok(());
+            }
+            for (i, &b) in data.iter().enumerate() {
+                let b = match self.corrupt.contains(&n) && i == data.len() / 2 {
+                    true => b ^ 0x04,
+                    false => b,
+                };
+                self.tx.send(b).map_err(|_| XferError::Link)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn pair() -> (ChannelLink, ChannelLink) {
+        let (a_tx, b_rx) = channel();
+        let (b_tx, a_rx) = channel();
+        let link = |rx, tx| ChannelLink {
+            rx,
+            tx,
+            writes: 0,
+            corrupt: Vec::new(),
+            drop: Vec::new(),
+        };
+        (link(a_rx, a_tx), link(b_rx, b_tx))
+    }
+
+    fn file(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 256) as u8).collect()
+    }
+
+    /// Size, name and data
+    type Received = (u32, String, Vec<u8>);
+
+    /// Run the receiver in a thread, returns the sender's result and what the receiver got
+    fn transfer(
+        mut sender: ChannelLink,
+        mut receiver: ChannelLink,
+        data: &[u8],
+    ) -> (Result<(), XferError>, Result<Received, XferError>) {
+        let rx = thread::spawn(move || {
+            let (mut name, mut data) = (String::new(), Vec::new());
+            receive(&mut receiver, |p| {
+                match p {
+                    Packet::Start { name: n, .. } => name.push_str(n),
+                    Packet::Data(d) => data.extend_from_slice(d),
+                    _ => {}
+                }
+                Ok(())
+            })
+            .map(|n| (n, name, data))
+        });
+        let res = send(&mut sender, "app.h7", data, |_| {});
+        (res, rx.join().unwrap())
+    }
+
+    #[test]
+    fn send_file() {
+        let data = file(5000);
+        let (sender, receiver) = pair();
+        let (sent, received) = transfer(sender, receiver, &data);
+        assert_eq!(sent, Ok(()));
+        assert_eq!(received, Ok((5000, "app.h7".into(), data)));
+    }
+
+    #[test]
+    fn empty_file() {
+        let (sender, receiver) = pair();
+        let (sent, received) = transfer(sender, receiver, &[]);
+        assert_eq!(sent, Ok(()));
+        assert_eq!(received, Ok((0, "app.h7".into(), Vec::new())));
+    }
+
+    #[test]
+    fn corrupt_frames_are_repeated() {
+        let data = file(3 * MAX_PAYLOAD);
+        let (mut sender, receiver) = pair();
+        // Start three times and the second data frame
+        sender.corrupt = vec![0, 1, 2, 5];
+        let (sent, received) = transfer(sender, receiver, &data);
+        assert_eq!(sent, Ok(()));
+        assert_eq!(received, Ok((data.len() as u32, "app.h7".into(), data)));
+    }
+
+    #[test]
+    fn lost_acks_are_repeated() {
+        let data = file(3 * MAX_PAYLOAD);
+        let (sender, mut receiver) = pair();
+        // READY, then the ACKs of the start and second data frame, and a corrupted one
+        receiver.drop = vec![1, 3];
+        receiver.corrupt = vec![4];
+        let (sent, received) = transfer(sender, receiver, &data);
+        assert_eq!(sent, Ok(()));
+        // Repeated frames are not stored twice
+        assert_eq!(received, Ok((data.len() as u32, "app.h7".into(), data)));
+    }
+
+    #[test]
+    fn lost_link() {
+        let (mut sender, receiver) = pair();
+        // Nothing gets through after the start
+        sender.drop = (1..100).collect();
+        let (sent, received) = transfer(sender, receiver, &file(100));
+        assert_eq!(sent, Err(XferError::Timeout));
+        assert_eq!(received, Err(XferError::Timeout));
+    }
+
+    #[test]
+    fn rejected() {
+        let (mut sender, mut receiver) = pair();
+        let rx = thread::spawn(move || {
+            receive(&mut receiver, |p| match p {
+                Packet::Data(_) => Err(XferError::Rejected),
+                _ => Ok(()),
+            })
+        });
+        assert_eq!(
+            send(&mut sender, "app.h7", &file(10), |_| {}),
+            Err(XferError::Cancelled)
+        );
+        assert_eq!(rx.join().unwrap(), Err(XferError::Rejected));
+    }
+
+    #[test]
+    fn size_mismatch() {
+        let (mut sender, mut receiver) = pair();
+        let rx = thread::spawn(move || receive(&mut receiver, |_| Ok(())));
+        assert_eq!(sender.read(), Some(READY));
+        let mut frame = [0u8; MAX_FRAME];
+        for (seq, packet) in [
+            (
+                0,
+                Packet::Start {
+                    size: 10,
+                    name: "a",
+                },
+            ),
+            (1, Packet::Data(b"1234")),
+            (2, Packet::End),
+        ] {
+            let n = crate::encode(seq, &packet, &mut frame).unwrap();
+            sender.write(&frame[..n]).unwrap();
+        }
+        assert_eq!(rx.join().unwrap(), Err(XferError::SizeMismatch(10, 4)));
+        let replies: Vec<u8> = sender.rx.try_iter().collect();
+        assert_eq!(replies, [ACK, 0, 0, ACK, 0, 1, CAN]);
+    }
+
+    #[test]
+    fn progress() {
+        let data = file(2 * MAX_PAYLOAD + 10);
+        let (mut sender, mut receiver) = pair();
+        let rx = thread::spawn(move || receive(&mut receiver, |_| Ok(())));
+        let mut steps = Vec::new();
+        send(&mut sender, "app.h7", &data, |n| steps.push(n)).unwrap();
+        assert_eq!(steps, [MAX_PAYLOAD, 2 * MAX_PAYLOAD, data.len()]);
+        assert_eq!(rx.join().unwrap(), Ok(data.len() as u32));
+    }
+}